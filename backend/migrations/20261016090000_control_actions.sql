@@ -0,0 +1,32 @@
+-- Audit trail of every command the control loop sends to a device.
+--
+--   commands : JSON array of the Tuya commands sent, e.g.
+--              [{"code": "switch", "value": true}]
+--   inputs   : JSON object of the cached readings the decision was based on,
+--              keyed by sensor_type and using the sensor_readings encoding, e.g.
+--              {"temperature": 2040, "temperature_setpoint": 2100, "relay_state": 0}
+--   rule     : machine-readable rule that triggered the action (e.g. "below_band")
+--   reason   : human-readable explanation of the decision
+--   success  : whether Tuya accepted the command
+--   tid      : Tuya request trace ID, when one was returned
+--   error    : error message when the call failed
+CREATE TABLE control_actions (
+    id          UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id   TEXT        NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    commands    JSONB       NOT NULL,
+    inputs      JSONB       NOT NULL,
+    rule        TEXT        NOT NULL,
+    reason      TEXT        NOT NULL,
+    success     BOOLEAN     NOT NULL,
+    tid         TEXT,
+    error       TEXT
+);
+
+-- Covers:
+--   WHERE recorded_at BETWEEN $1 AND $2 ORDER BY recorded_at
+--   WHERE device_id = $1 AND recorded_at BETWEEN $2 AND $3
+CREATE INDEX idx_control_actions_time
+    ON control_actions (recorded_at DESC);
+CREATE INDEX idx_control_actions_device_time
+    ON control_actions (device_id, recorded_at DESC);
@@ -0,0 +1,35 @@
+-- Weekly heating schedules for thermostats.
+--
+-- A schedule owns a set of blocks. Each block applies `setpoint` on one ISO
+-- weekday (1 = Monday … 7 = Sunday) from `start_time` (inclusive) to
+-- `end_time` (exclusive; 00:00 means end of day), evaluated in the service's
+-- configured local timezone. Outside every block the schedule's
+-- `default_setpoint` applies.
+--
+-- Setpoints use the sensor_readings encoding: °C × 100 (21.5 °C → 2150).
+CREATE TABLE heating_schedules (
+    id               UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id        TEXT        NOT NULL,
+    name             TEXT        NOT NULL,
+    enabled          BOOLEAN     NOT NULL DEFAULT true,
+    default_setpoint BIGINT      NOT NULL,
+    created_at       TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at       TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- At most one enabled schedule per thermostat.
+CREATE UNIQUE INDEX uq_heating_schedules_enabled_device
+    ON heating_schedules (device_id)
+    WHERE enabled;
+
+CREATE TABLE heating_schedule_blocks (
+    id          UUID     PRIMARY KEY DEFAULT gen_random_uuid(),
+    schedule_id UUID     NOT NULL REFERENCES heating_schedules (id) ON DELETE CASCADE,
+    weekday     SMALLINT NOT NULL CHECK (weekday BETWEEN 1 AND 7),
+    start_time  TIME     NOT NULL,
+    end_time    TIME     NOT NULL,
+    setpoint    BIGINT   NOT NULL
+);
+
+CREATE INDEX idx_heating_schedule_blocks_schedule
+    ON heating_schedule_blocks (schedule_id, weekday, start_time);
@@ -0,0 +1,19 @@
+-- Temporary manual setpoint overrides ("boost") for thermostats.
+--
+-- While `now() < expires_at` the control loop holds the thermostat at
+-- `setpoint` and ignores its schedule. Once expired (or cancelled, which sets
+-- `expires_at` to now) the control loop resumes normal control — re-applying
+-- the schedule, or restoring `previous_setpoint` when there is none — and
+-- deletes the row. Keeping the row until then lets an override that expired
+-- while the service was down still be undone after a restart.
+--
+-- Setpoints use the sensor_readings encoding: °C × 100 (23 °C → 2300).
+CREATE TABLE thermostat_overrides (
+    id                UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id         TEXT        NOT NULL UNIQUE,
+    setpoint          BIGINT      NOT NULL,
+    -- Setpoint in effect before the override, if it was known.
+    previous_setpoint BIGINT,
+    created_at        TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at        TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,48 @@
+-- Rooms group the devices that serve one space: at most one thermostat, one
+-- reference temperature probe (a weather station channel), one energy meter,
+-- and any number of window/door contact sensors.
+CREATE TYPE room_role AS ENUM (
+    'thermostat',
+    'temperature_probe',
+    'energy_meter',
+    'contact_sensor'
+);
+
+CREATE TABLE rooms (
+    id         UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    name       TEXT        NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE room_devices (
+    room_id   UUID        NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    role      room_role   NOT NULL,
+    device_id TEXT        NOT NULL,
+    -- The reading a temperature probe contributes, e.g. 'sub2_temperature'.
+    -- Set for temperature probes only.
+    channel   sensor_type,
+
+    CONSTRAINT ck_room_devices_channel
+        CHECK ((role = 'temperature_probe') = (channel IS NOT NULL))
+);
+
+CREATE UNIQUE INDEX uq_room_devices_thermostat
+    ON room_devices (room_id)
+    WHERE role = 'thermostat';
+
+CREATE UNIQUE INDEX uq_room_devices_probe
+    ON room_devices (room_id)
+    WHERE role = 'temperature_probe';
+
+CREATE UNIQUE INDEX uq_room_devices_energy_meter
+    ON room_devices (room_id)
+    WHERE role = 'energy_meter';
+
+-- A device — or one channel of a weather station — belongs to at most one room.
+CREATE UNIQUE INDEX uq_room_devices_device
+    ON room_devices (device_id)
+    WHERE channel IS NULL;
+
+CREATE UNIQUE INDEX uq_room_devices_channel
+    ON room_devices (device_id, channel)
+    WHERE channel IS NOT NULL;
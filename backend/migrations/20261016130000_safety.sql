@@ -0,0 +1,26 @@
+-- Thermostat safety readings (see ThermostatStatus):
+--   fault        : fault bitmask, stored as reported (0 = no fault)
+--   max_setpoint : highest setpoint the device accepts (upper_temp), °C × 100
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'fault';
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'max_setpoint';
+
+-- Conditions raised by the control loop that need a person to look at them,
+-- e.g. a thermostat reporting a fault.
+--
+--   kind       : machine-readable kind, e.g. "device_fault"
+--   message    : human-readable description
+--   cleared_at : when the condition went away; NULL while the alert is active
+CREATE TABLE alerts (
+    id         UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id  TEXT        NOT NULL,
+    kind       TEXT        NOT NULL,
+    message    TEXT        NOT NULL,
+    raised_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    cleared_at TIMESTAMPTZ
+);
+
+-- At most one active alert per device and kind.
+CREATE UNIQUE INDEX idx_alerts_active
+    ON alerts (device_id, kind) WHERE cleared_at IS NULL;
+CREATE INDEX idx_alerts_time
+    ON alerts (raised_at DESC);
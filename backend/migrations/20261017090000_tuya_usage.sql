@@ -0,0 +1,12 @@
+-- Tuya Cloud API calls per calendar month (UTC) and endpoint, counted by
+-- TuyaClient against the account's monthly quota.
+--
+--   month    : first day of the month
+--   endpoint : call kind, e.g. "device_status" or "send_commands"
+--   calls    : HTTP requests sent, retries included
+CREATE TABLE tuya_usage (
+    month    DATE   NOT NULL,
+    endpoint TEXT   NOT NULL,
+    calls    BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (month, endpoint)
+);
@@ -0,0 +1,16 @@
+-- Online/offline transitions per device, recorded by the sensor polling loop
+-- (see DeviceAvailability). The latest row per device is its current state.
+--
+--   online     : state the device changed to
+--   reason     : why, e.g. "3 consecutive poll failures"
+--   changed_at : when the change was noticed
+CREATE TABLE device_availability (
+    id         UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id  TEXT        NOT NULL,
+    online     BOOLEAN     NOT NULL,
+    reason     TEXT        NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_device_availability_device_time
+    ON device_availability (device_id, changed_at DESC);
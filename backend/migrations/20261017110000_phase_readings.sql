@@ -0,0 +1,15 @@
+-- Energy meter readings decoded from the phase_a/b/c DPs (see PhaseReading),
+-- per phase, all × 100:
+--   phase_*_voltage : V
+--   phase_*_current : mA, so that small loads (e.g. 21 mA) are not rounded away
+--   phase_*_power   : active power, W
+-- power_consumption holds the sum of the three phases' active power.
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'phase_a_voltage';
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'phase_a_current';
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'phase_a_power';
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'phase_b_voltage';
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'phase_b_current';
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'phase_b_power';
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'phase_c_voltage';
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'phase_c_current';
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'phase_c_power';
@@ -0,0 +1,6 @@
+-- Cumulative energy meter counters, kWh × 100 (see energy::consumption):
+--   forward_energy : energy drawn from the grid (total_forward_energy)
+--   reverse_energy : energy fed back into it (reverse_energy_total)
+-- Both only grow, except when the meter is reset or the counter rolls over.
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'forward_energy';
+ALTER TYPE sensor_type ADD VALUE IF NOT EXISTS 'reverse_energy';
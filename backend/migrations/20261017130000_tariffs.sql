@@ -0,0 +1,27 @@
+-- Electricity tariffs of energy meters (see energy::tariff).
+--
+--   valid_from  : first moment the tariff applies
+--   valid_until : first moment it no longer applies; NULL while it is current
+--   plan        : TariffPlan as JSON, tagged by "kind" (flat, time_of_use or
+--                 tiered); prices per kWh. Peak hours use ISO weekdays
+--                 (1 = Monday … 7 = Sunday) and local hours.
+--
+-- Tariffs of one meter do not overlap (tariffs_no_overlap); btree_gist lets
+-- the exclusion constraint compare device_id with =.
+CREATE EXTENSION IF NOT EXISTS btree_gist;
+
+CREATE TABLE tariffs (
+    id          UUID        PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_id   TEXT        NOT NULL,
+    name        TEXT        NOT NULL,
+    valid_from  TIMESTAMPTZ NOT NULL,
+    valid_until TIMESTAMPTZ,
+    plan        JSONB       NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (valid_until IS NULL OR valid_until > valid_from),
+    CONSTRAINT tariffs_no_overlap
+        EXCLUDE USING gist (device_id WITH =, tstzrange(valid_from, valid_until) WITH &&)
+);
+
+CREATE INDEX idx_tariffs_device
+    ON tariffs (device_id, valid_from);
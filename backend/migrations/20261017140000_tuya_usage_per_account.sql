@@ -0,0 +1,8 @@
+-- Count Tuya Cloud calls per account, since each account's project has its
+-- own monthly quota. Calls counted so far belong to the default account.
+--
+--   account : account name from TUYA_ACCOUNTS, or "default"
+ALTER TABLE tuya_usage ADD COLUMN account TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE tuya_usage ALTER COLUMN account DROP DEFAULT;
+ALTER TABLE tuya_usage DROP CONSTRAINT tuya_usage_pkey;
+ALTER TABLE tuya_usage ADD PRIMARY KEY (account, month, endpoint);
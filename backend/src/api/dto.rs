@@ -1,11 +1,24 @@
 use std::collections::BTreeMap;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::db::models::SensorType;
+use crate::{
+    config::DeviceType,
+    control::{ControllerState, ControllerStatus, PidState},
+    db::models::{
+        Alert, AvailabilityTransition, ControlAction, HeatingSchedule, HeatingScheduleBlock, Room,
+        SensorType, Tariff, ThermostatOverride,
+    },
+    energy::{
+        tariff::{PeakHours, Tier},
+        Granularity, TariffPlan,
+    },
+    rooms::{Probe, RoomDevices},
+    tuya::models::TuyaDevice,
+};
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct SensorReadingDto {
@@ -49,3 +62,661 @@ impl From<crate::db::models::SensorReading> for SensorReadingDto {
         }
     }
 }
+
+/// One command sent by the control loop, as recorded in `control_actions`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ControlActionDto {
+    pub id: Uuid,
+    pub device_id: String,
+    pub recorded_at: DateTime<Utc>,
+    /// Commands sent to Tuya, e.g. `[{"code": "switch", "value": true}]`.
+    #[schema(value_type = Vec<Object>)]
+    pub commands: serde_json::Value,
+    /// Readings the decision was based on, keyed by sensor type.
+    /// Values use the same encoding as `SensorReadingDto::value`.
+    #[schema(value_type = Object)]
+    pub inputs: serde_json::Value,
+    /// Machine-readable rule that triggered the action, e.g. `"below_band"`.
+    pub rule: String,
+    /// Human-readable explanation of the decision.
+    pub reason: String,
+    /// Whether Tuya accepted the command.
+    pub success: bool,
+    /// Tuya request trace ID, if one was returned.
+    pub tid: Option<String>,
+    /// Error message when the call failed.
+    pub error: Option<String>,
+}
+
+impl From<ControlAction> for ControlActionDto {
+    fn from(a: ControlAction) -> Self {
+        Self {
+            id: a.id,
+            device_id: a.device_id,
+            recorded_at: a.recorded_at,
+            commands: a.commands,
+            inputs: a.inputs,
+            rule: a.rule,
+            reason: a.reason,
+            success: a.success,
+            tid: a.tid,
+            error: a.error,
+        }
+    }
+}
+
+/// A condition raised by the control loop, e.g. a thermostat fault.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AlertDto {
+    pub id: Uuid,
+    pub device_id: String,
+    /// Machine-readable kind, e.g. `"device_fault"`.
+    pub kind: String,
+    pub message: String,
+    pub raised_at: DateTime<Utc>,
+    /// When the condition went away; `null` while the alert is active.
+    pub cleared_at: Option<DateTime<Utc>>,
+}
+
+impl From<Alert> for AlertDto {
+    fn from(a: Alert) -> Self {
+        Self {
+            id: a.id,
+            device_id: a.device_id,
+            kind: a.kind,
+            message: a.message,
+            raised_at: a.raised_at,
+            cleared_at: a.cleared_at,
+        }
+    }
+}
+
+/// Internal state of a PID controller.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PidStateDto {
+    /// Output per °C of error.
+    pub kp: f64,
+    /// Output per °C·hour of accumulated error.
+    pub ki: f64,
+    /// Output per °C/hour rate of change of the error.
+    pub kd: f64,
+    /// Relay cycle length in seconds.
+    pub cycle_secs: u64,
+    /// `setpoint - temperature` at the last update, °C.
+    pub error: f64,
+    pub proportional: f64,
+    pub integral: f64,
+    pub derivative: f64,
+    /// Duty cycle `0..=1`: clamped sum of the three terms.
+    pub output: f64,
+    pub cycle_started_at: Option<DateTime<Utc>>,
+    /// On-time of the current cycle in seconds.
+    pub on_secs: u64,
+}
+
+impl From<PidState> for PidStateDto {
+    fn from(s: PidState) -> Self {
+        Self {
+            kp: s.tuning.kp,
+            ki: s.tuning.ki,
+            kd: s.tuning.kd,
+            cycle_secs: s.tuning.cycle.as_secs(),
+            error: s.error,
+            proportional: s.proportional,
+            integral: s.integral,
+            derivative: s.derivative,
+            output: s.output,
+            cycle_started_at: s.cycle_started_at,
+            on_secs: s.on_for.as_secs(),
+        }
+    }
+}
+
+/// The last evaluation of a thermostat's controller.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ControllerStatusDto {
+    pub device_id: String,
+    /// `hysteresis` or `pid`.
+    pub controller: String,
+    /// Reference temperature in °C.
+    pub temperature: f64,
+    /// Setpoint in °C.
+    pub setpoint: f64,
+    pub relay_on: bool,
+    pub updated_at: DateTime<Utc>,
+    /// Half-width of the hysteresis band in °C; only for `hysteresis`.
+    pub band: Option<f64>,
+    /// Only for `pid`.
+    pub pid: Option<PidStateDto>,
+}
+
+impl ControllerStatusDto {
+    pub fn new(device_id: String, status: ControllerStatus) -> Self {
+        let (controller, band, pid) = match status.state {
+            ControllerState::Hysteresis { band } => ("hysteresis", Some(band), None),
+            ControllerState::Pid(state) => ("pid", None, Some(state.into())),
+        };
+        Self {
+            device_id,
+            controller: controller.to_owned(),
+            temperature: status.temperature,
+            setpoint: status.setpoint,
+            relay_on: status.relay_on,
+            updated_at: status.updated_at,
+            band,
+            pid,
+        }
+    }
+}
+
+/// A device linked to the Tuya account.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DiscoveredDeviceDto {
+    pub id: String,
+    pub name: String,
+    /// Tuya product category code, e.g. `"wk"`.
+    pub category: String,
+    pub product_id: String,
+    pub product_name: Option<String>,
+    pub online: bool,
+    /// Device type the category maps to, e.g. `"thermostat"`; `null` if the
+    /// service does not handle this category.
+    pub device_type: Option<String>,
+    /// Whether the service polls this device.
+    pub registered: bool,
+    /// Tuya account the device is linked to, e.g. `"default"`.
+    pub account: String,
+}
+
+impl DiscoveredDeviceDto {
+    pub fn new(account: String, d: TuyaDevice, registered: bool) -> Self {
+        Self {
+            device_type: DeviceType::from_category(&d.category).map(|t| t.to_string()),
+            id: d.id,
+            name: d.name,
+            category: d.category,
+            product_id: d.product_id,
+            product_name: d.product_name,
+            online: d.online,
+            registered,
+            account,
+        }
+    }
+}
+
+/// Availability of a device: its current state and uptime over the last
+/// day, week and 30 days.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceAvailabilityDto {
+    pub device_id: String,
+    /// `null` while nothing is known about the device.
+    pub online: Option<bool>,
+    /// Failed polls since the last successful one.
+    pub consecutive_failures: u32,
+    /// Last successful poll since the service started.
+    pub last_success: Option<DateTime<Utc>>,
+    /// Percentage of the known time the device was online; `null` if
+    /// nothing is known about the period.
+    pub uptime_24h: Option<f64>,
+    pub uptime_7d: Option<f64>,
+    pub uptime_30d: Option<f64>,
+    /// Online/offline changes of the last 30 days, newest first.
+    pub transitions: Vec<AvailabilityTransitionDto>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AvailabilityTransitionDto {
+    /// State the device changed to.
+    pub online: bool,
+    /// Why, e.g. `"3 consecutive poll failures"`.
+    pub reason: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+impl From<AvailabilityTransition> for AvailabilityTransitionDto {
+    fn from(t: AvailabilityTransition) -> Self {
+        Self {
+            online: t.online,
+            reason: t.reason,
+            changed_at: t.changed_at,
+        }
+    }
+}
+
+/// Energy a meter used per period, from its counters.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EnergyConsumptionDto {
+    pub device_id: String,
+    pub granularity: Granularity,
+    /// Periods with readings, oldest first.
+    pub periods: Vec<EnergyPeriodDto>,
+}
+
+/// Energy used in one hour, day or month, in kWh.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EnergyPeriodDto {
+    /// Start of the period, in the configured timezone's local time.
+    pub start: DateTime<Utc>,
+    /// Drawn from the grid.
+    pub forward_kwh: f64,
+    /// Fed back into the grid.
+    pub reverse_kwh: f64,
+}
+
+/// Cost of the energy a meter drew from the grid, per period.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EnergyCostDto {
+    pub device_id: String,
+    pub granularity: Granularity,
+    /// Cost of the priced energy over the whole range.
+    pub total_cost: f64,
+    /// Energy used while no tariff applied, in kWh.
+    pub unpriced_kwh: f64,
+    /// Periods with readings, oldest first.
+    pub periods: Vec<EnergyCostPeriodDto>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EnergyCostPeriodDto {
+    /// Start of the period, in the configured timezone's local time.
+    pub start: DateTime<Utc>,
+    /// Energy drawn from the grid, in kWh.
+    pub kwh: f64,
+    /// Cost of the part of `kwh` a tariff applied to.
+    pub cost: f64,
+    /// Part of `kwh` no tariff applied to.
+    pub unpriced_kwh: f64,
+}
+
+/// How energy is priced, per kWh, tagged by `kind`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TariffPlanDto {
+    /// One price at all times.
+    Flat { price_per_kwh: f64 },
+    /// The peak price within the peak hours, the off-peak price otherwise.
+    TimeOfUse {
+        peak_price_per_kwh: f64,
+        off_peak_price_per_kwh: f64,
+        peak_hours: Vec<PeakHoursDto>,
+    },
+    /// A price per tier of the energy used so far in the calendar month,
+    /// in order; the last tier has no limit.
+    Tiered { tiers: Vec<Tier> },
+}
+
+/// Peak hours of one day, local time.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PeakHoursDto {
+    pub day: DayOfWeek,
+    /// First peak hour, 0–23.
+    pub start_hour: u32,
+    /// First hour after the peak, 1–24.
+    pub end_hour: u32,
+}
+
+impl From<TariffPlanDto> for TariffPlan {
+    fn from(plan: TariffPlanDto) -> Self {
+        match plan {
+            TariffPlanDto::Flat { price_per_kwh } => TariffPlan::Flat { price_per_kwh },
+            TariffPlanDto::TimeOfUse {
+                peak_price_per_kwh,
+                off_peak_price_per_kwh,
+                peak_hours,
+            } => TariffPlan::TimeOfUse {
+                peak_price_per_kwh,
+                off_peak_price_per_kwh,
+                peak_hours: peak_hours
+                    .into_iter()
+                    .map(|h| PeakHours {
+                        weekday: h.day.iso(),
+                        start_hour: h.start_hour,
+                        end_hour: h.end_hour,
+                    })
+                    .collect(),
+            },
+            TariffPlanDto::Tiered { tiers } => TariffPlan::Tiered { tiers },
+        }
+    }
+}
+
+impl From<TariffPlan> for TariffPlanDto {
+    fn from(plan: TariffPlan) -> Self {
+        match plan {
+            TariffPlan::Flat { price_per_kwh } => TariffPlanDto::Flat { price_per_kwh },
+            TariffPlan::TimeOfUse {
+                peak_price_per_kwh,
+                off_peak_price_per_kwh,
+                peak_hours,
+            } => TariffPlanDto::TimeOfUse {
+                peak_price_per_kwh,
+                off_peak_price_per_kwh,
+                // Stored weekdays are validated on the way in.
+                peak_hours: peak_hours
+                    .into_iter()
+                    .filter_map(|h| {
+                        Some(PeakHoursDto {
+                            day: DayOfWeek::from_iso(h.weekday)?,
+                            start_hour: h.start_hour,
+                            end_hour: h.end_hour,
+                        })
+                    })
+                    .collect(),
+            },
+            TariffPlan::Tiered { tiers } => TariffPlanDto::Tiered { tiers },
+        }
+    }
+}
+
+/// Request body for `POST /energy/{device_id}/tariffs`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TariffRequest {
+    pub name: String,
+    /// First moment the tariff applies.
+    pub valid_from: DateTime<Utc>,
+    /// First moment it no longer applies; omit while it is current.
+    pub valid_until: Option<DateTime<Utc>>,
+    pub plan: TariffPlanDto,
+}
+
+/// Request body for `PATCH /energy/{device_id}/tariffs/{id}`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TariffUpdateRequest {
+    /// First moment the tariff no longer applies; `null` makes it current again.
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TariffDto {
+    pub id: Uuid,
+    pub device_id: String,
+    pub name: String,
+    pub valid_from: DateTime<Utc>,
+    pub valid_until: Option<DateTime<Utc>>,
+    pub plan: TariffPlanDto,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Tariff> for TariffDto {
+    fn from(t: Tariff) -> Self {
+        Self {
+            id: t.id,
+            device_id: t.device_id,
+            name: t.name,
+            valid_from: t.valid_from,
+            valid_until: t.valid_until,
+            plan: t.plan.0.into(),
+            created_at: t.created_at,
+        }
+    }
+}
+
+/// Request body for `POST /devices/{device_id}/switch`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SwitchRequest {
+    /// `true` to switch the device on, `false` to switch it off.
+    pub on: bool,
+}
+
+/// Response for `POST /devices/{device_id}/switch`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SwitchResponse {
+    pub device_id: String,
+    /// The state that was sent to the device.
+    pub on: bool,
+    /// Tuya request trace ID.
+    pub tid: Option<String>,
+}
+
+/// Request body for `PUT /devices/{device_id}/setpoint`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetpointRequest {
+    /// Target temperature in °C.
+    pub celsius: f64,
+}
+
+/// Response for `PUT /devices/{device_id}/setpoint`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SetpointResponse {
+    pub device_id: String,
+    /// Setpoint as requested, in °C.
+    pub requested_celsius: f64,
+    /// Setpoint actually sent to the device after clamping and rounding, in °C.
+    pub applied_celsius: f64,
+    /// `true` when the request was outside the device's limits and was clamped.
+    pub clamped: bool,
+    /// Tuya request trace ID.
+    pub tid: Option<String>,
+}
+
+/// Request body for `POST /devices/{device_id}/override`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct OverrideRequest {
+    /// Temperature to hold while the override is active, in °C.
+    pub celsius: f64,
+    /// How long the override lasts, in minutes from now.
+    pub duration_minutes: u32,
+}
+
+/// A temporary setpoint override ("boost") on a thermostat.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OverrideDto {
+    pub device_id: String,
+    /// Temperature held while the override is active, in °C.
+    pub celsius: f64,
+    /// Setpoint restored afterwards if the thermostat has no schedule, in °C.
+    pub previous_celsius: Option<f64>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<ThermostatOverride> for OverrideDto {
+    fn from(o: ThermostatOverride) -> Self {
+        Self {
+            device_id: o.device_id,
+            celsius: o.setpoint as f64 / 100.0,
+            previous_celsius: o.previous_setpoint.map(|v| v as f64 / 100.0),
+            created_at: o.created_at,
+            expires_at: o.expires_at,
+        }
+    }
+}
+
+/// Day of the week for schedule blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DayOfWeek {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl DayOfWeek {
+    /// ISO weekday number as stored in the database (1 = Monday … 7 = Sunday).
+    pub fn iso(self) -> i16 {
+        self as i16 + 1
+    }
+
+    pub fn from_iso(n: i16) -> Option<Self> {
+        use DayOfWeek::*;
+        [
+            Monday, Tuesday, Wednesday, Thursday, Friday, Saturday, Sunday,
+        ]
+        .get(usize::try_from(n - 1).ok()?)
+        .copied()
+    }
+}
+
+/// One block of a weekly heating schedule.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScheduleBlockDto {
+    pub day: DayOfWeek,
+    /// Local start time (inclusive), e.g. `"06:30"`.
+    #[schema(value_type = String, example = "06:30")]
+    pub start: NaiveTime,
+    /// Local end time (exclusive), e.g. `"08:00"`. `"00:00"` means end of day.
+    #[schema(value_type = String, example = "08:00")]
+    pub end: NaiveTime,
+    /// Target temperature in °C while the block is active.
+    pub celsius: f64,
+}
+
+/// Request body for `POST /schedules` and `PUT /schedules/{id}`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ScheduleRequest {
+    /// Thermostat this schedule controls.
+    pub device_id: String,
+    pub name: String,
+    /// Only one enabled schedule is allowed per thermostat. Defaults to `true`.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Target temperature in °C outside every block.
+    pub default_celsius: f64,
+    pub blocks: Vec<ScheduleBlockDto>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A weekly heating schedule with its blocks.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScheduleDto {
+    pub id: Uuid,
+    pub device_id: String,
+    pub name: String,
+    pub enabled: bool,
+    pub default_celsius: f64,
+    /// Blocks ordered by day and start time.
+    pub blocks: Vec<ScheduleBlockDto>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ScheduleDto {
+    pub fn new(schedule: HeatingSchedule, blocks: Vec<HeatingScheduleBlock>) -> Self {
+        Self {
+            id: schedule.id,
+            device_id: schedule.device_id,
+            name: schedule.name,
+            enabled: schedule.enabled,
+            default_celsius: schedule.default_setpoint as f64 / 100.0,
+            blocks: blocks
+                .into_iter()
+                .filter_map(|b| {
+                    Some(ScheduleBlockDto {
+                        day: DayOfWeek::from_iso(b.weekday)?,
+                        start: b.start_time,
+                        end: b.end_time,
+                        celsius: b.setpoint as f64 / 100.0,
+                    })
+                })
+                .collect(),
+            created_at: schedule.created_at,
+            updated_at: schedule.updated_at,
+        }
+    }
+}
+
+/// Request body for `POST /rooms` and `PUT /rooms/{id}`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RoomRequest {
+    pub name: String,
+}
+
+/// A weather station channel used as a room's reference temperature.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ProbeDto {
+    pub device_id: String,
+    /// `temperature` (the station itself) or `sub1_temperature` … `sub3_temperature`.
+    pub channel: SensorType,
+}
+
+/// Devices assigned to a room. Request body for `PUT /rooms/{id}/devices`,
+/// which replaces all assignments.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct RoomDevicesDto {
+    pub thermostat: Option<String>,
+    /// Reference temperature for the thermostat; without one the thermostat
+    /// regulates on its own `temp_current`.
+    pub temperature_probe: Option<ProbeDto>,
+    pub energy_meter: Option<String>,
+    /// Window/door contacts; heating pauses while any of them is open.
+    #[serde(default)]
+    pub contact_sensors: Vec<String>,
+}
+
+impl From<RoomDevices> for RoomDevicesDto {
+    fn from(d: RoomDevices) -> Self {
+        Self {
+            thermostat: d.thermostat,
+            temperature_probe: d.temperature_probe.map(|p| ProbeDto {
+                device_id: p.device_id,
+                channel: p.channel,
+            }),
+            energy_meter: d.energy_meter,
+            contact_sensors: d.contact_sensors,
+        }
+    }
+}
+
+impl From<RoomDevicesDto> for RoomDevices {
+    fn from(d: RoomDevicesDto) -> Self {
+        Self {
+            thermostat: d.thermostat,
+            temperature_probe: d.temperature_probe.map(|p| Probe {
+                device_id: p.device_id,
+                channel: p.channel,
+            }),
+            energy_meter: d.energy_meter,
+            contact_sensors: d.contact_sensors,
+        }
+    }
+}
+
+/// A room with its device assignments.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RoomDto {
+    pub id: Uuid,
+    pub name: String,
+    pub devices: RoomDevicesDto,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RoomDto {
+    pub fn new(room: Room, devices: RoomDevices) -> Self {
+        Self {
+            id: room.id,
+            name: room.name,
+            devices: devices.into(),
+            created_at: room.created_at,
+        }
+    }
+}
+
+/// Tuya Cloud calls made to one endpoint this month.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EndpointUsageDto {
+    /// Call kind, e.g. `"device_status"` or `"send_commands"`.
+    pub endpoint: String,
+    pub calls: i64,
+}
+
+/// Tuya Cloud calls one account made this month against its monthly quota.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TuyaUsageDto {
+    /// Account name, `"default"` for the one set by `TUYA_CLIENT_ID`.
+    pub account: String,
+    /// First day of the month (UTC).
+    pub month: NaiveDate,
+    pub calls: i64,
+    /// Calls allowed per month; `null` if no quota is configured.
+    pub monthly_quota: Option<u64>,
+    /// `true` once polling has slowed down to save calls.
+    pub degraded: bool,
+    pub endpoints: Vec<EndpointUsageDto>,
+}
@@ -6,18 +6,35 @@ use axum::{
 use serde_json::json;
 
 #[derive(Debug)]
-pub struct AppError(pub anyhow::Error);
+pub enum AppError {
+    /// The request is well-formed but not valid for the target resource (400).
+    BadRequest(String),
+    /// The addressed resource does not exist (404).
+    NotFound(String),
+    /// The request conflicts with the current state of a resource (409).
+    Conflict(String),
+    /// An upstream call (e.g. Tuya Cloud) failed (502).
+    BadGateway(anyhow::Error),
+    /// Anything else (500).
+    Internal(anyhow::Error),
+}
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let status = StatusCode::INTERNAL_SERVER_ERROR;
-        let body = Json(json!({ "error": self.0.to_string() }));
+        let (status, message) = match self {
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            AppError::BadGateway(e) => (StatusCode::BAD_GATEWAY, format!("{e:#}")),
+            AppError::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        };
+        let body = Json(json!({ "error": message }));
         (status, body).into_response()
     }
 }
 
 impl<E: Into<anyhow::Error>> From<E> for AppError {
     fn from(e: E) -> Self {
-        Self(e.into())
+        Self::Internal(e.into())
     }
 }
@@ -1,19 +1,57 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use axum::{
     extract::{Path, Query, State},
+    http::StatusCode,
     Json,
 };
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use utoipa::OpenApi;
+use uuid::Uuid;
 
 use super::{
-    dto::{SensorReadingDto, SensorReadingsRequest, SensorReadingsResponse},
+    dto::{
+        AlertDto, AvailabilityTransitionDto, ControlActionDto, ControllerStatusDto, DayOfWeek,
+        DeviceAvailabilityDto, DiscoveredDeviceDto, EndpointUsageDto, EnergyConsumptionDto,
+        EnergyCostDto, EnergyCostPeriodDto, EnergyPeriodDto, OverrideDto, OverrideRequest,
+        PeakHoursDto, PidStateDto, ProbeDto, RoomDevicesDto, RoomDto, RoomRequest,
+        ScheduleBlockDto, ScheduleDto, ScheduleRequest, SensorReadingDto, SensorReadingsRequest,
+        SensorReadingsResponse, SetpointRequest, SetpointResponse, SwitchRequest, SwitchResponse,
+        TariffDto, TariffPlanDto, TariffRequest, TariffUpdateRequest, TuyaUsageDto,
+    },
     errors::AppError,
+    state::AppState,
+};
+use crate::{
+    config::DeviceType,
+    control::{
+        audit::{self, NewAction},
+        overrides, safety, schedule, ControllerStatuses,
+    },
+    db::models::{
+        Alert, AvailabilityTransition, ControlAction, HeatingSchedule, HeatingScheduleBlock, Room,
+        RoomRole, SensorReading, SensorType, Tariff, ThermostatOverride,
+    },
+    energy::{
+        consumption,
+        tariff::{self, Tier},
+        Granularity, TariffPlan,
+    },
+    rooms::{self, RoomDevices, PROBE_CHANNELS},
+    sensors::{
+        availability,
+        service::{encode_scaled, setpoint_scale},
+    },
+    tuya::{
+        models::{
+            Command, DpValue, ThermostatStatus, THERMOSTAT_DEFAULT_MAX_SETPOINT_CELSIUS,
+            THERMOSTAT_MIN_SETPOINT_CELSIUS,
+        },
+        usage, DeviceBackend,
+    },
 };
-use crate::db::models::{SensorReading, SensorType};
 
 // ---------------------------------------------------------------------------
 // Query parameters
@@ -25,23 +63,66 @@ pub struct TimeRangeParams {
     pub to: Option<DateTime<Utc>>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RoomParams {
+    pub room_id: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScheduleListParams {
+    pub device_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ControlActionParams {
+    pub device_id: Option<String>,
+    pub room_id: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnergyParams {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub granularity: Granularity,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlertParams {
+    pub device_id: Option<String>,
+    /// `true`: only active alerts; `false`: only cleared ones.
+    pub active: Option<bool>,
+}
+
 // ---------------------------------------------------------------------------
 // Handlers
 // ---------------------------------------------------------------------------
 
 /// Fetch the latest reading for every known `(device_id, sensor_type)` pair.
+/// With `?room_id=`, only readings of that room's devices are returned; a
+/// temperature probe contributes only its channel.
 #[utoipa::path(
     get,
     path = "/sensors/latest",
+    params(("room_id" = Option<Uuid>, Query, description = "Only readings of this room's devices")),
     responses(
         (status = 200, description = "Latest reading per (device_id, sensor_type)", body = Vec<SensorReadingDto>),
+        (status = 404, description = "No such room"),
         (status = 500, description = "Internal server error"),
     ),
     tag = "sensors"
 )]
 pub async fn get_latest_readings(
     State(pool): State<PgPool>,
+    Query(params): Query<RoomParams>,
 ) -> Result<Json<Vec<SensorReadingDto>>, AppError> {
+    let room = match params.room_id {
+        Some(id) => Some(room_devices(&pool, id).await?),
+        None => None,
+    };
+
     let rows = sqlx::query_as!(
         SensorReading,
         r#"
@@ -58,7 +139,15 @@ pub async fn get_latest_readings(
     .fetch_all(&pool)
     .await?;
 
-    Ok(Json(rows.into_iter().map(Into::into).collect()))
+    Ok(Json(
+        rows.into_iter()
+            .filter(|r| {
+                room.as_ref()
+                    .is_none_or(|room| room.includes(&r.device_id, r.sensor_type))
+            })
+            .map(Into::into)
+            .collect(),
+    ))
 }
 
 /// Fetch time-series readings for a specific device and sensor type.
@@ -203,305 +292,3278 @@ pub async fn get_readings_multi(
 }
 
 // ---------------------------------------------------------------------------
-// Health check
+// Control
 // ---------------------------------------------------------------------------
 
-/// Returns `200 OK` with `{"status":"ok"}` when the server is running.
+/// Fetch the control-loop audit trail: every command sent to a device, with
+/// the readings and rule behind it. Optionally filter by `?device_id=`,
+/// `?room_id=` and time range `?from=<RFC3339>&to=<RFC3339>`. Results are
+/// ordered by `recorded_at ASC`.
 #[utoipa::path(
     get,
-    path = "/health",
+    path = "/control/actions",
+    params(
+        ("device_id" = Option<String>,        Query, description = "Only actions for this Tuya device ID"),
+        ("room_id"   = Option<Uuid>,          Query, description = "Only actions for this room's devices"),
+        ("from"      = Option<DateTime<Utc>>, Query, description = "Start of time range (RFC3339)"),
+        ("to"        = Option<DateTime<Utc>>, Query, description = "End of time range (RFC3339)"),
+    ),
     responses(
-        (status = 200, description = "Service is healthy"),
+        (status = 200, description = "Control actions", body = Vec<ControlActionDto>),
+        (status = 404, description = "No such room"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "control"
+)]
+pub async fn get_control_actions(
+    State(pool): State<PgPool>,
+    Query(params): Query<ControlActionParams>,
+) -> Result<Json<Vec<ControlActionDto>>, AppError> {
+    let room_device_ids = match params.room_id {
+        Some(id) => Some(room_devices(&pool, id).await?.device_ids()),
+        None => None,
+    };
+
+    let rows = sqlx::query_as!(
+        ControlAction,
+        r#"
+        SELECT id, device_id, recorded_at, commands, inputs,
+               rule, reason, success, tid, error
+        FROM control_actions
+        WHERE ($1::text        IS NULL OR device_id   =  $1)
+          AND ($2::timestamptz IS NULL OR recorded_at >= $2)
+          AND ($3::timestamptz IS NULL OR recorded_at <= $3)
+          AND ($4::text[]      IS NULL OR device_id   = ANY($4))
+        ORDER BY recorded_at ASC
+        "#,
+        params.device_id,
+        params.from,
+        params.to,
+        room_device_ids.as_deref(),
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(Into::into).collect()))
+}
+
+/// Fetch alerts raised by the control loop, newest first. Optionally filter
+/// by `?device_id=` and `?active=true|false`.
+#[utoipa::path(
+    get,
+    path = "/alerts",
+    params(
+        ("device_id" = Option<String>, Query, description = "Only alerts for this Tuya device ID"),
+        ("active"    = Option<bool>,   Query, description = "Only active (`true`) or cleared (`false`) alerts"),
+    ),
+    responses(
+        (status = 200, description = "Alerts", body = Vec<AlertDto>),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "control"
+)]
+pub async fn get_alerts(
+    State(pool): State<PgPool>,
+    Query(params): Query<AlertParams>,
+) -> Result<Json<Vec<AlertDto>>, AppError> {
+    let rows = sqlx::query_as!(
+        Alert,
+        r#"
+        SELECT id, device_id, kind, message, raised_at, cleared_at
+        FROM alerts
+        WHERE ($1::text IS NULL OR device_id = $1)
+          AND ($2::bool IS NULL OR (cleared_at IS NULL) = $2)
+        ORDER BY raised_at DESC
+        "#,
+        params.device_id,
+        params.active,
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(Into::into).collect()))
+}
+
+/// Tuya Cloud calls made this month per account and endpoint, against each
+/// account's monthly quota.
+#[utoipa::path(
+    get,
+    path = "/tuya/usage",
+    responses(
+        (status = 200, description = "Tuya call usage per account", body = Vec<TuyaUsageDto>),
+        (status = 500, description = "Internal server error"),
     ),
     tag = "system"
 )]
-pub async fn health() -> axum::Json<serde_json::Value> {
-    axum::Json(serde_json::json!({ "status": "ok" }))
+pub async fn get_tuya_usage(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<TuyaUsageDto>>, AppError> {
+    let month = usage::month_of(Utc::now().date_naive());
+    let mut accounts = Vec::new();
+    for (account, client) in state.tuya.clients() {
+        let usage = client.usage();
+        // Include the calls counted since the last periodic flush.
+        usage.flush().await;
+        let endpoints = sqlx::query_as!(
+            EndpointUsageDto,
+            r#"
+            SELECT endpoint, calls
+            FROM tuya_usage
+            WHERE account = $1 AND month = $2
+            ORDER BY endpoint
+            "#,
+            account,
+            month,
+        )
+        .fetch_all(&state.pool)
+        .await?;
+
+        let quota = usage.quota();
+        accounts.push(TuyaUsageDto {
+            account: account.to_owned(),
+            month,
+            calls: endpoints.iter().map(|e| e.calls).sum(),
+            monthly_quota: (quota.monthly_quota > 0).then_some(quota.monthly_quota),
+            degraded: usage.degraded(),
+            endpoints,
+        });
+    }
+    Ok(Json(accounts))
+}
+
+/// Latest state of every thermostat's controller, for tuning. Only
+/// thermostats the control loop has evaluated since start-up are listed.
+#[utoipa::path(
+    get,
+    path = "/control/controllers",
+    responses(
+        (status = 200, description = "Controller states", body = Vec<ControllerStatusDto>),
+    ),
+    tag = "control"
+)]
+pub async fn list_controllers(
+    State(statuses): State<ControllerStatuses>,
+) -> Json<Vec<ControllerStatusDto>> {
+    Json(
+        statuses
+            .all()
+            .await
+            .into_iter()
+            .map(|(id, s)| ControllerStatusDto::new(id, s))
+            .collect(),
+    )
+}
+
+/// Latest state of one thermostat's controller.
+#[utoipa::path(
+    get,
+    path = "/control/controllers/{device_id}",
+    params(
+        ("device_id" = String, Path, description = "Tuya device ID of the thermostat"),
+    ),
+    responses(
+        (status = 200, description = "Controller state", body = ControllerStatusDto),
+        (status = 404, description = "Not evaluated since start-up"),
+    ),
+    tag = "control"
+)]
+pub async fn get_controller(
+    State(statuses): State<ControllerStatuses>,
+    Path(device_id): Path<String>,
+) -> Result<Json<ControllerStatusDto>, AppError> {
+    let status = statuses
+        .get(&device_id)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("no controller state for {device_id}")))?;
+    Ok(Json(ControllerStatusDto::new(device_id, status)))
 }
 
 // ---------------------------------------------------------------------------
-// OpenAPI spec
+// Devices
 // ---------------------------------------------------------------------------
 
-#[derive(OpenApi)]
-#[openapi(
-    paths(get_latest_readings, get_sensor_readings, get_sensor_latest, get_readings_multi, health),
-    components(schemas(SensorReadingDto, SensorType, SensorReadingsRequest)),
-    tags(
-        (name = "sensors", description = "Sensor reading endpoints"),
-        (name = "system",  description = "System endpoints"),
+/// Look up the configured type of `device_id`, or 404 if it is unknown.
+fn device_type(state: &AppState, device_id: &str) -> Result<DeviceType, AppError> {
+    state
+        .devices
+        .get(device_id)
+        .cloned()
+        .ok_or_else(|| AppError::NotFound(format!("unknown device: {device_id}")))
+}
+
+/// List the devices linked to the Tuya accounts, with the account and device
+/// type of each and whether the service polls it. The online flag of each
+/// polled device updates its availability.
+#[utoipa::path(
+    get,
+    path = "/devices/discover",
+    responses(
+        (status = 200, description = "Linked Tuya devices", body = Vec<DiscoveredDeviceDto>),
+        (status = 502, description = "Tuya call failed"),
     ),
-    info(
-        title = "Smart Home Backend API",
-        version = "0.1.0",
-        description = "REST API for smart home sensor data"
+    tag = "devices"
+)]
+pub async fn discover_devices(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DiscoveredDeviceDto>>, AppError> {
+    let devices = state
+        .tuya
+        .list_devices()
+        .await
+        .map_err(|e| AppError::BadGateway(e.into()))?;
+
+    let mut discovered = Vec::with_capacity(devices.len());
+    for (account, d) in devices {
+        let registered = state.devices.contains_key(&d.id);
+        if registered {
+            state.availability.record_online_flag(&d.id, d.online).await;
+        }
+        discovered.push(DiscoveredDeviceDto::new(account, d, registered));
+    }
+    Ok(Json(discovered))
+}
+
+/// Whether a device is online, with its uptime over the last day, week and
+/// 30 days and the changes behind it.
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/availability",
+    params(("device_id" = String, Path, description = "Tuya device ID")),
+    responses(
+        (status = 200, description = "Device availability", body = DeviceAvailabilityDto),
+        (status = 404, description = "Unknown device"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "devices"
+)]
+pub async fn get_device_availability(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<Json<DeviceAvailabilityDto>, AppError> {
+    device_type(&state, &device_id)?;
+    let now = Utc::now();
+    let from = now - chrono::Duration::days(30);
+
+    let transitions = sqlx::query_as!(
+        AvailabilityTransition,
+        r#"
+        SELECT id, device_id, online, reason, changed_at
+        FROM device_availability
+        WHERE device_id = $1 AND changed_at >= $2
+        ORDER BY changed_at
+        "#,
+        device_id,
+        from,
     )
+    .fetch_all(&state.pool)
+    .await?;
+    // State at the start of the 30 days, if known.
+    let before = sqlx::query_scalar!(
+        r#"
+        SELECT online
+        FROM device_availability
+        WHERE device_id = $1 AND changed_at < $2
+        ORDER BY changed_at DESC
+        LIMIT 1
+        "#,
+        device_id,
+        from,
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+
+    let changes: Vec<_> = transitions
+        .iter()
+        .map(|t| (t.changed_at, t.online))
+        .collect();
+    let uptime = |days| {
+        let from = now - chrono::Duration::days(days);
+        // State at `from`: the last change before it.
+        let split = changes.partition_point(|(at, _)| *at < from);
+        let before = split.checked_sub(1).map_or(before, |i| Some(changes[i].1));
+        availability::uptime_percent(before, &changes[split..], from, now)
+    };
+    let status = state.availability.get(&device_id).await.unwrap_or_default();
+
+    Ok(Json(DeviceAvailabilityDto {
+        // Polling may not have reached the device since start-up.
+        online: status
+            .online
+            .or_else(|| changes.last().map(|(_, online)| *online).or(before)),
+        consecutive_failures: status.consecutive_failures,
+        last_success: status.last_success,
+        uptime_24h: uptime(1),
+        uptime_7d: uptime(7),
+        uptime_30d: uptime(30),
+        transitions: transitions.into_iter().rev().map(Into::into).collect(),
+        device_id,
+    }))
+}
+
+/// Switch a thermostat or energy meter relay on or off.
+///
+/// The new state is written to the reading cache immediately, so the control
+/// loop sees it before the next poll confirms it.
+#[utoipa::path(
+    post,
+    path = "/devices/{device_id}/switch",
+    params(("device_id" = String, Path, description = "Tuya device ID")),
+    request_body = SwitchRequest,
+    responses(
+        (status = 200, description = "Command accepted by Tuya", body = SwitchResponse),
+        (status = 400, description = "Device type has no switch"),
+        (status = 404, description = "Unknown device"),
+        (status = 502, description = "Tuya call failed"),
+    ),
+    tag = "devices"
 )]
-pub struct ApiDoc;
+pub async fn switch_device(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Json(body): Json<SwitchRequest>,
+) -> Result<Json<SwitchResponse>, AppError> {
+    match device_type(&state, &device_id)? {
+        DeviceType::Thermostat | DeviceType::EnergyMeter => {}
+        other => {
+            return Err(AppError::BadRequest(format!(
+                "device {device_id} ({other:?}) has no switch"
+            )))
+        }
+    }
 
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
+    let commands = vec![Command {
+        code: "switch".into(),
+        value: DpValue::Bool(body.on),
+    }];
+    let result = state.tuya.send_commands(&device_id, commands.clone()).await;
+    audit::record(
+        &state.pool,
+        NewAction {
+            device_id: &device_id,
+            commands: &commands,
+            inputs: &BTreeMap::new(),
+            rule: "api_switch",
+            reason: &format!(
+                "switch {} requested via API",
+                if body.on { "on" } else { "off" }
+            ),
+        },
+        &result,
+    )
+    .await;
+    let ack = result.map_err(|e| AppError::BadGateway(e.into()))?;
 
-#[cfg(test)]
-mod tests {
-    use axum_test::TestServer;
-    use serde_json::Value;
-    use sqlx::PgPool;
+    state
+        .cache
+        .set(&device_id, SensorType::RelayState, body.on as i64)
+        .await;
 
-    use crate::api::router;
+    Ok(Json(SwitchResponse {
+        device_id,
+        on: body.on,
+        tid: ack.tid,
+    }))
+}
 
-    fn test_server(pool: PgPool) -> TestServer {
-        TestServer::new(router(pool)).unwrap()
+/// Change a thermostat's target temperature.
+///
+/// The requested value is clamped to the thermostat's limits (5 °C up to its
+/// `upper_temp`) and rounded to the resolution of its `temp_set` DP, 0.1 °C
+/// unless its specification says otherwise. The new setpoint is written to the
+/// reading cache immediately.
+#[utoipa::path(
+    put,
+    path = "/devices/{device_id}/setpoint",
+    params(("device_id" = String, Path, description = "Tuya device ID")),
+    request_body = SetpointRequest,
+    responses(
+        (status = 200, description = "Command accepted by Tuya", body = SetpointResponse),
+        (status = 400, description = "Device is not a thermostat or value is invalid"),
+        (status = 404, description = "Unknown device"),
+        (status = 502, description = "Tuya call failed"),
+    ),
+    tag = "devices"
+)]
+pub async fn set_device_setpoint(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Json(body): Json<SetpointRequest>,
+) -> Result<Json<SetpointResponse>, AppError> {
+    let kind = device_type(&state, &device_id)?;
+    if kind != DeviceType::Thermostat {
+        return Err(AppError::BadRequest(format!(
+            "device {device_id} ({kind:?}) has no setpoint"
+        )));
+    }
+    if !body.celsius.is_finite() {
+        return Err(AppError::BadRequest(
+            "celsius must be a finite number".into(),
+        ));
     }
 
-    async fn insert_reading(pool: &PgPool, device_id: &str, sensor_type: &str, value: i64) {
-        sqlx::query(
-            "INSERT INTO sensor_readings (device_id, sensor_type, value) \
-             VALUES ($1, $2::sensor_type, $3)",
-        )
-        .bind(device_id)
-        .bind(sensor_type)
-        .bind(value)
-        .execute(pool)
+    // Fetch live status for the device's limits (`upper_temp`).
+    let dps = state
+        .tuya
+        .get_device_status(&device_id)
         .await
-        .unwrap();
-    }
+        .map_err(|e| AppError::BadGateway(e.into()))?;
+    let status = ThermostatStatus::try_from(dps.as_slice()).map_err(AppError::BadGateway)?;
+    // Without specifications, the default `temp_set` scale applies.
+    let specs = state
+        .tuya
+        .device_specs(&device_id)
+        .await
+        .unwrap_or_default();
+    let scale = setpoint_scale(&specs);
+    let (raw, clamped) = status.clamp_setpoint(body.celsius, scale);
+
+    let commands = vec![Command {
+        code: "temp_set".into(),
+        value: DpValue::Integer(raw),
+    }];
+    let result = state.tuya.send_commands(&device_id, commands.clone()).await;
+    audit::record(
+        &state.pool,
+        NewAction {
+            device_id: &device_id,
+            commands: &commands,
+            inputs: &BTreeMap::from([(
+                SensorType::TemperatureSetpoint,
+                encode_scaled(status.temp_set, scale),
+            )]),
+            rule: "api_setpoint",
+            reason: &format!(
+                "setpoint {:.1} °C requested via API{}",
+                body.celsius,
+                if clamped {
+                    ", clamped to device limits"
+                } else {
+                    ""
+                }
+            ),
+        },
+        &result,
+    )
+    .await;
+    let ack = result.map_err(|e| AppError::BadGateway(e.into()))?;
+
+    state
+        .cache
+        .set(
+            &device_id,
+            SensorType::TemperatureSetpoint,
+            encode_scaled(raw, scale),
+        )
+        .await;
 
-    // -----------------------------------------------------------------------
-    // GET /sensors/latest
-    // -----------------------------------------------------------------------
+    Ok(Json(SetpointResponse {
+        device_id,
+        requested_celsius: body.celsius,
+        applied_celsius: raw as f64 / 10_f64.powi(scale as i32),
+        clamped,
+        tid: ack.tid,
+    }))
+}
 
-    #[sqlx::test(migrations = "./migrations")]
-    async fn latest_empty_returns_empty_array(pool: PgPool) {
-        let server = test_server(pool);
-        let resp = server.get("/sensors/latest").await;
-        resp.assert_status_ok();
-        let body: Value = resp.json();
-        assert_eq!(body, serde_json::json!([]));
+/// Longest override accepted by `POST /devices/{device_id}/override` (7 days).
+const MAX_OVERRIDE_MINUTES: u32 = 7 * 24 * 60;
+
+/// Temporarily hold a thermostat at a fixed temperature ("boost"), e.g. 23 °C
+/// for the next 2 hours, then resume normal control.
+///
+/// The override replaces any existing one and takes precedence over the
+/// thermostat's schedule. It is applied by the control loop on its next
+/// iteration and stored in the database, so it survives a restart. When it
+/// expires the schedule is re-applied or, without one, the setpoint from
+/// before the override is restored.
+#[utoipa::path(
+    post,
+    path = "/devices/{device_id}/override",
+    params(("device_id" = String, Path, description = "Tuya device ID")),
+    request_body = OverrideRequest,
+    responses(
+        (status = 200, description = "Override stored", body = OverrideDto),
+        (status = 400, description = "Device is not a thermostat or value is invalid"),
+        (status = 404, description = "Unknown device"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "devices"
+)]
+pub async fn set_device_override(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Json(body): Json<OverrideRequest>,
+) -> Result<Json<OverrideDto>, AppError> {
+    let kind = device_type(&state, &device_id)?;
+    if kind != DeviceType::Thermostat {
+        return Err(AppError::BadRequest(format!(
+            "device {device_id} ({kind:?}) has no setpoint"
+        )));
+    }
+    let range = THERMOSTAT_MIN_SETPOINT_CELSIUS..=THERMOSTAT_DEFAULT_MAX_SETPOINT_CELSIUS;
+    if !range.contains(&body.celsius) {
+        return Err(AppError::BadRequest(format!(
+            "setpoint {} °C outside {}–{} °C",
+            body.celsius,
+            range.start(),
+            range.end()
+        )));
+    }
+    if !(1..=MAX_OVERRIDE_MINUTES).contains(&body.duration_minutes) {
+        return Err(AppError::BadRequest(format!(
+            "duration_minutes must be 1..={MAX_OVERRIDE_MINUTES}"
+        )));
     }
+    check_device_limit(&state, &device_id, body.celsius).await?;
 
-    #[sqlx::test(migrations = "./migrations")]
-    async fn latest_returns_most_recent_per_device_and_type(pool: PgPool) {
-        insert_reading(&pool, "dev1", "temperature", 2000).await;
-        insert_reading(&pool, "dev1", "temperature", 2500).await;
-        insert_reading(&pool, "dev1", "humidity", 6000).await;
+    let expires_at = Utc::now() + chrono::Duration::minutes(body.duration_minutes.into());
+    let previous = state
+        .cache
+        .get(&device_id, SensorType::TemperatureSetpoint)
+        .await
+        .map(|r| r.value);
 
-        let server = test_server(pool);
-        let resp = server.get("/sensors/latest").await;
-        resp.assert_status_ok();
+    // Replacing an override keeps the setpoint from before the first one, and
+    // gets a new ID so the control loop sends the new setpoint.
+    let o = sqlx::query_as!(
+        ThermostatOverride,
+        r#"
+        INSERT INTO thermostat_overrides (device_id, setpoint, previous_setpoint, expires_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (device_id) DO UPDATE
+        SET id = gen_random_uuid(),
+            setpoint = EXCLUDED.setpoint,
+            created_at = now(),
+            expires_at = EXCLUDED.expires_at
+        RETURNING id, device_id, setpoint, previous_setpoint, created_at, expires_at
+        "#,
+        device_id,
+        encode_celsius(body.celsius),
+        previous,
+        expires_at,
+    )
+    .fetch_one(&state.pool)
+    .await?;
 
-        let body: Vec<Value> = resp.json();
-        assert_eq!(body.len(), 2);
+    Ok(Json(o.into()))
+}
 
-        let temp = body.iter().find(|r| r["sensor_type"] == "temperature").unwrap();
-        assert_eq!(temp["device_id"], "dev1");
-        assert_eq!(temp["value"], 2500);
+/// Fetch the active override of a thermostat.
+#[utoipa::path(
+    get,
+    path = "/devices/{device_id}/override",
+    params(("device_id" = String, Path, description = "Tuya device ID")),
+    responses(
+        (status = 200, description = "Active override", body = OverrideDto),
+        (status = 404, description = "Unknown device or no active override"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "devices"
+)]
+pub async fn get_device_override(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<Json<OverrideDto>, AppError> {
+    device_type(&state, &device_id)?;
+    overrides::get(&state.pool, &device_id)
+        .await?
+        .filter(|o| o.expires_at > Utc::now())
+        .map(|o| Json(o.into()))
+        .ok_or_else(|| AppError::NotFound(format!("no active override on {device_id}")))
+}
 
-        let hum = body.iter().find(|r| r["sensor_type"] == "humidity").unwrap();
-        assert_eq!(hum["value"], 6000);
+/// Cancel the active override of a thermostat.
+///
+/// The override is marked as expired; the control loop resumes normal
+/// control on its next iteration.
+#[utoipa::path(
+    delete,
+    path = "/devices/{device_id}/override",
+    params(("device_id" = String, Path, description = "Tuya device ID")),
+    responses(
+        (status = 204, description = "Override cancelled"),
+        (status = 404, description = "Unknown device or no active override"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "devices"
+)]
+pub async fn cancel_device_override(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    device_type(&state, &device_id)?;
+    let cancelled = sqlx::query!(
+        r#"
+        UPDATE thermostat_overrides
+        SET expires_at = now()
+        WHERE device_id = $1 AND expires_at > now()
+        "#,
+        device_id,
+    )
+    .execute(&state.pool)
+    .await?
+    .rows_affected();
+
+    if cancelled == 0 {
+        return Err(AppError::NotFound(format!(
+            "no active override on {device_id}"
+        )));
     }
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    #[sqlx::test(migrations = "./migrations")]
-    async fn latest_returns_one_entry_per_device_type_combination(pool: PgPool) {
-        insert_reading(&pool, "dev1", "temperature", 2000).await;
-        insert_reading(&pool, "dev2", "temperature", 3000).await;
+// ---------------------------------------------------------------------------
+// Schedules
+// ---------------------------------------------------------------------------
 
-        let server = test_server(pool);
-        let resp = server.get("/sensors/latest").await;
-        resp.assert_status_ok();
+/// Refuse a setpoint above the thermostat's own limit (`upper_temp`), as
+/// last reported to the cache.
+async fn check_device_limit(
+    state: &AppState,
+    device_id: &str,
+    celsius: f64,
+) -> Result<(), AppError> {
+    let max = state
+        .cache
+        .get(device_id, SensorType::MaxSetpoint)
+        .await
+        .map(|r| r.value as f64 / 100.0);
+    safety::check_setpoint(celsius, max).map_err(AppError::BadRequest)
+}
 
-        let body: Vec<Value> = resp.json();
-        assert_eq!(body.len(), 2);
-    }
+/// Encode °C as the stored integer (°C × 100).
+fn encode_celsius(celsius: f64) -> i64 {
+    (celsius * 100.0).round() as i64
+}
 
-    // -----------------------------------------------------------------------
-    // GET /sensors/{device_id}/{sensor_type}
-    // -----------------------------------------------------------------------
+/// Validate a schedule body against the configured devices and the setpoint
+/// range. Setpoints must lie within 5–35 °C and below the thermostat's own
+/// limit; block overlap rules are in [`schedule::validate_blocks`].
+async fn validate_schedule(state: &AppState, body: &ScheduleRequest) -> Result<(), AppError> {
+    if state.devices.get(&body.device_id) != Some(&DeviceType::Thermostat) {
+        return Err(AppError::BadRequest(format!(
+            "device {} is not a configured thermostat",
+            body.device_id
+        )));
+    }
 
-    #[sqlx::test(migrations = "./migrations")]
-    async fn sensor_readings_empty_for_unknown_device(pool: PgPool) {
-        let server = test_server(pool);
-        let resp = server.get("/sensors/unknown/temperature").await;
-        resp.assert_status_ok();
-        let body: Value = resp.json();
-        assert_eq!(body, serde_json::json!([]));
+    let range = THERMOSTAT_MIN_SETPOINT_CELSIUS..=THERMOSTAT_DEFAULT_MAX_SETPOINT_CELSIUS;
+    let temps = std::iter::once(body.default_celsius).chain(body.blocks.iter().map(|b| b.celsius));
+    for c in temps {
+        if !range.contains(&c) {
+            return Err(AppError::BadRequest(format!(
+                "setpoint {c} °C outside {}–{} °C",
+                range.start(),
+                range.end()
+            )));
+        }
+        check_device_limit(state, &body.device_id, c).await?;
+    }
+
+    let blocks: Vec<_> = body
+        .blocks
+        .iter()
+        .map(|b| (b.day.iso(), b.start, b.end))
+        .collect();
+    schedule::validate_blocks(&blocks).map_err(AppError::BadRequest)
+}
+
+/// Map a unique-index violation (second enabled schedule for a thermostat) to 409.
+fn schedule_conflict(e: sqlx::Error) -> AppError {
+    match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => {
+            AppError::Conflict("thermostat already has an enabled schedule".into())
+        }
+        _ => e.into(),
+    }
+}
+
+async fn insert_blocks(
+    tx: &mut Transaction<'_, Postgres>,
+    schedule_id: Uuid,
+    blocks: &[ScheduleBlockDto],
+) -> sqlx::Result<Vec<HeatingScheduleBlock>> {
+    let weekdays: Vec<i16> = blocks.iter().map(|b| b.day.iso()).collect();
+    let starts: Vec<_> = blocks.iter().map(|b| b.start).collect();
+    let ends: Vec<_> = blocks.iter().map(|b| b.end).collect();
+    let setpoints: Vec<i64> = blocks.iter().map(|b| encode_celsius(b.celsius)).collect();
+
+    let mut rows = sqlx::query_as!(
+        HeatingScheduleBlock,
+        r#"
+        INSERT INTO heating_schedule_blocks (schedule_id, weekday, start_time, end_time, setpoint)
+        SELECT $1, * FROM UNNEST($2::smallint[], $3::time[], $4::time[], $5::bigint[])
+        RETURNING id, schedule_id, weekday, start_time, end_time, setpoint
+        "#,
+        schedule_id,
+        &weekdays,
+        &starts,
+        &ends,
+        &setpoints,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    rows.sort_by_key(|b| (b.weekday, b.start_time));
+    Ok(rows)
+}
+
+/// List heating schedules, optionally only those for `?device_id=`.
+#[utoipa::path(
+    get,
+    path = "/schedules",
+    params(("device_id" = Option<String>, Query, description = "Only schedules for this thermostat")),
+    responses(
+        (status = 200, description = "Schedules", body = Vec<ScheduleDto>),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "schedules"
+)]
+pub async fn list_schedules(
+    State(pool): State<PgPool>,
+    Query(params): Query<ScheduleListParams>,
+) -> Result<Json<Vec<ScheduleDto>>, AppError> {
+    let schedules = sqlx::query_as!(
+        HeatingSchedule,
+        r#"
+        SELECT id, device_id, name, enabled, default_setpoint, created_at, updated_at
+        FROM heating_schedules
+        WHERE ($1::text IS NULL OR device_id = $1)
+        ORDER BY device_id, created_at
+        "#,
+        params.device_id,
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let ids: Vec<Uuid> = schedules.iter().map(|s| s.id).collect();
+    let mut blocks = schedule::fetch_blocks(&pool, &ids).await?;
+
+    Ok(Json(
+        schedules
+            .into_iter()
+            .map(|s| {
+                let b = blocks.remove(&s.id).unwrap_or_default();
+                ScheduleDto::new(s, b)
+            })
+            .collect(),
+    ))
+}
+
+/// Fetch one heating schedule.
+#[utoipa::path(
+    get,
+    path = "/schedules/{id}",
+    params(("id" = Uuid, Path, description = "Schedule ID")),
+    responses(
+        (status = 200, description = "Schedule", body = ScheduleDto),
+        (status = 404, description = "No such schedule"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "schedules"
+)]
+pub async fn get_schedule(
+    State(pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ScheduleDto>, AppError> {
+    let s = sqlx::query_as!(
+        HeatingSchedule,
+        r#"
+        SELECT id, device_id, name, enabled, default_setpoint, created_at, updated_at
+        FROM heating_schedules
+        WHERE id = $1
+        "#,
+        id,
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("schedule {id} not found")))?;
+
+    let blocks = schedule::fetch_blocks(&pool, &[id])
+        .await?
+        .remove(&id)
+        .unwrap_or_default();
+    Ok(Json(ScheduleDto::new(s, blocks)))
+}
+
+/// Create a weekly heating schedule for a thermostat.
+///
+/// Block times are local wall-clock times in the service's configured
+/// `TIMEZONE`. The control loop sends the block's setpoint as `temp_set`
+/// whenever a block starts or ends.
+#[utoipa::path(
+    post,
+    path = "/schedules",
+    request_body = ScheduleRequest,
+    responses(
+        (status = 201, description = "Schedule created", body = ScheduleDto),
+        (status = 400, description = "Invalid schedule"),
+        (status = 409, description = "Thermostat already has an enabled schedule"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "schedules"
+)]
+pub async fn create_schedule(
+    State(state): State<AppState>,
+    Json(body): Json<ScheduleRequest>,
+) -> Result<(StatusCode, Json<ScheduleDto>), AppError> {
+    validate_schedule(&state, &body).await?;
+
+    let mut tx = state.pool.begin().await?;
+    let s = sqlx::query_as!(
+        HeatingSchedule,
+        r#"
+        INSERT INTO heating_schedules (device_id, name, enabled, default_setpoint)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, device_id, name, enabled, default_setpoint, created_at, updated_at
+        "#,
+        body.device_id,
+        body.name,
+        body.enabled,
+        encode_celsius(body.default_celsius),
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(schedule_conflict)?;
+    let blocks = insert_blocks(&mut tx, s.id, &body.blocks).await?;
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(ScheduleDto::new(s, blocks))))
+}
+
+/// Replace a heating schedule, including all of its blocks.
+#[utoipa::path(
+    put,
+    path = "/schedules/{id}",
+    params(("id" = Uuid, Path, description = "Schedule ID")),
+    request_body = ScheduleRequest,
+    responses(
+        (status = 200, description = "Schedule updated", body = ScheduleDto),
+        (status = 400, description = "Invalid schedule"),
+        (status = 404, description = "No such schedule"),
+        (status = 409, description = "Thermostat already has an enabled schedule"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "schedules"
+)]
+pub async fn update_schedule(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<ScheduleRequest>,
+) -> Result<Json<ScheduleDto>, AppError> {
+    validate_schedule(&state, &body).await?;
+
+    let mut tx = state.pool.begin().await?;
+    let s = sqlx::query_as!(
+        HeatingSchedule,
+        r#"
+        UPDATE heating_schedules
+        SET device_id = $2, name = $3, enabled = $4, default_setpoint = $5, updated_at = now()
+        WHERE id = $1
+        RETURNING id, device_id, name, enabled, default_setpoint, created_at, updated_at
+        "#,
+        id,
+        body.device_id,
+        body.name,
+        body.enabled,
+        encode_celsius(body.default_celsius),
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(schedule_conflict)?
+    .ok_or_else(|| AppError::NotFound(format!("schedule {id} not found")))?;
+
+    sqlx::query!(
+        "DELETE FROM heating_schedule_blocks WHERE schedule_id = $1",
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let blocks = insert_blocks(&mut tx, id, &body.blocks).await?;
+    tx.commit().await?;
+
+    Ok(Json(ScheduleDto::new(s, blocks)))
+}
+
+/// Delete a heating schedule and its blocks.
+#[utoipa::path(
+    delete,
+    path = "/schedules/{id}",
+    params(("id" = Uuid, Path, description = "Schedule ID")),
+    responses(
+        (status = 204, description = "Schedule deleted"),
+        (status = 404, description = "No such schedule"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "schedules"
+)]
+pub async fn delete_schedule(
+    State(pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let deleted = sqlx::query!("DELETE FROM heating_schedules WHERE id = $1", id)
+        .execute(&pool)
+        .await?
+        .rows_affected();
+
+    if deleted == 0 {
+        return Err(AppError::NotFound(format!("schedule {id} not found")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ---------------------------------------------------------------------------
+// Rooms
+// ---------------------------------------------------------------------------
+
+/// Fetch the device assignments of a room, or 404 if it does not exist.
+async fn room_devices(pool: &PgPool, id: Uuid) -> Result<RoomDevices, AppError> {
+    fetch_room(pool, id).await?;
+    Ok(rooms::fetch_devices(pool, &[id])
+        .await?
+        .remove(&id)
+        .unwrap_or_default())
+}
+
+async fn fetch_room(pool: &PgPool, id: Uuid) -> Result<Room, AppError> {
+    sqlx::query_as!(
+        Room,
+        "SELECT id, name, created_at FROM rooms WHERE id = $1",
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("room {id} not found")))
+}
+
+/// The thermostat of a room, for the room-level control endpoints.
+async fn room_thermostat(pool: &PgPool, id: Uuid) -> Result<String, AppError> {
+    room_devices(pool, id)
+        .await?
+        .thermostat
+        .ok_or_else(|| AppError::BadRequest(format!("room {id} has no thermostat")))
+}
+
+/// Map a unique violation on `rooms` / `room_devices` to 409.
+fn room_conflict(e: sqlx::Error) -> AppError {
+    match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => match db.constraint() {
+            Some("rooms_name_key") => AppError::Conflict("room name already in use".into()),
+            _ => AppError::Conflict("device is already assigned to another room".into()),
+        },
+        _ => e.into(),
+    }
+}
+
+/// Check every assigned device against the configured device types.
+fn validate_room_devices(state: &AppState, body: &RoomDevicesDto) -> Result<(), AppError> {
+    let expect = |device_id: &str, kind: DeviceType| {
+        if state.devices.get(device_id) == Some(&kind) {
+            Ok(())
+        } else {
+            Err(AppError::BadRequest(format!(
+                "device {device_id} is not a configured {kind:?}"
+            )))
+        }
+    };
+
+    if let Some(id) = &body.thermostat {
+        expect(id, DeviceType::Thermostat)?;
+    }
+    if let Some(ProbeDto { device_id, channel }) = &body.temperature_probe {
+        expect(device_id, DeviceType::WeatherStation)?;
+        if !PROBE_CHANNELS.contains(channel) {
+            return Err(AppError::BadRequest(format!(
+                "{channel} is not a temperature channel"
+            )));
+        }
+    }
+    if let Some(id) = &body.energy_meter {
+        expect(id, DeviceType::EnergyMeter)?;
+    }
+    for id in &body.contact_sensors {
+        expect(id, DeviceType::ContactSensor)?;
+    }
+    Ok(())
+}
+
+/// List rooms with their device assignments.
+#[utoipa::path(
+    get,
+    path = "/rooms",
+    responses(
+        (status = 200, description = "Rooms", body = Vec<RoomDto>),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "rooms"
+)]
+pub async fn list_rooms(State(pool): State<PgPool>) -> Result<Json<Vec<RoomDto>>, AppError> {
+    let rooms = sqlx::query_as!(Room, "SELECT id, name, created_at FROM rooms ORDER BY name")
+        .fetch_all(&pool)
+        .await?;
+
+    let ids: Vec<Uuid> = rooms.iter().map(|r| r.id).collect();
+    let mut devices = rooms::fetch_devices(&pool, &ids).await?;
+
+    Ok(Json(
+        rooms
+            .into_iter()
+            .map(|r| {
+                let d = devices.remove(&r.id).unwrap_or_default();
+                RoomDto::new(r, d)
+            })
+            .collect(),
+    ))
+}
+
+/// Fetch one room.
+#[utoipa::path(
+    get,
+    path = "/rooms/{id}",
+    params(("id" = Uuid, Path, description = "Room ID")),
+    responses(
+        (status = 200, description = "Room", body = RoomDto),
+        (status = 404, description = "No such room"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "rooms"
+)]
+pub async fn get_room(
+    State(pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<RoomDto>, AppError> {
+    let room = fetch_room(&pool, id).await?;
+    let devices = room_devices(&pool, id).await?;
+    Ok(Json(RoomDto::new(room, devices)))
+}
+
+/// Create an empty room.
+#[utoipa::path(
+    post,
+    path = "/rooms",
+    request_body = RoomRequest,
+    responses(
+        (status = 201, description = "Room created", body = RoomDto),
+        (status = 409, description = "Room name already in use"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "rooms"
+)]
+pub async fn create_room(
+    State(pool): State<PgPool>,
+    Json(body): Json<RoomRequest>,
+) -> Result<(StatusCode, Json<RoomDto>), AppError> {
+    let room = sqlx::query_as!(
+        Room,
+        "INSERT INTO rooms (name) VALUES ($1) RETURNING id, name, created_at",
+        body.name,
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(room_conflict)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(RoomDto::new(room, RoomDevices::default())),
+    ))
+}
+
+/// Rename a room.
+#[utoipa::path(
+    put,
+    path = "/rooms/{id}",
+    params(("id" = Uuid, Path, description = "Room ID")),
+    request_body = RoomRequest,
+    responses(
+        (status = 200, description = "Room updated", body = RoomDto),
+        (status = 404, description = "No such room"),
+        (status = 409, description = "Room name already in use"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "rooms"
+)]
+pub async fn update_room(
+    State(pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<RoomRequest>,
+) -> Result<Json<RoomDto>, AppError> {
+    let room = sqlx::query_as!(
+        Room,
+        "UPDATE rooms SET name = $2 WHERE id = $1 RETURNING id, name, created_at",
+        id,
+        body.name,
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(room_conflict)?
+    .ok_or_else(|| AppError::NotFound(format!("room {id} not found")))?;
+
+    let devices = room_devices(&pool, id).await?;
+    Ok(Json(RoomDto::new(room, devices)))
+}
+
+/// Delete a room. Its devices become unassigned.
+#[utoipa::path(
+    delete,
+    path = "/rooms/{id}",
+    params(("id" = Uuid, Path, description = "Room ID")),
+    responses(
+        (status = 204, description = "Room deleted"),
+        (status = 404, description = "No such room"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "rooms"
+)]
+pub async fn delete_room(
+    State(pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let deleted = sqlx::query!("DELETE FROM rooms WHERE id = $1", id)
+        .execute(&pool)
+        .await?
+        .rows_affected();
+
+    if deleted == 0 {
+        return Err(AppError::NotFound(format!("room {id} not found")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Replace the device assignments of a room.
+///
+/// Each device — or weather station channel — can belong to one room only.
+/// The control loop picks up the change on its next iteration: the
+/// thermostat regulates on the probe's temperature and pauses while a
+/// contact sensor is open.
+#[utoipa::path(
+    put,
+    path = "/rooms/{id}/devices",
+    params(("id" = Uuid, Path, description = "Room ID")),
+    request_body = RoomDevicesDto,
+    responses(
+        (status = 200, description = "Assignments replaced", body = RoomDto),
+        (status = 400, description = "Device of the wrong type or unknown"),
+        (status = 404, description = "No such room"),
+        (status = 409, description = "Device is already assigned to another room"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "rooms"
+)]
+pub async fn set_room_devices(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<RoomDevicesDto>,
+) -> Result<Json<RoomDto>, AppError> {
+    validate_room_devices(&state, &body)?;
+    let devices = RoomDevices::from(body);
+
+    let mut tx = state.pool.begin().await?;
+    let room = sqlx::query_as!(
+        Room,
+        "SELECT id, name, created_at FROM rooms WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("room {id} not found")))?;
+
+    sqlx::query!("DELETE FROM room_devices WHERE room_id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+    for (role, device_id, channel) in devices.rows() {
+        sqlx::query!(
+            r#"
+            INSERT INTO room_devices (room_id, role, device_id, channel)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            id,
+            role as RoomRole,
+            device_id,
+            channel as Option<SensorType>,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(room_conflict)?;
+    }
+    tx.commit().await?;
+
+    Ok(Json(RoomDto::new(room, devices)))
+}
+
+/// Change the target temperature of a room's thermostat.
+/// See `PUT /devices/{device_id}/setpoint`.
+#[utoipa::path(
+    put,
+    path = "/rooms/{id}/setpoint",
+    params(("id" = Uuid, Path, description = "Room ID")),
+    request_body = SetpointRequest,
+    responses(
+        (status = 200, description = "Command accepted by Tuya", body = SetpointResponse),
+        (status = 400, description = "Room has no thermostat or value is invalid"),
+        (status = 404, description = "No such room"),
+        (status = 502, description = "Tuya call failed"),
+    ),
+    tag = "rooms"
+)]
+pub async fn set_room_setpoint(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    body: Json<SetpointRequest>,
+) -> Result<Json<SetpointResponse>, AppError> {
+    let device_id = room_thermostat(&state.pool, id).await?;
+    set_device_setpoint(State(state), Path(device_id), body).await
+}
+
+/// Override the temperature of a room's thermostat for a while.
+/// See `POST /devices/{device_id}/override`.
+#[utoipa::path(
+    post,
+    path = "/rooms/{id}/override",
+    params(("id" = Uuid, Path, description = "Room ID")),
+    request_body = OverrideRequest,
+    responses(
+        (status = 200, description = "Override stored", body = OverrideDto),
+        (status = 400, description = "Room has no thermostat or value is invalid"),
+        (status = 404, description = "No such room"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "rooms"
+)]
+pub async fn set_room_override(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    body: Json<OverrideRequest>,
+) -> Result<Json<OverrideDto>, AppError> {
+    let device_id = room_thermostat(&state.pool, id).await?;
+    set_device_override(State(state), Path(device_id), body).await
+}
+
+/// Fetch the active override of a room's thermostat.
+#[utoipa::path(
+    get,
+    path = "/rooms/{id}/override",
+    params(("id" = Uuid, Path, description = "Room ID")),
+    responses(
+        (status = 200, description = "Active override", body = OverrideDto),
+        (status = 400, description = "Room has no thermostat"),
+        (status = 404, description = "No such room or no active override"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "rooms"
+)]
+pub async fn get_room_override(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<OverrideDto>, AppError> {
+    let device_id = room_thermostat(&state.pool, id).await?;
+    get_device_override(State(state), Path(device_id)).await
+}
+
+/// Cancel the active override of a room's thermostat.
+#[utoipa::path(
+    delete,
+    path = "/rooms/{id}/override",
+    params(("id" = Uuid, Path, description = "Room ID")),
+    responses(
+        (status = 204, description = "Override cancelled"),
+        (status = 400, description = "Room has no thermostat"),
+        (status = 404, description = "No such room or no active override"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "rooms"
+)]
+pub async fn cancel_room_override(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let device_id = room_thermostat(&state.pool, id).await?;
+    cancel_device_override(State(state), Path(device_id)).await
+}
+
+// ---------------------------------------------------------------------------
+// Energy
+// ---------------------------------------------------------------------------
+
+/// Energy a meter used per hour, day or month, from the changes of its
+/// cumulative counters. Periods follow the configured timezone; periods
+/// without readings are left out. Without `from`, the last day of hours, 30
+/// days or year of months is returned.
+#[utoipa::path(
+    get,
+    path = "/energy/{device_id}/consumption",
+    params(
+        ("device_id" = String, Path, description = "Tuya device ID of an energy meter"),
+        ("from" = Option<DateTime<Utc>>, Query, description = "Start of time range (RFC3339)"),
+        ("to"   = Option<DateTime<Utc>>, Query, description = "End of time range (RFC3339), exclusive"),
+        ("granularity" = Option<Granularity>, Query, description = "`hour`, `day` (default) or `month`"),
+    ),
+    responses(
+        (status = 200, description = "Energy used per period", body = EnergyConsumptionDto),
+        (status = 400, description = "Not an energy meter, or an empty time range"),
+        (status = 404, description = "Unknown device"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "energy"
+)]
+pub async fn get_energy_consumption(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Query(params): Query<EnergyParams>,
+) -> Result<Json<EnergyConsumptionDto>, AppError> {
+    let (from, to) = energy_range(&state, &device_id, &params)?;
+    // Without specifications, every counter drop is taken for a reset.
+    let specs = state
+        .tuya
+        .device_specs(&device_id)
+        .await
+        .unwrap_or_default();
+
+    let deltas =
+        |counter| consumption::counter_deltas(&state.pool, &device_id, counter, &specs, from, to);
+    let per_period =
+        |deltas: Vec<_>| consumption::per_period(&deltas, params.granularity, state.timezone);
+    let forward = per_period(deltas(SensorType::ForwardEnergy).await?);
+    let reverse = per_period(deltas(SensorType::ReverseEnergy).await?);
+    let kwh = |used: Option<&i64>| used.copied().unwrap_or(0) as f64 / 100.0;
+    let starts: BTreeSet<_> = forward.keys().chain(reverse.keys()).copied().collect();
+
+    Ok(Json(EnergyConsumptionDto {
+        periods: starts
+            .into_iter()
+            .map(|start| EnergyPeriodDto {
+                start,
+                forward_kwh: kwh(forward.get(&start)),
+                reverse_kwh: kwh(reverse.get(&start)),
+            })
+            .collect(),
+        granularity: params.granularity,
+        device_id,
+    }))
+}
+
+/// 404 for unknown devices, 400 for devices other than energy meters.
+fn check_energy_meter(state: &AppState, device_id: &str) -> Result<(), AppError> {
+    let kind = device_type(state, device_id)?;
+    if kind != DeviceType::EnergyMeter {
+        return Err(AppError::BadRequest(format!(
+            "device {device_id} ({kind:?}) is not an energy meter"
+        )));
+    }
+    Ok(())
+}
+
+/// Time range of an energy request for `device_id`, which must be an
+/// energy meter.
+fn energy_range(
+    state: &AppState,
+    device_id: &str,
+    params: &EnergyParams,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), AppError> {
+    check_energy_meter(state, device_id)?;
+    let to = params.to.unwrap_or_else(Utc::now);
+    let from = params
+        .from
+        .unwrap_or(to - params.granularity.default_span());
+    if from >= to {
+        return Err(AppError::BadRequest("`from` must be before `to`".into()));
+    }
+    Ok((from, to))
+}
+
+/// Cost of the energy a meter drew from the grid per hour, day or month,
+/// under the tariff that was valid when it was used. Energy used while no
+/// tariff applied is reported as unpriced. Ranges default as for
+/// consumption.
+#[utoipa::path(
+    get,
+    path = "/energy/{device_id}/cost",
+    params(
+        ("device_id" = String, Path, description = "Tuya device ID of an energy meter"),
+        ("from" = Option<DateTime<Utc>>, Query, description = "Start of time range (RFC3339)"),
+        ("to"   = Option<DateTime<Utc>>, Query, description = "End of time range (RFC3339), exclusive"),
+        ("granularity" = Option<Granularity>, Query, description = "`hour`, `day` (default) or `month`"),
+    ),
+    responses(
+        (status = 200, description = "Energy cost per period", body = EnergyCostDto),
+        (status = 400, description = "Not an energy meter, or an empty time range"),
+        (status = 404, description = "Unknown device"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "energy"
+)]
+pub async fn get_energy_cost(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Query(params): Query<EnergyParams>,
+) -> Result<Json<EnergyCostDto>, AppError> {
+    let (from, to) = energy_range(&state, &device_id, &params)?;
+    let specs = state
+        .tuya
+        .device_specs(&device_id)
+        .await
+        .unwrap_or_default();
+    // Tiers count the month's usage from its start.
+    let month_start = Granularity::Month.period_start(from, state.timezone);
+    let deltas = consumption::counter_deltas(
+        &state.pool,
+        &device_id,
+        SensorType::ForwardEnergy,
+        &specs,
+        month_start,
+        to,
+    )
+    .await?;
+    let tariffs = sqlx::query_as!(
+        Tariff,
+        r#"
+        SELECT id, device_id, name, valid_from, valid_until,
+               plan AS "plan: sqlx::types::Json<TariffPlan>", created_at
+        FROM tariffs
+        WHERE device_id = $1
+          AND valid_from < $3
+          AND (valid_until IS NULL OR valid_until > $2)
+        ORDER BY valid_from
+        "#,
+        device_id,
+        month_start,
+        to,
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    let costs = tariff::costs(&deltas, &tariffs, state.timezone);
+
+    let mut periods: BTreeMap<DateTime<Utc>, EnergyCostPeriodDto> = BTreeMap::new();
+    for (&(at, used), cost) in deltas.iter().zip(costs) {
+        if at < from {
+            continue;
+        }
+        let start = params.granularity.period_start(at, state.timezone);
+        let period = periods.entry(start).or_insert(EnergyCostPeriodDto {
+            start,
+            kwh: 0.0,
+            cost: 0.0,
+            unpriced_kwh: 0.0,
+        });
+        let kwh = used as f64 / 100.0;
+        period.kwh += kwh;
+        match cost {
+            Some(cost) => period.cost += cost,
+            None => period.unpriced_kwh += kwh,
+        }
+    }
+
+    Ok(Json(EnergyCostDto {
+        total_cost: periods.values().map(|p| p.cost).sum(),
+        unpriced_kwh: periods.values().map(|p| p.unpriced_kwh).sum(),
+        periods: periods.into_values().collect(),
+        granularity: params.granularity,
+        device_id,
+    }))
+}
+
+/// List the tariffs of an energy meter, oldest first.
+#[utoipa::path(
+    get,
+    path = "/energy/{device_id}/tariffs",
+    params(("device_id" = String, Path, description = "Tuya device ID of an energy meter")),
+    responses(
+        (status = 200, description = "Tariffs", body = Vec<TariffDto>),
+        (status = 400, description = "Not an energy meter"),
+        (status = 404, description = "Unknown device"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "energy"
+)]
+pub async fn list_tariffs(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<Json<Vec<TariffDto>>, AppError> {
+    check_energy_meter(&state, &device_id)?;
+    let rows = sqlx::query_as!(
+        Tariff,
+        r#"
+        SELECT id, device_id, name, valid_from, valid_until,
+               plan AS "plan: sqlx::types::Json<TariffPlan>", created_at
+        FROM tariffs
+        WHERE device_id = $1
+        ORDER BY valid_from
+        "#,
+        device_id,
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(rows.into_iter().map(Into::into).collect()))
+}
+
+/// Add a tariff to an energy meter. Its validity must not overlap that of
+/// the meter's other tariffs; end the current one first by setting its
+/// `valid_until` (`PATCH /energy/{device_id}/tariffs/{id}`).
+#[utoipa::path(
+    post,
+    path = "/energy/{device_id}/tariffs",
+    params(("device_id" = String, Path, description = "Tuya device ID of an energy meter")),
+    request_body = TariffRequest,
+    responses(
+        (status = 201, description = "Tariff created", body = TariffDto),
+        (status = 400, description = "Not an energy meter, or an invalid tariff"),
+        (status = 404, description = "Unknown device"),
+        (status = 409, description = "Overlaps another tariff of the meter"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "energy"
+)]
+pub async fn create_tariff(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Json(body): Json<TariffRequest>,
+) -> Result<(StatusCode, Json<TariffDto>), AppError> {
+    check_energy_meter(&state, &device_id)?;
+    if body
+        .valid_until
+        .is_some_and(|until| until <= body.valid_from)
+    {
+        return Err(AppError::BadRequest(
+            "`valid_until` must be after `valid_from`".into(),
+        ));
+    }
+    let plan = TariffPlan::from(body.plan);
+    plan.validate().map_err(AppError::BadRequest)?;
+
+    let t = sqlx::query_as!(
+        Tariff,
+        r#"
+        INSERT INTO tariffs (device_id, name, valid_from, valid_until, plan)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, device_id, name, valid_from, valid_until,
+                  plan AS "plan: sqlx::types::Json<TariffPlan>", created_at
+        "#,
+        device_id,
+        body.name,
+        body.valid_from,
+        body.valid_until,
+        sqlx::types::Json(plan) as _,
+    )
+    .fetch_one(&state.pool)
+    .await
+    .map_err(tariff_conflict)?;
+
+    Ok((StatusCode::CREATED, Json(t.into())))
+}
+
+/// Change when a tariff of an energy meter ends, e.g. to close the current
+/// one before adding its successor.
+#[utoipa::path(
+    patch,
+    path = "/energy/{device_id}/tariffs/{id}",
+    params(
+        ("device_id" = String, Path, description = "Tuya device ID of an energy meter"),
+        ("id" = Uuid, Path, description = "Tariff ID"),
+    ),
+    request_body = TariffUpdateRequest,
+    responses(
+        (status = 200, description = "Tariff updated", body = TariffDto),
+        (status = 400, description = "Not an energy meter, or an invalid `valid_until`"),
+        (status = 404, description = "Unknown device or no such tariff"),
+        (status = 409, description = "Overlaps another tariff of the meter"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "energy"
+)]
+pub async fn update_tariff(
+    State(state): State<AppState>,
+    Path((device_id, id)): Path<(String, Uuid)>,
+    Json(body): Json<TariffUpdateRequest>,
+) -> Result<Json<TariffDto>, AppError> {
+    check_energy_meter(&state, &device_id)?;
+    let t = sqlx::query_as!(
+        Tariff,
+        r#"
+        UPDATE tariffs SET valid_until = $3
+        WHERE id = $1 AND device_id = $2
+        RETURNING id, device_id, name, valid_from, valid_until,
+                  plan AS "plan: sqlx::types::Json<TariffPlan>", created_at
+        "#,
+        id,
+        device_id,
+        body.valid_until,
+    )
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(tariff_conflict)?
+    .ok_or_else(|| AppError::NotFound(format!("tariff {id} not found")))?;
+
+    Ok(Json(t.into()))
+}
+
+/// Map the `tariffs` constraints to client errors.
+fn tariff_conflict(e: sqlx::Error) -> AppError {
+    match e.as_database_error() {
+        // exclusion_violation of `tariffs_no_overlap`
+        Some(db) if db.code().as_deref() == Some("23P01") => {
+            AppError::Conflict("overlaps another tariff of the meter".into())
+        }
+        Some(db) if db.is_check_violation() => {
+            AppError::BadRequest("`valid_until` must be after `valid_from`".into())
+        }
+        _ => e.into(),
+    }
+}
+
+/// Delete a tariff of an energy meter.
+#[utoipa::path(
+    delete,
+    path = "/energy/{device_id}/tariffs/{id}",
+    params(
+        ("device_id" = String, Path, description = "Tuya device ID of an energy meter"),
+        ("id" = Uuid, Path, description = "Tariff ID"),
+    ),
+    responses(
+        (status = 204, description = "Tariff deleted"),
+        (status = 404, description = "No such tariff"),
+        (status = 500, description = "Internal server error"),
+    ),
+    tag = "energy"
+)]
+pub async fn delete_tariff(
+    State(pool): State<PgPool>,
+    Path((device_id, id)): Path<(String, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    let deleted = sqlx::query!(
+        "DELETE FROM tariffs WHERE id = $1 AND device_id = $2",
+        id,
+        device_id
+    )
+    .execute(&pool)
+    .await?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(AppError::NotFound(format!("tariff {id} not found")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ---------------------------------------------------------------------------
+// Health check
+// ---------------------------------------------------------------------------
+
+/// Returns `200 OK` with `{"status":"ok"}` when the server is running.
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, description = "Service is healthy"),
+    ),
+    tag = "system"
+)]
+pub async fn health() -> axum::Json<serde_json::Value> {
+    axum::Json(serde_json::json!({ "status": "ok" }))
+}
+
+// ---------------------------------------------------------------------------
+// OpenAPI spec
+// ---------------------------------------------------------------------------
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_latest_readings,
+        get_sensor_readings,
+        get_sensor_latest,
+        get_readings_multi,
+        get_control_actions,
+        list_controllers,
+        get_controller,
+        get_alerts,
+        get_tuya_usage,
+        discover_devices,
+        get_device_availability,
+        switch_device,
+        set_device_setpoint,
+        set_device_override,
+        get_device_override,
+        cancel_device_override,
+        list_schedules,
+        get_schedule,
+        create_schedule,
+        update_schedule,
+        delete_schedule,
+        list_rooms,
+        get_room,
+        create_room,
+        update_room,
+        delete_room,
+        set_room_devices,
+        set_room_setpoint,
+        set_room_override,
+        get_room_override,
+        cancel_room_override,
+        get_energy_consumption,
+        get_energy_cost,
+        list_tariffs,
+        create_tariff,
+        update_tariff,
+        delete_tariff,
+        health,
+    ),
+    components(schemas(
+        SensorReadingDto,
+        SensorType,
+        SensorReadingsRequest,
+        ControlActionDto,
+        ControllerStatusDto,
+        PidStateDto,
+        AlertDto,
+        TuyaUsageDto,
+        EndpointUsageDto,
+        DiscoveredDeviceDto,
+        DeviceAvailabilityDto,
+        AvailabilityTransitionDto,
+        SwitchRequest,
+        SwitchResponse,
+        SetpointRequest,
+        SetpointResponse,
+        OverrideRequest,
+        OverrideDto,
+        DayOfWeek,
+        ScheduleBlockDto,
+        ScheduleRequest,
+        ScheduleDto,
+        RoomRequest,
+        ProbeDto,
+        RoomDevicesDto,
+        RoomDto,
+        Granularity,
+        EnergyConsumptionDto,
+        EnergyPeriodDto,
+        EnergyCostDto,
+        EnergyCostPeriodDto,
+        TariffPlanDto,
+        PeakHoursDto,
+        Tier,
+        TariffRequest,
+        TariffUpdateRequest,
+        TariffDto,
+    )),
+    tags(
+        (name = "sensors", description = "Sensor reading endpoints"),
+        (name = "control", description = "Control loop endpoints"),
+        (name = "devices", description = "Device command endpoints"),
+        (name = "schedules", description = "Weekly heating schedules"),
+        (name = "rooms",     description = "Rooms and their devices"),
+        (name = "energy",    description = "Energy meter consumption"),
+        (name = "system",  description = "System endpoints"),
+    ),
+    info(
+        title = "Smart Home Backend API",
+        version = "0.1.0",
+        description = "REST API for smart home sensor data"
+    )
+)]
+pub struct ApiDoc;
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use axum_test::TestServer;
+    use serde_json::Value;
+    use sqlx::PgPool;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer,
+    };
+
+    use crate::{
+        api::{router, AppState},
+        config::DeviceType,
+        control::{ControlInput, Controller, ControllerStatus, ControllerStatuses, Pid, PidTuning},
+        db::models::SensorType,
+        reading_cache::ReadingCache,
+        sensors::DeviceAvailability,
+        tuya::{
+            mock::{self, ok},
+            QuotaSettings, TuyaClient, Usage,
+        },
+    };
+
+    fn test_state(pool: PgPool, tuya_base_url: &str) -> AppState {
+        AppState {
+            tuya: TuyaClient::with_credentials(tuya_base_url, "client", "secret")
+                .with_usage(Usage::new(
+                    pool.clone(),
+                    "default",
+                    QuotaSettings::default(),
+                ))
+                .into(),
+            availability: DeviceAvailability::new(pool.clone()),
+            timezone: chrono_tz::UTC,
+            pool,
+            cache: ReadingCache::new(),
+            controllers: ControllerStatuses::new(),
+            devices: Arc::new(HashMap::from([
+                ("th1".to_owned(), DeviceType::Thermostat),
+                ("em1".to_owned(), DeviceType::EnergyMeter),
+                ("ws1".to_owned(), DeviceType::WeatherStation),
+                ("c1".to_owned(), DeviceType::ContactSensor),
+            ])),
+        }
+    }
+
+    fn test_server(pool: PgPool) -> TestServer {
+        // Nothing listens on port 9 — any accidental Tuya call fails fast.
+        TestServer::new(router(test_state(pool, "http://127.0.0.1:9"))).unwrap()
+    }
+
+    /// Start a fake Tuya Cloud that hands out a token and accepts commands.
+    async fn fake_tuya() -> MockServer {
+        let server = mock::cloud().await;
+        mock::mount_status(
+            &server,
+            "th1",
+            serde_json::json!([
+                {"code": "switch", "value": true},
+                {"code": "temp_set", "value": 200},
+                {"code": "temp_current", "value": 189},
+                {"code": "mode", "value": "manual"},
+                {"code": "upper_temp", "value": 30}
+            ]),
+        )
+        .await;
+        Mock::given(method("POST"))
+            .and(path("/v1.0/devices/th1/commands"))
+            .respond_with(ok(serde_json::json!(true)))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    async fn insert_reading(pool: &PgPool, device_id: &str, sensor_type: &str, value: i64) {
+        sqlx::query(
+            "INSERT INTO sensor_readings (device_id, sensor_type, value) \
+             VALUES ($1, $2::sensor_type, $3)",
+        )
+        .bind(device_id)
+        .bind(sensor_type)
+        .bind(value)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    // -----------------------------------------------------------------------
+    // GET /sensors/latest
+    // -----------------------------------------------------------------------
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn latest_empty_returns_empty_array(pool: PgPool) {
+        let server = test_server(pool);
+        let resp = server.get("/sensors/latest").await;
+        resp.assert_status_ok();
+        let body: Value = resp.json();
+        assert_eq!(body, serde_json::json!([]));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn latest_returns_most_recent_per_device_and_type(pool: PgPool) {
+        insert_reading(&pool, "dev1", "temperature", 2000).await;
+        insert_reading(&pool, "dev1", "temperature", 2500).await;
+        insert_reading(&pool, "dev1", "humidity", 6000).await;
+
+        let server = test_server(pool);
+        let resp = server.get("/sensors/latest").await;
+        resp.assert_status_ok();
+
+        let body: Vec<Value> = resp.json();
+        assert_eq!(body.len(), 2);
+
+        let temp = body
+            .iter()
+            .find(|r| r["sensor_type"] == "temperature")
+            .unwrap();
+        assert_eq!(temp["device_id"], "dev1");
+        assert_eq!(temp["value"], 2500);
+
+        let hum = body
+            .iter()
+            .find(|r| r["sensor_type"] == "humidity")
+            .unwrap();
+        assert_eq!(hum["value"], 6000);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn latest_returns_one_entry_per_device_type_combination(pool: PgPool) {
+        insert_reading(&pool, "dev1", "temperature", 2000).await;
+        insert_reading(&pool, "dev2", "temperature", 3000).await;
+
+        let server = test_server(pool);
+        let resp = server.get("/sensors/latest").await;
+        resp.assert_status_ok();
+
+        let body: Vec<Value> = resp.json();
+        assert_eq!(body.len(), 2);
+    }
+
+    // -----------------------------------------------------------------------
+    // GET /sensors/{device_id}/{sensor_type}
+    // -----------------------------------------------------------------------
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn sensor_readings_empty_for_unknown_device(pool: PgPool) {
+        let server = test_server(pool);
+        let resp = server.get("/sensors/unknown/temperature").await;
+        resp.assert_status_ok();
+        let body: Value = resp.json();
+        assert_eq!(body, serde_json::json!([]));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn sensor_readings_returns_all_in_asc_order(pool: PgPool) {
+        insert_reading(&pool, "dev1", "temperature", 2000).await;
+        insert_reading(&pool, "dev1", "temperature", 2100).await;
+        insert_reading(&pool, "dev1", "temperature", 2200).await;
+
+        let server = test_server(pool);
+        let resp = server.get("/sensors/dev1/temperature").await;
+        resp.assert_status_ok();
+
+        let body: Vec<Value> = resp.json();
+        assert_eq!(body.len(), 3);
+        assert!(
+            body[0]["recorded_at"].as_str().unwrap() <= body[1]["recorded_at"].as_str().unwrap()
+        );
+        assert!(
+            body[1]["recorded_at"].as_str().unwrap() <= body[2]["recorded_at"].as_str().unwrap()
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn sensor_readings_filters_by_sensor_type(pool: PgPool) {
+        insert_reading(&pool, "dev1", "temperature", 2000).await;
+        insert_reading(&pool, "dev1", "humidity", 6000).await;
+
+        let server = test_server(pool);
+        let resp = server.get("/sensors/dev1/humidity").await;
+        resp.assert_status_ok();
+
+        let body: Vec<Value> = resp.json();
+        assert_eq!(body.len(), 1);
+        assert_eq!(body[0]["sensor_type"], "humidity");
+        assert_eq!(body[0]["value"], 6000);
+    }
+
+    // -----------------------------------------------------------------------
+    // GET /sensors/{device_id}/{sensor_type}/latest
+    // -----------------------------------------------------------------------
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn sensor_latest_returns_null_when_no_readings(pool: PgPool) {
+        let server = test_server(pool);
+        let resp = server.get("/sensors/unknown/temperature/latest").await;
+        resp.assert_status_ok();
+        let body: Value = resp.json();
+        assert!(body.is_null());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn sensor_latest_returns_most_recent(pool: PgPool) {
+        insert_reading(&pool, "dev1", "temperature", 2000).await;
+        insert_reading(&pool, "dev1", "temperature", 2999).await;
+
+        let server = test_server(pool);
+        let resp = server.get("/sensors/dev1/temperature/latest").await;
+        resp.assert_status_ok();
+
+        let body: Value = resp.json();
+        assert_eq!(body["value"], 2999);
+        assert_eq!(body["device_id"], "dev1");
+        assert_eq!(body["sensor_type"], "temperature");
+    }
+
+    // -----------------------------------------------------------------------
+    // POST /sensors/readings
+    // -----------------------------------------------------------------------
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn readings_multi_empty_db_returns_empty_map(pool: PgPool) {
+        let server = test_server(pool);
+        let resp = server
+            .post("/sensors/readings")
+            .json(&serde_json::json!({
+                "device_ids":   ["dev1"],
+                "sensor_types": ["temperature"]
+            }))
+            .await;
+        resp.assert_status_ok();
+        let body: Value = resp.json();
+        assert_eq!(body, serde_json::json!({}));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn readings_multi_groups_by_device_and_type(pool: PgPool) {
+        insert_reading(&pool, "dev1", "temperature", 2000).await;
+        insert_reading(&pool, "dev1", "humidity", 6000).await;
+        insert_reading(&pool, "dev2", "temperature", 3000).await;
+
+        let server = test_server(pool);
+        let resp = server
+            .post("/sensors/readings")
+            .json(&serde_json::json!({
+                "device_ids":   ["dev1", "dev2"],
+                "sensor_types": ["temperature", "humidity"]
+            }))
+            .await;
+        resp.assert_status_ok();
+
+        let body: Value = resp.json();
+        assert_eq!(body["dev1"]["temperature"][0]["value"], 2000);
+        assert_eq!(body["dev1"]["humidity"][0]["value"], 6000);
+        assert_eq!(body["dev2"]["temperature"][0]["value"], 3000);
+        assert!(body["dev2"]["humidity"].is_null() || body["dev2"].get("humidity").is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn readings_multi_excludes_unrequested_sensor_types(pool: PgPool) {
+        insert_reading(&pool, "dev1", "temperature", 2000).await;
+        insert_reading(&pool, "dev1", "humidity", 6000).await;
+
+        let server = test_server(pool);
+        let resp = server
+            .post("/sensors/readings")
+            .json(&serde_json::json!({
+                "device_ids":   ["dev1"],
+                "sensor_types": ["temperature"]
+            }))
+            .await;
+        resp.assert_status_ok();
+
+        let body: Value = resp.json();
+        assert!(body["dev1"]["temperature"].is_array());
+        assert!(body["dev1"].get("humidity").is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn readings_multi_excludes_unrequested_devices(pool: PgPool) {
+        insert_reading(&pool, "dev1", "temperature", 2000).await;
+        insert_reading(&pool, "dev2", "temperature", 3000).await;
+
+        let server = test_server(pool);
+        let resp = server
+            .post("/sensors/readings")
+            .json(&serde_json::json!({
+                "device_ids":   ["dev1"],
+                "sensor_types": ["temperature"]
+            }))
+            .await;
+        resp.assert_status_ok();
+
+        let body: Value = resp.json();
+        assert!(body["dev1"].is_object());
+        assert!(body.get("dev2").is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn readings_multi_readings_ordered_asc(pool: PgPool) {
+        insert_reading(&pool, "dev1", "temperature", 2000).await;
+        insert_reading(&pool, "dev1", "temperature", 2100).await;
+        insert_reading(&pool, "dev1", "temperature", 2200).await;
+
+        let server = test_server(pool);
+        let resp = server
+            .post("/sensors/readings")
+            .json(&serde_json::json!({
+                "device_ids":   ["dev1"],
+                "sensor_types": ["temperature"]
+            }))
+            .await;
+        resp.assert_status_ok();
+
+        let body: Value = resp.json();
+        let readings = body["dev1"]["temperature"].as_array().unwrap();
+        assert_eq!(readings.len(), 3);
+        assert!(
+            readings[0]["recorded_at"].as_str().unwrap()
+                <= readings[1]["recorded_at"].as_str().unwrap()
+        );
+        assert!(
+            readings[1]["recorded_at"].as_str().unwrap()
+                <= readings[2]["recorded_at"].as_str().unwrap()
+        );
+    }
+
+    // -----------------------------------------------------------------------
+    // GET /control/actions
+    // -----------------------------------------------------------------------
+
+    async fn insert_action(pool: &PgPool, device_id: &str, recorded_at: &str, rule: &str) {
+        sqlx::query(
+            "INSERT INTO control_actions \
+                 (device_id, recorded_at, commands, inputs, rule, reason, success, tid) \
+             VALUES ($1, $2::timestamptz, \
+                     '[{\"code\":\"switch\",\"value\":true}]', \
+                     '{\"temperature\":1950}', $3, 'test', true, 'tid-1')",
+        )
+        .bind(device_id)
+        .bind(recorded_at)
+        .bind(rule)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn control_actions_empty_returns_empty_array(pool: PgPool) {
+        let server = test_server(pool);
+        let resp = server.get("/control/actions").await;
+        resp.assert_status_ok();
+        let body: Value = resp.json();
+        assert_eq!(body, serde_json::json!([]));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn control_actions_returns_recorded_fields(pool: PgPool) {
+        insert_action(&pool, "dev1", "2026-03-01T10:00:00Z", "below_band").await;
+
+        let server = test_server(pool);
+        let resp = server.get("/control/actions").await;
+        resp.assert_status_ok();
+
+        let body: Vec<Value> = resp.json();
+        assert_eq!(body.len(), 1);
+        assert_eq!(body[0]["device_id"], "dev1");
+        assert_eq!(body[0]["rule"], "below_band");
+        assert_eq!(body[0]["commands"][0]["code"], "switch");
+        assert_eq!(body[0]["inputs"]["temperature"], 1950);
+        assert_eq!(body[0]["success"], true);
+        assert_eq!(body[0]["tid"], "tid-1");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn control_actions_filters_by_time_range_and_device(pool: PgPool) {
+        insert_action(&pool, "dev1", "2026-03-01T10:00:00Z", "below_band").await;
+        insert_action(&pool, "dev1", "2026-03-01T12:00:00Z", "above_band").await;
+        insert_action(&pool, "dev1", "2026-03-01T14:00:00Z", "below_band").await;
+        insert_action(&pool, "dev2", "2026-03-01T12:00:00Z", "below_band").await;
+
+        let server = test_server(pool);
+        let resp = server
+            .get("/control/actions")
+            .add_query_param("from", "2026-03-01T11:00:00Z")
+            .add_query_param("to", "2026-03-01T13:00:00Z")
+            .await;
+        resp.assert_status_ok();
+        let body: Vec<Value> = resp.json();
+        assert_eq!(body.len(), 2);
+
+        let resp = server
+            .get("/control/actions")
+            .add_query_param("device_id", "dev1")
+            .add_query_param("from", "2026-03-01T11:00:00Z")
+            .await;
+        let body: Vec<Value> = resp.json();
+        assert_eq!(body.len(), 2);
+        assert_eq!(body[0]["rule"], "above_band");
+        assert_eq!(body[1]["rule"], "below_band");
+    }
+
+    // -----------------------------------------------------------------------
+    // POST /devices/{device_id}/switch, PUT /devices/{device_id}/setpoint
+    // -----------------------------------------------------------------------
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn switch_unknown_device_is_not_found(pool: PgPool) {
+        let server = test_server(pool);
+        let resp = server
+            .post("/devices/nope/switch")
+            .json(&serde_json::json!({ "on": true }))
+            .await;
+        resp.assert_status_not_found();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn switch_weather_station_is_bad_request(pool: PgPool) {
+        let server = test_server(pool);
+        let resp = server
+            .post("/devices/ws1/switch")
+            .json(&serde_json::json!({ "on": true }))
+            .await;
+        resp.assert_status_bad_request();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn setpoint_on_energy_meter_is_bad_request(pool: PgPool) {
+        let server = test_server(pool);
+        let resp = server
+            .put("/devices/em1/setpoint")
+            .json(&serde_json::json!({ "celsius": 21.0 }))
+            .await;
+        resp.assert_status_bad_request();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn switch_tuya_unreachable_is_bad_gateway(pool: PgPool) {
+        let server = test_server(pool);
+        let resp = server
+            .post("/devices/th1/switch")
+            .json(&serde_json::json!({ "on": true }))
+            .await;
+        resp.assert_status(axum::http::StatusCode::BAD_GATEWAY);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn switch_sends_command_updates_cache_and_audits(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        let state = test_state(pool.clone(), &tuya.uri());
+        let cache = state.cache.clone();
+        let server = TestServer::new(router(state)).unwrap();
+
+        let resp = server
+            .post("/devices/th1/switch")
+            .json(&serde_json::json!({ "on": false }))
+            .await;
+        resp.assert_status_ok();
+        let body: Value = resp.json();
+        assert_eq!(body["on"], false);
+        assert_eq!(body["tid"], "ok");
+
+        let relay = cache.get("th1", SensorType::RelayState).await.unwrap();
+        assert_eq!(relay.value, 0);
+
+        let (rule, tid): (String, Option<String>) =
+            sqlx::query_as("SELECT rule, tid FROM control_actions WHERE device_id = 'th1'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(rule, "api_switch");
+        assert_eq!(tid.as_deref(), Some("ok"));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn setpoint_is_clamped_to_upper_temp(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        let state = test_state(pool, &tuya.uri());
+        let cache = state.cache.clone();
+        let server = TestServer::new(router(state)).unwrap();
+
+        let resp = server
+            .put("/devices/th1/setpoint")
+            .json(&serde_json::json!({ "celsius": 45.0 }))
+            .await;
+        resp.assert_status_ok();
+        let body: Value = resp.json();
+        assert_eq!(body["applied_celsius"], 30.0);
+        assert_eq!(body["clamped"], true);
+
+        let sent = tuya
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|r| r.url.path() == "/v1.0/devices/th1/commands")
+            .unwrap();
+        let sent: Value = serde_json::from_slice(&sent.body).unwrap();
+        assert_eq!(sent["commands"][0]["code"], "temp_set");
+        assert_eq!(sent["commands"][0]["value"], 300);
+
+        let sp = cache
+            .get("th1", SensorType::TemperatureSetpoint)
+            .await
+            .unwrap();
+        assert_eq!(sp.value, 3000);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn setpoint_within_limits_is_not_clamped(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        let server = TestServer::new(router(test_state(pool, &tuya.uri()))).unwrap();
+
+        let resp = server
+            .put("/devices/th1/setpoint")
+            .json(&serde_json::json!({ "celsius": 21.46 }))
+            .await;
+        resp.assert_status_ok();
+        let body: Value = resp.json();
+        assert_eq!(body["applied_celsius"], 21.5);
+        assert_eq!(body["clamped"], false);
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn sensor_readings_returns_all_in_asc_order(pool: PgPool) {
-        insert_reading(&pool, "dev1", "temperature", 2000).await;
-        insert_reading(&pool, "dev1", "temperature", 2100).await;
-        insert_reading(&pool, "dev1", "temperature", 2200).await;
+    async fn setpoint_uses_the_temp_set_scale_of_the_spec(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/th1/specifications"))
+            .respond_with(ok(serde_json::json!({
+                "category": "wk",
+                "status": [{"code": "temp_set", "type": "Integer",
+                            "values": "{\"unit\":\"℃\",\"scale\":2}"}]
+            })))
+            .mount(&tuya)
+            .await;
+        let state = test_state(pool, &tuya.uri());
+        let cache = state.cache.clone();
+        let server = TestServer::new(router(state)).unwrap();
 
-        let server = test_server(pool);
-        let resp = server.get("/sensors/dev1/temperature").await;
+        let resp = server
+            .put("/devices/th1/setpoint")
+            .json(&serde_json::json!({ "celsius": 21.46 }))
+            .await;
         resp.assert_status_ok();
+        let body: Value = resp.json();
+        assert_eq!(body["applied_celsius"], 21.46);
+
+        let sent = tuya
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|r| r.url.path() == "/v1.0/devices/th1/commands")
+            .unwrap();
+        let sent: Value = serde_json::from_slice(&sent.body).unwrap();
+        assert_eq!(sent["commands"][0]["value"], 2146);
+        let sp = cache
+            .get("th1", SensorType::TemperatureSetpoint)
+            .await
+            .unwrap();
+        assert_eq!(sp.value, 2146);
+    }
 
-        let body: Vec<Value> = resp.json();
-        assert_eq!(body.len(), 3);
-        assert!(
-            body[0]["recorded_at"].as_str().unwrap()
-                <= body[1]["recorded_at"].as_str().unwrap()
-        );
+    // -----------------------------------------------------------------------
+    // /devices/{device_id}/override
+    // -----------------------------------------------------------------------
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn override_roundtrip(pool: PgPool) {
+        let state = test_state(pool, "http://127.0.0.1:9");
+        state
+            .cache
+            .set("th1", SensorType::TemperatureSetpoint, 2000)
+            .await;
+        let server = TestServer::new(router(state)).unwrap();
+
+        server
+            .get("/devices/th1/override")
+            .await
+            .assert_status_not_found();
+
+        let resp = server
+            .post("/devices/th1/override")
+            .json(&serde_json::json!({ "celsius": 23.0, "duration_minutes": 120 }))
+            .await;
+        resp.assert_status_ok();
+        let body: Value = resp.json();
+        assert_eq!(body["celsius"], 23.0);
+        assert_eq!(body["previous_celsius"], 20.0);
+        let created: chrono::DateTime<chrono::Utc> =
+            body["created_at"].as_str().unwrap().parse().unwrap();
+        let expires: chrono::DateTime<chrono::Utc> =
+            body["expires_at"].as_str().unwrap().parse().unwrap();
         assert!(
-            body[1]["recorded_at"].as_str().unwrap()
-                <= body[2]["recorded_at"].as_str().unwrap()
+            (expires - created - chrono::Duration::minutes(120))
+                .num_seconds()
+                .abs()
+                < 5
         );
+
+        let got: Value = server.get("/devices/th1/override").await.json();
+        assert_eq!(got["celsius"], 23.0);
+
+        server
+            .delete("/devices/th1/override")
+            .await
+            .assert_status(axum::http::StatusCode::NO_CONTENT);
+        server
+            .get("/devices/th1/override")
+            .await
+            .assert_status_not_found();
+        server
+            .delete("/devices/th1/override")
+            .await
+            .assert_status_not_found();
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn sensor_readings_filters_by_sensor_type(pool: PgPool) {
-        insert_reading(&pool, "dev1", "temperature", 2000).await;
-        insert_reading(&pool, "dev1", "humidity", 6000).await;
+    async fn replacing_override_keeps_original_previous_setpoint(pool: PgPool) {
+        let state = test_state(pool, "http://127.0.0.1:9");
+        let cache = state.cache.clone();
+        cache
+            .set("th1", SensorType::TemperatureSetpoint, 2000)
+            .await;
+        let server = TestServer::new(router(state)).unwrap();
+
+        let first: Value = server
+            .post("/devices/th1/override")
+            .json(&serde_json::json!({ "celsius": 23.0, "duration_minutes": 60 }))
+            .await
+            .json();
+        // The control loop has applied the first override.
+        cache
+            .set("th1", SensorType::TemperatureSetpoint, 2300)
+            .await;
+
+        let second: Value = server
+            .post("/devices/th1/override")
+            .json(&serde_json::json!({ "celsius": 24.0, "duration_minutes": 30 }))
+            .await
+            .json();
+        assert_eq!(second["celsius"], 24.0);
+        assert_eq!(second["previous_celsius"], 20.0);
+        assert_ne!(first["expires_at"], second["expires_at"]);
+    }
 
+    #[sqlx::test(migrations = "./migrations")]
+    async fn override_rejects_invalid_requests(pool: PgPool) {
         let server = test_server(pool);
-        let resp = server.get("/sensors/dev1/humidity").await;
-        resp.assert_status_ok();
 
-        let body: Vec<Value> = resp.json();
-        assert_eq!(body.len(), 1);
-        assert_eq!(body[0]["sensor_type"], "humidity");
-        assert_eq!(body[0]["value"], 6000);
+        server
+            .post("/devices/em1/override")
+            .json(&serde_json::json!({ "celsius": 23.0, "duration_minutes": 60 }))
+            .await
+            .assert_status_bad_request();
+        server
+            .post("/devices/th1/override")
+            .json(&serde_json::json!({ "celsius": 40.0, "duration_minutes": 60 }))
+            .await
+            .assert_status_bad_request();
+        server
+            .post("/devices/th1/override")
+            .json(&serde_json::json!({ "celsius": 23.0, "duration_minutes": 0 }))
+            .await
+            .assert_status_bad_request();
+        server
+            .post("/devices/nope/override")
+            .json(&serde_json::json!({ "celsius": 23.0, "duration_minutes": 60 }))
+            .await
+            .assert_status_not_found();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn setpoints_above_device_limit_are_refused(pool: PgPool) {
+        let state = test_state(pool, "http://127.0.0.1:9");
+        state.cache.set("th1", SensorType::MaxSetpoint, 2800).await;
+        let server = TestServer::new(router(state)).unwrap();
+
+        let resp = server
+            .post("/devices/th1/override")
+            .json(&serde_json::json!({ "celsius": 30.0, "duration_minutes": 60 }))
+            .await;
+        resp.assert_status_bad_request();
+        assert!(resp.text().contains("device limit"));
+
+        let mut body = schedule_body("th1", true);
+        body["blocks"][0]["celsius"] = 29.0.into();
+        server
+            .post("/schedules")
+            .json(&body)
+            .await
+            .assert_status_bad_request();
     }
 
     // -----------------------------------------------------------------------
-    // GET /sensors/{device_id}/{sensor_type}/latest
+    // /schedules
     // -----------------------------------------------------------------------
 
+    fn schedule_body(device_id: &str, enabled: bool) -> Value {
+        serde_json::json!({
+            "device_id": device_id,
+            "name": "workdays",
+            "enabled": enabled,
+            "default_celsius": 18.0,
+            "blocks": [
+                { "day": "monday", "start": "06:00", "end": "08:00", "celsius": 21.5 },
+                { "day": "monday", "start": "17:00", "end": "22:00", "celsius": 21.0 }
+            ]
+        })
+    }
+
     #[sqlx::test(migrations = "./migrations")]
-    async fn sensor_latest_returns_null_when_no_readings(pool: PgPool) {
+    async fn schedule_crud_roundtrip(pool: PgPool) {
         let server = test_server(pool);
-        let resp = server.get("/sensors/unknown/temperature/latest").await;
+
+        let resp = server
+            .post("/schedules")
+            .json(&schedule_body("th1", true))
+            .await;
+        resp.assert_status(axum::http::StatusCode::CREATED);
+        let created: Value = resp.json();
+        let id = created["id"].as_str().unwrap().to_owned();
+        assert_eq!(created["default_celsius"], 18.0);
+        assert_eq!(created["blocks"].as_array().unwrap().len(), 2);
+        assert_eq!(created["blocks"][0]["day"], "monday");
+        assert_eq!(created["blocks"][0]["celsius"], 21.5);
+
+        let got: Value = server.get(&format!("/schedules/{id}")).await.json();
+        assert_eq!(got["name"], "workdays");
+
+        let mut update = schedule_body("th1", true);
+        update["name"] = "weekend".into();
+        update["blocks"] = serde_json::json!([
+            { "day": "saturday", "start": "08:00", "end": "00:00", "celsius": 22.0 }
+        ]);
+        let resp = server.put(&format!("/schedules/{id}")).json(&update).await;
         resp.assert_status_ok();
-        let body: Value = resp.json();
-        assert!(body.is_null());
+        let updated: Value = resp.json();
+        assert_eq!(updated["name"], "weekend");
+        assert_eq!(updated["blocks"].as_array().unwrap().len(), 1);
+        assert_eq!(updated["blocks"][0]["day"], "saturday");
+
+        let list: Vec<Value> = server
+            .get("/schedules")
+            .add_query_param("device_id", "th1")
+            .await
+            .json();
+        assert_eq!(list.len(), 1);
+
+        server
+            .delete(&format!("/schedules/{id}"))
+            .await
+            .assert_status(axum::http::StatusCode::NO_CONTENT);
+        server
+            .get(&format!("/schedules/{id}"))
+            .await
+            .assert_status_not_found();
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn sensor_latest_returns_most_recent(pool: PgPool) {
-        insert_reading(&pool, "dev1", "temperature", 2000).await;
-        insert_reading(&pool, "dev1", "temperature", 2999).await;
+    async fn schedule_rejects_non_thermostat_and_overlaps(pool: PgPool) {
+        let server = test_server(pool);
 
+        server
+            .post("/schedules")
+            .json(&schedule_body("em1", true))
+            .await
+            .assert_status_bad_request();
+
+        let mut overlapping = schedule_body("th1", true);
+        overlapping["blocks"][1]["start"] = "07:00".into();
+        server
+            .post("/schedules")
+            .json(&overlapping)
+            .await
+            .assert_status_bad_request();
+
+        let mut too_hot = schedule_body("th1", true);
+        too_hot["default_celsius"] = 40.0.into();
+        server
+            .post("/schedules")
+            .json(&too_hot)
+            .await
+            .assert_status_bad_request();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn second_enabled_schedule_conflicts(pool: PgPool) {
         let server = test_server(pool);
-        let resp = server.get("/sensors/dev1/temperature/latest").await;
+
+        server
+            .post("/schedules")
+            .json(&schedule_body("th1", true))
+            .await
+            .assert_status(axum::http::StatusCode::CREATED);
+        server
+            .post("/schedules")
+            .json(&schedule_body("th1", true))
+            .await
+            .assert_status(axum::http::StatusCode::CONFLICT);
+        server
+            .post("/schedules")
+            .json(&schedule_body("th1", false))
+            .await
+            .assert_status(axum::http::StatusCode::CREATED);
+    }
+
+    // -----------------------------------------------------------------------
+    // GET /alerts
+    // -----------------------------------------------------------------------
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn alerts_filter_by_device_and_state(pool: PgPool) {
+        sqlx::query(
+            "INSERT INTO alerts (device_id, kind, message, raised_at, cleared_at) VALUES \
+             ('th1', 'device_fault', 'fault 0x1', '2026-01-01T00:00:00Z', '2026-01-01T01:00:00Z'), \
+             ('th1', 'device_fault', 'fault 0x4', '2026-01-02T00:00:00Z', NULL), \
+             ('th2', 'device_fault', 'fault 0x2', '2026-01-03T00:00:00Z', NULL)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let server = test_server(pool);
+
+        let all: Vec<Value> = server.get("/alerts").await.json();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0]["device_id"], "th2");
+
+        let active: Vec<Value> = server
+            .get("/alerts")
+            .add_query_param("device_id", "th1")
+            .add_query_param("active", true)
+            .await
+            .json();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0]["message"], "fault 0x4");
+        assert!(active[0]["cleared_at"].is_null());
+    }
+
+    // -----------------------------------------------------------------------
+    // GET /devices/discover
+    // -----------------------------------------------------------------------
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn discover_lists_account_devices(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/users/u/devices"))
+            .respond_with(ok(serde_json::json!([
+                {
+                    "id": "th1", "name": "Living room", "category": "wk",
+                    "product_id": "p1", "product_name": "Thermostat", "online": true,
+                    "local_key": "k", "uid": "u"
+                },
+                {
+                    "id": "th9", "name": "Bedroom", "category": "wk",
+                    "product_id": "p1", "online": false
+                },
+                {
+                    "id": "lamp", "name": "Lamp", "category": "dj",
+                    "product_id": "p2", "online": true
+                }
+            ])))
+            .mount(&tuya)
+            .await;
+        let state = test_state(pool, &tuya.uri());
+        let availability = state.availability.clone();
+        let server = TestServer::new(router(state)).unwrap();
+
+        let resp = server.get("/devices/discover").await;
         resp.assert_status_ok();
+        let body: Vec<Value> = resp.json();
+        assert_eq!(body.len(), 3);
 
-        let body: Value = resp.json();
-        assert_eq!(body["value"], 2999);
-        assert_eq!(body["device_id"], "dev1");
-        assert_eq!(body["sensor_type"], "temperature");
+        assert_eq!(body[0]["id"], "th1");
+        assert_eq!(body[0]["device_type"], "thermostat");
+        assert_eq!(body[0]["product_name"], "Thermostat");
+        assert_eq!(body[0]["registered"], true);
+        assert_eq!(body[0]["account"], "default");
+
+        assert_eq!(body[1]["device_type"], "thermostat");
+        assert_eq!(body[1]["online"], false);
+        assert_eq!(body[1]["registered"], false);
+
+        assert!(body[2]["device_type"].is_null());
+        assert_eq!(body[2]["registered"], false);
+
+        // Only registered devices are tracked.
+        let th1 = availability.get("th1").await.unwrap();
+        assert_eq!(th1.online, Some(true));
+        assert!(availability.get("th9").await.is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn discover_tuya_unreachable_is_bad_gateway(pool: PgPool) {
+        let server = test_server(pool);
+        let resp = server.get("/devices/discover").await;
+        resp.assert_status(axum::http::StatusCode::BAD_GATEWAY);
     }
 
     // -----------------------------------------------------------------------
-    // POST /sensors/readings
+    // GET /devices/{device_id}/availability
     // -----------------------------------------------------------------------
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn readings_multi_empty_db_returns_empty_map(pool: PgPool) {
+    async fn availability_reports_uptime_and_transitions(pool: PgPool) {
+        // Online since 40 days ago, offline for the last 6 hours.
+        sqlx::query!(
+            r#"
+            INSERT INTO device_availability (device_id, online, reason, changed_at)
+            VALUES ('th1', true, 'poll succeeded', now() - interval '40 days'),
+                   ('th1', false, '3 consecutive poll failures', now() - interval '6 hours'),
+                   ('em1', false, 'Tuya reports the device offline', now())
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
         let server = test_server(pool);
+
+        let resp = server.get("/devices/th1/availability").await;
+        resp.assert_status_ok();
+        let body: Value = resp.json();
+        let uptime = |key: &str| body[key].as_f64().unwrap();
+        assert!((uptime("uptime_24h") - 75.0).abs() < 0.01);
+        assert!((uptime("uptime_7d") - 100.0 * 162.0 / 168.0).abs() < 0.01);
+        assert!(uptime("uptime_30d") > 99.0);
+        // Only the change within the last 30 days.
+        assert_eq!(body["transitions"].as_array().unwrap().len(), 1);
+        assert_eq!(body["transitions"][0]["online"], false);
+        // Nothing polled since start-up: the stored state.
+        assert_eq!(body["online"], false);
+        assert_eq!(body["consecutive_failures"], 0);
+
+        server
+            .get("/devices/unknown/availability")
+            .await
+            .assert_status_not_found();
+    }
+
+    // -----------------------------------------------------------------------
+    // GET /energy/{device_id}/consumption
+    // -----------------------------------------------------------------------
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn consumption_is_summed_per_day_across_rollovers_and_resets(pool: PgPool) {
+        sqlx::query!(
+            r#"
+            INSERT INTO sensor_readings (device_id, sensor_type, value, recorded_at)
+            VALUES ('em1', 'forward_energy', 99999000, '2026-10-14T23:00:00Z'),
+                   ('em1', 'forward_energy', 99999500, '2026-10-15T10:00:00Z'),
+                   ('em1', 'forward_energy', 200,      '2026-10-16T01:00:00Z'),
+                   ('em1', 'forward_energy', 50,       '2026-10-16T12:00:00Z'),
+                   ('em1', 'reverse_energy', 100,      '2026-10-15T10:00:00Z'),
+                   ('em1', 'reverse_energy', 300,      '2026-10-16T12:00:00Z'),
+                   ('em1', 'forward_energy', 900,      '2026-10-17T00:00:00Z')
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let tuya = fake_tuya().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/em1/specifications"))
+            .respond_with(ok(serde_json::json!({
+                "category": "zndb",
+                "status": [{"code": "total_forward_energy", "type": "Integer",
+                            "values": "{\"unit\":\"kW·h\",\"max\":99999999,\"scale\":2}"}]
+            })))
+            .mount(&tuya)
+            .await;
+        let server = TestServer::new(router(test_state(pool, &tuya.uri()))).unwrap();
+
         let resp = server
-            .post("/sensors/readings")
-            .json(&serde_json::json!({
-                "device_ids":   ["dev1"],
-                "sensor_types": ["temperature"]
-            }))
+            .get("/energy/em1/consumption")
+            .add_query_param("from", "2026-10-15T00:00:00Z")
+            .add_query_param("to", "2026-10-17T00:00:00Z")
             .await;
         resp.assert_status_ok();
         let body: Value = resp.json();
-        assert_eq!(body, serde_json::json!({}));
+        assert_eq!(body["granularity"], "day");
+        // 5 kWh on the 15th; on the 16th 7 kWh across the rollover and
+        // 0.5 kWh after the reset.
+        assert_eq!(
+            body["periods"],
+            serde_json::json!([
+                {"start": "2026-10-15T00:00:00Z", "forward_kwh": 5.0, "reverse_kwh": 0.0},
+                {"start": "2026-10-16T00:00:00Z", "forward_kwh": 7.5, "reverse_kwh": 2.0},
+            ])
+        );
+
+        server
+            .get("/energy/th1/consumption")
+            .await
+            .assert_status_bad_request();
+        server
+            .get("/energy/em1/consumption")
+            .add_query_param("from", "2026-10-17T00:00:00Z")
+            .add_query_param("to", "2026-10-15T00:00:00Z")
+            .await
+            .assert_status_bad_request();
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn readings_multi_groups_by_device_and_type(pool: PgPool) {
-        insert_reading(&pool, "dev1", "temperature", 2000).await;
-        insert_reading(&pool, "dev1", "humidity", 6000).await;
-        insert_reading(&pool, "dev2", "temperature", 3000).await;
-
+    async fn cost_applies_the_tariff_valid_at_each_moment(pool: PgPool) {
+        sqlx::query!(
+            r#"
+            INSERT INTO sensor_readings (device_id, sensor_type, value, recorded_at)
+            VALUES ('em1', 'forward_energy', 1000, '2026-09-30T23:00:00Z'),
+                   ('em1', 'forward_energy', 1400, '2026-10-15T12:00:00Z'),
+                   ('em1', 'forward_energy', 1600, '2026-10-16T06:00:00Z'),
+                   ('em1', 'forward_energy', 1700, '2026-10-16T20:00:00Z')
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
         let server = test_server(pool);
-        let resp = server
-            .post("/sensors/readings")
+
+        let flat = serde_json::json!({
+            "name": "flat",
+            "valid_from": "2026-10-01T00:00:00Z",
+            "valid_until": "2026-10-16T00:00:00Z",
+            "plan": {"kind": "flat", "price_per_kwh": 1.0}
+        });
+        server
+            .post("/energy/em1/tariffs")
+            .json(&flat)
+            .await
+            .assert_status(axum::http::StatusCode::CREATED);
+        let peak_hours: Vec<_> = ["monday", "tuesday", "wednesday", "thursday", "friday"]
+            .iter()
+            .map(|day| serde_json::json!({"day": day, "start_hour": 6, "end_hour": 20}))
+            .collect();
+        let time_of_use = serde_json::json!({
+            "name": "peak/off-peak",
+            "valid_from": "2026-10-16T00:00:00Z",
+            "plan": {
+                "kind": "time_of_use",
+                "peak_price_per_kwh": 0.75,
+                "off_peak_price_per_kwh": 0.5,
+                "peak_hours": peak_hours
+            }
+        });
+        let resp = server.post("/energy/em1/tariffs").json(&time_of_use).await;
+        resp.assert_status(axum::http::StatusCode::CREATED);
+        let body: Value = resp.json();
+        assert_eq!(body["plan"]["peak_hours"][4]["day"], "friday");
+        // Overlaps both.
+        server
+            .post("/energy/em1/tariffs")
             .json(&serde_json::json!({
-                "device_ids":   ["dev1", "dev2"],
-                "sensor_types": ["temperature", "humidity"]
+                "name": "late",
+                "valid_from": "2026-10-10T00:00:00Z",
+                "plan": {"kind": "flat", "price_per_kwh": 2.0}
+            }))
+            .await
+            .assert_status(axum::http::StatusCode::CONFLICT);
+        server
+            .post("/energy/em1/tariffs")
+            .json(&serde_json::json!({
+                "name": "broken",
+                "valid_from": "2027-01-01T00:00:00Z",
+                "plan": {"kind": "tiered", "tiers": []}
             }))
+            .await
+            .assert_status_bad_request();
+
+        let resp = server
+            .get("/energy/em1/cost")
+            .add_query_param("from", "2026-10-15T00:00:00Z")
+            .add_query_param("to", "2026-10-17T00:00:00Z")
             .await;
         resp.assert_status_ok();
-
         let body: Value = resp.json();
-        assert_eq!(body["dev1"]["temperature"][0]["value"], 2000);
-        assert_eq!(body["dev1"]["humidity"][0]["value"], 6000);
-        assert_eq!(body["dev2"]["temperature"][0]["value"], 3000);
-        assert!(body["dev2"]["humidity"].is_null() || body["dev2"].get("humidity").is_none());
+        // 4 kWh at the flat price on the 15th; on the 16th 2 kWh at the peak
+        // price (06:00 UTC is in the peak hours) and 1 kWh after them.
+        assert_eq!(
+            body["periods"],
+            serde_json::json!([
+                {"start": "2026-10-15T00:00:00Z", "kwh": 4.0, "cost": 4.0, "unpriced_kwh": 0.0},
+                {"start": "2026-10-16T00:00:00Z", "kwh": 3.0, "cost": 2.0, "unpriced_kwh": 0.0},
+            ])
+        );
+        assert_eq!(body["total_cost"], 6.0);
+
+        let tariffs: Value = server.get("/energy/em1/tariffs").await.json();
+        assert_eq!(tariffs.as_array().unwrap().len(), 2);
+        let id = tariffs[0]["id"].as_str().unwrap();
+        server
+            .delete(&format!("/energy/em1/tariffs/{id}"))
+            .await
+            .assert_status(axum::http::StatusCode::NO_CONTENT);
+        // The 15th is no longer priced.
+        let body: Value = server
+            .get("/energy/em1/cost")
+            .add_query_param("from", "2026-10-15T00:00:00Z")
+            .add_query_param("to", "2026-10-16T00:00:00Z")
+            .await
+            .json();
+        assert_eq!(body["unpriced_kwh"], 4.0);
+        assert_eq!(body["total_cost"], 0.0);
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn readings_multi_excludes_unrequested_sensor_types(pool: PgPool) {
-        insert_reading(&pool, "dev1", "temperature", 2000).await;
-        insert_reading(&pool, "dev1", "humidity", 6000).await;
-
+    async fn current_tariff_is_closed_before_its_successor(pool: PgPool) {
         let server = test_server(pool);
+        let tariff = |name: &str, valid_from: &str| {
+            serde_json::json!({
+                "name": name,
+                "valid_from": valid_from,
+                "plan": {"kind": "flat", "price_per_kwh": 1.0}
+            })
+        };
         let resp = server
-            .post("/sensors/readings")
-            .json(&serde_json::json!({
-                "device_ids":   ["dev1"],
-                "sensor_types": ["temperature"]
-            }))
+            .post("/energy/em1/tariffs")
+            .json(&tariff("2026", "2026-01-01T00:00:00Z"))
+            .await;
+        resp.assert_status(axum::http::StatusCode::CREATED);
+        let id = resp.json::<Value>()["id"].as_str().unwrap().to_owned();
+        server
+            .post("/energy/em1/tariffs")
+            .json(&tariff("2027", "2027-01-01T00:00:00Z"))
+            .await
+            .assert_status(axum::http::StatusCode::CONFLICT);
+
+        let end = |valid_until: Value| serde_json::json!({ "valid_until": valid_until });
+        server
+            .patch(&format!("/energy/em1/tariffs/{id}"))
+            .json(&end("2025-12-31T00:00:00Z".into()))
+            .await
+            .assert_status_bad_request();
+        server
+            .patch(&format!("/energy/nope/tariffs/{id}"))
+            .json(&end("2027-01-01T00:00:00Z".into()))
+            .await
+            .assert_status_not_found();
+        server
+            .patch(&format!("/energy/th1/tariffs/{id}"))
+            .json(&end("2027-01-01T00:00:00Z".into()))
+            .await
+            .assert_status_bad_request();
+        server
+            .patch(&format!("/energy/em1/tariffs/{}", uuid::Uuid::new_v4()))
+            .json(&end("2027-01-01T00:00:00Z".into()))
+            .await
+            .assert_status_not_found();
+        let resp = server
+            .patch(&format!("/energy/em1/tariffs/{id}"))
+            .json(&end("2027-01-01T00:00:00Z".into()))
             .await;
         resp.assert_status_ok();
+        assert_eq!(resp.json::<Value>()["valid_until"], "2027-01-01T00:00:00Z");
+        server
+            .post("/energy/em1/tariffs")
+            .json(&tariff("2027", "2027-01-01T00:00:00Z"))
+            .await
+            .assert_status(axum::http::StatusCode::CREATED);
+
+        // Reopening it would overlap its successor.
+        server
+            .patch(&format!("/energy/em1/tariffs/{id}"))
+            .json(&end(Value::Null))
+            .await
+            .assert_status(axum::http::StatusCode::CONFLICT);
+    }
 
-        let body: Value = resp.json();
-        assert!(body["dev1"]["temperature"].is_array());
-        assert!(body["dev1"].get("humidity").is_none());
+    // -----------------------------------------------------------------------
+    // GET /tuya/usage
+    // -----------------------------------------------------------------------
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn tuya_usage_counts_calls_per_endpoint(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        let server = TestServer::new(router(test_state(pool, &tuya.uri()))).unwrap();
+
+        server
+            .post("/devices/th1/switch")
+            .json(&serde_json::json!({ "on": true }))
+            .await
+            .assert_status_ok();
+        server
+            .post("/devices/th1/switch")
+            .json(&serde_json::json!({ "on": false }))
+            .await
+            .assert_status_ok();
+
+        let resp = server.get("/tuya/usage").await;
+        resp.assert_status_ok();
+        let accounts: Vec<Value> = resp.json();
+        assert_eq!(accounts.len(), 1);
+        let body = &accounts[0];
+        assert_eq!(body["account"], "default");
+        assert_eq!(body["calls"], 3);
+        assert!(body["monthly_quota"].is_null());
+        assert_eq!(body["degraded"], false);
+        assert_eq!(
+            body["endpoints"],
+            serde_json::json!([
+                { "endpoint": "send_commands", "calls": 2 },
+                { "endpoint": "token", "calls": 1 },
+            ])
+        );
     }
 
+    // -----------------------------------------------------------------------
+    // GET /control/controllers
+    // -----------------------------------------------------------------------
+
     #[sqlx::test(migrations = "./migrations")]
-    async fn readings_multi_excludes_unrequested_devices(pool: PgPool) {
-        insert_reading(&pool, "dev1", "temperature", 2000).await;
-        insert_reading(&pool, "dev2", "temperature", 3000).await;
+    async fn controllers_expose_pid_state(pool: PgPool) {
+        let state = test_state(pool, "http://127.0.0.1:9");
+        let tuning = PidTuning {
+            kp: 0.5,
+            ki: 0.1,
+            kd: 0.0,
+            cycle: std::time::Duration::from_secs(900),
+            min_pulse: std::time::Duration::from_secs(300),
+        };
+        let mut pid = Pid::new(tuning);
+        let input = ControlInput {
+            temperature: 20.0,
+            setpoint: 21.0,
+            relay_on: false,
+            since_last_switch: None,
+            now: chrono::Utc::now(),
+        };
+        pid.update(&input);
+        state
+            .controllers
+            .set(
+                "th1",
+                ControllerStatus {
+                    state: pid.state(),
+                    temperature: input.temperature,
+                    setpoint: input.setpoint,
+                    relay_on: input.relay_on,
+                    updated_at: input.now,
+                },
+            )
+            .await;
+        let server = TestServer::new(router(state)).unwrap();
+
+        let list: Vec<Value> = server.get("/control/controllers").await.json();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0]["controller"], "pid");
+
+        let body: Value = server.get("/control/controllers/th1").await.json();
+        assert_eq!(body["pid"]["output"], 0.5);
+        assert_eq!(body["pid"]["on_secs"], 450);
+        assert!(body["band"].is_null());
+
+        server
+            .get("/control/controllers/em1")
+            .await
+            .assert_status_not_found();
+    }
+
+    // -----------------------------------------------------------------------
+    // /rooms
+    // -----------------------------------------------------------------------
+
+    async fn create_room(server: &TestServer, name: &str) -> String {
+        let resp = server
+            .post("/rooms")
+            .json(&serde_json::json!({ "name": name }))
+            .await;
+        resp.assert_status(axum::http::StatusCode::CREATED);
+        resp.json::<Value>()["id"].as_str().unwrap().to_owned()
+    }
 
+    fn living_room_devices() -> Value {
+        serde_json::json!({
+            "thermostat": "th1",
+            "temperature_probe": { "device_id": "ws1", "channel": "sub2_temperature" },
+            "energy_meter": "em1",
+            "contact_sensors": ["c1"]
+        })
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn room_crud_and_device_assignment(pool: PgPool) {
         let server = test_server(pool);
+        let id = create_room(&server, "Living room").await;
+
         let resp = server
-            .post("/sensors/readings")
-            .json(&serde_json::json!({
-                "device_ids":   ["dev1"],
-                "sensor_types": ["temperature"]
-            }))
+            .put(&format!("/rooms/{id}/devices"))
+            .json(&living_room_devices())
             .await;
         resp.assert_status_ok();
+        let room: Value = resp.json();
+        assert_eq!(room["devices"]["thermostat"], "th1");
+        assert_eq!(
+            room["devices"]["temperature_probe"]["channel"],
+            "sub2_temperature"
+        );
 
-        let body: Value = resp.json();
-        assert!(body["dev1"].is_object());
-        assert!(body.get("dev2").is_none());
+        let resp = server
+            .put(&format!("/rooms/{id}"))
+            .json(&serde_json::json!({ "name": "Lounge" }))
+            .await;
+        resp.assert_status_ok();
+        let room: Value = resp.json();
+        assert_eq!(room["name"], "Lounge");
+        assert_eq!(
+            room["devices"]["contact_sensors"],
+            serde_json::json!(["c1"])
+        );
+
+        let list: Vec<Value> = server.get("/rooms").await.json();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0]["devices"]["energy_meter"], "em1");
+
+        // Replacing assignments drops the ones not listed.
+        let resp = server
+            .put(&format!("/rooms/{id}/devices"))
+            .json(&serde_json::json!({ "thermostat": "th1" }))
+            .await;
+        let room: Value = resp.json();
+        assert!(room["devices"]["temperature_probe"].is_null());
+        assert_eq!(room["devices"]["contact_sensors"], serde_json::json!([]));
+
+        server
+            .delete(&format!("/rooms/{id}"))
+            .await
+            .assert_status(axum::http::StatusCode::NO_CONTENT);
+        server
+            .get(&format!("/rooms/{id}"))
+            .await
+            .assert_status_not_found();
     }
 
     #[sqlx::test(migrations = "./migrations")]
-    async fn readings_multi_readings_ordered_asc(pool: PgPool) {
-        insert_reading(&pool, "dev1", "temperature", 2000).await;
-        insert_reading(&pool, "dev1", "temperature", 2100).await;
-        insert_reading(&pool, "dev1", "temperature", 2200).await;
+    async fn room_devices_are_validated(pool: PgPool) {
+        let server = test_server(pool);
+        let id = create_room(&server, "Office").await;
+
+        for body in [
+            serde_json::json!({ "thermostat": "em1" }),
+            serde_json::json!({ "thermostat": "nope" }),
+            serde_json::json!({ "contact_sensors": ["th1"] }),
+            serde_json::json!({
+                "temperature_probe": { "device_id": "ws1", "channel": "sub2_humidity" }
+            }),
+        ] {
+            server
+                .put(&format!("/rooms/{id}/devices"))
+                .json(&body)
+                .await
+                .assert_status_bad_request();
+        }
+
+        server
+            .put(&format!("/rooms/{}/devices", uuid::Uuid::new_v4()))
+            .json(&serde_json::json!({}))
+            .await
+            .assert_status_not_found();
+    }
 
+    #[sqlx::test(migrations = "./migrations")]
+    async fn room_conflicts(pool: PgPool) {
         let server = test_server(pool);
-        let resp = server
-            .post("/sensors/readings")
+        let a = create_room(&server, "A").await;
+        let b = create_room(&server, "B").await;
+
+        server
+            .post("/rooms")
+            .json(&serde_json::json!({ "name": "A" }))
+            .await
+            .assert_status(axum::http::StatusCode::CONFLICT);
+
+        server
+            .put(&format!("/rooms/{a}/devices"))
             .json(&serde_json::json!({
-                "device_ids":   ["dev1"],
-                "sensor_types": ["temperature"]
+                "thermostat": "th1",
+                "temperature_probe": { "device_id": "ws1", "channel": "sub1_temperature" }
+            }))
+            .await
+            .assert_status_ok();
+        server
+            .put(&format!("/rooms/{b}/devices"))
+            .json(&serde_json::json!({ "thermostat": "th1" }))
+            .await
+            .assert_status(axum::http::StatusCode::CONFLICT);
+        // Another channel of the same weather station is fine.
+        server
+            .put(&format!("/rooms/{b}/devices"))
+            .json(&serde_json::json!({
+                "temperature_probe": { "device_id": "ws1", "channel": "sub2_temperature" }
+            }))
+            .await
+            .assert_status_ok();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn latest_readings_filtered_by_room(pool: PgPool) {
+        insert_reading(&pool, "th1", "temperature", 2100).await;
+        insert_reading(&pool, "ws1", "sub1_temperature", 1500).await;
+        insert_reading(&pool, "ws1", "sub2_temperature", 2050).await;
+        insert_reading(&pool, "em1", "relay_state", 1).await;
+        let server = test_server(pool);
+        let id = create_room(&server, "Living room").await;
+        server
+            .put(&format!("/rooms/{id}/devices"))
+            .json(&serde_json::json!({
+                "thermostat": "th1",
+                "temperature_probe": { "device_id": "ws1", "channel": "sub2_temperature" }
             }))
+            .await
+            .assert_status_ok();
+
+        let body: Vec<Value> = server
+            .get("/sensors/latest")
+            .add_query_param("room_id", &id)
+            .await
+            .json();
+        let mut got: Vec<(String, String)> = body
+            .iter()
+            .map(|r| {
+                (
+                    r["device_id"].as_str().unwrap().to_owned(),
+                    r["sensor_type"].as_str().unwrap().to_owned(),
+                )
+            })
+            .collect();
+        got.sort();
+        assert_eq!(
+            got,
+            vec![
+                ("th1".to_owned(), "temperature".to_owned()),
+                ("ws1".to_owned(), "sub2_temperature".to_owned()),
+            ]
+        );
+
+        server
+            .get("/sensors/latest")
+            .add_query_param("room_id", uuid::Uuid::new_v4())
+            .await
+            .assert_status_not_found();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn control_actions_filtered_by_room(pool: PgPool) {
+        insert_action(&pool, "th1", "2026-01-01T00:00:00Z", "below_band").await;
+        insert_action(&pool, "th2", "2026-01-01T00:01:00Z", "below_band").await;
+        let server = test_server(pool);
+        let id = create_room(&server, "Living room").await;
+        server
+            .put(&format!("/rooms/{id}/devices"))
+            .json(&serde_json::json!({ "thermostat": "th1" }))
+            .await
+            .assert_status_ok();
+
+        let body: Vec<Value> = server
+            .get("/control/actions")
+            .add_query_param("room_id", &id)
+            .await
+            .json();
+        assert_eq!(body.len(), 1);
+        assert_eq!(body[0]["device_id"], "th1");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn room_setpoint_targets_room_thermostat(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        let server = TestServer::new(router(test_state(pool, &tuya.uri()))).unwrap();
+        let id = create_room(&server, "Living room").await;
+
+        server
+            .put(&format!("/rooms/{id}/setpoint"))
+            .json(&serde_json::json!({ "celsius": 21.0 }))
+            .await
+            .assert_status_bad_request();
+
+        server
+            .put(&format!("/rooms/{id}/devices"))
+            .json(&serde_json::json!({ "thermostat": "th1" }))
+            .await
+            .assert_status_ok();
+        let resp = server
+            .put(&format!("/rooms/{id}/setpoint"))
+            .json(&serde_json::json!({ "celsius": 21.0 }))
             .await;
         resp.assert_status_ok();
-
         let body: Value = resp.json();
-        let readings = body["dev1"]["temperature"].as_array().unwrap();
-        assert_eq!(readings.len(), 3);
-        assert!(
-            readings[0]["recorded_at"].as_str().unwrap()
-                <= readings[1]["recorded_at"].as_str().unwrap()
-        );
-        assert!(
-            readings[1]["recorded_at"].as_str().unwrap()
-                <= readings[2]["recorded_at"].as_str().unwrap()
-        );
+        assert_eq!(body["device_id"], "th1");
+        assert_eq!(body["applied_celsius"], 21.0);
+
+        server
+            .post(&format!("/rooms/{id}/override"))
+            .json(&serde_json::json!({ "celsius": 23.0, "duration_minutes": 60 }))
+            .await
+            .assert_status_ok();
+        let got: Value = server.get("/devices/th1/override").await.json();
+        assert_eq!(got["celsius"], 23.0);
     }
 
     // -----------------------------------------------------------------------
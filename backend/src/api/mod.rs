@@ -1,15 +1,19 @@
 pub mod dto;
 pub mod errors;
 pub mod handlers;
+pub mod state;
 
-use axum::{routing::{get, post}, Router};
-use sqlx::PgPool;
+use axum::{
+    routing::{get, patch, post, put},
+    Router,
+};
 use utoipa::OpenApi;
 use utoipa_axum::router::OpenApiRouter;
 
 use handlers::ApiDoc;
+pub use state::AppState;
 
-pub fn router(pool: PgPool) -> Router {
+pub fn router(state: AppState) -> Router {
     let (router, api) = OpenApiRouter::with_openapi(ApiDoc::openapi())
         .route("/sensors/latest", get(handlers::get_latest_readings))
         .route("/sensors/readings", post(handlers::get_readings_multi))
@@ -21,13 +25,76 @@ pub fn router(pool: PgPool) -> Router {
             "/sensors/{device_id}/{sensor_type}/latest",
             get(handlers::get_sensor_latest),
         )
-        .with_state(pool)
-        .split_for_parts();
-
-    router
-        .route("/health", get(handlers::health))
+        .route("/control/actions", get(handlers::get_control_actions))
+        .route("/control/controllers", get(handlers::list_controllers))
+        .route("/alerts", get(handlers::get_alerts))
+        .route("/tuya/usage", get(handlers::get_tuya_usage))
+        .route(
+            "/control/controllers/{device_id}",
+            get(handlers::get_controller),
+        )
+        .route(
+            "/energy/{device_id}/consumption",
+            get(handlers::get_energy_consumption),
+        )
+        .route("/energy/{device_id}/cost", get(handlers::get_energy_cost))
+        .route(
+            "/energy/{device_id}/tariffs",
+            get(handlers::list_tariffs).post(handlers::create_tariff),
+        )
+        .route(
+            "/energy/{device_id}/tariffs/{id}",
+            patch(handlers::update_tariff).delete(handlers::delete_tariff),
+        )
+        .route("/devices/discover", get(handlers::discover_devices))
+        .route(
+            "/devices/{device_id}/availability",
+            get(handlers::get_device_availability),
+        )
+        .route("/devices/{device_id}/switch", post(handlers::switch_device))
+        .route(
+            "/devices/{device_id}/setpoint",
+            put(handlers::set_device_setpoint),
+        )
+        .route(
+            "/devices/{device_id}/override",
+            get(handlers::get_device_override)
+                .post(handlers::set_device_override)
+                .delete(handlers::cancel_device_override),
+        )
+        .route(
+            "/schedules",
+            get(handlers::list_schedules).post(handlers::create_schedule),
+        )
+        .route(
+            "/schedules/{id}",
+            get(handlers::get_schedule)
+                .put(handlers::update_schedule)
+                .delete(handlers::delete_schedule),
+        )
         .route(
-            "/api-docs/openapi.json",
-            get(move || async move { axum::Json(api) }),
+            "/rooms",
+            get(handlers::list_rooms).post(handlers::create_room),
         )
+        .route(
+            "/rooms/{id}",
+            get(handlers::get_room)
+                .put(handlers::update_room)
+                .delete(handlers::delete_room),
+        )
+        .route("/rooms/{id}/devices", put(handlers::set_room_devices))
+        .route("/rooms/{id}/setpoint", put(handlers::set_room_setpoint))
+        .route(
+            "/rooms/{id}/override",
+            get(handlers::get_room_override)
+                .post(handlers::set_room_override)
+                .delete(handlers::cancel_room_override),
+        )
+        .with_state(state)
+        .split_for_parts();
+
+    router.route("/health", get(handlers::health)).route(
+        "/api-docs/openapi.json",
+        get(move || async move { axum::Json(api) }),
+    )
 }
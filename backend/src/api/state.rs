@@ -0,0 +1,47 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::extract::FromRef;
+use chrono_tz::Tz;
+use sqlx::PgPool;
+
+use crate::{
+    config::DeviceType, control::ControllerStatuses, reading_cache::ReadingCache,
+    sensors::DeviceAvailability, tuya::TuyaAccounts,
+};
+
+/// Shared state handed to every handler.
+///
+/// Handlers that only need the database keep extracting `State<PgPool>`;
+/// the `FromRef` impls below pick the relevant field out of `AppState`.
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub tuya: TuyaAccounts,
+    pub cache: ReadingCache,
+    /// Configured devices, `device_id → DeviceType`.
+    pub devices: Arc<HashMap<String, DeviceType>>,
+    /// Latest controller state per thermostat, published by the control loop.
+    pub controllers: ControllerStatuses,
+    /// Whether each device is reachable, tracked by the polling loop.
+    pub availability: DeviceAvailability,
+    /// Timezone in which energy use is summed per day and month.
+    pub timezone: Tz,
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for ReadingCache {
+    fn from_ref(state: &AppState) -> Self {
+        state.cache.clone()
+    }
+}
+
+impl FromRef<AppState> for ControllerStatuses {
+    fn from_ref(state: &AppState) -> Self {
+        state.controllers.clone()
+    }
+}
@@ -1,6 +1,13 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, fmt, path::PathBuf, str::FromStr};
 
 use anyhow::{Context, Result};
+use chrono_tz::Tz;
+
+use crate::{
+    db::models::SensorType,
+    rooms::{Probe, PROBE_CHANNELS},
+    tuya::local::LocalDevice,
+};
 
 // ---------------------------------------------------------------------------
 // DeviceType
@@ -13,6 +20,8 @@ pub enum DeviceType {
     Thermostat,
     EnergyMeter,
     WeatherStation,
+    /// Door/window contact sensor (Tuya category `mcs`).
+    ContactSensor,
 }
 
 impl FromStr for DeviceType {
@@ -23,11 +32,83 @@ impl FromStr for DeviceType {
             "thermostat" => Ok(Self::Thermostat),
             "energy_meter" => Ok(Self::EnergyMeter),
             "weather_station" => Ok(Self::WeatherStation),
+            "contact_sensor" => Ok(Self::ContactSensor),
             other => Err(anyhow::anyhow!("unknown device type: {other:?}")),
         }
     }
 }
 
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Thermostat => "thermostat",
+            Self::EnergyMeter => "energy_meter",
+            Self::WeatherStation => "weather_station",
+            Self::ContactSensor => "contact_sensor",
+        };
+        f.write_str(s)
+    }
+}
+
+impl DeviceType {
+    /// Device type for a Tuya product category code, or `None` for
+    /// categories this service does not handle.
+    pub fn from_category(category: &str) -> Option<Self> {
+        match category {
+            "wk" => Some(Self::Thermostat),
+            "zndb" => Some(Self::EnergyMeter),
+            "wsdcg" => Some(Self::WeatherStation),
+            "mcs" => Some(Self::ContactSensor),
+            _ => None,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// ControllerKind
+// ---------------------------------------------------------------------------
+
+/// Algorithm the control loop uses to drive a thermostat relay.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ControllerKind {
+    /// On/off with a hysteresis band.
+    #[default]
+    Hysteresis,
+    /// PID mapped to relay duty cycles.
+    Pid,
+}
+
+impl FromStr for ControllerKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hysteresis" => Ok(Self::Hysteresis),
+            "pid" => Ok(Self::Pid),
+            other => Err(anyhow::anyhow!("unknown controller: {other:?}")),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// TuyaAccount
+// ---------------------------------------------------------------------------
+
+/// Name of the account configured by `TUYA_BASE_URL`, `TUYA_CLIENT_ID` and
+/// `TUYA_CLIENT_SECRET`.
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+/// Credentials and data centre of one Tuya Cloud project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TuyaAccount {
+    pub base_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Message service endpoint of the project's data centre; `None`
+    /// disables event ingest for the account.
+    pub events_url: Option<String>,
+}
+
 // ---------------------------------------------------------------------------
 // Config
 // ---------------------------------------------------------------------------
@@ -38,37 +119,240 @@ pub struct Config {
     pub tuya_client_id: String,
     pub tuya_client_secret: String,
     pub tuya_base_url: String,
+    /// Further Tuya accounts by name, e.g. for a second data centre.
+    /// Format: `TUYA_ACCOUNTS="us,cn"`; each account reads
+    /// `TUYA_<NAME>_BASE_URL`, `TUYA_<NAME>_CLIENT_ID`,
+    /// `TUYA_<NAME>_CLIENT_SECRET` and optionally `TUYA_<NAME>_EVENTS_URL`.
+    pub tuya_accounts: HashMap<String, TuyaAccount>,
+    /// Maps device_id → account name; devices not listed belong to the
+    /// default account. Format: `"id1:us,id2:us"`.
+    pub device_accounts: HashMap<String, String>,
+    /// Attempts per Tuya call, including the first, for transient failures.
+    pub tuya_retry_attempts: u32,
+    /// Base and maximum delay of the exponential retry backoff, in ms.
+    pub tuya_retry_base_ms: u64,
+    pub tuya_retry_max_ms: u64,
+    /// Sustained Tuya request rate per second and burst size, shared by
+    /// polling, control and the API.
+    pub tuya_rate_per_sec: f64,
+    pub tuya_rate_burst: u32,
+    /// Tuya Cloud calls allowed per account and calendar month; `0` means
+    /// no quota.
+    pub tuya_monthly_quota: u64,
+    /// Fraction of the monthly quota after which sensor polling slows down.
+    pub tuya_quota_degrade_at: f64,
+    /// Sensor polling interval multiplier once degraded.
+    pub tuya_degraded_poll_factor: u32,
+    /// Tuya message service endpoint for real-time status events of the
+    /// default account; `None` disables its event ingest and leaves polling
+    /// as the only source.
+    pub tuya_events_url: Option<String>,
+    /// Message topic environment: `event`, or `event-test` for the test
+    /// channel.
+    pub tuya_events_env: String,
+    /// Directory raw Tuya responses are saved under for offline analysis;
+    /// `None` (empty `RESPONSE_DIR`) turns saving off.
+    pub response_dir: Option<PathBuf>,
+    /// Devices reachable over the LAN and how to reach each one.
+    /// Format: `"id1:transport:version:host:local_key,..."`, with transport
+    /// `cloud`, `local` or `local_fallback` and version `3.3`, `3.4` or
+    /// `3.5`. DP IDs come from `LOCAL_DP_IDS`
+    /// (`"id1:1=switch;2=temp_set,..."`) or are learned from the cloud.
+    pub local_devices: Vec<LocalDevice>,
     pub server_host: String,
     pub server_port: u16,
     /// Maps device_id → DeviceType,
     /// Format: `"id1:type1,id2:type2"` (e.g. `"abc:thermostat,def:energy_meter"`).
     pub device_ids: HashMap<String, DeviceType>,
+    /// Register devices linked to the Tuya account at start-up, in addition
+    /// to `device_ids`. Entries in `device_ids` take precedence.
+    pub discover_devices: bool,
     /// Sensor polling interval in seconds.
     pub poll_interval_secs: u64,
+    /// Polling interval in seconds per device, overriding
+    /// `poll_interval_secs`. Format: `"meter1:10,station1:300"`.
+    pub poll_intervals: HashMap<String, u64>,
+    /// Most Tuya calls the polling loop has in flight at once.
+    pub poll_concurrency: usize,
+    /// A poll call still unanswered after this many seconds is abandoned,
+    /// together with every device of its status batch.
+    pub poll_timeout_secs: u64,
     /// Control loop interval in seconds.
     pub control_interval_secs: u64,
+    /// Half-width of the thermostat hysteresis band in °C.
+    pub control_hysteresis_celsius: f64,
+    /// Minimum time in seconds a thermostat relay stays on or off before the
+    /// control loop may switch it again.
+    pub control_min_dwell_secs: u64,
+    /// IANA timezone in which heating schedules are evaluated and energy use is
+    /// summed per day, e.g. `Europe/Warsaw`.
+    pub timezone: Tz,
+    /// Maps thermostat_id → window contact sensor IDs in the same room.
+    /// Format: `"contact1:thermostat1,contact2:thermostat1"`.
+    pub window_contacts: HashMap<String, Vec<String>>,
+    /// Temperature drop in °C within `window_drop_minutes` that is treated as
+    /// an open window. `0` disables the rule.
+    pub window_drop_celsius: f64,
+    /// Window over which the temperature drop is measured, in minutes.
+    pub window_drop_minutes: u64,
+    /// Heating resumes after this many minutes even if the window still
+    /// appears to be open.
+    pub window_timeout_minutes: u64,
+    /// Maps thermostat_id → external reading the thermostat regulates on
+    /// instead of its own `temp_current`. A room's temperature probe takes
+    /// precedence. Format: `"thermostat1:station1:sub2_temperature"`.
+    pub reference_sensors: HashMap<String, Probe>,
+    /// External readings older than this many seconds are ignored and the
+    /// thermostat falls back to its own temperature.
+    pub reference_max_age_secs: u64,
+    /// Maps thermostat_id → controller; thermostats not listed use hysteresis.
+    /// Format: `"thermostat1:pid,thermostat2:hysteresis"`.
+    pub controllers: HashMap<String, ControllerKind>,
+    /// PID gains: output (duty cycle `0..=1`) per °C of error, per °C·hour of
+    /// accumulated error and per °C/hour of error change.
+    pub pid_kp: f64,
+    pub pid_ki: f64,
+    pub pid_kd: f64,
+    /// Length of one PID relay cycle in minutes.
+    pub pid_cycle_minutes: u64,
+    /// Heating is forced on at or below this temperature in °C, whatever the
+    /// controller, schedule or window detection say.
+    pub frost_floor_celsius: f64,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self> {
+        let tuya_accounts = parse_tuya_accounts(&optional("TUYA_ACCOUNTS", ""), required)?;
+        let device_accounts =
+            parse_device_accounts(&optional("DEVICE_ACCOUNTS", ""), &tuya_accounts)?;
         Ok(Self {
             database_url: required("DATABASE_URL")?,
             tuya_client_id: required("TUYA_CLIENT_ID")?,
             tuya_client_secret: required("TUYA_CLIENT_SECRET")?,
             tuya_base_url: required("TUYA_BASE_URL")?,
+            tuya_accounts,
+            device_accounts,
+            tuya_retry_attempts: optional("TUYA_RETRY_ATTEMPTS", "3")
+                .parse()
+                .context("TUYA_RETRY_ATTEMPTS must be a positive integer")?,
+            tuya_retry_base_ms: optional("TUYA_RETRY_BASE_MS", "500")
+                .parse()
+                .context("TUYA_RETRY_BASE_MS must be a positive integer")?,
+            tuya_retry_max_ms: optional("TUYA_RETRY_MAX_MS", "10000")
+                .parse()
+                .context("TUYA_RETRY_MAX_MS must be a positive integer")?,
+            tuya_rate_per_sec: optional("TUYA_RATE_PER_SEC", "5")
+                .parse()
+                .ok()
+                .filter(|r: &f64| *r > 0.0)
+                .context("TUYA_RATE_PER_SEC must be a positive number")?,
+            tuya_rate_burst: optional("TUYA_RATE_BURST", "10")
+                .parse()
+                .context("TUYA_RATE_BURST must be a positive integer")?,
+            tuya_monthly_quota: optional("TUYA_MONTHLY_QUOTA", "0")
+                .parse()
+                .context("TUYA_MONTHLY_QUOTA must be a non-negative integer")?,
+            tuya_quota_degrade_at: optional("TUYA_QUOTA_DEGRADE_AT", "0.8")
+                .parse()
+                .ok()
+                .filter(|f: &f64| (0.0..=1.0).contains(f))
+                .context("TUYA_QUOTA_DEGRADE_AT must be a fraction between 0 and 1")?,
+            tuya_degraded_poll_factor: optional("TUYA_DEGRADED_POLL_FACTOR", "4")
+                .parse()
+                .context("TUYA_DEGRADED_POLL_FACTOR must be a positive integer")?,
+            tuya_events_url: Some(optional("TUYA_EVENTS_URL", "")).filter(|s| !s.is_empty()),
+            tuya_events_env: optional("TUYA_EVENTS_ENV", "event"),
+            response_dir: Some(optional("RESPONSE_DIR", "responses"))
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            local_devices: parse_local_devices(
+                &optional("LOCAL_DEVICES", ""),
+                &optional("LOCAL_DP_IDS", ""),
+            )?,
             server_host: optional("SERVER_HOST", "0.0.0.0"),
             server_port: optional("SERVER_PORT", "8080")
                 .parse()
                 .context("SERVER_PORT must be a valid port number")?,
             device_ids: parse_device_ids(&optional("TUYA_DEVICE_IDS", ""))?,
+            discover_devices: optional("DISCOVER_DEVICES", "false")
+                .parse()
+                .context("DISCOVER_DEVICES must be true or false")?,
             poll_interval_secs: optional("POLL_INTERVAL_SECS", "60")
                 .parse()
                 .context("POLL_INTERVAL_SECS must be a positive integer")?,
+            poll_intervals: parse_poll_intervals(&optional("POLL_INTERVALS", ""))?,
+            poll_concurrency: optional("POLL_CONCURRENCY", "4")
+                .parse()
+                .ok()
+                .filter(|&n| n > 0)
+                .context("POLL_CONCURRENCY must be a positive integer")?,
+            poll_timeout_secs: optional("POLL_TIMEOUT_SECS", "30")
+                .parse()
+                .context("POLL_TIMEOUT_SECS must be a positive integer")?,
             control_interval_secs: optional("CONTROL_INTERVAL_SECS", "60")
                 .parse()
                 .context("CONTROL_INTERVAL_SECS must be a positive integer")?,
+            control_hysteresis_celsius: optional("CONTROL_HYSTERESIS_C", "0.3")
+                .parse()
+                .context("CONTROL_HYSTERESIS_C must be a number of °C")?,
+            control_min_dwell_secs: optional("CONTROL_MIN_DWELL_SECS", "300")
+                .parse()
+                .context("CONTROL_MIN_DWELL_SECS must be a positive integer")?,
+            timezone: optional("TIMEZONE", "UTC")
+                .parse()
+                .map_err(|e| anyhow::anyhow!("TIMEZONE must be an IANA timezone name: {e}"))?,
+            window_contacts: parse_window_contacts(&optional("WINDOW_CONTACTS", ""))?,
+            window_drop_celsius: optional("WINDOW_DROP_C", "1.0")
+                .parse()
+                .context("WINDOW_DROP_C must be a number of °C")?,
+            window_drop_minutes: optional("WINDOW_DROP_MINUTES", "5")
+                .parse()
+                .context("WINDOW_DROP_MINUTES must be a positive integer")?,
+            window_timeout_minutes: optional("WINDOW_TIMEOUT_MINUTES", "30")
+                .parse()
+                .context("WINDOW_TIMEOUT_MINUTES must be a positive integer")?,
+            reference_sensors: parse_reference_sensors(&optional("REFERENCE_SENSORS", ""))?,
+            reference_max_age_secs: optional("REFERENCE_MAX_AGE_SECS", "600")
+                .parse()
+                .context("REFERENCE_MAX_AGE_SECS must be a positive integer")?,
+            controllers: parse_controllers(&optional("CONTROLLERS", ""))?,
+            pid_kp: optional("PID_KP", "0.5")
+                .parse()
+                .context("PID_KP must be a number")?,
+            pid_ki: optional("PID_KI", "0.1")
+                .parse()
+                .context("PID_KI must be a number")?,
+            pid_kd: optional("PID_KD", "0")
+                .parse()
+                .context("PID_KD must be a number")?,
+            pid_cycle_minutes: optional("PID_CYCLE_MINUTES", "15")
+                .parse()
+                .context("PID_CYCLE_MINUTES must be a positive integer")?,
+            frost_floor_celsius: optional("FROST_FLOOR_C", "5.0")
+                .parse()
+                .context("FROST_FLOOR_C must be a number of °C")?,
         })
     }
+
+    /// Credentials of a named account, the default one included.
+    pub fn tuya_account(&self, name: &str) -> Option<TuyaAccount> {
+        if name == DEFAULT_ACCOUNT {
+            return Some(TuyaAccount {
+                base_url: self.tuya_base_url.clone(),
+                client_id: self.tuya_client_id.clone(),
+                client_secret: self.tuya_client_secret.clone(),
+                events_url: self.tuya_events_url.clone(),
+            });
+        }
+        self.tuya_accounts.get(name).cloned()
+    }
+
+    /// Name of the account `device_id` belongs to.
+    pub fn account_of(&self, device_id: &str) -> &str {
+        self.device_accounts
+            .get(device_id)
+            .map_or(DEFAULT_ACCOUNT, String::as_str)
+    }
 }
 
 /// Parse `"id1:type1,id2:type2"` into a `HashMap<String, DeviceType>`.
@@ -90,6 +374,200 @@ fn parse_device_ids(raw: &str) -> Result<HashMap<String, DeviceType>> {
         .collect()
 }
 
+/// Parse `"id1:10,id2:300"` into `device_id → interval in seconds`.
+fn parse_poll_intervals(raw: &str) -> Result<HashMap<String, u64>> {
+    raw.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let (id, secs) = entry.split_once(':').with_context(|| {
+                format!("POLL_INTERVALS entry must be 'device_id:seconds', got: {entry:?}")
+            })?;
+            let secs = secs
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|&secs| secs > 0)
+                .with_context(|| {
+                    format!("POLL_INTERVALS entry {entry:?} needs a positive number of seconds")
+                })?;
+            Ok((id.trim().to_owned(), secs))
+        })
+        .collect()
+}
+
+/// Parse `"contact1:thermostat1,contact2:thermostat1"` into
+/// `thermostat_id → [contact_id, ...]`.
+fn parse_window_contacts(raw: &str) -> Result<HashMap<String, Vec<String>>> {
+    let mut map: HashMap<String, Vec<String>> = HashMap::new();
+    for entry in raw.split(',').filter(|s| !s.is_empty()) {
+        let (contact, thermostat) = entry.split_once(':').with_context(|| {
+            format!("WINDOW_CONTACTS entry must be 'contact_id:thermostat_id', got: {entry:?}")
+        })?;
+        map.entry(thermostat.trim().to_owned())
+            .or_default()
+            .push(contact.trim().to_owned());
+    }
+    Ok(map)
+}
+
+/// Parse `"thermostat1:pid,thermostat2:hysteresis"` into
+/// `thermostat_id → ControllerKind`.
+fn parse_controllers(raw: &str) -> Result<HashMap<String, ControllerKind>> {
+    raw.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let (id, kind) = entry.split_once(':').with_context(|| {
+                format!("CONTROLLERS entry must be 'thermostat_id:controller', got: {entry:?}")
+            })?;
+            let kind = kind
+                .trim()
+                .parse::<ControllerKind>()
+                .with_context(|| format!("unknown controller in CONTROLLERS entry {entry:?}"))?;
+            Ok((id.trim().to_owned(), kind))
+        })
+        .collect()
+}
+
+/// Parse `"thermostat1:station1:sub2_temperature,..."` into
+/// `thermostat_id → Probe`.
+fn parse_reference_sensors(raw: &str) -> Result<HashMap<String, Probe>> {
+    raw.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let mut parts = entry.splitn(3, ':').map(str::trim);
+            let (Some(thermostat), Some(device), Some(channel)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                anyhow::bail!(
+                    "REFERENCE_SENSORS entry must be 'thermostat_id:device_id:channel', got: {entry:?}"
+                );
+            };
+            let channel = channel
+                .parse::<SensorType>()
+                .ok()
+                .filter(|c| PROBE_CHANNELS.contains(c))
+                .with_context(|| {
+                    format!("REFERENCE_SENSORS channel must be a temperature channel, got: {entry:?}")
+                })?;
+            Ok((
+                thermostat.to_owned(),
+                Probe {
+                    device_id: device.to_owned(),
+                    channel,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Parse `LOCAL_DEVICES` entries, attaching the DP IDs `LOCAL_DP_IDS` lists
+/// for them. The local key comes last so it may contain `:`.
+fn parse_local_devices(devices: &str, dp_ids: &str) -> Result<Vec<LocalDevice>> {
+    let mut dp_codes: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for entry in dp_ids.split(',').filter(|s| !s.is_empty()) {
+        let (id, dps) = entry.split_once(':').with_context(|| {
+            format!("LOCAL_DP_IDS entry must be 'device_id:dp_id=code;...', got: {entry:?}")
+        })?;
+        let codes = dp_codes.entry(id.trim().to_owned()).or_default();
+        for dp in dps.split(';').filter(|s| !s.is_empty()) {
+            let (dp_id, code) = dp
+                .split_once('=')
+                .with_context(|| format!("LOCAL_DP_IDS DP must be 'dp_id=code', got: {dp:?}"))?;
+            codes.insert(dp_id.trim().to_owned(), code.trim().to_owned());
+        }
+    }
+
+    devices
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let mut parts = entry.trim().splitn(5, ':');
+            let (Some(id), Some(transport), Some(version), Some(host), Some(key)) = (
+                parts.next(),
+                parts.next(),
+                parts.next(),
+                parts.next(),
+                parts.next(),
+            ) else {
+                anyhow::bail!(
+                    "LOCAL_DEVICES entry must be 'device_id:transport:version:host:local_key', \
+                     got: {entry:?}"
+                );
+            };
+            Ok(LocalDevice {
+                device_id: id.to_owned(),
+                host: host.to_owned(),
+                local_key: key.as_bytes().try_into().with_context(|| {
+                    format!("LOCAL_DEVICES local key of {id} must be 16 characters")
+                })?,
+                version: version
+                    .parse()
+                    .with_context(|| format!("invalid version in LOCAL_DEVICES entry of {id}"))?,
+                transport: transport
+                    .parse()
+                    .with_context(|| format!("invalid transport in LOCAL_DEVICES entry of {id}"))?,
+                dp_codes: dp_codes.remove(id).unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// Parse `"us,cn"` into named accounts, reading each one's credentials
+/// through `var` from `TUYA_<NAME>_BASE_URL`, `TUYA_<NAME>_CLIENT_ID` and
+/// `TUYA_<NAME>_CLIENT_SECRET`, and its events endpoint from
+/// `TUYA_<NAME>_EVENTS_URL` if set.
+fn parse_tuya_accounts(
+    raw: &str,
+    var: impl Fn(&str) -> Result<String>,
+) -> Result<HashMap<String, TuyaAccount>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|name| {
+            if name == DEFAULT_ACCOUNT
+                || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                anyhow::bail!(
+                    "TUYA_ACCOUNTS names must be words other than 'default', got: {name:?}"
+                );
+            }
+            let prefix = format!("TUYA_{}", name.to_ascii_uppercase());
+            let account = TuyaAccount {
+                base_url: var(&format!("{prefix}_BASE_URL"))?,
+                client_id: var(&format!("{prefix}_CLIENT_ID"))?,
+                client_secret: var(&format!("{prefix}_CLIENT_SECRET"))?,
+                events_url: var(&format!("{prefix}_EVENTS_URL"))
+                    .ok()
+                    .filter(|s| !s.is_empty()),
+            };
+            Ok((name.to_owned(), account))
+        })
+        .collect()
+}
+
+/// Parse `"id1:us,id2:us"` into `device_id → account`, checking that each
+/// account is configured.
+fn parse_device_accounts(
+    raw: &str,
+    accounts: &HashMap<String, TuyaAccount>,
+) -> Result<HashMap<String, String>> {
+    raw.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let (id, account) = entry.split_once(':').with_context(|| {
+                format!("DEVICE_ACCOUNTS entry must be 'device_id:account', got: {entry:?}")
+            })?;
+            let account = account.trim();
+            if account != DEFAULT_ACCOUNT && !accounts.contains_key(account) {
+                anyhow::bail!(
+                    "DEVICE_ACCOUNTS entry {entry:?} names an account not in TUYA_ACCOUNTS"
+                );
+            }
+            Ok((id.trim().to_owned(), account.to_owned()))
+        })
+        .collect()
+}
+
 fn required(key: &str) -> Result<String> {
     std::env::var(key).with_context(|| format!("missing required env var: {key}"))
 }
@@ -101,6 +579,7 @@ fn optional(key: &str, default: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tuya::local::{protocol::Version, Transport};
 
     #[test]
     fn parse_device_ids_empty() {
@@ -128,6 +607,49 @@ mod tests {
         assert!(err.to_string().contains("device_id:device_type"));
     }
 
+    #[test]
+    fn parse_poll_intervals_per_device() {
+        let m = parse_poll_intervals("em1:10, ws1:300").unwrap();
+        assert_eq!(m["em1"], 10);
+        assert_eq!(m["ws1"], 300);
+        assert!(parse_poll_intervals("").unwrap().is_empty());
+        assert!(parse_poll_intervals("em1:0").is_err());
+        assert!(parse_poll_intervals("em1").is_err());
+    }
+
+    #[test]
+    fn device_type_display_round_trips() {
+        for kind in [
+            DeviceType::Thermostat,
+            DeviceType::EnergyMeter,
+            DeviceType::WeatherStation,
+            DeviceType::ContactSensor,
+        ] {
+            assert_eq!(kind.to_string().parse::<DeviceType>().unwrap(), kind);
+        }
+    }
+
+    #[test]
+    fn tuya_categories_map_to_device_types() {
+        assert_eq!(
+            DeviceType::from_category("wk"),
+            Some(DeviceType::Thermostat)
+        );
+        assert_eq!(
+            DeviceType::from_category("zndb"),
+            Some(DeviceType::EnergyMeter)
+        );
+        assert_eq!(
+            DeviceType::from_category("wsdcg"),
+            Some(DeviceType::WeatherStation)
+        );
+        assert_eq!(
+            DeviceType::from_category("mcs"),
+            Some(DeviceType::ContactSensor)
+        );
+        assert_eq!(DeviceType::from_category("dj"), None);
+    }
+
     #[test]
     fn device_type_from_str_roundtrip() {
         assert_eq!(
@@ -142,5 +664,133 @@ mod tests {
             "weather_station".parse::<DeviceType>().unwrap(),
             DeviceType::WeatherStation
         );
+        assert_eq!(
+            "contact_sensor".parse::<DeviceType>().unwrap(),
+            DeviceType::ContactSensor
+        );
+    }
+
+    #[test]
+    fn parse_window_contacts_groups_by_thermostat() {
+        let m = parse_window_contacts("c1:th1, c2:th1,c3:th2").unwrap();
+        assert_eq!(m["th1"], vec!["c1", "c2"]);
+        assert_eq!(m["th2"], vec!["c3"]);
+        assert!(parse_window_contacts("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_window_contacts_missing_colon_errors() {
+        let err = parse_window_contacts("c1").unwrap_err();
+        assert!(err.to_string().contains("contact_id:thermostat_id"));
+    }
+
+    #[test]
+    fn parse_controllers_selects_per_thermostat() {
+        let m = parse_controllers("th1:pid, th2:hysteresis").unwrap();
+        assert_eq!(m["th1"], ControllerKind::Pid);
+        assert_eq!(m["th2"], ControllerKind::Hysteresis);
+        let err = parse_controllers("th1:mpc").unwrap_err();
+        assert!(err.to_string().contains("unknown controller"));
+    }
+
+    #[test]
+    fn parse_reference_sensors_binds_thermostat_to_channel() {
+        let m = parse_reference_sensors("th1:ws1:sub2_temperature, th2:ws1:temperature").unwrap();
+        assert_eq!(
+            m["th1"],
+            Probe {
+                device_id: "ws1".into(),
+                channel: SensorType::Sub2Temperature,
+            }
+        );
+        assert_eq!(m["th2"].channel, SensorType::Temperature);
+        assert!(parse_reference_sensors("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_reference_sensors_rejects_bad_entries() {
+        let err = parse_reference_sensors("th1:ws1").unwrap_err();
+        assert!(err.to_string().contains("thermostat_id:device_id:channel"));
+        let err = parse_reference_sensors("th1:ws1:sub2_humidity").unwrap_err();
+        assert!(err.to_string().contains("temperature channel"));
+    }
+
+    #[test]
+    fn parse_local_devices_reads_transport_and_dp_ids() {
+        let devices = parse_local_devices(
+            "th1:local_fallback:3.4:192.168.1.20:0123456789:bcdef,\
+             em1:local:3.3:em.lan:fedcba9876543210",
+            "th1:1=switch;2=temp_set",
+        )
+        .unwrap();
+        assert_eq!(devices.len(), 2);
+        let th1 = &devices[0];
+        assert_eq!(th1.transport, Transport::LocalFallback);
+        assert_eq!(th1.version, Version::V34);
+        assert_eq!(th1.host, "192.168.1.20");
+        assert_eq!(&th1.local_key, b"0123456789:bcdef");
+        assert_eq!(th1.dp_codes["2"], "temp_set");
+        assert!(devices[1].dp_codes.is_empty());
+        assert!(parse_local_devices("", "").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_local_devices_rejects_bad_entries() {
+        for (entry, expected) in [
+            (
+                "th1:local:3.3:host",
+                "device_id:transport:version:host:local_key",
+            ),
+            ("th1:lan:3.3:host:0123456789abcdef", "invalid transport"),
+            ("th1:local:3.1:host:0123456789abcdef", "invalid version"),
+            ("th1:local:3.3:host:short", "16 characters"),
+        ] {
+            let err = parse_local_devices(entry, "").unwrap_err();
+            assert!(err.to_string().contains(expected), "{entry}: {err}");
+        }
+        assert!(parse_local_devices("", "th1:1").is_err());
+    }
+
+    #[test]
+    fn parse_tuya_accounts_reads_credentials_per_name() {
+        let vars = HashMap::from([
+            ("TUYA_US_BASE_URL", "https://openapi.tuyaus.com"),
+            ("TUYA_US_CLIENT_ID", "us-id"),
+            ("TUYA_US_CLIENT_SECRET", "us-secret"),
+        ]);
+        let var = |key: &str| {
+            vars.get(key)
+                .map(|v| v.to_string())
+                .with_context(|| format!("missing {key}"))
+        };
+
+        let accounts = parse_tuya_accounts("us", var).unwrap();
+        assert_eq!(accounts["us"].base_url, "https://openapi.tuyaus.com");
+        assert_eq!(accounts["us"].client_id, "us-id");
+        assert_eq!(accounts["us"].events_url, None);
+        assert!(parse_tuya_accounts("", var).unwrap().is_empty());
+
+        let err = parse_tuya_accounts("us,eu", var).unwrap_err();
+        assert!(err.to_string().contains("TUYA_EU_BASE_URL"));
+        assert!(parse_tuya_accounts("default", var).is_err());
+    }
+
+    #[test]
+    fn parse_device_accounts_checks_account_names() {
+        let accounts = HashMap::from([(
+            "us".to_owned(),
+            TuyaAccount {
+                base_url: "u".into(),
+                client_id: "i".into(),
+                client_secret: "s".into(),
+                events_url: None,
+            },
+        )]);
+        let m = parse_device_accounts("th1:us, ws1:default", &accounts).unwrap();
+        assert_eq!(m["th1"], "us");
+        assert_eq!(m["ws1"], DEFAULT_ACCOUNT);
+
+        let err = parse_device_accounts("th1:eu", &accounts).unwrap_err();
+        assert!(err.to_string().contains("not in TUYA_ACCOUNTS"));
     }
 }
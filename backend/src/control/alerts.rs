@@ -0,0 +1,55 @@
+use sqlx::PgPool;
+use tracing::{error, info};
+
+/// Kind of the alert raised while a thermostat reports a fault.
+pub const DEVICE_FAULT: &str = "device_fault";
+
+/// Raise an alert unless one of the same kind is already active for the
+/// device.
+///
+/// Best-effort, like [`super::audit::record`]: a failed insert is logged and
+/// never propagated.
+pub async fn raise(pool: &PgPool, device_id: &str, kind: &str, message: &str) {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO alerts (device_id, kind, message)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (device_id, kind) WHERE cleared_at IS NULL DO NOTHING
+        "#,
+        device_id,
+        kind,
+        message,
+    )
+    .execute(pool)
+    .await;
+
+    match inserted {
+        Ok(r) if r.rows_affected() > 0 => {
+            error!(device_id = %device_id, kind, message, "Alert raised");
+        }
+        Ok(_) => {}
+        Err(e) => error!(device_id = %device_id, kind, error = %e, "Failed to raise alert"),
+    }
+}
+
+/// Clear the active alert of `kind` for the device, if any. Best-effort.
+pub async fn clear(pool: &PgPool, device_id: &str, kind: &str) {
+    let cleared = sqlx::query!(
+        r#"
+        UPDATE alerts SET cleared_at = now()
+        WHERE device_id = $1 AND kind = $2 AND cleared_at IS NULL
+        "#,
+        device_id,
+        kind,
+    )
+    .execute(pool)
+    .await;
+
+    match cleared {
+        Ok(r) if r.rows_affected() > 0 => {
+            info!(device_id = %device_id, kind, "Alert cleared");
+        }
+        Ok(_) => {}
+        Err(e) => error!(device_id = %device_id, kind, error = %e, "Failed to clear alert"),
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use sqlx::PgPool;
+use tracing::error;
+
+use crate::{
+    db::models::SensorType,
+    tuya::{
+        models::{Command, CommandAck},
+        TuyaError,
+    },
+};
+
+/// A command about to be written to the `control_actions` audit table.
+#[derive(Debug)]
+pub struct NewAction<'a> {
+    pub device_id: &'a str,
+    pub commands: &'a [Command],
+    /// Readings the decision was based on (encoded values).
+    pub inputs: &'a BTreeMap<SensorType, i64>,
+    /// Machine-readable rule, e.g. `"below_band"` or `"api_switch"`.
+    pub rule: &'a str,
+    pub reason: &'a str,
+}
+
+/// Append a row to the `control_actions` audit table, recording the outcome
+/// of the Tuya call.
+///
+/// Best-effort: a failed insert is logged and never propagated, so auditing
+/// can not stop the control loop or fail an API request.
+pub async fn record(pool: &PgPool, action: NewAction<'_>, result: &Result<CommandAck, TuyaError>) {
+    let commands = serde_json::to_value(action.commands).unwrap_or_default();
+    let inputs = serde_json::Value::Object(
+        action
+            .inputs
+            .iter()
+            .map(|(k, v)| (k.to_string(), (*v).into()))
+            .collect(),
+    );
+    let (success, tid, error) = match result {
+        Ok(ack) => (ack.result, ack.tid.clone(), None),
+        Err(e) => (false, e.tid().map(str::to_owned), Some(format!("{e:#}"))),
+    };
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO control_actions
+            (device_id, commands, inputs, rule, reason, success, tid, error)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        action.device_id,
+        commands,
+        inputs,
+        action.rule,
+        action.reason,
+        success,
+        tid,
+        error,
+    )
+    .execute(pool)
+    .await;
+
+    if let Err(e) = inserted {
+        error!(device_id = %action.device_id, error = %e, "Failed to record control action");
+    }
+}
@@ -0,0 +1,96 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::RwLock;
+
+use super::{
+    hysteresis::{Decision, Hysteresis},
+    pid::{Pid, PidState, PidTuning},
+};
+use crate::config::ControllerKind;
+
+/// Everything a controller gets to see for one thermostat evaluation.
+#[derive(Debug, Clone, Copy)]
+pub struct ControlInput {
+    /// Reference temperature, °C.
+    pub temperature: f64,
+    /// Target temperature, °C.
+    pub setpoint: f64,
+    /// Current relay state as last reported by the device.
+    pub relay_on: bool,
+    /// Time since the control loop last switched the relay, or `None` if it
+    /// has not switched it since start-up.
+    pub since_last_switch: Option<Duration>,
+    pub now: DateTime<Utc>,
+}
+
+/// Decides the relay state of one thermostat. One instance is kept per
+/// thermostat, so implementations may carry state between evaluations.
+pub trait Controller: Send + Sync {
+    fn update(&mut self, input: &ControlInput) -> Decision;
+
+    /// Internal state for inspection through the API.
+    fn state(&self) -> ControllerState;
+}
+
+/// Build the controller of the given kind.
+pub fn build(kind: ControllerKind, hysteresis: Hysteresis, pid: PidTuning) -> Box<dyn Controller> {
+    match kind {
+        ControllerKind::Hysteresis => Box::new(hysteresis),
+        ControllerKind::Pid => Box::new(Pid::new(pid)),
+    }
+}
+
+/// Snapshot of a controller's internal state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControllerState {
+    Hysteresis { band: f64 },
+    Pid(PidState),
+}
+
+/// The last evaluation of one thermostat's controller.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControllerStatus {
+    pub state: ControllerState,
+    pub temperature: f64,
+    pub setpoint: f64,
+    pub relay_on: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Latest `ControllerStatus` per thermostat, written by the control loop and
+/// read by the API. Cheap to clone, like `ReadingCache`.
+#[derive(Clone, Default)]
+pub struct ControllerStatuses {
+    inner: Arc<RwLock<HashMap<String, ControllerStatus>>>,
+}
+
+impl ControllerStatuses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn set(&self, device_id: &str, status: ControllerStatus) {
+        self.inner
+            .write()
+            .await
+            .insert(device_id.to_owned(), status);
+    }
+
+    pub async fn get(&self, device_id: &str) -> Option<ControllerStatus> {
+        self.inner.read().await.get(device_id).cloned()
+    }
+
+    /// All statuses, ordered by device ID.
+    pub async fn all(&self) -> Vec<(String, ControllerStatus)> {
+        let mut all: Vec<_> = self
+            .inner
+            .read()
+            .await
+            .iter()
+            .map(|(id, s)| (id.clone(), s.clone()))
+            .collect();
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        all
+    }
+}
@@ -0,0 +1,226 @@
+use std::{fmt, time::Duration};
+
+use super::controller::{ControlInput, Controller, ControllerState};
+
+/// Parameters of the on/off (bang-bang) thermostat controller.
+#[derive(Debug, Clone, Copy)]
+pub struct Hysteresis {
+    /// Half-width of the dead band around the setpoint, in °C.
+    ///
+    /// Heating turns on at or below `setpoint - band` and off at or above
+    /// `setpoint + band`; inside the band the relay keeps its current state.
+    pub band: f64,
+    /// Minimum time the relay must stay in one state before it may be
+    /// switched again. Protects the relay and the boiler from short-cycling.
+    pub min_dwell: Duration,
+}
+
+/// The rule that produced a [`Decision`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// Temperature fell to or below the lower band edge — heat.
+    BelowBand,
+    /// Temperature rose to or above the upper band edge — stop heating.
+    AboveBand,
+    /// Temperature is inside the dead band, or the relay is already in the
+    /// wanted state — nothing to do.
+    WithinBand,
+    /// A switch was wanted but the relay changed state too recently.
+    MinDwell,
+    /// PID duty cycle: inside the on-time of the current cycle.
+    DutyOn,
+    /// PID duty cycle: past the on-time of the current cycle.
+    DutyOff,
+}
+
+impl Rule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rule::BelowBand => "below_band",
+            Rule::AboveBand => "above_band",
+            Rule::WithinBand => "within_band",
+            Rule::MinDwell => "min_dwell",
+            Rule::DutyOn => "duty_on",
+            Rule::DutyOff => "duty_off",
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Outcome of one controller evaluation for a single thermostat.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    /// `Some(state)` when the relay should be switched to `state`;
+    /// `None` when it should be left alone.
+    pub switch_to: Option<bool>,
+    pub rule: Rule,
+    /// Human-readable explanation including the inputs that were compared.
+    pub reason: String,
+}
+
+impl Hysteresis {
+    /// Decide whether the relay should change state.
+    ///
+    /// - `temperature`, `setpoint`: °C.
+    /// - `relay_on`: current relay state as last reported by the device.
+    /// - `since_last_switch`: time since this controller last switched the
+    ///   relay, or `None` if it has not switched it since start-up.
+    pub fn decide(
+        &self,
+        temperature: f64,
+        setpoint: f64,
+        relay_on: bool,
+        since_last_switch: Option<Duration>,
+    ) -> Decision {
+        let low = setpoint - self.band;
+        let high = setpoint + self.band;
+
+        let (wanted, rule, reason) = if temperature <= low {
+            (
+                true,
+                Rule::BelowBand,
+                format!(
+                    "temperature {temperature:.2} °C <= {low:.2} °C (setpoint {setpoint:.2} - {:.2})",
+                    self.band
+                ),
+            )
+        } else if temperature >= high {
+            (
+                false,
+                Rule::AboveBand,
+                format!(
+                    "temperature {temperature:.2} °C >= {high:.2} °C (setpoint {setpoint:.2} + {:.2})",
+                    self.band
+                ),
+            )
+        } else {
+            return Decision {
+                switch_to: None,
+                rule: Rule::WithinBand,
+                reason: format!(
+                    "temperature {temperature:.2} °C within [{low:.2}, {high:.2}] °C; relay stays {}",
+                    on_off(relay_on)
+                ),
+            };
+        };
+
+        if wanted == relay_on {
+            return Decision {
+                switch_to: None,
+                rule: Rule::WithinBand,
+                reason: format!("{reason}; relay already {}", on_off(relay_on)),
+            };
+        }
+
+        if let Some(elapsed) = since_last_switch {
+            if elapsed < self.min_dwell {
+                return Decision {
+                    switch_to: None,
+                    rule: Rule::MinDwell,
+                    reason: format!(
+                        "{reason}; wanted {} but relay switched {}s ago (min dwell {}s)",
+                        on_off(wanted),
+                        elapsed.as_secs(),
+                        self.min_dwell.as_secs()
+                    ),
+                };
+            }
+        }
+
+        Decision {
+            switch_to: Some(wanted),
+            rule,
+            reason,
+        }
+    }
+}
+
+impl Controller for Hysteresis {
+    fn update(&mut self, input: &ControlInput) -> Decision {
+        self.decide(
+            input.temperature,
+            input.setpoint,
+            input.relay_on,
+            input.since_last_switch,
+        )
+    }
+
+    fn state(&self) -> ControllerState {
+        ControllerState::Hysteresis { band: self.band }
+    }
+}
+
+fn on_off(v: bool) -> &'static str {
+    if v {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctl() -> Hysteresis {
+        Hysteresis {
+            band: 0.5,
+            min_dwell: Duration::from_secs(300),
+        }
+    }
+
+    #[test]
+    fn below_band_turns_relay_on() {
+        let d = ctl().decide(20.4, 21.0, false, None);
+        assert_eq!(d.switch_to, Some(true));
+        assert_eq!(d.rule, Rule::BelowBand);
+    }
+
+    #[test]
+    fn above_band_turns_relay_off() {
+        let d = ctl().decide(21.5, 21.0, true, None);
+        assert_eq!(d.switch_to, Some(false));
+        assert_eq!(d.rule, Rule::AboveBand);
+    }
+
+    #[test]
+    fn inside_band_keeps_state() {
+        for relay_on in [false, true] {
+            let d = ctl().decide(21.2, 21.0, relay_on, None);
+            assert_eq!(d.switch_to, None);
+            assert_eq!(d.rule, Rule::WithinBand);
+        }
+    }
+
+    #[test]
+    fn relay_already_in_wanted_state_is_noop() {
+        let d = ctl().decide(19.0, 21.0, true, None);
+        assert_eq!(d.switch_to, None);
+        assert!(d.reason.contains("already on"));
+    }
+
+    #[test]
+    fn min_dwell_blocks_switch() {
+        let d = ctl().decide(19.0, 21.0, false, Some(Duration::from_secs(60)));
+        assert_eq!(d.switch_to, None);
+        assert_eq!(d.rule, Rule::MinDwell);
+    }
+
+    #[test]
+    fn switch_allowed_after_dwell_elapsed() {
+        let d = ctl().decide(19.0, 21.0, false, Some(Duration::from_secs(300)));
+        assert_eq!(d.switch_to, Some(true));
+    }
+
+    #[test]
+    fn reason_mentions_inputs() {
+        let d = ctl().decide(20.0, 21.0, false, None);
+        assert!(d.reason.contains("20.00"));
+        assert!(d.reason.contains("20.50"));
+    }
+}
@@ -1,3 +1,18 @@
+pub mod alerts;
+pub mod audit;
+pub mod controller;
+pub mod hysteresis;
+pub mod overrides;
+pub mod pid;
+pub mod safety;
+pub mod schedule;
 pub mod service;
+pub mod window;
 
-pub use service::ControlService;
+pub use controller::{
+    ControlInput, Controller, ControllerState, ControllerStatus, ControllerStatuses,
+};
+pub use hysteresis::{Decision, Hysteresis, Rule};
+pub use pid::{Pid, PidState, PidTuning};
+pub use safety::{Safety, SafetyLimits};
+pub use service::{ControlService, ControlSettings};
@@ -0,0 +1,42 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::models::ThermostatOverride;
+
+/// Load every override, including expired ones that still have to be undone.
+pub async fn load_all(pool: &PgPool) -> sqlx::Result<Vec<ThermostatOverride>> {
+    sqlx::query_as!(
+        ThermostatOverride,
+        r#"
+        SELECT id, device_id, setpoint, previous_setpoint, created_at, expires_at
+        FROM thermostat_overrides
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Load the override for `device_id`, if any (active or not yet cleaned up).
+pub async fn get(pool: &PgPool, device_id: &str) -> sqlx::Result<Option<ThermostatOverride>> {
+    sqlx::query_as!(
+        ThermostatOverride,
+        r#"
+        SELECT id, device_id, setpoint, previous_setpoint, created_at, expires_at
+        FROM thermostat_overrides
+        WHERE device_id = $1
+        "#,
+        device_id,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Delete an override once normal control has been restored.
+///
+/// Matches on `id` so an override replaced in the meantime is left alone.
+pub async fn delete(pool: &PgPool, id: Uuid) -> sqlx::Result<()> {
+    sqlx::query!("DELETE FROM thermostat_overrides WHERE id = $1", id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use super::{
+    controller::{ControlInput, Controller, ControllerState},
+    hysteresis::{Decision, Rule},
+};
+
+/// Parameters of the PID controller.
+///
+/// The controller output is a heating duty cycle between `0` (off) and `1`
+/// (on for the whole cycle); the gains map an error in °C onto it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PidTuning {
+    /// Output per °C of error.
+    pub kp: f64,
+    /// Output per °C·hour of accumulated error.
+    pub ki: f64,
+    /// Output per °C/hour rate of change of the error.
+    pub kd: f64,
+    /// Length of one relay cycle. The output is turned into an on-time once
+    /// per cycle.
+    pub cycle: Duration,
+    /// On- or off-times shorter than this are skipped, so the relay never
+    /// switches faster than the hysteresis minimum dwell would allow.
+    pub min_pulse: Duration,
+}
+
+/// Internal state of a [`Pid`], for tuning.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PidState {
+    pub tuning: PidTuning,
+    /// `setpoint - temperature` at the last update, °C.
+    pub error: f64,
+    pub proportional: f64,
+    pub integral: f64,
+    pub derivative: f64,
+    /// Clamped sum of the three terms, `0..=1`.
+    pub output: f64,
+    /// Start of the current relay cycle.
+    pub cycle_started_at: Option<DateTime<Utc>>,
+    /// How long the relay stays on in the current cycle.
+    pub on_for: Duration,
+}
+
+/// PID controller driving the relay with a slow PWM: once per cycle the output
+/// is converted into an on-time at the start of the cycle.
+///
+/// Anti-windup: the integral only accumulates while the output is not
+/// saturated (or while the error pulls it back out of saturation) and is
+/// limited to the output range.
+#[derive(Debug, Clone)]
+pub struct Pid {
+    tuning: PidTuning,
+    integral: f64,
+    last: Option<(DateTime<Utc>, f64)>,
+    cycle: Option<(DateTime<Utc>, Duration)>,
+    state: PidState,
+}
+
+impl Pid {
+    pub fn new(tuning: PidTuning) -> Self {
+        Self {
+            tuning,
+            integral: 0.0,
+            last: None,
+            cycle: None,
+            state: PidState {
+                tuning,
+                error: 0.0,
+                proportional: 0.0,
+                integral: 0.0,
+                derivative: 0.0,
+                output: 0.0,
+                cycle_started_at: None,
+                on_for: Duration::ZERO,
+            },
+        }
+    }
+
+    /// Update the PID terms and return the clamped output.
+    fn step(&mut self, now: DateTime<Utc>, error: f64) -> f64 {
+        let t = &self.tuning;
+        // After a gap longer than one cycle (start-up, window open, missing
+        // readings) the previous error says nothing about the current trend.
+        let dt_hours = self
+            .last
+            .and_then(|(at, prev)| Some(((now - at).to_std().ok()?, prev)))
+            .filter(|(dt, _)| !dt.is_zero() && *dt <= t.cycle)
+            .map(|(dt, prev)| (dt.as_secs_f64() / 3600.0, prev));
+
+        let proportional = t.kp * error;
+        let derivative = dt_hours.map_or(0.0, |(dt, prev)| t.kd * (error - prev) / dt);
+        if let Some((dt, _)) = dt_hours {
+            let integral = self.integral + t.ki * error * dt;
+            let unclamped = proportional + integral + derivative;
+            let saturated = (unclamped > 1.0 && error > 0.0) || (unclamped < 0.0 && error < 0.0);
+            if !saturated {
+                self.integral = integral.clamp(0.0, 1.0);
+            }
+        }
+        let output = (proportional + self.integral + derivative).clamp(0.0, 1.0);
+
+        self.last = Some((now, error));
+        self.state.error = error;
+        self.state.proportional = proportional;
+        self.state.integral = self.integral;
+        self.state.derivative = derivative;
+        self.state.output = output;
+        output
+    }
+
+    /// The current cycle, starting a new one from `output` if the previous
+    /// one has ended.
+    fn cycle(&mut self, now: DateTime<Utc>, output: f64) -> (DateTime<Utc>, Duration) {
+        let t = &self.tuning;
+        let ended = self.cycle.is_none_or(|(start, _)| {
+            (now - start)
+                .to_std()
+                .map_or(true, |elapsed| elapsed >= t.cycle)
+        });
+        if ended {
+            let mut on_for = t.cycle.mul_f64(output);
+            if on_for < t.min_pulse {
+                on_for = Duration::ZERO;
+            } else if t.cycle.saturating_sub(on_for) < t.min_pulse {
+                on_for = t.cycle;
+            }
+            self.cycle = Some((now, on_for));
+            self.state.cycle_started_at = Some(now);
+            self.state.on_for = on_for;
+        }
+        self.cycle.unwrap_or((now, Duration::ZERO))
+    }
+}
+
+impl Controller for Pid {
+    fn update(&mut self, input: &ControlInput) -> Decision {
+        let output = self.step(input.now, input.setpoint - input.temperature);
+        let (start, on_for) = self.cycle(input.now, output);
+        let elapsed = (input.now - start).to_std().unwrap_or_default();
+        let wanted = elapsed < on_for;
+
+        let reason = format!(
+            "temperature {:.2} °C, setpoint {:.2} °C; output {output:.2} \
+             (P {:.2}, I {:.2}, D {:.2}); on for {}s of {}s cycle",
+            input.temperature,
+            input.setpoint,
+            self.state.proportional,
+            self.state.integral,
+            self.state.derivative,
+            on_for.as_secs(),
+            self.tuning.cycle.as_secs(),
+        );
+        Decision {
+            switch_to: (wanted != input.relay_on).then_some(wanted),
+            rule: if wanted { Rule::DutyOn } else { Rule::DutyOff },
+            reason,
+        }
+    }
+
+    fn state(&self) -> ControllerState {
+        ControllerState::Pid(self.state.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tuning() -> PidTuning {
+        PidTuning {
+            kp: 0.5,
+            ki: 0.2,
+            kd: 0.0,
+            cycle: Duration::from_secs(15 * 60),
+            min_pulse: Duration::from_secs(60),
+        }
+    }
+
+    fn t0() -> DateTime<Utc> {
+        "2026-01-10T08:00:00Z".parse().unwrap()
+    }
+
+    fn input(minute: i64, temperature: f64, relay_on: bool) -> ControlInput {
+        ControlInput {
+            temperature,
+            setpoint: 21.0,
+            relay_on,
+            since_last_switch: None,
+            now: t0() + chrono::Duration::minutes(minute),
+        }
+    }
+
+    #[test]
+    fn output_becomes_duty_cycle() {
+        let mut pid = Pid::new(tuning());
+        // Error 1 °C → P = 0.5 → on for half of the 15 min cycle.
+        let d = pid.update(&input(0, 20.0, false));
+        assert_eq!(d.switch_to, Some(true));
+        assert_eq!(d.rule, Rule::DutyOn);
+
+        let ControllerState::Pid(state) = pid.state() else {
+            panic!("not a PID state");
+        };
+        assert_eq!(state.on_for, Duration::from_secs(450));
+
+        assert_eq!(pid.update(&input(7, 20.0, true)).switch_to, None);
+        let d = pid.update(&input(8, 20.0, true));
+        assert_eq!(d.switch_to, Some(false));
+        assert_eq!(d.rule, Rule::DutyOff);
+    }
+
+    #[test]
+    fn output_is_fixed_for_the_cycle() {
+        let mut pid = Pid::new(tuning());
+        pid.update(&input(0, 20.0, false));
+        // Warmer mid-cycle: the on-time of the running cycle does not change.
+        pid.update(&input(5, 22.0, true));
+        let ControllerState::Pid(state) = pid.state() else {
+            panic!("not a PID state");
+        };
+        assert_eq!(state.on_for, Duration::from_secs(450));
+        assert_eq!(state.output, 0.0);
+    }
+
+    #[test]
+    fn short_pulses_are_skipped() {
+        let mut pid = Pid::new(tuning());
+        // Error 0.1 °C → 0.05 × 15 min = 45 s < min pulse.
+        let d = pid.update(&input(0, 20.9, false));
+        assert_eq!(d.switch_to, None);
+        assert_eq!(d.rule, Rule::DutyOff);
+
+        // Error 3 °C → saturated; off-time 0 → on for the whole cycle.
+        let mut pid = Pid::new(tuning());
+        pid.update(&input(0, 18.0, false));
+        let ControllerState::Pid(state) = pid.state() else {
+            panic!("not a PID state");
+        };
+        assert_eq!(state.on_for, tuning().cycle);
+    }
+
+    #[test]
+    fn integral_does_not_wind_up_while_saturated() {
+        let mut pid = Pid::new(tuning());
+        // 5 °C below setpoint for 10 hours: P alone saturates the output.
+        for minute in 0..600 {
+            pid.update(&input(minute, 16.0, true));
+        }
+        let ControllerState::Pid(state) = pid.state() else {
+            panic!("not a PID state");
+        };
+        assert_eq!(state.integral, 0.0);
+
+        // Once at setpoint the output drops immediately.
+        pid.update(&input(600, 21.0, true));
+        let ControllerState::Pid(state) = pid.state() else {
+            panic!("not a PID state");
+        };
+        assert_eq!(state.output, 0.0);
+    }
+
+    #[test]
+    fn integral_removes_steady_state_error() {
+        let mut pid = Pid::new(tuning());
+        // 0.2 °C below setpoint for 5 hours: I = 0.2 × 0.2 × 5 = 0.2.
+        for minute in 0..=300 {
+            pid.update(&input(minute, 20.8, true));
+        }
+        let ControllerState::Pid(state) = pid.state() else {
+            panic!("not a PID state");
+        };
+        assert!((state.integral - 0.2).abs() < 1e-9);
+        assert!((state.output - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn gap_longer_than_a_cycle_skips_integration() {
+        let mut pid = Pid::new(tuning());
+        pid.update(&input(0, 20.8, true));
+        pid.update(&input(120, 20.8, true));
+        let ControllerState::Pid(state) = pid.state() else {
+            panic!("not a PID state");
+        };
+        assert_eq!(state.integral, 0.0);
+    }
+}
@@ -0,0 +1,83 @@
+/// Limits checked before any control decision.
+#[derive(Debug, Clone, Copy)]
+pub struct SafetyLimits {
+    /// Heating is forced on at or below this temperature, °C.
+    pub frost_floor: f64,
+}
+
+/// A safety rule that takes over from the normal control decision.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Safety {
+    /// The device reports a non-zero fault bitmask — send it nothing.
+    Fault(i64),
+    /// The temperature is at or below the frost floor — heating must be on.
+    Frost { temperature: f64, floor: f64 },
+}
+
+impl SafetyLimits {
+    /// Check one thermostat.
+    ///
+    /// - `temperatures`: every temperature known for the thermostat (°C), e.g.
+    ///   its own and its reference probe; the lowest one counts.
+    /// - `fault`: last reported fault bitmask.
+    ///
+    /// A fault wins over frost: a faulty device is not commanded at all.
+    pub fn check(&self, temperatures: &[f64], fault: Option<i64>) -> Option<Safety> {
+        if let Some(mask) = fault.filter(|m| *m != 0) {
+            return Some(Safety::Fault(mask));
+        }
+        let lowest = temperatures.iter().copied().reduce(f64::min)?;
+        (lowest <= self.frost_floor).then_some(Safety::Frost {
+            temperature: lowest,
+            floor: self.frost_floor,
+        })
+    }
+}
+
+/// Refuse a setpoint above the device's own limit (`upper_temp`), in °C.
+/// Without a known limit every setpoint passes.
+pub fn check_setpoint(celsius: f64, max_celsius: Option<f64>) -> Result<(), String> {
+    match max_celsius {
+        Some(max) if celsius > max => Err(format!(
+            "setpoint {celsius:.1} °C above the device limit of {max:.1} °C"
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> SafetyLimits {
+        SafetyLimits { frost_floor: 5.0 }
+    }
+
+    #[test]
+    fn fault_blocks_commands() {
+        assert_eq!(limits().check(&[20.0], Some(4)), Some(Safety::Fault(4)));
+        // Even below the frost floor.
+        assert_eq!(limits().check(&[3.0], Some(1)), Some(Safety::Fault(1)));
+        assert_eq!(limits().check(&[20.0], Some(0)), None);
+    }
+
+    #[test]
+    fn lowest_temperature_below_frost_floor_forces_heating() {
+        assert_eq!(
+            limits().check(&[18.0, 4.5], None),
+            Some(Safety::Frost {
+                temperature: 4.5,
+                floor: 5.0
+            })
+        );
+        assert_eq!(limits().check(&[5.1], None), None);
+        assert_eq!(limits().check(&[], None), None);
+    }
+
+    #[test]
+    fn setpoint_above_device_limit_is_refused() {
+        assert!(check_setpoint(30.0, Some(28.0)).is_err());
+        assert!(check_setpoint(28.0, Some(28.0)).is_ok());
+        assert!(check_setpoint(60.0, None).is_ok());
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, NaiveDateTime, NaiveTime, Timelike, Utc};
+use chrono_tz::Tz;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::models::{HeatingSchedule, HeatingScheduleBlock};
+
+/// A schedule together with its blocks, ready for evaluation.
+#[derive(Debug, Clone)]
+pub struct WeeklySchedule {
+    pub schedule: HeatingSchedule,
+    pub blocks: Vec<HeatingScheduleBlock>,
+}
+
+/// The setpoint a schedule asks for at a given moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduledSetpoint {
+    /// The block in effect, or `None` when the default setpoint applies.
+    pub block_id: Option<Uuid>,
+    /// Setpoint encoded as °C × 100.
+    pub setpoint: i64,
+}
+
+impl WeeklySchedule {
+    /// Evaluate the schedule at `now`, interpreting block times in `tz`.
+    pub fn setpoint_at_utc(&self, now: DateTime<Utc>, tz: Tz) -> ScheduledSetpoint {
+        self.setpoint_at(now.with_timezone(&tz).naive_local())
+    }
+
+    /// Evaluate the schedule at a local wall-clock time.
+    pub fn setpoint_at(&self, local: NaiveDateTime) -> ScheduledSetpoint {
+        let weekday = local.weekday().number_from_monday() as i16;
+        let secs = local.time().num_seconds_from_midnight();
+
+        self.blocks
+            .iter()
+            .find(|b| {
+                b.weekday == weekday
+                    && secs >= b.start_time.num_seconds_from_midnight()
+                    && secs < end_secs(b.end_time)
+            })
+            .map(|b| ScheduledSetpoint {
+                block_id: Some(b.id),
+                setpoint: b.setpoint,
+            })
+            .unwrap_or(ScheduledSetpoint {
+                block_id: None,
+                setpoint: self.schedule.default_setpoint,
+            })
+    }
+}
+
+/// Block end as seconds from midnight, treating `00:00` as end of day.
+fn end_secs(end: NaiveTime) -> u32 {
+    match end.num_seconds_from_midnight() {
+        0 => 24 * 3600,
+        s => s,
+    }
+}
+
+/// Check that every block has `start < end` and that no two blocks on the
+/// same weekday overlap.
+///
+/// Each tuple is `(iso_weekday, start, end)`; `end == 00:00` means end of day.
+pub fn validate_blocks(blocks: &[(i16, NaiveTime, NaiveTime)]) -> Result<(), String> {
+    let mut by_day: HashMap<i16, Vec<(u32, u32)>> = HashMap::new();
+    for &(weekday, start, end) in blocks {
+        if !(1..=7).contains(&weekday) {
+            return Err(format!("weekday must be 1..=7, got {weekday}"));
+        }
+        let (s, e) = (start.num_seconds_from_midnight(), end_secs(end));
+        if s >= e {
+            return Err(format!("block {start}–{end} must start before it ends"));
+        }
+        by_day.entry(weekday).or_default().push((s, e));
+    }
+
+    for ranges in by_day.values_mut() {
+        ranges.sort_unstable();
+        if ranges.windows(2).any(|w| w[0].1 > w[1].0) {
+            return Err("blocks on the same day must not overlap".to_owned());
+        }
+    }
+    Ok(())
+}
+
+/// Fetch the blocks of the given schedules, grouped by schedule ID and
+/// ordered by weekday and start time.
+pub async fn fetch_blocks(
+    pool: &PgPool,
+    schedule_ids: &[Uuid],
+) -> sqlx::Result<HashMap<Uuid, Vec<HeatingScheduleBlock>>> {
+    let rows = sqlx::query_as!(
+        HeatingScheduleBlock,
+        r#"
+        SELECT id, schedule_id, weekday, start_time, end_time, setpoint
+        FROM heating_schedule_blocks
+        WHERE schedule_id = ANY($1)
+        ORDER BY schedule_id, weekday, start_time
+        "#,
+        schedule_ids,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut grouped: HashMap<Uuid, Vec<HeatingScheduleBlock>> = HashMap::new();
+    for row in rows {
+        grouped.entry(row.schedule_id).or_default().push(row);
+    }
+    Ok(grouped)
+}
+
+/// Load every enabled schedule with its blocks.
+pub async fn load_enabled(pool: &PgPool) -> sqlx::Result<Vec<WeeklySchedule>> {
+    let schedules = sqlx::query_as!(
+        HeatingSchedule,
+        r#"
+        SELECT id, device_id, name, enabled, default_setpoint, created_at, updated_at
+        FROM heating_schedules
+        WHERE enabled
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let ids: Vec<Uuid> = schedules.iter().map(|s| s.id).collect();
+    let mut blocks = fetch_blocks(pool, &ids).await?;
+
+    Ok(schedules
+        .into_iter()
+        .map(|schedule| WeeklySchedule {
+            blocks: blocks.remove(&schedule.id).unwrap_or_default(),
+            schedule,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone};
+
+    use super::*;
+
+    fn t(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    fn block(
+        weekday: i16,
+        start: NaiveTime,
+        end: NaiveTime,
+        setpoint: i64,
+    ) -> HeatingScheduleBlock {
+        HeatingScheduleBlock {
+            id: Uuid::new_v4(),
+            schedule_id: Uuid::nil(),
+            weekday,
+            start_time: start,
+            end_time: end,
+            setpoint,
+        }
+    }
+
+    fn schedule(blocks: Vec<HeatingScheduleBlock>) -> WeeklySchedule {
+        WeeklySchedule {
+            schedule: HeatingSchedule {
+                id: Uuid::nil(),
+                device_id: "th1".into(),
+                name: "test".into(),
+                enabled: true,
+                default_setpoint: 1800,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            },
+            blocks,
+        }
+    }
+
+    /// 2026-03-02 is a Monday.
+    fn monday_at(h: u32, m: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 3, 2)
+            .unwrap()
+            .and_time(t(h, m))
+    }
+
+    #[test]
+    fn block_applies_inside_its_window() {
+        let morning = block(1, t(6, 0), t(8, 0), 2150);
+        let id = morning.id;
+        let s = schedule(vec![morning]);
+
+        let sp = s.setpoint_at(monday_at(6, 0));
+        assert_eq!(
+            sp,
+            ScheduledSetpoint {
+                block_id: Some(id),
+                setpoint: 2150
+            }
+        );
+        assert_eq!(s.setpoint_at(monday_at(7, 59)).setpoint, 2150);
+    }
+
+    #[test]
+    fn default_applies_outside_blocks_and_on_other_days() {
+        let s = schedule(vec![block(1, t(6, 0), t(8, 0), 2150)]);
+        assert_eq!(s.setpoint_at(monday_at(8, 0)).block_id, None);
+        assert_eq!(s.setpoint_at(monday_at(8, 0)).setpoint, 1800);
+        // Tuesday 07:00
+        let tuesday = monday_at(7, 0) + chrono::Duration::days(1);
+        assert_eq!(s.setpoint_at(tuesday).setpoint, 1800);
+    }
+
+    #[test]
+    fn midnight_end_covers_rest_of_day() {
+        let s = schedule(vec![block(1, t(22, 0), t(0, 0), 1700)]);
+        assert_eq!(s.setpoint_at(monday_at(23, 59)).setpoint, 1700);
+    }
+
+    #[test]
+    fn evaluates_in_local_timezone() {
+        let s = schedule(vec![block(1, t(7, 0), t(9, 0), 2200)]);
+        let tz: Tz = "Europe/Warsaw".parse().unwrap();
+        // 05:30 UTC on a summer Monday is 07:30 in Warsaw (UTC+2).
+        let now = Utc.with_ymd_and_hms(2026, 6, 1, 5, 30, 0).unwrap();
+        assert_eq!(s.setpoint_at_utc(now, tz).setpoint, 2200);
+        assert_eq!(s.setpoint_at_utc(now, chrono_tz::UTC).setpoint, 1800);
+    }
+
+    #[test]
+    fn validate_accepts_adjacent_blocks() {
+        let blocks = [
+            (1, t(6, 0), t(8, 0)),
+            (1, t(8, 0), t(0, 0)),
+            (2, t(6, 0), t(8, 0)),
+        ];
+        assert!(validate_blocks(&blocks).is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_overlap() {
+        let blocks = [(1, t(6, 0), t(8, 0)), (1, t(7, 0), t(9, 0))];
+        assert!(validate_blocks(&blocks).unwrap_err().contains("overlap"));
+    }
+
+    #[test]
+    fn validate_rejects_inverted_block() {
+        let blocks = [(3, t(9, 0), t(8, 0))];
+        assert!(validate_blocks(&blocks).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_bad_weekday() {
+        assert!(validate_blocks(&[(0, t(6, 0), t(8, 0))]).is_err());
+    }
+}
@@ -1,36 +1,164 @@
-use std::time::Duration;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant},
+};
 
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use sqlx::PgPool;
 use tokio::time;
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 
 use crate::{
-    db::models::SensorType,
+    config::{Config, ControllerKind, DeviceType},
+    db::models::{SensorReading, SensorType, ThermostatOverride},
     reading_cache::ReadingCache,
-    tuya::TuyaClient,
+    rooms::{self, Probe, RoomDevices},
+    sensors::service::{decode_scaled, encode_scaled, setpoint_scale},
+    tuya::{
+        models::{Command, DpValue},
+        DeviceBackend, TuyaClient,
+    },
+};
+
+use super::{
+    alerts,
+    audit::{self, NewAction},
+    controller::{self, ControlInput, Controller, ControllerStatus, ControllerStatuses},
+    hysteresis::Hysteresis,
+    overrides,
+    pid::PidTuning,
+    safety::{self, Safety, SafetyLimits},
+    schedule::{self, WeeklySchedule},
+    window::{WindowCause, WindowDetection, WindowEvent, WindowState},
 };
 
-pub struct ControlService {
-    // Intentionally retained for when control logic sends commands to Tuya.
-    #[allow(dead_code)]
-    tuya: TuyaClient,
+/// Tunables for the control loop, built from `Config`.
+#[derive(Debug, Clone)]
+pub struct ControlSettings {
+    pub interval: Duration,
+    pub hysteresis: Hysteresis,
+    /// Controller per thermostat; thermostats not listed use `hysteresis`.
+    pub controllers: HashMap<String, ControllerKind>,
+    pub pid: PidTuning,
+    pub safety: SafetyLimits,
+    /// Timezone in which schedule block times are evaluated.
+    pub timezone: Tz,
+    pub window: WindowDetection,
+    /// Window contact sensors per thermostat, `thermostat_id → [contact_id]`.
+    pub window_contacts: HashMap<String, Vec<String>>,
+    /// External reference reading per thermostat, used when its room has no
+    /// temperature probe.
+    pub reference_sensors: HashMap<String, Probe>,
+    /// Probe readings older than this are ignored.
+    pub reference_max_age: Duration,
+}
+
+impl ControlSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            interval: Duration::from_secs(config.control_interval_secs),
+            hysteresis: Hysteresis {
+                band: config.control_hysteresis_celsius,
+                min_dwell: Duration::from_secs(config.control_min_dwell_secs),
+            },
+            controllers: config.controllers.clone(),
+            pid: PidTuning {
+                kp: config.pid_kp,
+                ki: config.pid_ki,
+                kd: config.pid_kd,
+                cycle: Duration::from_secs(config.pid_cycle_minutes * 60),
+                min_pulse: Duration::from_secs(config.control_min_dwell_secs),
+            },
+            safety: SafetyLimits {
+                frost_floor: config.frost_floor_celsius,
+            },
+            timezone: config.timezone,
+            window: WindowDetection {
+                drop_celsius: config.window_drop_celsius,
+                drop_window: Duration::from_secs(config.window_drop_minutes * 60),
+                timeout: Duration::from_secs(config.window_timeout_minutes * 60),
+            },
+            window_contacts: config.window_contacts.clone(),
+            reference_sensors: config.reference_sensors.clone(),
+            reference_max_age: Duration::from_secs(config.reference_max_age_secs),
+        }
+    }
+}
+
+/// Identifies the schedule slot last applied to a thermostat. A new `temp_set`
+/// is only sent when this changes — i.e. at a block boundary, or when the
+/// schedule itself is edited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AppliedSlot {
+    schedule_id: Uuid,
+    schedule_updated_at: DateTime<Utc>,
+    block_id: Option<Uuid>,
+}
+
+pub struct ControlService<B = TuyaClient> {
+    pool: PgPool,
+    backend: B,
     cache: ReadingCache,
-    interval: Duration,
+    device_ids: HashMap<String, DeviceType>,
+    settings: ControlSettings,
+    /// When this service last switched each thermostat relay — drives the
+    /// minimum dwell time. Lost on restart, which only makes the first
+    /// decision after start-up more permissive.
+    last_switch: HashMap<String, Instant>,
+    /// Schedule slot last applied per thermostat. Empty after a restart, so
+    /// the current slot is re-applied once on start-up.
+    applied_slots: HashMap<String, AppliedSlot>,
+    /// Override last applied per thermostat, by override ID. Empty after a
+    /// restart, so active overrides are re-sent once on start-up.
+    applied_overrides: HashMap<String, Uuid>,
+    /// Window-open detection state per thermostat.
+    windows: HashMap<String, WindowState>,
+    /// Controller per thermostat, created on first use.
+    controllers: HashMap<String, Box<dyn Controller>>,
+    statuses: ControllerStatuses,
 }
 
-impl ControlService {
-    pub fn new(tuya: TuyaClient, cache: ReadingCache, interval_secs: u64) -> Self {
+impl<B: DeviceBackend> ControlService<B> {
+    pub fn new(
+        pool: PgPool,
+        backend: B,
+        cache: ReadingCache,
+        device_ids: HashMap<String, DeviceType>,
+        settings: ControlSettings,
+    ) -> Self {
         Self {
-            tuya,
+            pool,
+            backend,
             cache,
-            interval: Duration::from_secs(interval_secs),
+            device_ids,
+            settings,
+            last_switch: HashMap::new(),
+            applied_slots: HashMap::new(),
+            applied_overrides: HashMap::new(),
+            windows: HashMap::new(),
+            controllers: HashMap::new(),
+            statuses: ControllerStatuses::new(),
         }
     }
 
+    /// Handle to the latest controller state per thermostat, for the API.
+    pub fn controller_statuses(&self) -> ControllerStatuses {
+        self.statuses.clone()
+    }
+
     /// Runs the control loop indefinitely.
     /// Spawn this via `tokio::spawn`.
-    pub async fn run(self) {
-        info!(interval_secs = self.interval.as_secs(), "Control loop started");
-        let mut ticker = time::interval(self.interval);
+    pub async fn run(mut self) {
+        info!(
+            interval_secs = self.settings.interval.as_secs(),
+            band = self.settings.hysteresis.band,
+            min_dwell_secs = self.settings.hysteresis.min_dwell.as_secs(),
+            timezone = %self.settings.timezone,
+            "Control loop started"
+        );
+        let mut ticker = time::interval(self.settings.interval);
 
         loop {
             ticker.tick().await;
@@ -40,53 +168,963 @@ impl ControlService {
         }
     }
 
-    async fn run_once(&self) -> anyhow::Result<()> {
-        let readings = self.cache.all().await;
+    async fn run_once(&mut self) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let schedules = schedule::load_enabled(&self.pool)
+            .await
+            .unwrap_or_else(|e| {
+                error!(error = %e, "Failed to load heating schedules");
+                Vec::new()
+            });
 
-        if readings.is_empty() {
-            info!("No sensor readings in cache yet; skipping control iteration");
-            return Ok(());
+        // Overrides take precedence: thermostats under an active override are
+        // left out of schedule application.
+        let overridden = match self.apply_overrides(now, &schedules).await {
+            Ok(overridden) => overridden,
+            Err(e) => {
+                // Without knowing which overrides are active, applying
+                // schedules could cancel a boost — skip them this round.
+                error!(error = %e, "Failed to apply thermostat overrides");
+                self.device_ids.keys().cloned().collect()
+            }
+        };
+        self.apply_schedules(now, &schedules, &overridden).await;
+
+        let rooms = rooms::load_by_thermostat(&self.pool)
+            .await
+            .unwrap_or_else(|e| {
+                error!(error = %e, "Failed to load room assignments");
+                HashMap::new()
+            });
+
+        let thermostats: Vec<String> = self
+            .device_ids
+            .iter()
+            .filter(|(_, kind)| **kind == DeviceType::Thermostat)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for device_id in &thermostats {
+            let room = rooms.get(device_id);
+            if let Err(e) = self.control_thermostat(device_id, room).await {
+                error!(device_id = %device_id, error = %e, "Thermostat control failed");
+            }
         }
 
-        // Group by device_id for convenient per-device logic.
-        let mut by_device: std::collections::HashMap<&str, Vec<_>> = std::collections::HashMap::new();
-        for r in &readings {
-            by_device.entry(r.device_id.as_str()).or_default().push(r);
+        Ok(())
+    }
+
+    /// Evaluate the controller of one thermostat and send a `switch` command
+    /// if the relay should change state.
+    ///
+    /// Safety limits come first: a thermostat reporting a fault is not
+    /// commanded at all, and below the frost floor the relay is switched on.
+    /// Otherwise, while a window is open the relay is switched off and the
+    /// controller is not consulted.
+    async fn control_thermostat(
+        &mut self,
+        device_id: &str,
+        room: Option<&RoomDevices>,
+    ) -> anyhow::Result<()> {
+        let mut inputs = BTreeMap::new();
+        for sensor_type in [SensorType::TemperatureSetpoint, SensorType::RelayState] {
+            if let Some(r) = self.cache.get(device_id, sensor_type).await {
+                inputs.insert(sensor_type, r.value);
+            }
+        }
+        let reference = self.reference_temperature(device_id, room).await;
+        if let Some(r) = &reference {
+            inputs.insert(SensorType::Temperature, r.value);
         }
 
-        for (device_id, device_readings) in &by_device {
-            // Look up individual sensor values by type.
-            let temperature = device_readings
-                .iter()
-                .find(|r| r.sensor_type == SensorType::Temperature)
-                .map(|r| r.value as f64 / 100.0);
+        let temperature = inputs
+            .get(&SensorType::Temperature)
+            .map(|v| *v as f64 / 100.0);
+        let setpoint = inputs
+            .get(&SensorType::TemperatureSetpoint)
+            .map(|v| *v as f64 / 100.0);
+        let relay_on = inputs.get(&SensorType::RelayState).map(|v| *v != 0);
 
-            let relay_state = device_readings
-                .iter()
-                .find(|r| r.sensor_type == SensorType::RelayState)
-                .map(|r| r.value != 0);
+        if self
+            .check_safety(device_id, reference.as_ref(), relay_on, &inputs)
+            .await?
+        {
+            return Ok(());
+        }
 
-            info!(
+        let (Some(temperature), Some(setpoint), Some(relay_on)) = (temperature, setpoint, relay_on)
+        else {
+            debug!(
                 device_id = %device_id,
                 temperature = ?temperature,
-                relay_state = ?relay_state,
-                "Control iteration — latest readings"
+                setpoint = ?setpoint,
+                relay_on = ?relay_on,
+                "Incomplete readings in cache; skipping thermostat"
             );
+            return Ok(());
+        };
+
+        if let Some(cause) = self.check_window(device_id, room, reference.as_ref()).await {
+            if !relay_on {
+                return Ok(());
+            }
+            if matches!(cause, WindowCause::Contact(_)) {
+                inputs.insert(SensorType::DoorOpen, 1);
+            }
+            let reason = format!("{cause}; heating paused");
+            return self
+                .switch_relay(device_id, false, &inputs, "window_open", &reason)
+                .await;
+        }
+
+        let input = ControlInput {
+            temperature,
+            setpoint,
+            relay_on,
+            since_last_switch: self.last_switch.get(device_id).map(Instant::elapsed),
+            now: Utc::now(),
+        };
+        let settings = &self.settings;
+        let controller = self
+            .controllers
+            .entry(device_id.to_owned())
+            .or_insert_with(|| {
+                let kind = settings
+                    .controllers
+                    .get(device_id)
+                    .copied()
+                    .unwrap_or_default();
+                controller::build(kind, settings.hysteresis, settings.pid)
+            });
+        let decision = controller.update(&input);
+        self.statuses
+            .set(
+                device_id,
+                ControllerStatus {
+                    state: controller.state(),
+                    temperature,
+                    setpoint,
+                    relay_on,
+                    updated_at: input.now,
+                },
+            )
+            .await;
+
+        info!(
+            device_id = %device_id,
+            temperature,
+            reference = ?reference.as_ref().map(|r| (&r.device_id, r.sensor_type)),
+            setpoint,
+            relay_on,
+            switch_to = ?decision.switch_to,
+            rule = %decision.rule,
+            reason = %decision.reason,
+            "Control decision"
+        );
+
+        let Some(on) = decision.switch_to else {
+            return Ok(());
+        };
+        self.switch_relay(
+            device_id,
+            on,
+            &inputs,
+            decision.rule.as_str(),
+            &decision.reason,
+        )
+        .await
+    }
+
+    /// Apply the safety limits to one thermostat. Returns `true` when they
+    /// took over and the normal control decision must be skipped.
+    async fn check_safety(
+        &mut self,
+        device_id: &str,
+        reference: Option<&SensorReading>,
+        relay_on: Option<bool>,
+        inputs: &BTreeMap<SensorType, i64>,
+    ) -> anyhow::Result<bool> {
+        let own = self.cache.get(device_id, SensorType::Temperature).await;
+        let fault = self
+            .cache
+            .get(device_id, SensorType::Fault)
+            .await
+            .map(|r| r.value);
+        let temperatures: Vec<f64> = own
+            .iter()
+            .chain(reference)
+            .map(|r| r.value as f64 / 100.0)
+            .collect();
+
+        match self.settings.safety.check(&temperatures, fault) {
+            Some(Safety::Fault(mask)) => {
+                let message = format!("fault bitmask {mask:#x}; control suspended");
+                debug!(
+                    device_id = %device_id,
+                    fault = mask,
+                    "Thermostat reports a fault; skipping"
+                );
+                alerts::raise(&self.pool, device_id, alerts::DEVICE_FAULT, &message).await;
+                Ok(true)
+            }
+            Some(Safety::Frost { temperature, floor }) => {
+                if relay_on == Some(true) {
+                    return Ok(true);
+                }
+                let reason = format!(
+                    "temperature {temperature:.2} °C <= frost floor {floor:.2} °C; heating forced on"
+                );
+                self.switch_relay(device_id, true, inputs, "frost_protection", &reason)
+                    .await?;
+                Ok(true)
+            }
+            None => {
+                if fault == Some(0) {
+                    alerts::clear(&self.pool, device_id, alerts::DEVICE_FAULT).await;
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// The temperature a thermostat regulates on: its room's probe, or else the
+    /// probe bound in `reference_sensors`, if that has a reading younger than
+    /// `reference_max_age`; otherwise the thermostat's own `temp_current`.
+    async fn reference_temperature(
+        &self,
+        device_id: &str,
+        room: Option<&RoomDevices>,
+    ) -> Option<SensorReading> {
+        let probe = room
+            .and_then(|r| r.temperature_probe.as_ref())
+            .or_else(|| self.settings.reference_sensors.get(device_id));
+        if let Some(p) = probe {
+            match self.cache.get(&p.device_id, p.channel).await {
+                Some(r) if !is_stale(&r, Utc::now(), self.settings.reference_max_age) => {
+                    return Some(r)
+                }
+                Some(r) => warn!(
+                    device_id = %device_id,
+                    probe = %p.device_id,
+                    channel = %p.channel,
+                    recorded_at = %r.recorded_at,
+                    "Probe reading is stale; using thermostat temperature"
+                ),
+                None => debug!(
+                    device_id = %device_id,
+                    probe = %p.device_id,
+                    channel = %p.channel,
+                    "No reading from probe; using thermostat temperature"
+                ),
+            }
+        }
+        self.cache.get(device_id, SensorType::Temperature).await
+    }
+
+    /// Update the window-open detection of one thermostat from the contact
+    /// sensors of its room and the history of its reference temperature.
+    ///
+    /// Returns the cause while heating should stay paused.
+    async fn check_window(
+        &mut self,
+        device_id: &str,
+        room: Option<&RoomDevices>,
+        reference: Option<&SensorReading>,
+    ) -> Option<WindowCause> {
+        let now = Utc::now();
+        let contacts = self
+            .settings
+            .window_contacts
+            .get(device_id)
+            .into_iter()
+            .flatten()
+            .chain(room.into_iter().flat_map(|r| &r.contact_sensors));
+        let mut open_contacts = Vec::new();
+        for id in contacts {
+            let open = self.cache.get(id, SensorType::DoorOpen).await;
+            if open.is_some_and(|r| r.value != 0) && !open_contacts.contains(id) {
+                open_contacts.push(id.clone());
+            }
+        }
+        // `evaluate` picks the readings within the drop window itself.
+        let temperatures = match reference {
+            Some(r) => {
+                self.cache
+                    .history(&r.device_id, r.sensor_type, DateTime::<Utc>::MIN_UTC)
+                    .await
+            }
+            None => Vec::new(),
+        };
+
+        let state = self.windows.entry(device_id.to_owned()).or_default();
+        match self
+            .settings
+            .window
+            .evaluate(state, now, &open_contacts, &temperatures)
+        {
+            Some(WindowEvent::Opened(cause)) => {
+                info!(device_id = %device_id, cause = %cause, "Window open; pausing heating");
+            }
+            Some(WindowEvent::Closed { reason }) => {
+                info!(device_id = %device_id, reason = %reason, "Window closed; resuming heating");
+            }
+            None => {}
+        }
+        state.open_cause().cloned()
+    }
+
+    /// Send a `switch` command, audit it under `rule`, and remember the switch
+    /// for the minimum dwell time.
+    async fn switch_relay(
+        &mut self,
+        device_id: &str,
+        on: bool,
+        inputs: &BTreeMap<SensorType, i64>,
+        rule: &str,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        let commands = vec![Command {
+            code: "switch".into(),
+            value: DpValue::Bool(on),
+        }];
+        let result = self
+            .backend
+            .send_commands(device_id, commands.clone())
+            .await;
+        audit::record(
+            &self.pool,
+            NewAction {
+                device_id,
+                commands: &commands,
+                inputs,
+                rule,
+                reason,
+            },
+            &result,
+        )
+        .await;
+
+        result?;
+        self.last_switch
+            .insert(device_id.to_owned(), Instant::now());
+        self.cache
+            .set(device_id, SensorType::RelayState, on as i64)
+            .await;
+        info!(device_id = %device_id, on, rule, "Thermostat relay switched");
+        Ok(())
+    }
+
+    /// Hold every thermostat with an active override at its override setpoint,
+    /// and resume normal control for overrides that have expired.
+    ///
+    /// Returns the thermostats that are currently overridden.
+    async fn apply_overrides(
+        &mut self,
+        now: DateTime<Utc>,
+        schedules: &[WeeklySchedule],
+    ) -> anyhow::Result<HashSet<String>> {
+        let mut overridden = HashSet::new();
+
+        for o in overrides::load_all(&self.pool).await? {
+            let device_id = o.device_id.as_str();
+            if self.device_ids.get(device_id) != Some(&DeviceType::Thermostat) {
+                warn!(
+                    device_id = %device_id,
+                    "Override targets a device that is not a configured thermostat; skipping"
+                );
+                continue;
+            }
+
+            if o.expires_at <= now {
+                let scheduled = schedules.iter().any(|s| s.schedule.device_id == device_id);
+                if let Err(e) = self.end_override(&o, scheduled).await {
+                    error!(device_id = %device_id, error = %e, "Failed to end override");
+                    // Still overridden until the previous setpoint is back.
+                    overridden.insert(o.device_id.clone());
+                }
+                continue;
+            }
+
+            overridden.insert(o.device_id.clone());
+            if self.applied_overrides.get(device_id) == Some(&o.id) {
+                continue;
+            }
+            let reason = format!(
+                "override → {:.1} °C until {}",
+                o.setpoint as f64 / 100.0,
+                o.expires_at
+                    .with_timezone(&self.settings.timezone)
+                    .format("%Y-%m-%d %H:%M")
+            );
+            match self
+                .send_setpoint(device_id, o.setpoint, "override", &reason)
+                .await
+            {
+                Ok(()) => {
+                    self.applied_overrides.insert(o.device_id.clone(), o.id);
+                }
+                Err(e) => error!(device_id = %device_id, error = %e, "Failed to apply override"),
+            }
+        }
+
+        Ok(overridden)
+    }
+
+    /// Resume normal control after an override has expired or was cancelled,
+    /// then delete it.
+    ///
+    /// A thermostat with an enabled schedule gets its current slot re-applied
+    /// by the schedule step that follows; otherwise the setpoint from before
+    /// the override is restored.
+    async fn end_override(
+        &mut self,
+        o: &ThermostatOverride,
+        scheduled: bool,
+    ) -> anyhow::Result<()> {
+        let device_id = o.device_id.as_str();
+        self.applied_overrides.remove(device_id);
+
+        if scheduled {
+            self.applied_slots.remove(device_id);
+        } else if let Some(previous) = o.previous_setpoint {
+            let reason = format!(
+                "override ended → restoring {:.1} °C",
+                previous as f64 / 100.0
+            );
+            self.send_setpoint(device_id, previous, "override_ended", &reason)
+                .await?;
+        }
+
+        overrides::delete(&self.pool, o.id).await?;
+        info!(device_id = %device_id, "Override ended; normal control resumed");
+        Ok(())
+    }
+
+    /// Send the scheduled `temp_set` to every thermostat whose enabled
+    /// schedule has moved into a new slot since the last iteration, skipping
+    /// thermostats in `overridden`.
+    async fn apply_schedules(
+        &mut self,
+        now: DateTime<Utc>,
+        schedules: &[WeeklySchedule],
+        overridden: &HashSet<String>,
+    ) {
+        for s in schedules {
+            let device_id = s.schedule.device_id.as_str();
+            if overridden.contains(device_id) {
+                continue;
+            }
+            if self.device_ids.get(device_id) != Some(&DeviceType::Thermostat) {
+                warn!(
+                    device_id = %device_id,
+                    schedule = %s.schedule.name,
+                    "Schedule targets a device that is not a configured thermostat; skipping"
+                );
+                continue;
+            }
+            if let Err(e) = self.apply_schedule(s, now).await {
+                error!(device_id = %device_id, error = %e, "Failed to apply schedule");
+            }
+        }
+    }
 
-            // Control logic goes here.
-            // self.tuya.send_commands(device_id, vec![...]).await?;
-            // Example: thermostat — compare temperature vs setpoint and toggle relay.
-            //   let setpoint = device_readings.iter()
-            //       .find(|r| r.sensor_type == SensorType::TemperatureSetpoint)
-            //       .map(|r| r.value as f64 / 100.0);
-            //   if let (Some(temp), Some(sp)) = (temperature, setpoint) {
-            //       let should_heat = temp < sp;
-            //       self.tuya.send_commands(device_id, vec![
-            //           Command { code: "switch_1".into(), value: should_heat.into() },
-            //       ]).await?;
-            //   }
+    async fn apply_schedule(
+        &mut self,
+        s: &WeeklySchedule,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let device_id = s.schedule.device_id.as_str();
+        let scheduled = s.setpoint_at_utc(now, self.settings.timezone);
+        let slot = AppliedSlot {
+            schedule_id: s.schedule.id,
+            schedule_updated_at: s.schedule.updated_at,
+            block_id: scheduled.block_id,
+        };
+        if self.applied_slots.get(device_id) == Some(&slot) {
+            return Ok(());
         }
 
+        let celsius = scheduled.setpoint as f64 / 100.0;
+        let reason = match scheduled
+            .block_id
+            .and_then(|id| s.blocks.iter().find(|b| b.id == id))
+        {
+            Some(b) => format!(
+                "schedule '{}': weekday {} {}–{} → {celsius:.1} °C",
+                s.schedule.name,
+                b.weekday,
+                b.start_time.format("%H:%M"),
+                b.end_time.format("%H:%M"),
+            ),
+            None => format!(
+                "schedule '{}': outside blocks, default → {celsius:.1} °C",
+                s.schedule.name,
+            ),
+        };
+
+        self.send_setpoint(device_id, scheduled.setpoint, "schedule", &reason)
+            .await?;
+        self.applied_slots.insert(device_id.to_owned(), slot);
         Ok(())
     }
+
+    /// Send `temp_set` for a setpoint encoded as °C × 100, audit it under
+    /// `rule`, and update the cached setpoint on success.
+    async fn send_setpoint(
+        &self,
+        device_id: &str,
+        setpoint: i64,
+        rule: &str,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        if let Some(mask) = self
+            .cache
+            .get(device_id, SensorType::Fault)
+            .await
+            .map(|r| r.value)
+            .filter(|m| *m != 0)
+        {
+            anyhow::bail!("thermostat reports fault bitmask {mask:#x}; setpoint not sent");
+        }
+        let max = self
+            .cache
+            .get(device_id, SensorType::MaxSetpoint)
+            .await
+            .map(|r| r.value as f64 / 100.0);
+        safety::check_setpoint(setpoint as f64 / 100.0, max).map_err(anyhow::Error::msg)?;
+
+        // Without specifications, the default `temp_set` scale applies.
+        let specs = self
+            .backend
+            .device_specs(device_id)
+            .await
+            .unwrap_or_default();
+        let scale = setpoint_scale(&specs);
+        let raw = decode_scaled(setpoint, scale);
+
+        let mut inputs = BTreeMap::new();
+        if let Some(r) = self
+            .cache
+            .get(device_id, SensorType::TemperatureSetpoint)
+            .await
+        {
+            inputs.insert(SensorType::TemperatureSetpoint, r.value);
+        }
+
+        info!(device_id = %device_id, raw, rule, reason = %reason, "Sending setpoint");
+        let commands = vec![Command {
+            code: "temp_set".into(),
+            value: DpValue::Integer(raw),
+        }];
+        let result = self
+            .backend
+            .send_commands(device_id, commands.clone())
+            .await;
+        audit::record(
+            &self.pool,
+            NewAction {
+                device_id,
+                commands: &commands,
+                inputs: &inputs,
+                rule,
+                reason,
+            },
+            &result,
+        )
+        .await;
+        result?;
+
+        self.cache
+            .set(
+                device_id,
+                SensorType::TemperatureSetpoint,
+                encode_scaled(raw, scale),
+            )
+            .await;
+        Ok(())
+    }
+}
+
+fn is_stale(reading: &SensorReading, now: DateTime<Utc>, max_age: Duration) -> bool {
+    (now - reading.recorded_at)
+        .to_std()
+        .is_ok_and(|age| age > max_age)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::control::ControllerState;
+    use crate::tuya::{fake::FakeBackend, models::Specifications};
+
+    fn service(pool: PgPool, backend: &FakeBackend) -> ControlService<FakeBackend> {
+        ControlService::new(
+            pool,
+            backend.clone(),
+            ReadingCache::new(),
+            HashMap::from([("th1".to_owned(), DeviceType::Thermostat)]),
+            ControlSettings {
+                interval: Duration::from_secs(60),
+                hysteresis: Hysteresis {
+                    band: 0.3,
+                    min_dwell: Duration::from_secs(300),
+                },
+                controllers: HashMap::new(),
+                pid: PidTuning {
+                    kp: 0.5,
+                    ki: 0.1,
+                    kd: 0.0,
+                    cycle: Duration::from_secs(15 * 60),
+                    min_pulse: Duration::from_secs(300),
+                },
+                safety: SafetyLimits { frost_floor: 5.0 },
+                timezone: chrono_tz::UTC,
+                window: WindowDetection {
+                    drop_celsius: 1.0,
+                    drop_window: Duration::from_secs(5 * 60),
+                    timeout: Duration::from_secs(30 * 60),
+                },
+                window_contacts: HashMap::from([("th1".to_owned(), vec!["c1".to_owned()])]),
+                reference_sensors: HashMap::from([(
+                    "th1".to_owned(),
+                    Probe {
+                        device_id: "ws1".into(),
+                        channel: SensorType::Sub1Temperature,
+                    },
+                )]),
+                reference_max_age: Duration::from_secs(600),
+            },
+        )
+    }
+
+    /// Raw `temp_set` values sent to th1, in order.
+    fn sent_setpoints(backend: &FakeBackend) -> Vec<i64> {
+        backend
+            .commands()
+            .into_iter()
+            .filter(|(device_id, _)| device_id == "th1")
+            .filter(|(_, commands)| commands[0].code == "temp_set")
+            .map(|(_, commands)| commands[0].value.as_i64().unwrap())
+            .collect()
+    }
+
+    async fn insert_override(pool: &PgPool, setpoint: i64, previous: i64, expires_in_secs: i64) {
+        sqlx::query(
+            "INSERT INTO thermostat_overrides (device_id, setpoint, previous_setpoint, expires_at) \
+             VALUES ('th1', $1, $2, now() + make_interval(secs => $3))",
+        )
+        .bind(setpoint)
+        .bind(previous)
+        .bind(expires_in_secs as f64)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn override_count(pool: &PgPool) -> i64 {
+        sqlx::query_scalar("SELECT count(*) FROM thermostat_overrides")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn active_override_wins_over_schedule_and_is_sent_once(pool: PgPool) {
+        let backend = FakeBackend::new();
+        sqlx::query(
+            "INSERT INTO heating_schedules (device_id, name, default_setpoint) \
+             VALUES ('th1', 'always', 1800)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        insert_override(&pool, 2300, 2000, 3600).await;
+
+        let mut svc = service(pool, &backend);
+        svc.run_once().await.unwrap();
+        svc.run_once().await.unwrap();
+
+        assert_eq!(sent_setpoints(&backend), vec![230]);
+        let sp = svc.cache.get("th1", SensorType::TemperatureSetpoint).await;
+        assert_eq!(sp.unwrap().value, 2300);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn expired_override_restores_previous_setpoint(pool: PgPool) {
+        let backend = FakeBackend::new();
+        // Expired while the service was down.
+        insert_override(&pool, 2300, 2000, -60).await;
+
+        let mut svc = service(pool.clone(), &backend);
+        svc.run_once().await.unwrap();
+
+        assert_eq!(sent_setpoints(&backend), vec![200]);
+        assert_eq!(override_count(&pool).await, 0);
+        let rule: String = sqlx::query_scalar("SELECT rule FROM control_actions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rule, "override_ended");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn expired_override_resumes_schedule(pool: PgPool) {
+        let backend = FakeBackend::new();
+        sqlx::query(
+            "INSERT INTO heating_schedules (device_id, name, default_setpoint) \
+             VALUES ('th1', 'always', 1800)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        insert_override(&pool, 2300, 2000, 3600).await;
+
+        let mut svc = service(pool.clone(), &backend);
+        svc.run_once().await.unwrap();
+        sqlx::query("UPDATE thermostat_overrides SET expires_at = now()")
+            .execute(&pool)
+            .await
+            .unwrap();
+        svc.run_once().await.unwrap();
+
+        assert_eq!(sent_setpoints(&backend), vec![230, 180]);
+        assert_eq!(override_count(&pool).await, 0);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn open_window_contact_switches_heating_off(pool: PgPool) {
+        let backend = FakeBackend::new();
+        let mut svc = service(pool.clone(), &backend);
+        svc.cache.set("th1", SensorType::Temperature, 1800).await;
+        svc.cache
+            .set("th1", SensorType::TemperatureSetpoint, 2100)
+            .await;
+        svc.cache.set("th1", SensorType::RelayState, 1).await;
+        svc.cache.set("c1", SensorType::DoorOpen, 1).await;
+
+        svc.run_once().await.unwrap();
+        // Relay is now off in the cache — nothing more to send while paused.
+        svc.run_once().await.unwrap();
+
+        let switches = backend.commands();
+        assert_eq!(switches.len(), 1);
+        assert_eq!(switches[0].1[0].code, "switch");
+        assert_eq!(switches[0].1[0].value.as_bool(), Some(false));
+
+        let rule: String = sqlx::query_scalar("SELECT rule FROM control_actions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rule, "window_open");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn room_probe_is_the_reference_temperature(pool: PgPool) {
+        let backend = FakeBackend::new();
+        let room: uuid::Uuid =
+            sqlx::query_scalar("INSERT INTO rooms (name) VALUES ('Living') RETURNING id")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        sqlx::query(
+            "INSERT INTO room_devices (room_id, role, device_id, channel) VALUES \
+             ($1, 'thermostat', 'th1', NULL), \
+             ($1, 'temperature_probe', 'ws1', 'sub2_temperature')",
+        )
+        .bind(room)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut svc = service(pool.clone(), &backend);
+        // Warm by the thermostat, cold across the room.
+        svc.cache.set("th1", SensorType::Temperature, 2200).await;
+        svc.cache
+            .set("th1", SensorType::TemperatureSetpoint, 2100)
+            .await;
+        svc.cache.set("th1", SensorType::RelayState, 0).await;
+        svc.cache
+            .set("ws1", SensorType::Sub2Temperature, 1900)
+            .await;
+
+        svc.run_once().await.unwrap();
+
+        let (rule, inputs): (String, Value) =
+            sqlx::query_as("SELECT rule, inputs FROM control_actions")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(rule, "below_band");
+        assert_eq!(inputs["temperature"], 1900);
+    }
+
+    async fn control_inputs(pool: &PgPool) -> Vec<(String, Value)> {
+        sqlx::query_as("SELECT rule, inputs FROM control_actions ORDER BY recorded_at")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn bound_probe_is_the_reference_temperature(pool: PgPool) {
+        let backend = FakeBackend::new();
+        let mut svc = service(pool.clone(), &backend);
+        // Warm at the wall, cold at the bound probe.
+        svc.cache.set("th1", SensorType::Temperature, 2300).await;
+        svc.cache
+            .set("th1", SensorType::TemperatureSetpoint, 2100)
+            .await;
+        svc.cache.set("th1", SensorType::RelayState, 0).await;
+        svc.cache
+            .set("ws1", SensorType::Sub1Temperature, 1900)
+            .await;
+
+        svc.run_once().await.unwrap();
+
+        let actions = control_inputs(&pool).await;
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].0, "below_band");
+        assert_eq!(actions[0].1["temperature"], 1900);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn stale_probe_falls_back_to_thermostat_temperature(pool: PgPool) {
+        let backend = FakeBackend::new();
+        let mut svc = service(pool.clone(), &backend);
+        svc.cache.set("th1", SensorType::Temperature, 1900).await;
+        svc.cache
+            .set("th1", SensorType::TemperatureSetpoint, 2100)
+            .await;
+        svc.cache.set("th1", SensorType::RelayState, 0).await;
+        svc.cache
+            .update(SensorReading {
+                id: Uuid::new_v4(),
+                device_id: "ws1".into(),
+                sensor_type: SensorType::Sub1Temperature,
+                recorded_at: Utc::now() - chrono::Duration::minutes(30),
+                value: 2300,
+            })
+            .await;
+
+        svc.run_once().await.unwrap();
+
+        let actions = control_inputs(&pool).await;
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].0, "below_band");
+        assert_eq!(actions[0].1["temperature"], 1900);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn pid_controller_drives_relay_by_duty_cycle(pool: PgPool) {
+        let backend = FakeBackend::new();
+        let mut svc = service(pool.clone(), &backend);
+        svc.settings
+            .controllers
+            .insert("th1".to_owned(), ControllerKind::Pid);
+        // Error 1 °C → output 0.5 → on for half the cycle.
+        svc.cache.set("th1", SensorType::Temperature, 2000).await;
+        svc.cache
+            .set("th1", SensorType::TemperatureSetpoint, 2100)
+            .await;
+        svc.cache.set("th1", SensorType::RelayState, 0).await;
+
+        svc.run_once().await.unwrap();
+
+        let actions = control_inputs(&pool).await;
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].0, "duty_on");
+
+        let status = svc.controller_statuses().get("th1").await.unwrap();
+        let ControllerState::Pid(state) = status.state else {
+            panic!("expected PID state");
+        };
+        assert_eq!(state.output, 0.5);
+        assert_eq!(state.on_for, Duration::from_secs(450));
+        assert!(!status.relay_on);
+    }
+
+    async fn alerts(pool: &PgPool) -> Vec<(String, bool)> {
+        sqlx::query_as("SELECT kind, cleared_at IS NULL FROM alerts ORDER BY raised_at")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn faulty_thermostat_is_not_commanded_and_raises_alert(pool: PgPool) {
+        let backend = FakeBackend::new();
+        let mut svc = service(pool.clone(), &backend);
+        svc.cache.set("th1", SensorType::Temperature, 400).await;
+        svc.cache
+            .set("th1", SensorType::TemperatureSetpoint, 2100)
+            .await;
+        svc.cache.set("th1", SensorType::RelayState, 0).await;
+        svc.cache.set("th1", SensorType::Fault, 4).await;
+
+        svc.run_once().await.unwrap();
+        svc.run_once().await.unwrap();
+
+        assert!(control_inputs(&pool).await.is_empty());
+        assert_eq!(alerts(&pool).await, vec![("device_fault".to_owned(), true)]);
+
+        svc.cache.set("th1", SensorType::Fault, 0).await;
+        svc.cache.set("th1", SensorType::Temperature, 2100).await;
+        svc.run_once().await.unwrap();
+        assert_eq!(
+            alerts(&pool).await,
+            vec![("device_fault".to_owned(), false)]
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn frost_floor_forces_heating_even_with_window_open(pool: PgPool) {
+        let backend = FakeBackend::new();
+        let mut svc = service(pool.clone(), &backend);
+        svc.cache.set("th1", SensorType::Temperature, 450).await;
+        svc.cache
+            .set("th1", SensorType::TemperatureSetpoint, 500)
+            .await;
+        svc.cache.set("th1", SensorType::RelayState, 0).await;
+        svc.cache.set("c1", SensorType::DoorOpen, 1).await;
+
+        svc.run_once().await.unwrap();
+
+        let actions = control_inputs(&pool).await;
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].0, "frost_protection");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn setpoint_above_device_limit_is_not_sent(pool: PgPool) {
+        let backend = FakeBackend::new();
+        let mut svc = service(pool.clone(), &backend);
+        svc.cache.set("th1", SensorType::MaxSetpoint, 2800).await;
+        insert_override(&pool, 3000, 2000, 3600).await;
+
+        svc.run_once().await.unwrap();
+
+        assert!(sent_setpoints(&backend).is_empty());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn setpoint_is_sent_in_the_temp_set_scale_of_the_spec(pool: PgPool) {
+        let backend = FakeBackend::new();
+        let specs: Specifications = serde_json::from_value(serde_json::json!({
+            "category": "wk",
+            "status": [{"code": "temp_set", "type": "Integer",
+                        "values": "{\"unit\":\"℃\",\"scale\":2}"}]
+        }))
+        .unwrap();
+        backend.set_specs("th1", specs.into());
+        insert_override(&pool, 2350, 2000, 3600).await;
+
+        let mut svc = service(pool, &backend);
+        svc.run_once().await.unwrap();
+
+        assert_eq!(sent_setpoints(&backend), vec![2350]);
+        let sp = svc.cache.get("th1", SensorType::TemperatureSetpoint).await;
+        assert_eq!(sp.unwrap().value, 2350);
+    }
 }
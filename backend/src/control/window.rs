@@ -0,0 +1,285 @@
+use std::{fmt, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+use crate::db::models::SensorReading;
+
+/// Parameters of window-open detection.
+#[derive(Debug, Clone, Copy)]
+pub struct WindowDetection {
+    /// Temperature drop in °C within `drop_window` that is treated as an open
+    /// window. `0` disables the rule.
+    pub drop_celsius: f64,
+    pub drop_window: Duration,
+    /// Heating resumes after this long even if the window still appears to be
+    /// open — protects against a forgotten window or a stuck contact.
+    pub timeout: Duration,
+}
+
+/// Why a window is considered open.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowCause {
+    /// A contact sensor reports open.
+    Contact(String),
+    /// The temperature fell by this many °C within the drop window.
+    TemperatureDrop(f64),
+}
+
+impl fmt::Display for WindowCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowCause::Contact(id) => write!(f, "contact {id} open"),
+            WindowCause::TemperatureDrop(drop) => write!(f, "temperature dropped {drop:.2} °C"),
+        }
+    }
+}
+
+/// A change in the window state of one thermostat.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowEvent {
+    /// Heating should pause.
+    Opened(WindowCause),
+    /// Heating may resume.
+    Closed { reason: String },
+}
+
+/// Window detection state of one thermostat.
+#[derive(Debug, Default)]
+pub struct WindowState {
+    open: Option<(DateTime<Utc>, WindowCause)>,
+    /// A pause ended by timeout while a contact was still open; contacts are
+    /// ignored until they all close.
+    contact_latched: bool,
+    /// Temperatures recorded before this instant are ignored by the drop rule,
+    /// so the drop that caused a pause can not cause the next one.
+    rearm_after: Option<DateTime<Utc>>,
+}
+
+impl WindowState {
+    /// The reason heating is paused, or `None` if it is not.
+    pub fn open_cause(&self) -> Option<&WindowCause> {
+        self.open.as_ref().map(|(_, cause)| cause)
+    }
+}
+
+impl WindowDetection {
+    /// Update `state` from the current inputs.
+    ///
+    /// - `open_contacts`: contact sensors of the thermostat's room that report open.
+    /// - `temperatures`: recent temperature readings, oldest first; only those
+    ///   within `drop_window` of `now` are considered.
+    ///
+    /// Returns the transition, if any.
+    pub fn evaluate(
+        &self,
+        state: &mut WindowState,
+        now: DateTime<Utc>,
+        open_contacts: &[String],
+        temperatures: &[SensorReading],
+    ) -> Option<WindowEvent> {
+        if open_contacts.is_empty() {
+            state.contact_latched = false;
+        }
+
+        if let Some((since, cause)) = &state.open {
+            let reason = match cause {
+                WindowCause::Contact(_) if open_contacts.is_empty() => "window closed".to_owned(),
+                _ if now - *since >= to_chrono(self.timeout) => format!(
+                    "{cause} for {} min; timeout reached",
+                    (now - *since).num_minutes()
+                ),
+                _ => return None,
+            };
+            state.open = None;
+            state.contact_latched = !open_contacts.is_empty();
+            state.rearm_after = Some(now);
+            return Some(WindowEvent::Closed { reason });
+        }
+
+        let cause = if let Some(id) = open_contacts.first().filter(|_| !state.contact_latched) {
+            WindowCause::Contact(id.clone())
+        } else {
+            let from = now
+                .checked_sub_signed(to_chrono(self.drop_window))
+                .unwrap_or(DateTime::<Utc>::MIN_UTC);
+            let from = state.rearm_after.map_or(from, |t| t.max(from));
+            let recent: Vec<_> = temperatures
+                .iter()
+                .filter(|r| r.recorded_at >= from)
+                .cloned()
+                .collect();
+            match temperature_drop(&recent) {
+                Some(drop) if self.drop_celsius > 0.0 && drop >= self.drop_celsius => {
+                    WindowCause::TemperatureDrop(drop)
+                }
+                _ => return None,
+            }
+        };
+
+        state.open = Some((now, cause.clone()));
+        Some(WindowEvent::Opened(cause))
+    }
+}
+
+/// Drop in °C from the highest reading to the latest one, or `None` with
+/// fewer than two readings. Values use the stored encoding (°C × 100).
+pub fn temperature_drop(readings: &[SensorReading]) -> Option<f64> {
+    let [.., _, latest] = readings else {
+        return None;
+    };
+    let peak = readings.iter().map(|r| r.value).max()?;
+    Some((peak - latest.value) as f64 / 100.0)
+}
+
+fn to_chrono(d: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(d).unwrap_or(chrono::Duration::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::db::models::SensorType;
+
+    fn detection() -> WindowDetection {
+        WindowDetection {
+            drop_celsius: 1.0,
+            drop_window: Duration::from_secs(5 * 60),
+            timeout: Duration::from_secs(30 * 60),
+        }
+    }
+
+    fn t0() -> DateTime<Utc> {
+        "2026-01-10T08:00:00Z".parse().unwrap()
+    }
+
+    fn mins(m: i64) -> chrono::Duration {
+        chrono::Duration::minutes(m)
+    }
+
+    /// Readings as `(minutes after t0, °C × 100)`.
+    fn temps(points: &[(i64, i64)]) -> Vec<SensorReading> {
+        points
+            .iter()
+            .map(|&(m, value)| SensorReading {
+                id: Uuid::new_v4(),
+                device_id: "th1".into(),
+                sensor_type: SensorType::Temperature,
+                recorded_at: t0() + mins(m),
+                value,
+            })
+            .collect()
+    }
+
+    fn contacts(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn temperature_drop_is_peak_minus_latest() {
+        assert_eq!(
+            temperature_drop(&temps(&[(0, 2100), (1, 2150), (2, 2000)])),
+            Some(1.5)
+        );
+        assert_eq!(temperature_drop(&temps(&[(0, 2000), (1, 2100)])), Some(0.0));
+        assert_eq!(temperature_drop(&temps(&[(0, 2000)])), None);
+    }
+
+    #[test]
+    fn open_contact_pauses_until_closed() {
+        let d = detection();
+        let mut s = WindowState::default();
+
+        let e = d.evaluate(&mut s, t0(), &contacts(&["c1"]), &[]);
+        assert_eq!(
+            e,
+            Some(WindowEvent::Opened(WindowCause::Contact("c1".into())))
+        );
+        assert!(s.open_cause().is_some());
+
+        assert_eq!(
+            d.evaluate(&mut s, t0() + mins(5), &contacts(&["c1"]), &[]),
+            None
+        );
+
+        let e = d.evaluate(&mut s, t0() + mins(6), &[], &[]);
+        assert!(matches!(e, Some(WindowEvent::Closed { .. })));
+        assert!(s.open_cause().is_none());
+    }
+
+    #[test]
+    fn timeout_resumes_and_latches_until_contact_closes() {
+        let d = detection();
+        let mut s = WindowState::default();
+        let open = contacts(&["c1"]);
+
+        d.evaluate(&mut s, t0(), &open, &[]);
+        let e = d.evaluate(&mut s, t0() + mins(30), &open, &[]);
+        assert!(matches!(e, Some(WindowEvent::Closed { reason }) if reason.contains("timeout")));
+
+        // Still open, but latched — no new pause.
+        assert_eq!(d.evaluate(&mut s, t0() + mins(31), &open, &[]), None);
+        assert!(s.open_cause().is_none());
+
+        // Closed, then opened again — pauses again.
+        assert_eq!(d.evaluate(&mut s, t0() + mins(40), &[], &[]), None);
+        let e = d.evaluate(&mut s, t0() + mins(41), &open, &[]);
+        assert!(matches!(e, Some(WindowEvent::Opened(_))));
+    }
+
+    #[test]
+    fn sharp_temperature_drop_pauses() {
+        let d = detection();
+        let mut s = WindowState::default();
+        let readings = temps(&[(0, 2100), (2, 2050), (4, 1980)]);
+
+        let e = d.evaluate(&mut s, t0() + mins(4), &[], &readings);
+        assert!(matches!(
+            e,
+            Some(WindowEvent::Opened(WindowCause::TemperatureDrop(drop))) if (drop - 1.2).abs() < 1e-9
+        ));
+    }
+
+    #[test]
+    fn slow_or_small_drop_is_ignored() {
+        let d = detection();
+        let mut s = WindowState::default();
+
+        // 1.5 °C, but the peak is outside the 5 min window.
+        let slow = temps(&[(0, 2150), (6, 2050), (10, 2000)]);
+        assert_eq!(d.evaluate(&mut s, t0() + mins(10), &[], &slow), None);
+
+        let small = temps(&[(0, 2100), (4, 2020)]);
+        assert_eq!(d.evaluate(&mut s, t0() + mins(4), &[], &small), None);
+    }
+
+    #[test]
+    fn zero_threshold_disables_drop_rule() {
+        let d = WindowDetection {
+            drop_celsius: 0.0,
+            ..detection()
+        };
+        let mut s = WindowState::default();
+        let readings = temps(&[(0, 2100), (4, 1500)]);
+        assert_eq!(d.evaluate(&mut s, t0() + mins(4), &[], &readings), None);
+    }
+
+    #[test]
+    fn drop_pause_ends_at_timeout_and_does_not_retrigger_on_old_readings() {
+        let d = WindowDetection {
+            drop_window: Duration::from_secs(60 * 60),
+            ..detection()
+        };
+        let mut s = WindowState::default();
+        let readings = temps(&[(0, 2100), (4, 1950)]);
+
+        assert!(d.evaluate(&mut s, t0() + mins(4), &[], &readings).is_some());
+        let e = d.evaluate(&mut s, t0() + mins(34), &[], &readings);
+        assert!(matches!(e, Some(WindowEvent::Closed { .. })));
+
+        // The old drop is still inside the 60 min window, but predates the resume.
+        let later = temps(&[(0, 2100), (4, 1950), (40, 1960)]);
+        assert_eq!(d.evaluate(&mut s, t0() + mins(40), &[], &later), None);
+    }
+}
@@ -1,18 +1,34 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
 
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use chrono::{DateTime, NaiveTime, Utc};
+use serde::{de::IntoDeserializer, Deserialize, Serialize};
+use sqlx::{types::Json, FromRow};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::energy::tariff::TariffPlan;
+
 /// Mirrors the `sensor_type` Postgres enum.
 ///
 /// Value encoding convention (stored as `BIGINT`):
 /// - Numeric readings: `round(real_value * 100.0) as i64`
 ///   e.g. 21.45 °C → 2145, 60.5 % → 6050, 1234.56 W → 123456
 /// - Boolean readings: `false` → 0, `true` → 1
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema)]
+/// - Bitmask readings (`Fault`): stored as reported
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    ToSchema,
+)]
 #[sqlx(type_name = "sensor_type", rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 pub enum SensorType {
@@ -31,6 +47,26 @@ pub enum SensorType {
     Sub2Humidity,
     Sub3Temperature,
     Sub3Humidity,
+
+    // Thermostat safety
+    Fault,
+    /// Highest setpoint the device accepts (`upper_temp`).
+    MaxSetpoint,
+
+    // Energy meter phases, decoded from `phase_a/b/c`: V, mA and W
+    PhaseAVoltage,
+    PhaseACurrent,
+    PhaseAPower,
+    PhaseBVoltage,
+    PhaseBCurrent,
+    PhaseBPower,
+    PhaseCVoltage,
+    PhaseCCurrent,
+    PhaseCPower,
+
+    // Energy meter counters, kWh
+    ForwardEnergy,
+    ReverseEnergy,
 }
 
 impl fmt::Display for SensorType {
@@ -48,11 +84,33 @@ impl fmt::Display for SensorType {
             SensorType::Sub2Humidity => "sub2_humidity",
             SensorType::Sub3Temperature => "sub3_temperature",
             SensorType::Sub3Humidity => "sub3_humidity",
+            SensorType::Fault => "fault",
+            SensorType::MaxSetpoint => "max_setpoint",
+            SensorType::PhaseAVoltage => "phase_a_voltage",
+            SensorType::PhaseACurrent => "phase_a_current",
+            SensorType::PhaseAPower => "phase_a_power",
+            SensorType::PhaseBVoltage => "phase_b_voltage",
+            SensorType::PhaseBCurrent => "phase_b_current",
+            SensorType::PhaseBPower => "phase_b_power",
+            SensorType::PhaseCVoltage => "phase_c_voltage",
+            SensorType::PhaseCCurrent => "phase_c_current",
+            SensorType::PhaseCPower => "phase_c_power",
+            SensorType::ForwardEnergy => "forward_energy",
+            SensorType::ReverseEnergy => "reverse_energy",
         };
         f.write_str(s)
     }
 }
 
+/// Parses the snake_case name used by `Display` and the API.
+impl FromStr for SensorType {
+    type Err = serde::de::value::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(s.into_deserializer())
+    }
+}
+
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct SensorReading {
     pub id: Uuid,
@@ -62,3 +120,129 @@ pub struct SensorReading {
     /// Encoded integer value — see `SensorType` for convention.
     pub value: i64,
 }
+
+/// One row of the `control_actions` audit table — a command the control loop
+/// sent to a device, together with the inputs and rule behind it.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct ControlAction {
+    pub id: Uuid,
+    pub device_id: String,
+    pub recorded_at: DateTime<Utc>,
+    /// JSON array of `{code, value}` commands sent to Tuya.
+    pub commands: serde_json::Value,
+    /// JSON object of input readings keyed by `sensor_type` (encoded values).
+    pub inputs: serde_json::Value,
+    pub rule: String,
+    pub reason: String,
+    pub success: bool,
+    pub tid: Option<String>,
+    pub error: Option<String>,
+}
+
+/// A weekly heating schedule for one thermostat (`heating_schedules`).
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct HeatingSchedule {
+    pub id: Uuid,
+    pub device_id: String,
+    pub name: String,
+    pub enabled: bool,
+    /// Setpoint outside every block, encoded as °C × 100.
+    pub default_setpoint: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One time block of a [`HeatingSchedule`] (`heating_schedule_blocks`).
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct HeatingScheduleBlock {
+    pub id: Uuid,
+    pub schedule_id: Uuid,
+    /// ISO weekday: 1 = Monday … 7 = Sunday.
+    pub weekday: i16,
+    /// Local start time (inclusive).
+    pub start_time: NaiveTime,
+    /// Local end time (exclusive); `00:00` means end of day.
+    pub end_time: NaiveTime,
+    /// Setpoint encoded as °C × 100.
+    pub setpoint: i64,
+}
+
+/// A temporary setpoint override for one thermostat (`thermostat_overrides`).
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct ThermostatOverride {
+    pub id: Uuid,
+    pub device_id: String,
+    /// Override setpoint, encoded as °C × 100.
+    pub setpoint: i64,
+    /// Setpoint before the override (°C × 100), restored on expiry when the
+    /// thermostat has no schedule.
+    pub previous_setpoint: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Mirrors the `room_role` Postgres enum: what a device does in its room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "room_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RoomRole {
+    Thermostat,
+    TemperatureProbe,
+    EnergyMeter,
+    ContactSensor,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Room {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One device assignment in `room_devices`.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct RoomDevice {
+    pub room_id: Uuid,
+    pub role: RoomRole,
+    pub device_id: String,
+    /// Reading used from a temperature probe, e.g. `Sub2Temperature`.
+    pub channel: Option<SensorType>,
+}
+
+/// One row of the `alerts` table — a condition raised by the control loop,
+/// e.g. a thermostat fault.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Alert {
+    pub id: Uuid,
+    pub device_id: String,
+    pub kind: String,
+    pub message: String,
+    pub raised_at: DateTime<Utc>,
+    /// `None` while the alert is active.
+    pub cleared_at: Option<DateTime<Utc>>,
+}
+
+/// One row of the `device_availability` table — a device going online or
+/// offline.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct AvailabilityTransition {
+    pub id: Uuid,
+    pub device_id: String,
+    pub online: bool,
+    pub reason: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// An electricity tariff of an energy meter (`tariffs`), applying in
+/// `[valid_from, valid_until)`.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Tariff {
+    pub id: Uuid,
+    pub device_id: String,
+    pub name: String,
+    pub valid_from: DateTime<Utc>,
+    /// `None` while the tariff is current.
+    pub valid_until: Option<DateTime<Utc>>,
+    pub plan: Json<TariffPlan>,
+    pub created_at: DateTime<Utc>,
+}
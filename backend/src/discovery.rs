@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use tracing::{debug, info};
+
+use crate::{config::DeviceType, tuya::models::TuyaDevice};
+
+/// Add discovered devices to the configured `devices`.
+///
+/// Devices whose category has no [`DeviceType`] are skipped, and configured
+/// entries are kept as they are, so `TUYA_DEVICE_IDS` can still override the
+/// type of a discovered device. Returns the number of devices added.
+pub fn register(devices: &mut HashMap<String, DeviceType>, discovered: &[TuyaDevice]) -> usize {
+    let mut added = 0;
+    for device in discovered {
+        let Some(kind) = DeviceType::from_category(&device.category) else {
+            debug!(device_id = %device.id, category = %device.category, "Unsupported category");
+            continue;
+        };
+        if devices.contains_key(&device.id) {
+            continue;
+        }
+        info!(device_id = %device.id, name = %device.name, kind = %kind, "Registering device");
+        devices.insert(device.id.clone(), kind);
+        added += 1;
+    }
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: &str, category: &str) -> TuyaDevice {
+        TuyaDevice {
+            id: id.to_owned(),
+            name: format!("{id} name"),
+            category: category.to_owned(),
+            product_id: "p".to_owned(),
+            product_name: None,
+            online: true,
+        }
+    }
+
+    #[test]
+    fn known_categories_are_registered() {
+        let mut devices = HashMap::new();
+        let added = register(
+            &mut devices,
+            &[
+                device("th1", "wk"),
+                device("c1", "mcs"),
+                device("lamp", "dj"),
+            ],
+        );
+        assert_eq!(added, 2);
+        assert_eq!(devices["th1"], DeviceType::Thermostat);
+        assert_eq!(devices["c1"], DeviceType::ContactSensor);
+        assert!(!devices.contains_key("lamp"));
+    }
+
+    #[test]
+    fn configured_devices_take_precedence() {
+        let mut devices = HashMap::from([("em1".to_owned(), DeviceType::EnergyMeter)]);
+        let added = register(&mut devices, &[device("em1", "wsdcg")]);
+        assert_eq!(added, 0);
+        assert_eq!(devices["em1"], DeviceType::EnergyMeter);
+    }
+}
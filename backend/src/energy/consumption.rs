@@ -0,0 +1,261 @@
+//! Energy consumption from an energy meter's cumulative counters
+//! (`forward_energy`, `reverse_energy`), stored as kWh × 100.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+use crate::{db::models::SensorType, sensors::service::encode_scaled, tuya::models::DeviceSpecs};
+
+/// Length of the periods consumption is summed over, in local time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Hour,
+    #[default]
+    Day,
+    Month,
+}
+
+impl Granularity {
+    /// Time covered when a request gives no start: a day of hours, 30 days,
+    /// or a year of months.
+    pub fn default_span(self) -> Duration {
+        match self {
+            Granularity::Hour => Duration::days(1),
+            Granularity::Day => Duration::days(30),
+            Granularity::Month => Duration::days(365),
+        }
+    }
+
+    /// Start of the period `at` falls in, in `tz`.
+    pub fn period_start(self, at: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+        let local = at.with_timezone(&tz);
+        let date = match self {
+            // Whole hours of the local offset, so a repeated hour at the end
+            // of summer time is a period of its own.
+            Granularity::Hour => {
+                let into_hour =
+                    Duration::seconds(local.minute() as i64 * 60 + local.second() as i64)
+                        + Duration::nanoseconds(local.nanosecond() as i64);
+                return at - into_hour;
+            }
+            Granularity::Day => local.date_naive(),
+            Granularity::Month => local.date_naive().with_day(1).expect("day 1 exists"),
+        };
+        local_midnight(date, tz)
+    }
+}
+
+/// First instant of `date` in `tz`, even where the day starts after a
+/// daylight saving gap.
+fn local_midnight(date: NaiveDate, tz: Tz) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("midnight exists");
+    (0..24)
+        .find_map(|h| {
+            tz.from_local_datetime(&(midnight + Duration::hours(h)))
+                .earliest()
+        })
+        .expect("every day has a local hour")
+        .with_timezone(&Utc)
+}
+
+/// Scale of the counter DPs when their specification has none, as stored by
+/// the sensor service.
+const COUNTER_SCALE: u32 = 2;
+
+/// Part of the counter range a drop must start from to be taken for a
+/// rollover: the top tenth.
+const ROLLOVER_ZONE: (i64, i64) = (9, 10);
+
+/// Stored value at which the counter `code` rolls over to zero, from the
+/// maximum in its DP specification. `default_scale` is the scale the
+/// counter is stored with when the specification has none.
+pub fn counter_wrap(specs: &DeviceSpecs, code: &str, default_scale: u32) -> Option<i64> {
+    let max = specs.status.get(code)?.values.max?;
+    Some(encode_scaled(
+        max + 1,
+        specs.scale(code).unwrap_or(default_scale),
+    ))
+}
+
+/// Energy used between consecutive counter readings, at the time of the
+/// later one. `readings` are `(recorded_at, value)` in time order.
+///
+/// A counter that goes down either rolled over at `wrap` or was reset (e.g.
+/// with `energy_reset`) and counts from zero again. It is taken for a
+/// rollover only if `wrap` is known and the previous reading was in the top
+/// tenth of the range; after a reset, the new reading is what was used since.
+pub fn deltas(readings: &[(DateTime<Utc>, i64)], wrap: Option<i64>) -> Vec<(DateTime<Utc>, i64)> {
+    readings
+        .windows(2)
+        .map(|pair| {
+            let ((_, before), (at, after)) = (pair[0], pair[1]);
+            let used = if after >= before {
+                after - before
+            } else {
+                match wrap {
+                    Some(wrap) if before * ROLLOVER_ZONE.1 >= wrap * ROLLOVER_ZONE.0 => {
+                        wrap - before + after
+                    }
+                    _ => after,
+                }
+            };
+            (at, used)
+        })
+        .collect()
+}
+
+/// Sum `deltas` per period, in time order.
+pub fn per_period(
+    deltas: &[(DateTime<Utc>, i64)],
+    granularity: Granularity,
+    tz: Tz,
+) -> BTreeMap<DateTime<Utc>, i64> {
+    let mut periods = BTreeMap::new();
+    for &(at, used) in deltas {
+        *periods.entry(granularity.period_start(at, tz)).or_default() += used;
+    }
+    periods
+}
+
+/// DP a counter is read from.
+fn counter_code(counter: SensorType) -> Option<&'static str> {
+    match counter {
+        SensorType::ForwardEnergy => Some("total_forward_energy"),
+        SensorType::ReverseEnergy => Some("reverse_energy_total"),
+        _ => None,
+    }
+}
+
+/// Energy used in `[from, to)` according to `counter` of `device_id`, as
+/// [`deltas`] in kWh × 100. `specs` give the counter's range.
+pub async fn counter_deltas(
+    pool: &PgPool,
+    device_id: &str,
+    counter: SensorType,
+    specs: &DeviceSpecs,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<(DateTime<Utc>, i64)>, sqlx::Error> {
+    let readings = counter_readings(pool, device_id, counter, from, to).await?;
+    let wrap = counter_code(counter).and_then(|code| counter_wrap(specs, code, COUNTER_SCALE));
+    Ok(deltas(&readings, wrap))
+}
+
+/// Readings of a counter in `[from, to)`, in time order, preceded by the
+/// last one before `from` so the first delta is known.
+async fn counter_readings(
+    pool: &PgPool,
+    device_id: &str,
+    counter: SensorType,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<(DateTime<Utc>, i64)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT recorded_at, value
+        FROM sensor_readings
+        WHERE device_id   = $1
+          AND sensor_type = $2
+          AND recorded_at >= COALESCE(
+              (SELECT max(recorded_at)
+               FROM sensor_readings
+               WHERE device_id = $1 AND sensor_type = $2 AND recorded_at < $3),
+              $3)
+          AND recorded_at < $4
+        ORDER BY recorded_at
+        "#,
+        device_id,
+        counter as SensorType,
+        from,
+        to,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.recorded_at, r.value)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        rfc3339.parse().unwrap()
+    }
+
+    #[test]
+    fn drops_are_resets_unless_near_the_wrap() {
+        let t = |m| at("2026-10-17T10:00:00Z") + Duration::minutes(m);
+        let readings = [
+            (t(0), 9_500),
+            (t(1), 9_700),
+            // Rolled over at 10 000.
+            (t(2), 100),
+            (t(3), 300),
+            // Reset.
+            (t(4), 50),
+        ];
+        let used: Vec<_> = deltas(&readings, Some(10_000))
+            .into_iter()
+            .map(|(_, u)| u)
+            .collect();
+        assert_eq!(used, [200, 400, 200, 50]);
+
+        // Without a known range, every drop is a reset.
+        let used: Vec<_> = deltas(&readings, None)
+            .into_iter()
+            .map(|(_, u)| u)
+            .collect();
+        assert_eq!(used, [200, 100, 200, 50]);
+    }
+
+    #[test]
+    fn periods_follow_local_time() {
+        let tz: Tz = "Europe/Warsaw".parse().unwrap();
+        // 23:30 UTC is 00:30 the next day in Warsaw (UTC+1 in winter).
+        let late = at("2026-11-30T23:30:00Z");
+        assert_eq!(
+            Granularity::Hour.period_start(late, tz),
+            at("2026-11-30T23:00:00Z")
+        );
+        assert_eq!(
+            Granularity::Day.period_start(late, tz),
+            at("2026-11-30T23:00:00Z")
+        );
+        assert_eq!(
+            Granularity::Month.period_start(late, tz),
+            at("2026-11-30T23:00:00Z")
+        );
+
+        // The repeated hour at the end of summer time is its own period.
+        let first = at("2026-10-25T00:30:00Z");
+        let second = at("2026-10-25T01:30:00Z");
+        let hours = per_period(&[(first, 1), (second, 2)], Granularity::Hour, tz);
+        assert_eq!(hours.into_values().collect::<Vec<_>>(), [1, 2]);
+        let days = per_period(&[(first, 1), (second, 2)], Granularity::Day, tz);
+        assert_eq!(days, BTreeMap::from([(at("2026-10-24T22:00:00Z"), 3)]));
+    }
+
+    #[test]
+    fn wrap_comes_from_the_counter_specification() {
+        let specs: DeviceSpecs =
+            serde_json::from_value::<crate::tuya::models::Specifications>(serde_json::json!({
+                "category": "zndb",
+                "functions": [],
+                "status": [{"code": "total_forward_energy", "type": "Integer",
+                            "values": "{\"unit\":\"kW·h\",\"min\":0,\"max\":99999999,\"scale\":2}"}]
+            }))
+            .unwrap()
+            .into();
+        assert_eq!(
+            counter_wrap(&specs, "total_forward_energy", 2),
+            Some(100_000_000)
+        );
+        assert_eq!(counter_wrap(&specs, "reverse_energy_total", 2), None);
+    }
+}
@@ -0,0 +1,5 @@
+pub mod consumption;
+pub mod tariff;
+
+pub use consumption::Granularity;
+pub use tariff::TariffPlan;
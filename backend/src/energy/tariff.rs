@@ -0,0 +1,289 @@
+//! Electricity tariffs of energy meters and the cost of energy used under
+//! them.
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::db::models::Tariff;
+
+use super::Granularity;
+
+/// How energy is priced. Prices are per kWh.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TariffPlan {
+    /// One price at all times.
+    Flat { price_per_kwh: f64 },
+    /// The peak price within the peak hours, the off-peak price otherwise.
+    TimeOfUse {
+        peak_price_per_kwh: f64,
+        off_peak_price_per_kwh: f64,
+        peak_hours: Vec<PeakHours>,
+    },
+    /// A price per tier of the energy used so far in the calendar month.
+    Tiered { tiers: Vec<Tier> },
+}
+
+/// Local hours `[start_hour, end_hour)` of one weekday.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeakHours {
+    /// ISO weekday: 1 = Monday … 7 = Sunday.
+    pub weekday: i16,
+    pub start_hour: u32,
+    /// 24 means end of day.
+    pub end_hour: u32,
+}
+
+/// One tier of a tiered tariff.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Tier {
+    /// Energy used in the month, in kWh, up to which the price applies;
+    /// `null` for the last tier.
+    pub up_to_kwh: Option<f64>,
+    pub price_per_kwh: f64,
+}
+
+impl TariffPlan {
+    /// Check prices, hours and tiers.
+    pub fn validate(&self) -> Result<(), String> {
+        let prices: Vec<f64> = match self {
+            TariffPlan::Flat { price_per_kwh } => vec![*price_per_kwh],
+            TariffPlan::TimeOfUse {
+                peak_price_per_kwh,
+                off_peak_price_per_kwh,
+                peak_hours,
+            } => {
+                for h in peak_hours {
+                    if !(1..=7).contains(&h.weekday) {
+                        return Err(format!("weekday {} outside 1–7", h.weekday));
+                    }
+                    if h.start_hour >= h.end_hour || h.end_hour > 24 {
+                        return Err(format!(
+                            "peak hours {}–{} are not within a day",
+                            h.start_hour, h.end_hour
+                        ));
+                    }
+                }
+                vec![*peak_price_per_kwh, *off_peak_price_per_kwh]
+            }
+            TariffPlan::Tiered { tiers } => {
+                let Some((last, bounded)) = tiers.split_last() else {
+                    return Err("a tiered tariff needs at least one tier".into());
+                };
+                if last.up_to_kwh.is_some() {
+                    return Err("the last tier must have no upper limit".into());
+                }
+                let mut below = 0.0;
+                for tier in bounded {
+                    match tier.up_to_kwh {
+                        Some(limit) if limit > below => below = limit,
+                        _ => return Err("tier limits must be given and increase".into()),
+                    }
+                }
+                tiers.iter().map(|t| t.price_per_kwh).collect()
+            }
+        };
+        match prices.iter().find(|p| !p.is_finite() || **p < 0.0) {
+            Some(p) => Err(format!("price {p} must be zero or more")),
+            None => Ok(()),
+        }
+    }
+
+    /// Cost of `kwh` used at local time `at`, after `month_kwh` had been
+    /// used earlier in the month.
+    fn cost(&self, at: DateTime<Tz>, month_kwh: f64, kwh: f64) -> f64 {
+        match self {
+            TariffPlan::Flat { price_per_kwh } => kwh * price_per_kwh,
+            TariffPlan::TimeOfUse {
+                peak_price_per_kwh,
+                off_peak_price_per_kwh,
+                peak_hours,
+            } => {
+                let weekday = at.weekday().number_from_monday() as i16;
+                let peak = peak_hours.iter().any(|h| {
+                    h.weekday == weekday && (h.start_hour..h.end_hour).contains(&at.hour())
+                });
+                kwh * if peak {
+                    peak_price_per_kwh
+                } else {
+                    off_peak_price_per_kwh
+                }
+            }
+            TariffPlan::Tiered { tiers } => {
+                let (mut cost, mut lower) = (0.0, 0.0);
+                for tier in tiers {
+                    let upper = tier.up_to_kwh.unwrap_or(f64::INFINITY);
+                    let in_tier = (month_kwh + kwh).min(upper) - month_kwh.max(lower);
+                    if in_tier > 0.0 {
+                        cost += in_tier * tier.price_per_kwh;
+                    }
+                    lower = upper;
+                }
+                cost
+            }
+        }
+    }
+}
+
+/// Cost of each of `deltas` (kWh × 100, in time order, as from
+/// `consumption::deltas`) under the tariff valid at its time; `None` where
+/// no tariff applies. Tiers count the month's usage from the first delta of
+/// the month on, so the deltas should start at the beginning of a month.
+pub fn costs(deltas: &[(DateTime<Utc>, i64)], tariffs: &[Tariff], tz: Tz) -> Vec<Option<f64>> {
+    let (mut month, mut month_kwh) = (None, 0.0);
+    deltas
+        .iter()
+        .map(|&(at, used)| {
+            let kwh = used as f64 / 100.0;
+            let start = Granularity::Month.period_start(at, tz);
+            if month != Some(start) {
+                (month, month_kwh) = (Some(start), 0.0);
+            }
+            let before = month_kwh;
+            month_kwh += kwh;
+
+            let tariff = tariffs
+                .iter()
+                .find(|t| t.valid_from <= at && t.valid_until.is_none_or(|until| at < until))?;
+            Some(tariff.plan.cost(at.with_timezone(&tz), before, kwh))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::types::Json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        rfc3339.parse().unwrap()
+    }
+
+    fn tariff(from: &str, until: Option<&str>, plan: TariffPlan) -> Tariff {
+        Tariff {
+            id: Uuid::new_v4(),
+            device_id: "em1".into(),
+            name: "test".into(),
+            valid_from: at(from),
+            valid_until: until.map(at),
+            plan: Json(plan),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn tariff_valid_at_each_moment_applies() {
+        let weekdays_7_to_21 = (1..=5)
+            .map(|weekday| PeakHours {
+                weekday,
+                start_hour: 7,
+                end_hour: 21,
+            })
+            .collect();
+        let tariffs = [
+            tariff(
+                "2026-10-01T00:00:00Z",
+                Some("2026-10-16T00:00:00Z"),
+                TariffPlan::Flat { price_per_kwh: 1.0 },
+            ),
+            tariff(
+                "2026-10-16T00:00:00Z",
+                None,
+                TariffPlan::TimeOfUse {
+                    peak_price_per_kwh: 0.8,
+                    off_peak_price_per_kwh: 0.4,
+                    peak_hours: weekdays_7_to_21,
+                },
+            ),
+        ];
+        let deltas = [
+            // Before any tariff.
+            (at("2026-09-30T12:00:00Z"), 100),
+            (at("2026-10-15T12:00:00Z"), 200),
+            // Friday 08:00 in Warsaw: peak.
+            (at("2026-10-16T06:00:00Z"), 100),
+            // Friday 22:00: off-peak.
+            (at("2026-10-16T20:00:00Z"), 100),
+            // Saturday noon: off-peak.
+            (at("2026-10-17T10:00:00Z"), 100),
+        ];
+        let tz: Tz = "Europe/Warsaw".parse().unwrap();
+        assert_eq!(
+            costs(&deltas, &tariffs, tz),
+            [None, Some(2.0), Some(0.8), Some(0.4), Some(0.4)]
+        );
+    }
+
+    #[test]
+    fn tiers_follow_the_months_usage() {
+        let tiers = TariffPlan::Tiered {
+            tiers: vec![
+                Tier {
+                    up_to_kwh: Some(10.0),
+                    price_per_kwh: 0.5,
+                },
+                Tier {
+                    up_to_kwh: None,
+                    price_per_kwh: 1.0,
+                },
+            ],
+        };
+        let tariffs = [tariff("2026-01-01T00:00:00Z", None, tiers)];
+        let deltas = [
+            (at("2026-10-10T00:00:00Z"), 800),
+            // 2 kWh in the first tier, 3 kWh in the second.
+            (at("2026-10-20T00:00:00Z"), 500),
+            // A new month starts in the first tier again.
+            (at("2026-11-02T00:00:00Z"), 400),
+        ];
+        assert_eq!(
+            costs(&deltas, &tariffs, chrono_tz::UTC),
+            [Some(4.0), Some(4.0), Some(2.0)]
+        );
+    }
+
+    #[test]
+    fn invalid_plans_are_refused() {
+        let tiered = |tiers: Vec<(Option<f64>, f64)>| TariffPlan::Tiered {
+            tiers: tiers
+                .into_iter()
+                .map(|(up_to_kwh, price_per_kwh)| Tier {
+                    up_to_kwh,
+                    price_per_kwh,
+                })
+                .collect(),
+        };
+        assert!(tiered(vec![(Some(10.0), 0.5), (None, 1.0)])
+            .validate()
+            .is_ok());
+        assert!(tiered(vec![]).validate().is_err());
+        assert!(tiered(vec![(Some(10.0), 0.5)]).validate().is_err());
+        assert!(
+            tiered(vec![(Some(10.0), 0.5), (Some(5.0), 0.7), (None, 1.0)])
+                .validate()
+                .is_err()
+        );
+        assert!(TariffPlan::Flat {
+            price_per_kwh: -1.0
+        }
+        .validate()
+        .is_err());
+
+        let peak = |weekday, start_hour, end_hour| TariffPlan::TimeOfUse {
+            peak_price_per_kwh: 0.8,
+            off_peak_price_per_kwh: 0.4,
+            peak_hours: vec![PeakHours {
+                weekday,
+                start_hour,
+                end_hour,
+            }],
+        };
+        assert!(peak(1, 7, 24).validate().is_ok());
+        assert!(peak(8, 7, 21).validate().is_err());
+        assert!(peak(1, 21, 7).validate().is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use tokio::sync::mpsc;
+
+use super::{EventSource, RawEvent};
+
+/// In-process event source fed through a channel, e.g. to replay captured
+/// messages or push canned ones in tests. Closes once every sender is
+/// dropped.
+pub struct ChannelSource {
+    rx: mpsc::Receiver<RawEvent>,
+    acked: Arc<Mutex<Vec<String>>>,
+}
+
+impl ChannelSource {
+    pub fn new(buffer: usize) -> (mpsc::Sender<RawEvent>, Self) {
+        let (tx, rx) = mpsc::channel(buffer);
+        let source = Self {
+            rx,
+            acked: Arc::default(),
+        };
+        (tx, source)
+    }
+
+    /// IDs of acknowledged messages, in order; stays readable after the
+    /// source has been moved into an ingest task.
+    pub fn acked(&self) -> Arc<Mutex<Vec<String>>> {
+        self.acked.clone()
+    }
+}
+
+impl EventSource for ChannelSource {
+    async fn next(&mut self) -> Result<Option<RawEvent>> {
+        Ok(self.rx.recv().await)
+    }
+
+    async fn ack(&mut self, id: &str) -> Result<()> {
+        self.acked
+            .lock()
+            .expect("acked lock poisoned")
+            .push(id.to_owned());
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use aes::cipher::{generic_array::GenericArray, KeyInit};
+use aes_gcm::{aead::Aead, Aes128Gcm, Nonce};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::{Digest, Md5};
+
+use crate::tuya::local::protocol;
+
+/// Message payloads are encrypted with characters 8..24 of the access
+/// secret as AES-128 key.
+pub fn payload_key(access_secret: &str) -> Result<[u8; 16]> {
+    access_secret
+        .as_bytes()
+        .get(8..24)
+        .and_then(|key| key.try_into().ok())
+        .context("access secret is shorter than 24 characters")
+}
+
+/// Password for the message service: characters 8..24 of
+/// `md5_hex(access_id + md5_hex(access_secret))`.
+pub fn password(access_id: &str, access_secret: &str) -> String {
+    let secret_hash = hex::encode(Md5::digest(access_secret.as_bytes()));
+    let hash = hex::encode(Md5::digest(format!("{access_id}{secret_hash}").as_bytes()));
+    hash[8..24].to_owned()
+}
+
+/// Decrypt a base64 AES-128-ECB payload with PKCS#7 padding.
+pub fn decrypt_ecb(data: &str, key: &[u8; 16]) -> Result<Vec<u8>> {
+    let buf = STANDARD.decode(data).context("payload is not base64")?;
+    protocol::ecb_decrypt(key, &buf).context("wrong access secret?")
+}
+
+/// Decrypt a base64 AES-128-GCM payload: a 12-byte nonce followed by the
+/// ciphertext and tag.
+pub fn decrypt_gcm(data: &str, key: &[u8; 16]) -> Result<Vec<u8>> {
+    let raw = STANDARD.decode(data).context("payload is not base64")?;
+    if raw.len() < 12 + 16 {
+        bail!("GCM payload of {} bytes is too short", raw.len());
+    }
+    let (nonce, ciphertext) = raw.split_at(12);
+
+    Aes128Gcm::new(GenericArray::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("GCM payload failed authentication; wrong access secret?"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const SECRET: &str = "0123456789abcdefghijklmnopqrstuv";
+
+    /// Encrypt like the message service does, for canned test messages.
+    pub(crate) fn encrypt_ecb(plain: &[u8], key: &[u8; 16]) -> String {
+        STANDARD.encode(protocol::ecb_encrypt(key, plain))
+    }
+
+    pub(crate) fn encrypt_gcm(plain: &[u8], key: &[u8; 16]) -> String {
+        let nonce = [7u8; 12];
+        let mut out = nonce.to_vec();
+        out.extend(
+            Aes128Gcm::new(GenericArray::from_slice(key))
+                .encrypt(Nonce::from_slice(&nonce), plain)
+                .unwrap(),
+        );
+        STANDARD.encode(out)
+    }
+
+    #[test]
+    fn key_is_middle_of_secret() {
+        assert_eq!(&payload_key(SECRET).unwrap(), b"89abcdefghijklmn");
+        assert!(payload_key("short").is_err());
+    }
+
+    #[test]
+    fn ecb_round_trips() {
+        let key = payload_key(SECRET).unwrap();
+        for plain in [
+            &b"{}"[..],
+            b"exactly 16 bytes",
+            b"{\"devId\":\"th1\",\"status\":[]}",
+        ] {
+            let data = encrypt_ecb(plain, &key);
+            assert_eq!(decrypt_ecb(&data, &key).unwrap(), plain);
+        }
+    }
+
+    #[test]
+    fn gcm_round_trips() {
+        let key = payload_key(SECRET).unwrap();
+        let data = encrypt_gcm(b"{\"devId\":\"th1\"}", &key);
+        assert_eq!(decrypt_gcm(&data, &key).unwrap(), b"{\"devId\":\"th1\"}");
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let key = payload_key(SECRET).unwrap();
+        let other = *b"ffffffffffffffff";
+        assert!(decrypt_gcm(&encrypt_gcm(b"{}", &key), &other).is_err());
+        assert!(decrypt_ecb(&encrypt_ecb(b"{}", &key), &other).is_err());
+    }
+
+    #[test]
+    fn password_is_sixteen_hex_chars() {
+        let pwd = password("access-id", SECRET);
+        assert_eq!(pwd.len(), 16);
+        assert!(pwd.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(pwd, password("other-id", SECRET));
+    }
+}
@@ -0,0 +1,322 @@
+pub mod channel;
+pub mod crypto;
+pub mod pulsar;
+
+use std::{future::Future, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    sensors::SensorService,
+    tuya::{models::DeviceProperty, DeviceBackend, TuyaClient},
+};
+
+pub use self::{channel::ChannelSource, pulsar::PulsarSource};
+
+/// Delay before asking a failed source for the next message again.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Tuya message protocol number of device status reports.
+const PROTOCOL_STATUS_REPORT: i32 = 4;
+
+/// Tuya message protocol number of device lifecycle events, such as going
+/// online or offline.
+const PROTOCOL_BIZ_EVENT: i32 = 20;
+
+/// A message as received from the transport, still encrypted.
+#[derive(Debug, Clone)]
+pub struct RawEvent {
+    /// Transport message ID, used to acknowledge it.
+    pub id: String,
+    /// Message body: the Tuya envelope JSON.
+    pub payload: Vec<u8>,
+    /// Payload encryption, e.g. `"aes_gcm"`; `None` for AES-ECB.
+    pub encryption: Option<String>,
+}
+
+/// Transport delivering Tuya messages, e.g. [`PulsarSource`] or, for tests
+/// and replays, [`ChannelSource`].
+pub trait EventSource: Send {
+    /// The next message; `None` once the source is closed for good.
+    /// Errors are transient: the caller may ask again.
+    fn next(&mut self) -> impl Future<Output = Result<Option<RawEvent>>> + Send;
+
+    /// Acknowledge a message so it is not delivered again.
+    fn ack(&mut self, id: &str) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Envelope around every Tuya message.
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    /// Encrypted, base64-encoded message.
+    data: String,
+    protocol: i32,
+}
+
+/// Decrypted device status report: the DPs that changed.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusReport {
+    pub dev_id: String,
+    #[serde(default)]
+    pub status: Vec<DeviceProperty>,
+}
+
+/// Decrypted device lifecycle event, e.g. `online` or `nameUpdate`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BizEvent {
+    biz_code: String,
+    dev_id: String,
+}
+
+/// A message the ingest acts on.
+#[derive(Debug)]
+pub enum DeviceEvent {
+    /// DPs that changed.
+    Status(StatusReport),
+    /// The device went online (`true`) or offline.
+    Online { dev_id: String, online: bool },
+}
+
+/// Decrypt a message. Returns `None` for messages the ingest does not act
+/// on, e.g. name changes.
+pub fn decode(event: &RawEvent, key: &[u8; 16]) -> Result<Option<DeviceEvent>> {
+    let envelope: Envelope =
+        serde_json::from_slice(&event.payload).context("Unexpected message envelope")?;
+    if envelope.protocol != PROTOCOL_STATUS_REPORT && envelope.protocol != PROTOCOL_BIZ_EVENT {
+        return Ok(None);
+    }
+
+    let plain = match event.encryption.as_deref() {
+        Some("aes_gcm") => crypto::decrypt_gcm(&envelope.data, key)?,
+        _ => crypto::decrypt_ecb(&envelope.data, key)?,
+    };
+    if envelope.protocol == PROTOCOL_STATUS_REPORT {
+        let report = serde_json::from_slice(&plain).context("Unexpected status report")?;
+        return Ok(Some(DeviceEvent::Status(report)));
+    }
+    let biz: BizEvent = serde_json::from_slice(&plain).context("Unexpected device event")?;
+    let online = match biz.biz_code.as_str() {
+        "online" => true,
+        "offline" => false,
+        _ => return Ok(None),
+    };
+    Ok(Some(DeviceEvent::Online {
+        dev_id: biz.dev_id,
+        online,
+    }))
+}
+
+/// Feeds device status reports from an [`EventSource`] into the same
+/// persistence and cache path as sensor polling, and online/offline events
+/// into the sensors' device availability.
+///
+/// Messages are decrypted with the access secret of one account; with
+/// several Tuya accounts, run one ingest per account's message service.
+pub struct EventIngest<S, B = TuyaClient> {
+    source: S,
+    sensors: SensorService<B>,
+    key: [u8; 16],
+}
+
+impl<S: EventSource, B: DeviceBackend> EventIngest<S, B> {
+    pub fn new(source: S, sensors: SensorService<B>, access_secret: &str) -> Result<Self> {
+        Ok(Self {
+            source,
+            sensors,
+            key: crypto::payload_key(access_secret)?,
+        })
+    }
+
+    /// Consume messages until the source closes.
+    pub async fn run(mut self) {
+        info!("Event ingest started");
+        loop {
+            match self.source.next().await {
+                Ok(Some(event)) => self.handle(event).await,
+                Ok(None) => {
+                    info!("Event source closed; event ingest stopped");
+                    return;
+                }
+                Err(e) => {
+                    warn!(error = %format!("{e:#}"), "Event source failed; retrying");
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Persist one message. Messages that can never be processed are
+    /// acknowledged and dropped; ones that failed to persist are left
+    /// unacknowledged so they are delivered again.
+    async fn handle(&mut self, event: RawEvent) {
+        let processed = match decode(&event, &self.key) {
+            Ok(Some(DeviceEvent::Status(report))) => {
+                debug!(device_id = %report.dev_id, dps = report.status.len(), "Status report");
+                match self
+                    .sensors
+                    .persist_dps(&report.dev_id, &report.status)
+                    .await
+                {
+                    Ok(()) => true,
+                    Err(e) => {
+                        error!(device_id = %report.dev_id, error = %e, "Failed to persist event");
+                        false
+                    }
+                }
+            }
+            Ok(Some(DeviceEvent::Online { dev_id, online })) => {
+                debug!(device_id = %dev_id, online, "Online/offline event");
+                self.sensors.record_online_flag(&dev_id, online).await;
+                true
+            }
+            Ok(None) => true,
+            Err(e) => {
+                warn!(id = %event.id, error = %format!("{e:#}"), "Dropping undecodable event");
+                true
+            }
+        };
+
+        if processed {
+            if let Err(e) = self.source.ack(&event.id).await {
+                warn!(id = %event.id, error = %e, "Failed to acknowledge event");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use sqlx::PgPool;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer,
+    };
+
+    use super::{crypto::tests as canned, *};
+    use crate::{
+        config::DeviceType,
+        db::models::SensorType,
+        reading_cache::ReadingCache,
+        sensors::DeviceAvailability,
+        tuya::{mock, TuyaClient},
+    };
+
+    /// Fake Tuya Cloud that refuses DP specifications, so default scales apply.
+    async fn fake_tuya() -> MockServer {
+        let server = mock::cloud().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/th1/specifications"))
+            .respond_with(mock::failed(2003))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn event(id: &str, protocol: i32, report: serde_json::Value, gcm: bool) -> RawEvent {
+        let key = crypto::payload_key(canned::SECRET).unwrap();
+        let plain = report.to_string();
+        let data = if gcm {
+            canned::encrypt_gcm(plain.as_bytes(), &key)
+        } else {
+            canned::encrypt_ecb(plain.as_bytes(), &key)
+        };
+        RawEvent {
+            id: id.to_owned(),
+            payload: serde_json::json!({ "data": data, "protocol": protocol, "pv": "2.0" })
+                .to_string()
+                .into_bytes(),
+            encryption: gcm.then(|| "aes_gcm".to_owned()),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn status_reports_are_persisted_and_acked(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        let cache = ReadingCache::new();
+        let availability = DeviceAvailability::new(pool.clone());
+        let sensors = SensorService::new(
+            pool.clone(),
+            TuyaClient::with_credentials(&tuya.uri(), "client", canned::SECRET),
+            cache.clone(),
+            HashMap::from([("th1".to_owned(), DeviceType::Thermostat)]),
+        )
+        .with_availability(availability.clone());
+        let (tx, source) = ChannelSource::new(8);
+        let acked = source.acked();
+
+        let report = |status: serde_json::Value| serde_json::json!({ "dataId": "d", "devId": "th1", "productKey": "p", "status": status });
+        let events = [
+            event(
+                "m1",
+                4,
+                report(serde_json::json!([
+                    {"code": "temp_current", "value": 201, "t": 1, "1": 201},
+                    {"code": "switch", "value": false, "t": 1}
+                ])),
+                false,
+            ),
+            // Not a status report: marks the device offline.
+            event(
+                "m2",
+                20,
+                serde_json::json!({ "bizCode": "offline", "devId": "th1" }),
+                false,
+            ),
+            // Encrypted with another key: dropped.
+            RawEvent {
+                id: "m3".to_owned(),
+                payload: br#"{"data":"AAAA","protocol":4}"#.to_vec(),
+                encryption: None,
+            },
+            // Unconfigured device: acknowledged, nothing stored.
+            event(
+                "m4",
+                4,
+                serde_json::json!({
+                    "devId": "other",
+                    "status": [{"code": "switch", "value": true}]
+                }),
+                true,
+            ),
+            event(
+                "m5",
+                4,
+                report(serde_json::json!([{"code": "temp_set", "value": 215}])),
+                true,
+            ),
+        ];
+        for e in events {
+            tx.send(e).await.unwrap();
+        }
+        drop(tx);
+
+        EventIngest::new(source, sensors, canned::SECRET)
+            .unwrap()
+            .run()
+            .await;
+
+        assert_eq!(*acked.lock().unwrap(), ["m1", "m2", "m3", "m4", "m5"]);
+
+        let value = |sensor_type| {
+            let cache = cache.clone();
+            async move { cache.get("th1", sensor_type).await.map(|r| r.value) }
+        };
+        assert_eq!(value(SensorType::Temperature).await, Some(2010));
+        assert_eq!(value(SensorType::RelayState).await, Some(0));
+        assert_eq!(value(SensorType::TemperatureSetpoint).await, Some(2150));
+
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sensor_readings")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 3);
+        let status = availability.get("th1").await.unwrap();
+        assert_eq!(status.online, Some(false));
+    }
+}
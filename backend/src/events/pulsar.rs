@@ -0,0 +1,125 @@
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, info};
+
+use super::{crypto, EventSource, RawEvent};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Consumes Tuya's message service through the Pulsar WebSocket API.
+///
+/// Connects lazily and drops the connection on any error; the next call to
+/// [`EventSource::next`] reconnects.
+pub struct PulsarSource {
+    topic_url: String,
+    username: String,
+    password: String,
+    socket: Option<Socket>,
+}
+
+/// A message as delivered by the Pulsar WebSocket consumer.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PulsarMessage {
+    message_id: String,
+    /// Base64 of the message body.
+    payload: String,
+    #[serde(default)]
+    properties: PulsarProperties,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PulsarProperties {
+    /// Payload encryption, e.g. `"aes_gcm"`; absent for AES-ECB.
+    em: Option<String>,
+}
+
+impl PulsarSource {
+    /// `base_url` is the region's message service endpoint and `env` the
+    /// topic environment (`event`, or `event-test` for the test channel).
+    pub fn new(base_url: &str, env: &str, access_id: &str, access_secret: &str) -> Self {
+        let topic_url = format!(
+            "{}/ws/v2/consumer/persistent/{access_id}/out/{env}/{access_id}-sub\
+             ?ackTimeoutMillis=3000&subscriptionType=Failover",
+            base_url.trim_end_matches('/'),
+        );
+        Self {
+            topic_url,
+            username: access_id.to_owned(),
+            password: crypto::password(access_id, access_secret),
+            socket: None,
+        }
+    }
+
+    async fn socket(&mut self) -> Result<&mut Socket> {
+        if self.socket.is_none() {
+            let mut request = self.topic_url.as_str().into_client_request()?;
+            let headers = request.headers_mut();
+            headers.insert("username", HeaderValue::from_str(&self.username)?);
+            headers.insert("password", HeaderValue::from_str(&self.password)?);
+
+            let (socket, _) = connect_async(request)
+                .await
+                .context("Failed to connect to the Tuya message service")?;
+            info!("Connected to the Tuya message service");
+            self.socket = Some(socket);
+        }
+        Ok(self.socket.as_mut().expect("socket was just connected"))
+    }
+}
+
+impl EventSource for PulsarSource {
+    async fn next(&mut self) -> Result<Option<RawEvent>> {
+        loop {
+            let received = self.socket().await?.next().await;
+            let text = match received {
+                Some(Ok(Message::Text(text))) => text,
+                // Pings are answered by the socket itself.
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => continue,
+                Some(Ok(Message::Binary(_))) => {
+                    debug!("Ignoring binary message");
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | None => {
+                    self.socket = None;
+                    bail!("Tuya message service closed the connection");
+                }
+                Some(Err(e)) => {
+                    self.socket = None;
+                    return Err(e).context("Tuya message service connection failed");
+                }
+            };
+
+            let message: PulsarMessage =
+                serde_json::from_str(&text).context("Unexpected Pulsar message")?;
+            return Ok(Some(RawEvent {
+                payload: STANDARD
+                    .decode(&message.payload)
+                    .context("Pulsar payload is not base64")?,
+                id: message.message_id,
+                encryption: message.properties.em,
+            }));
+        }
+    }
+
+    async fn ack(&mut self, id: &str) -> Result<()> {
+        // Acks only count on the connection that delivered the message.
+        let Some(socket) = self.socket.as_mut() else {
+            bail!("Connection lost before the ack; the message will be redelivered");
+        };
+        let ack = serde_json::json!({ "messageId": id }).to_string();
+        if let Err(e) = socket.send(Message::Text(ack.into())).await {
+            self.socket = None;
+            return Err(e).context("Failed to acknowledge Tuya message");
+        }
+        Ok(())
+    }
+}
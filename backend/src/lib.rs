@@ -2,7 +2,11 @@ pub mod api;
 pub mod config;
 pub mod control;
 pub mod db;
+pub mod discovery;
+pub mod energy;
+pub mod events;
 pub mod reading_cache;
 pub mod response_store;
+pub mod rooms;
 pub mod sensors;
 pub mod tuya;
@@ -1,17 +1,18 @@
 use anyhow::Result;
-use std::time::Duration;
-use tokio::{net::TcpListener, signal, time};
-use tracing::info;
+use std::{sync::Arc, time::Duration};
+use tokio::{net::TcpListener, signal};
+use tracing::{info, warn};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 use smart_home_service::{
     api,
     config::Config,
-    control::ControlService,
-    db,
-    reading_cache::ReadingCache,
-    sensors::SensorService,
-    tuya::TuyaClient,
+    control::{ControlService, ControlSettings},
+    db, discovery,
+    events::{EventIngest, PulsarSource},
+    reading_cache::{ReadingCache, DEFAULT_HISTORY_RETENTION},
+    sensors::{DeviceAvailability, PollSettings, SensorService},
+    tuya::{usage::FLUSH_INTERVAL, TuyaAccounts},
 };
 
 #[tokio::main]
@@ -33,57 +34,161 @@ async fn main() -> Result<()> {
     db::run_migrations(&pool).await?;
     info!("Database ready");
 
-    // Shared in-memory cache of latest readings per device
-    let cache = ReadingCache::new();
-
-    // Build shared Tuya client
-    let tuya = TuyaClient::new(&config);
-
-    // Spawn sensor-polling task
-    {
-        let pool = pool.clone();
-        let tuya = tuya.clone();
-        let cache = cache.clone();
-        let device_ids = config.device_ids.clone();
-        let interval = Duration::from_secs(config.poll_interval_secs);
-
-        tokio::spawn(async move {
-            let service = SensorService::new(pool, tuya, cache, device_ids);
-            let mut ticker = time::interval(interval);
-            info!(interval_secs = interval.as_secs(), "Sensor polling loop started");
-
-            loop {
-                ticker.tick().await;
-                for device_id in service.device_ids() {
-                    if let Err(e) = service.fetch_and_persist(device_id).await {
-                        tracing::error!(device_id = %device_id, error = %e, "Failed to fetch sensor reading");
+    // Shared in-memory cache of recent readings per device — long enough for
+    // the window-open temperature-drop rule
+    let longest_poll_interval = config
+        .poll_intervals
+        .values()
+        .copied()
+        .fold(config.poll_interval_secs, u64::max);
+    let cache = ReadingCache::with_retention(
+        Duration::from_secs(config.window_drop_minutes * 60 + longest_poll_interval)
+            .max(DEFAULT_HISTORY_RETENTION),
+    );
+
+    // Build shared Tuya clients, one per account, each with its own quota
+    let mut tuya = TuyaAccounts::load(&config, pool.clone()).await?;
+
+    // Online/offline state per device, continued from the stored transitions
+    let availability = DeviceAvailability::load(pool.clone()).await?;
+
+    // Configured devices, plus each account's devices if discovery is enabled
+    let mut device_ids = config.device_ids.clone();
+    if config.discover_devices {
+        let mut bindings = Vec::new();
+        for (account, client) in tuya.clients() {
+            match client.list_devices().await {
+                Ok(found) => {
+                    let added = discovery::register(&mut device_ids, &found);
+                    info!(
+                        account,
+                        found = found.len(),
+                        added,
+                        "Tuya device discovery finished"
+                    );
+                    for device in &found {
+                        if device_ids.contains_key(&device.id) {
+                            availability
+                                .record_online_flag(&device.id, device.online)
+                                .await;
+                        }
                     }
+                    bindings.extend(found.into_iter().map(|d| (d.id, account.to_owned())));
                 }
+                Err(e) => warn!(
+                    account,
+                    error = %e,
+                    "Tuya device discovery failed; using configured devices only"
+                ),
             }
-        });
+        }
+        // Configured bindings win over where discovery found a device
+        for (device_id, account) in bindings {
+            if !config.device_accounts.contains_key(&device_id) {
+                tuya.bind(&device_id, &account);
+            }
+        }
     }
 
-    // Spawn control loop task — shares the same cache, no DB queries needed
+    for (_, client) in tuya.clients() {
+        // Spawn token refresh task — renews the access token ahead of expiry
+        tokio::spawn(client.clone().run_token_refresh());
+
+        // Spawn usage flush task — writes the counted calls to `tuya_usage`
+        tokio::spawn(client.usage().clone().run_flush(FLUSH_INTERVAL));
+
+        // Spawn local heartbeat task — keeps LAN connections to devices open
+        if let Some(local) = client.local() {
+            tokio::spawn(local.clone().run_heartbeats());
+        }
+    }
+
+    // Spawn sensor-polling task — each device on its own interval
     {
-        let control = ControlService::new(tuya, cache, config.control_interval_secs);
-        tokio::spawn(control.run());
+        let service = SensorService::new(
+            pool.clone(),
+            tuya.clone(),
+            cache.clone(),
+            device_ids.clone(),
+        )
+        .with_availability(availability.clone());
+        tokio::spawn(service.run(PollSettings::from_config(&config)));
     }
 
+    // Spawn event ingest tasks — persist status reports as they happen, one
+    // per account with a message service endpoint
+    for (name, _) in tuya.clients() {
+        let account = config
+            .tuya_account(name)
+            .expect("configured accounts have credentials");
+        let Some(url) = &account.events_url else {
+            continue;
+        };
+        let source = PulsarSource::new(
+            url,
+            &config.tuya_events_env,
+            &account.client_id,
+            &account.client_secret,
+        );
+        let sensors = SensorService::new(
+            pool.clone(),
+            tuya.clone(),
+            cache.clone(),
+            device_ids.clone(),
+        )
+        .with_availability(availability.clone());
+        let ingest = EventIngest::new(source, sensors, &account.client_secret)?;
+        info!(account = name, "Starting Tuya event ingest");
+        tokio::spawn(ingest.run());
+    }
+
+    // Spawn control loop task — shares the same cache; writes only to the audit table
+    let controllers = {
+        let control = ControlService::new(
+            pool.clone(),
+            tuya.clone(),
+            cache.clone(),
+            device_ids.clone(),
+            ControlSettings::from_config(&config),
+        );
+        let controllers = control.controller_statuses();
+        tokio::spawn(control.run());
+        controllers
+    };
+
     // Start HTTP server
     let addr = format!("{}:{}", config.server_host, config.server_port);
     let listener = TcpListener::bind(&addr).await?;
     info!(addr = %addr, "HTTP server listening");
 
-    axum::serve(listener, api::router(pool))
+    let accounts = tuya.clone();
+    let state = api::AppState {
+        pool,
+        tuya,
+        cache,
+        devices: Arc::new(device_ids),
+        controllers,
+        availability,
+        timezone: config.timezone,
+    };
+
+    axum::serve(listener, api::router(state))
         .with_graceful_shutdown(shutdown_signal())
         .await?;
 
+    // Keep the calls counted since the last flush
+    for (_, client) in accounts.clients() {
+        client.usage().flush().await;
+    }
+
     Ok(())
 }
 
 async fn shutdown_signal() {
     let ctrl_c = async {
-        signal::ctrl_c().await.expect("failed to install Ctrl+C handler");
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
     };
 
     #[cfg(unix)]
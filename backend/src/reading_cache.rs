@@ -1,16 +1,38 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
 
+use chrono::{DateTime, Utc};
 use tokio::sync::RwLock;
+use uuid::Uuid;
 
 use crate::db::models::{SensorReading, SensorType};
 
-/// In-memory store of the most recent `SensorReading` per `(device_id, SensorType)`.
+/// History kept per `(device_id, SensorType)` when no retention is given.
+pub const DEFAULT_HISTORY_RETENTION: Duration = Duration::from_secs(15 * 60);
+
+/// Readings per `(device_id, SensorType)`, oldest first; the back is the latest.
+type History = HashMap<(String, SensorType), VecDeque<SensorReading>>;
+
+/// In-memory store of the recent `SensorReading`s per `(device_id, SensorType)`.
+///
+/// Besides the latest reading, a short history (see [`ReadingCache::with_retention`])
+/// is kept for rules that look at trends, e.g. window-open detection.
 ///
 /// Wrapped in `Arc` so it can be cheaply cloned and shared across tasks.
 /// Uses `tokio::sync::RwLock` so concurrent readers never block each other.
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct ReadingCache {
-    inner: Arc<RwLock<HashMap<(String, SensorType), SensorReading>>>,
+    inner: Arc<RwLock<History>>,
+    retention: chrono::Duration,
+}
+
+impl Default for ReadingCache {
+    fn default() -> Self {
+        Self::with_retention(DEFAULT_HISTORY_RETENTION)
+    }
 }
 
 impl ReadingCache {
@@ -18,17 +40,54 @@ impl ReadingCache {
         Self::default()
     }
 
-    /// Overwrite the cached reading for `(reading.device_id, reading.sensor_type)`.
+    /// Keep readings up to `retention` older than the latest one per key.
+    pub fn with_retention(retention: Duration) -> Self {
+        Self {
+            inner: Arc::default(),
+            retention: chrono::Duration::from_std(retention).unwrap_or(chrono::Duration::MAX),
+        }
+    }
+
+    /// Record `reading` for `(reading.device_id, reading.sensor_type)` in
+    /// `recorded_at` order and drop history older than the retention period.
+    ///
+    /// A reading that arrives late, e.g. a poll overtaken by an event, lands
+    /// in its place in the history and does not replace a newer latest one.
     pub async fn update(&self, reading: SensorReading) {
-        self.inner
-            .write()
-            .await
-            .insert((reading.device_id.clone(), reading.sensor_type), reading);
+        let mut inner = self.inner.write().await;
+        let history = inner
+            .entry((reading.device_id.clone(), reading.sensor_type))
+            .or_default();
+        let at = history.partition_point(|r| r.recorded_at <= reading.recorded_at);
+        history.insert(at, reading);
+        let cutoff = history[history.len() - 1].recorded_at - self.retention;
+        while history.front().is_some_and(|r| r.recorded_at < cutoff) {
+            history.pop_front();
+        }
+    }
+
+    /// Overwrite the cached value for `(device_id, sensor_type)` with a reading
+    /// stamped now that is not persisted — e.g. a state that was just commanded
+    /// and will be confirmed by the next poll.
+    pub async fn set(&self, device_id: &str, sensor_type: SensorType, value: i64) {
+        self.update(SensorReading {
+            id: Uuid::new_v4(),
+            device_id: device_id.to_owned(),
+            sensor_type,
+            recorded_at: Utc::now(),
+            value,
+        })
+        .await;
     }
 
     /// Return a snapshot of all latest readings across every device and sensor type.
     pub async fn all(&self) -> Vec<SensorReading> {
-        self.inner.read().await.values().cloned().collect()
+        self.inner
+            .read()
+            .await
+            .values()
+            .filter_map(|h| h.back().cloned())
+            .collect()
     }
 
     /// Return all latest readings for a specific device (one per sensor type).
@@ -39,26 +98,43 @@ impl ReadingCache {
             .await
             .iter()
             .filter(|((id, _), _)| id == device_id)
-            .map(|(_, r)| r.clone())
+            .filter_map(|(_, h)| h.back().cloned())
             .collect()
     }
 
     /// Return the latest reading for a specific `(device_id, sensor_type)`, if present.
-    #[allow(dead_code)]
     pub async fn get(&self, device_id: &str, sensor_type: SensorType) -> Option<SensorReading> {
         self.inner
             .read()
             .await
             .get(&(device_id.to_owned(), sensor_type))
-            .cloned()
+            .and_then(|h| h.back().cloned())
+    }
+
+    /// Return the cached readings for `(device_id, sensor_type)` recorded at
+    /// or after `since`, oldest first.
+    pub async fn history(
+        &self,
+        device_id: &str,
+        sensor_type: SensorType,
+        since: DateTime<Utc>,
+    ) -> Vec<SensorReading> {
+        self.inner
+            .read()
+            .await
+            .get(&(device_id.to_owned(), sensor_type))
+            .map(|h| {
+                h.iter()
+                    .filter(|r| r.recorded_at >= since)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::Utc;
-    use uuid::Uuid;
-
     use super::*;
     use crate::db::models::{SensorReading, SensorType};
 
@@ -95,8 +171,12 @@ mod tests {
     #[tokio::test]
     async fn update_overwrites_previous_reading() {
         let cache = ReadingCache::new();
-        cache.update(make_reading("dev1", SensorType::Temperature, 2000)).await;
-        cache.update(make_reading("dev1", SensorType::Temperature, 2500)).await;
+        cache
+            .update(make_reading("dev1", SensorType::Temperature, 2000))
+            .await;
+        cache
+            .update(make_reading("dev1", SensorType::Temperature, 2500))
+            .await;
 
         let got = cache.get("dev1", SensorType::Temperature).await.unwrap();
         assert_eq!(got.value, 2500);
@@ -107,31 +187,69 @@ mod tests {
     #[tokio::test]
     async fn different_sensor_types_are_separate_entries() {
         let cache = ReadingCache::new();
-        cache.update(make_reading("dev1", SensorType::Temperature, 2145)).await;
-        cache.update(make_reading("dev1", SensorType::Humidity, 6050)).await;
+        cache
+            .update(make_reading("dev1", SensorType::Temperature, 2145))
+            .await;
+        cache
+            .update(make_reading("dev1", SensorType::Humidity, 6050))
+            .await;
 
         assert_eq!(cache.all().await.len(), 2);
-        assert_eq!(cache.get("dev1", SensorType::Temperature).await.unwrap().value, 2145);
-        assert_eq!(cache.get("dev1", SensorType::Humidity).await.unwrap().value, 6050);
+        assert_eq!(
+            cache
+                .get("dev1", SensorType::Temperature)
+                .await
+                .unwrap()
+                .value,
+            2145
+        );
+        assert_eq!(
+            cache.get("dev1", SensorType::Humidity).await.unwrap().value,
+            6050
+        );
     }
 
     #[tokio::test]
     async fn different_devices_are_separate_entries() {
         let cache = ReadingCache::new();
-        cache.update(make_reading("dev1", SensorType::Temperature, 2000)).await;
-        cache.update(make_reading("dev2", SensorType::Temperature, 3000)).await;
+        cache
+            .update(make_reading("dev1", SensorType::Temperature, 2000))
+            .await;
+        cache
+            .update(make_reading("dev2", SensorType::Temperature, 3000))
+            .await;
 
         assert_eq!(cache.all().await.len(), 2);
-        assert_eq!(cache.get("dev1", SensorType::Temperature).await.unwrap().value, 2000);
-        assert_eq!(cache.get("dev2", SensorType::Temperature).await.unwrap().value, 3000);
+        assert_eq!(
+            cache
+                .get("dev1", SensorType::Temperature)
+                .await
+                .unwrap()
+                .value,
+            2000
+        );
+        assert_eq!(
+            cache
+                .get("dev2", SensorType::Temperature)
+                .await
+                .unwrap()
+                .value,
+            3000
+        );
     }
 
     #[tokio::test]
     async fn get_device_returns_only_that_devices_readings() {
         let cache = ReadingCache::new();
-        cache.update(make_reading("dev1", SensorType::Temperature, 2145)).await;
-        cache.update(make_reading("dev1", SensorType::Humidity, 6050)).await;
-        cache.update(make_reading("dev2", SensorType::Temperature, 1800)).await;
+        cache
+            .update(make_reading("dev1", SensorType::Temperature, 2145))
+            .await;
+        cache
+            .update(make_reading("dev1", SensorType::Humidity, 6050))
+            .await;
+        cache
+            .update(make_reading("dev2", SensorType::Temperature, 1800))
+            .await;
 
         let dev1 = cache.get_device("dev1").await;
         assert_eq!(dev1.len(), 2);
@@ -142,12 +260,105 @@ mod tests {
         assert_eq!(dev2[0].value, 1800);
     }
 
+    #[tokio::test]
+    async fn set_overwrites_with_fresh_reading() {
+        let cache = ReadingCache::new();
+        cache
+            .update(make_reading("dev1", SensorType::TemperatureSetpoint, 2000))
+            .await;
+        cache
+            .set("dev1", SensorType::TemperatureSetpoint, 2250)
+            .await;
+
+        let got = cache
+            .get("dev1", SensorType::TemperatureSetpoint)
+            .await
+            .unwrap();
+        assert_eq!(got.value, 2250);
+        assert_eq!(got.device_id, "dev1");
+    }
+
+    #[tokio::test]
+    async fn history_keeps_readings_within_retention() {
+        let cache = ReadingCache::with_retention(Duration::from_secs(600));
+        let now = Utc::now();
+        for (mins_ago, value) in [(20, 2100), (9, 2050), (5, 2000), (0, 1900)] {
+            let mut r = make_reading("dev1", SensorType::Temperature, value);
+            r.recorded_at = now - chrono::Duration::minutes(mins_ago);
+            cache.update(r).await;
+        }
+
+        let all: Vec<i64> = cache
+            .history(
+                "dev1",
+                SensorType::Temperature,
+                now - chrono::Duration::hours(1),
+            )
+            .await
+            .iter()
+            .map(|r| r.value)
+            .collect();
+        assert_eq!(all, vec![2050, 2000, 1900]);
+
+        let recent = cache
+            .history(
+                "dev1",
+                SensorType::Temperature,
+                now - chrono::Duration::minutes(6),
+            )
+            .await;
+        assert_eq!(recent.len(), 2);
+        assert_eq!(
+            cache
+                .get("dev1", SensorType::Temperature)
+                .await
+                .unwrap()
+                .value,
+            1900
+        );
+        assert_eq!(cache.all().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn late_reading_does_not_replace_newer_one() {
+        let cache = ReadingCache::with_retention(Duration::from_secs(600));
+        let now = Utc::now();
+        for (mins_ago, value) in [(0, 1900), (5, 2000), (20, 2100)] {
+            let mut r = make_reading("dev1", SensorType::Temperature, value);
+            r.recorded_at = now - chrono::Duration::minutes(mins_ago);
+            cache.update(r).await;
+        }
+
+        assert_eq!(
+            cache
+                .get("dev1", SensorType::Temperature)
+                .await
+                .unwrap()
+                .value,
+            1900
+        );
+        // Sorted into the history; the one beyond retention is dropped.
+        let values: Vec<i64> = cache
+            .history(
+                "dev1",
+                SensorType::Temperature,
+                now - chrono::Duration::hours(1),
+            )
+            .await
+            .iter()
+            .map(|r| r.value)
+            .collect();
+        assert_eq!(values, vec![2000, 1900]);
+    }
+
     #[tokio::test]
     async fn clone_shares_state() {
         let cache = ReadingCache::new();
         let clone = cache.clone();
 
-        cache.update(make_reading("dev1", SensorType::RelayState, 1)).await;
+        cache
+            .update(make_reading("dev1", SensorType::RelayState, 1))
+            .await;
 
         // Clone sees the same data
         let got = clone.get("dev1", SensorType::RelayState).await.unwrap();
@@ -1,17 +1,19 @@
-/// Saves raw Tuya API response bytes to `responses/{endpoint}/{timestamp}_{suffix}.json`
-/// for offline analysis.
+/// Saves raw Tuya API response bytes to `{dir}/{endpoint}/{timestamp}_{suffix}.json`
+/// for offline analysis, `dir` being the configured `RESPONSE_DIR`.
 ///
 /// Errors are logged and swallowed — saving is best-effort and must never
 /// interrupt normal application flow.
+use std::path::Path;
+
 use tokio::fs;
 use tracing::warn;
 
-/// Write `bytes` to `responses/{endpoint}/{timestamp}_{suffix}.json`.
+/// Write `bytes` to `{dir}/{endpoint}/{timestamp}_{suffix}.json`.
 ///
 /// - `endpoint`: used as the sub-directory name, e.g. `"token"` or `"device_status"`.
 /// - `suffix`: appended after the timestamp, e.g. a device ID. Pass `""` to omit.
 /// - `bytes`: the raw HTTP response body as received from Tuya.
-pub async fn save(endpoint: &str, suffix: &str, bytes: &[u8]) {
+pub async fn save(dir: &Path, endpoint: &str, suffix: &str, bytes: &[u8]) {
     let ts = chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
     let filename = if suffix.is_empty() {
         format!("{ts}.json")
@@ -19,11 +21,11 @@ pub async fn save(endpoint: &str, suffix: &str, bytes: &[u8]) {
         format!("{ts}_{suffix}.json")
     };
 
-    let dir = format!("responses/{endpoint}");
-    let path = format!("{dir}/{filename}");
+    let dir = dir.join(endpoint);
+    let path = dir.join(filename);
 
     if let Err(e) = fs::create_dir_all(&dir).await {
-        warn!(path = %path, error = %e, "response_store: failed to create directory");
+        warn!(path = %path.display(), error = %e, "response_store: failed to create directory");
         return;
     }
 
@@ -37,8 +39,8 @@ pub async fn save(endpoint: &str, suffix: &str, bytes: &[u8]) {
     };
 
     if let Err(e) = fs::write(&path, &content).await {
-        warn!(path = %path, error = %e, "response_store: failed to write response file");
+        warn!(path = %path.display(), error = %e, "response_store: failed to write response file");
     } else {
-        tracing::debug!(path = %path, bytes = content.len(), "response_store: saved");
+        tracing::debug!(path = %path.display(), bytes = content.len(), "response_store: saved");
     }
 }
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::models::{RoomDevice, RoomRole, SensorType};
+
+/// Weather station channels that can serve as a room's reference temperature.
+pub const PROBE_CHANNELS: [SensorType; 4] = [
+    SensorType::Temperature,
+    SensorType::Sub1Temperature,
+    SensorType::Sub2Temperature,
+    SensorType::Sub3Temperature,
+];
+
+/// A single reading of one device, e.g. channel `Sub2Temperature` of a
+/// weather station.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+    pub device_id: String,
+    pub channel: SensorType,
+}
+
+/// The devices assigned to one room.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoomDevices {
+    pub thermostat: Option<String>,
+    /// Reference temperature for the room's thermostat.
+    pub temperature_probe: Option<Probe>,
+    pub energy_meter: Option<String>,
+    pub contact_sensors: Vec<String>,
+}
+
+impl RoomDevices {
+    pub fn from_rows(rows: impl IntoIterator<Item = RoomDevice>) -> Self {
+        let mut devices = Self::default();
+        for row in rows {
+            match (row.role, row.channel) {
+                (RoomRole::Thermostat, _) => devices.thermostat = Some(row.device_id),
+                (RoomRole::TemperatureProbe, Some(channel)) => {
+                    devices.temperature_probe = Some(Probe {
+                        device_id: row.device_id,
+                        channel,
+                    })
+                }
+                (RoomRole::TemperatureProbe, None) => {}
+                (RoomRole::EnergyMeter, _) => devices.energy_meter = Some(row.device_id),
+                (RoomRole::ContactSensor, _) => devices.contact_sensors.push(row.device_id),
+            }
+        }
+        devices.contact_sensors.sort();
+        devices
+    }
+
+    /// `(role, device_id, channel)` for every assignment, as stored in `room_devices`.
+    pub fn rows(&self) -> Vec<(RoomRole, String, Option<SensorType>)> {
+        let mut rows = Vec::new();
+        if let Some(id) = &self.thermostat {
+            rows.push((RoomRole::Thermostat, id.clone(), None));
+        }
+        if let Some(p) = &self.temperature_probe {
+            rows.push((
+                RoomRole::TemperatureProbe,
+                p.device_id.clone(),
+                Some(p.channel),
+            ));
+        }
+        if let Some(id) = &self.energy_meter {
+            rows.push((RoomRole::EnergyMeter, id.clone(), None));
+        }
+        for id in &self.contact_sensors {
+            rows.push((RoomRole::ContactSensor, id.clone(), None));
+        }
+        rows
+    }
+
+    /// IDs of every device assigned to the room, probes included.
+    pub fn device_ids(&self) -> Vec<String> {
+        self.rows().into_iter().map(|(_, id, _)| id).collect()
+    }
+
+    /// Whether readings of `(device_id, sensor_type)` belong to this room.
+    ///
+    /// Whole devices contribute all their readings; a probe only its channel.
+    pub fn includes(&self, device_id: &str, sensor_type: SensorType) -> bool {
+        self.rows()
+            .iter()
+            .any(|(_, id, channel)| id == device_id && channel.is_none_or(|c| c == sensor_type))
+    }
+}
+
+/// Fetch the device assignments of the given rooms.
+pub async fn fetch_devices(
+    pool: &PgPool,
+    room_ids: &[Uuid],
+) -> sqlx::Result<HashMap<Uuid, RoomDevices>> {
+    let rows = sqlx::query_as!(
+        RoomDevice,
+        r#"
+        SELECT room_id,
+               role AS "role: RoomRole",
+               device_id,
+               channel AS "channel: SensorType"
+        FROM room_devices
+        WHERE room_id = ANY($1)
+        "#,
+        room_ids,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(group(rows))
+}
+
+/// Load the rooms that have a thermostat, keyed by thermostat ID.
+pub async fn load_by_thermostat(pool: &PgPool) -> sqlx::Result<HashMap<String, RoomDevices>> {
+    let rows = sqlx::query_as!(
+        RoomDevice,
+        r#"
+        SELECT room_id,
+               role AS "role: RoomRole",
+               device_id,
+               channel AS "channel: SensorType"
+        FROM room_devices
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(group(rows)
+        .into_values()
+        .filter_map(|room| Some((room.thermostat.clone()?, room)))
+        .collect())
+}
+
+fn group(rows: Vec<RoomDevice>) -> HashMap<Uuid, RoomDevices> {
+    let mut grouped: HashMap<Uuid, Vec<RoomDevice>> = HashMap::new();
+    for row in rows {
+        grouped.entry(row.room_id).or_default().push(row);
+    }
+    grouped
+        .into_iter()
+        .map(|(id, rows)| (id, RoomDevices::from_rows(rows)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(role: RoomRole, device_id: &str, channel: Option<SensorType>) -> RoomDevice {
+        RoomDevice {
+            room_id: Uuid::nil(),
+            role,
+            device_id: device_id.into(),
+            channel,
+        }
+    }
+
+    fn living_room() -> RoomDevices {
+        RoomDevices::from_rows([
+            row(RoomRole::ContactSensor, "c2", None),
+            row(RoomRole::Thermostat, "th1", None),
+            row(
+                RoomRole::TemperatureProbe,
+                "ws1",
+                Some(SensorType::Sub2Temperature),
+            ),
+            row(RoomRole::ContactSensor, "c1", None),
+        ])
+    }
+
+    #[test]
+    fn from_rows_groups_by_role() {
+        let room = living_room();
+        assert_eq!(room.thermostat.as_deref(), Some("th1"));
+        assert_eq!(
+            room.temperature_probe,
+            Some(Probe {
+                device_id: "ws1".into(),
+                channel: SensorType::Sub2Temperature
+            })
+        );
+        assert_eq!(room.energy_meter, None);
+        assert_eq!(room.contact_sensors, vec!["c1", "c2"]);
+    }
+
+    #[test]
+    fn rows_roundtrip() {
+        let room = living_room();
+        let rows = room
+            .rows()
+            .into_iter()
+            .map(|(role, id, channel)| row(role, &id, channel));
+        assert_eq!(RoomDevices::from_rows(rows), room);
+    }
+
+    #[test]
+    fn probe_contributes_only_its_channel() {
+        let room = living_room();
+        assert!(room.includes("th1", SensorType::Temperature));
+        assert!(room.includes("th1", SensorType::RelayState));
+        assert!(room.includes("ws1", SensorType::Sub2Temperature));
+        assert!(!room.includes("ws1", SensorType::Sub1Temperature));
+        assert!(!room.includes("em1", SensorType::PowerConsumption));
+    }
+}
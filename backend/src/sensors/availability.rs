@@ -0,0 +1,270 @@
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+
+use crate::tuya::TuyaError;
+
+/// Consecutive failed polls after which a device counts as offline.
+pub const OFFLINE_AFTER_FAILURES: u32 = 3;
+
+/// What is known about whether a device is reachable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AvailabilityStatus {
+    /// `None` until the device has been polled or reported on.
+    pub online: Option<bool>,
+    /// Failed polls since the last successful one.
+    pub consecutive_failures: u32,
+    /// Last successful poll since start-up.
+    pub last_success: Option<DateTime<Utc>>,
+}
+
+/// Online/offline state per device, fed by polling, by Tuya's `online` flag
+/// in device lists and by online/offline events. Transitions are stored in
+/// `device_availability`. Clones share the state, so the poller, the event
+/// ingest and the API all see the same devices.
+#[derive(Clone)]
+pub struct DeviceAvailability {
+    pool: PgPool,
+    inner: Arc<RwLock<HashMap<String, AvailabilityStatus>>>,
+}
+
+impl DeviceAvailability {
+    /// Start with nothing known.
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            inner: Arc::default(),
+        }
+    }
+
+    /// Continue from the last state stored for each device, so a restart
+    /// does not record transitions that did not happen.
+    pub async fn load(pool: PgPool) -> Result<Self, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT DISTINCT ON (device_id) device_id, online
+            FROM device_availability
+            ORDER BY device_id, changed_at DESC
+            "#
+        )
+        .fetch_all(&pool)
+        .await?;
+
+        let states = rows
+            .into_iter()
+            .map(|r| {
+                let status = AvailabilityStatus {
+                    online: Some(r.online),
+                    ..Default::default()
+                };
+                (r.device_id, status)
+            })
+            .collect();
+        Ok(Self {
+            pool,
+            inner: Arc::new(RwLock::new(states)),
+        })
+    }
+
+    pub async fn get(&self, device_id: &str) -> Option<AvailabilityStatus> {
+        self.inner.read().await.get(device_id).cloned()
+    }
+
+    /// Record the outcome of a poll. A device goes offline after
+    /// `OFFLINE_AFTER_FAILURES` failed polls in a row, or at once when Tuya
+    /// reports it offline; one successful poll brings it back.
+    pub async fn record_poll<T>(&self, device_id: &str, result: &Result<T, TuyaError>) {
+        let change = {
+            let mut states = self.inner.write().await;
+            let status = states.entry(device_id.to_owned()).or_default();
+            match result {
+                Ok(_) => {
+                    status.consecutive_failures = 0;
+                    status.last_success = Some(Utc::now());
+                    set(status, true, "poll succeeded".to_owned())
+                }
+                Err(e) => {
+                    status.consecutive_failures += 1;
+                    let failures = status.consecutive_failures;
+                    if e.is_device_offline() {
+                        set(status, false, "Tuya reports the device offline".to_owned())
+                    } else if failures >= OFFLINE_AFTER_FAILURES {
+                        set(
+                            status,
+                            false,
+                            format!("{failures} consecutive poll failures"),
+                        )
+                    } else {
+                        None
+                    }
+                }
+            }
+        };
+        if let Some((online, reason)) = change {
+            self.store(device_id, online, &reason).await;
+        }
+    }
+
+    /// Record Tuya's `online` flag for a device, e.g. from the device list.
+    pub async fn record_online_flag(&self, device_id: &str, online: bool) {
+        let change = {
+            let mut states = self.inner.write().await;
+            let status = states.entry(device_id.to_owned()).or_default();
+            let reason = if online {
+                "Tuya reports the device online"
+            } else {
+                "Tuya reports the device offline"
+            };
+            set(status, online, reason.to_owned())
+        };
+        if let Some((online, reason)) = change {
+            self.store(device_id, online, &reason).await;
+        }
+    }
+
+    /// Insert a transition. The in-memory state has already changed, so a
+    /// failed insert only leaves a gap in the uptime history and is logged
+    /// rather than returned.
+    async fn store(&self, device_id: &str, online: bool, reason: &str) {
+        if online {
+            info!(device_id = %device_id, reason, "Device online");
+        } else {
+            warn!(device_id = %device_id, reason, "Device offline");
+        }
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO device_availability (device_id, online, reason)
+            VALUES ($1, $2, $3)
+            "#,
+            device_id,
+            online,
+            reason,
+        )
+        .execute(&self.pool)
+        .await;
+        if let Err(e) = inserted {
+            error!(device_id = %device_id, error = %e, "Failed to store availability change");
+        }
+    }
+}
+
+/// Set `status` online or offline; returns the transition if it changed.
+fn set(status: &mut AvailabilityStatus, online: bool, reason: String) -> Option<(bool, String)> {
+    if status.online == Some(online) {
+        return None;
+    }
+    status.online = Some(online);
+    Some((online, reason))
+}
+
+/// Percentage of the known time in `[from, to)` the device was online.
+/// `before` is its state at `from`, if known; `transitions` are
+/// `(changed_at, online)` in time order, all at or after `from`. `None` if
+/// nothing is known about the window.
+pub fn uptime_percent(
+    before: Option<bool>,
+    transitions: &[(DateTime<Utc>, bool)],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Option<f64> {
+    let mut state = before.map(|online| (from, online));
+    let (mut known, mut online_time) = (0, 0);
+    let changes = transitions
+        .iter()
+        .filter(|(at, _)| *at < to)
+        .copied()
+        .chain([(to, false)]);
+    for (at, online) in changes {
+        if let Some((since, was_online)) = state {
+            let span = (at - since).num_milliseconds();
+            known += span;
+            if was_online {
+                online_time += span;
+            }
+        }
+        state = Some((at, online));
+    }
+    (known > 0).then(|| online_time as f64 * 100.0 / known as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::tuya::models::TuyaApiError;
+
+    fn offline() -> TuyaError {
+        TuyaError::classify(TuyaApiError {
+            code: 2001,
+            msg: "device is offline".into(),
+            tid: None,
+        })
+    }
+
+    async fn transitions(pool: &PgPool) -> Vec<(bool, String)> {
+        sqlx::query!("SELECT online, reason FROM device_availability ORDER BY changed_at")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.online, r.reason))
+            .collect()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn repeated_failures_take_a_device_offline(pool: PgPool) {
+        let availability = DeviceAvailability::new(pool.clone());
+        let failed: Result<(), _> = Err(TuyaError::Decode("timeout".into()));
+
+        availability.record_poll("th1", &Ok(())).await;
+        for _ in 0..OFFLINE_AFTER_FAILURES {
+            availability.record_poll("th1", &failed).await;
+        }
+        let status = availability.get("th1").await.unwrap();
+        assert_eq!(status.online, Some(false));
+        assert_eq!(status.consecutive_failures, OFFLINE_AFTER_FAILURES);
+        assert!(status.last_success.is_some());
+
+        availability.record_poll("th1", &Ok(())).await;
+        assert_eq!(
+            transitions(&pool).await,
+            [
+                (true, "poll succeeded".to_owned()),
+                (false, "3 consecutive poll failures".to_owned()),
+                (true, "poll succeeded".to_owned()),
+            ]
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn tuya_offline_flag_applies_at_once_and_survives_restarts(pool: PgPool) {
+        let availability = DeviceAvailability::new(pool.clone());
+        availability.record_online_flag("th1", true).await;
+        availability.record_poll::<()>("th1", &Err(offline())).await;
+        assert_eq!(availability.get("th1").await.unwrap().online, Some(false));
+
+        let restarted = DeviceAvailability::load(pool.clone()).await.unwrap();
+        restarted.record_online_flag("th1", false).await;
+        assert_eq!(transitions(&pool).await.len(), 2);
+    }
+
+    #[test]
+    fn uptime_counts_only_known_time() {
+        let from = DateTime::<Utc>::from_timestamp(0, 0).unwrap();
+        let to = from + Duration::hours(10);
+        let at = |h| from + Duration::hours(h);
+
+        // Online at the start, offline for 2 of 10 hours.
+        let changes = [(at(4), false), (at(6), true)];
+        assert_eq!(uptime_percent(Some(true), &changes, from, to), Some(80.0));
+        // First seen after 5 hours, offline for 1 of the remaining 5.
+        let changes = [(at(5), true), (at(9), false)];
+        assert_eq!(uptime_percent(None, &changes, from, to), Some(80.0));
+        assert_eq!(uptime_percent(None, &[], from, to), None);
+        assert_eq!(uptime_percent(Some(false), &[], from, to), Some(0.0));
+    }
+}
@@ -1,4 +1,7 @@
+pub mod availability;
+pub mod poller;
 pub mod service;
 
+pub use availability::DeviceAvailability;
+pub use poller::PollSettings;
 pub use service::SensorService;
-
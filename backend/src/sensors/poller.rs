@@ -0,0 +1,296 @@
+//! Sensor polling loop: each device on its own interval, a bounded number of
+//! calls in flight, and a timeout per call, so one slow call never delays
+//! the others. A call fetches a status batch of up to
+//! [`STATUS_BATCH_SIZE`] devices, or a single weather station.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use tokio::{
+    sync::Semaphore,
+    task::{self, JoinSet},
+    time::{self, Instant, MissedTickBehavior},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{Config, DeviceType},
+    tuya::{DeviceBackend, STATUS_BATCH_SIZE},
+};
+
+use super::SensorService;
+
+/// How often the loop checks which devices are due.
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct PollSettings {
+    /// Interval of devices without one of their own.
+    pub interval: Duration,
+    /// Interval per device.
+    pub intervals: HashMap<String, Duration>,
+    /// Most poll calls in flight at once.
+    pub concurrency: usize,
+    /// A call still unanswered after this long is abandoned; its devices are
+    /// polled again when next due. The timeout covers the whole call, so all
+    /// devices of a status batch share it.
+    pub timeout: Duration,
+}
+
+impl PollSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            interval: Duration::from_secs(config.poll_interval_secs),
+            intervals: config
+                .poll_intervals
+                .iter()
+                .map(|(id, secs)| (id.clone(), Duration::from_secs(*secs)))
+                .collect(),
+            concurrency: config.poll_concurrency,
+            timeout: Duration::from_secs(config.poll_timeout_secs),
+        }
+    }
+
+    /// Polling interval of `device_id`.
+    pub fn interval(&self, device_id: &str) -> Duration {
+        self.intervals
+            .get(device_id)
+            .copied()
+            .unwrap_or(self.interval)
+    }
+}
+
+/// When each device is next due.
+#[derive(Debug)]
+struct Schedule {
+    next: HashMap<String, Instant>,
+}
+
+impl Schedule {
+    /// Every device is due at `now`.
+    fn new<'a>(device_ids: impl Iterator<Item = &'a String>, now: Instant) -> Self {
+        Self {
+            next: device_ids.map(|id| (id.clone(), now)).collect(),
+        }
+    }
+
+    /// Devices due at `now`, in ID order, each next due its `factor` times
+    /// its interval later. Busy devices stay due until they are free.
+    fn due(
+        &mut self,
+        now: Instant,
+        settings: &PollSettings,
+        factor: impl Fn(&str) -> u32,
+        busy: impl Fn(&str) -> bool,
+    ) -> Vec<String> {
+        let mut due: Vec<String> = self
+            .next
+            .iter()
+            .filter(|(id, at)| **at <= now && !busy(id))
+            .map(|(id, _)| id.clone())
+            .collect();
+        due.sort();
+        for id in &due {
+            self.next
+                .insert(id.clone(), now + settings.interval(id) * factor(id));
+        }
+        due
+    }
+}
+
+impl<B: DeviceBackend + 'static> SensorService<B> {
+    /// Poll every device on its interval, stretched by the poll factor of
+    /// its account's quota. Due devices are grouped into one call per status batch, and
+    /// one per weather station. Runs forever; spawn it next to the control
+    /// loop.
+    pub async fn run(self, settings: PollSettings) {
+        info!(
+            interval_secs = settings.interval.as_secs(),
+            own_intervals = settings.intervals.len(),
+            concurrency = settings.concurrency,
+            timeout_secs = settings.timeout.as_secs(),
+            "Sensor polling loop started"
+        );
+        let service = Arc::new(self);
+        let permits = Arc::new(Semaphore::new(settings.concurrency));
+        let mut schedule = Schedule::new(service.device_ids(), Instant::now());
+        let mut tasks = JoinSet::new();
+        // Devices of each call in flight, by task.
+        let mut in_flight: HashMap<task::Id, Vec<String>> = HashMap::new();
+
+        let mut ticker = time::interval(TICK);
+        // After a stall, check once instead of catching up on every missed tick.
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            while let Some(done) = tasks.try_join_next_with_id() {
+                let id = match done {
+                    Ok((id, ())) => id,
+                    Err(e) => {
+                        error!(error = %e, "Sensor poll task failed");
+                        e.id()
+                    }
+                };
+                in_flight.remove(&id);
+            }
+
+            let due = schedule.due(
+                Instant::now(),
+                &settings,
+                |id| service.poll_factor(id),
+                |id| in_flight.values().flatten().any(|busy| busy == id),
+            );
+            for call in calls(&service, due) {
+                let service = service.clone();
+                let permits = permits.clone();
+                let timeout = settings.timeout;
+                let device_ids = call.clone();
+                let handle = tasks.spawn(async move {
+                    let _permit = permits.acquire().await.expect("semaphore is never closed");
+                    match time::timeout(timeout, service.poll(&device_ids)).await {
+                        Ok(failures) => {
+                            for (device_id, e) in failures {
+                                error!(
+                                    device_id = %device_id,
+                                    error = %e,
+                                    "Failed to fetch sensor reading"
+                                );
+                            }
+                        }
+                        Err(_) => {
+                            warn!(
+                                devices = ?device_ids,
+                                timeout_secs = timeout.as_secs(),
+                                "Sensor poll timed out; abandoning it"
+                            );
+                            for device_id in &device_ids {
+                                service.record_timeout(device_id, timeout).await;
+                            }
+                        }
+                    }
+                });
+                in_flight.insert(handle.id(), call);
+            }
+        }
+    }
+}
+
+/// Split due devices into calls: batches of devices with a v1 status
+/// endpoint, and each weather station on its own.
+fn calls<B: DeviceBackend>(service: &SensorService<B>, due: Vec<String>) -> Vec<Vec<String>> {
+    let (stations, devices): (Vec<_>, Vec<_>) = due
+        .into_iter()
+        .partition(|id| service.device_type(id) == Some(DeviceType::WeatherStation));
+    devices
+        .chunks(STATUS_BATCH_SIZE)
+        .map(<[String]>::to_vec)
+        .chain(stations.into_iter().map(|id| vec![id]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{
+        reading_cache::ReadingCache,
+        sensors::{availability::OFFLINE_AFTER_FAILURES, DeviceAvailability},
+        tuya::fake::FakeBackend,
+    };
+
+    fn settings() -> PollSettings {
+        PollSettings {
+            interval: Duration::from_secs(60),
+            intervals: HashMap::from([("em1".to_owned(), Duration::from_secs(2))]),
+            concurrency: 2,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn devices_are_due_on_their_own_interval() {
+        let ids = ["em1".to_owned(), "th1".to_owned()];
+        let start = Instant::now();
+        let mut schedule = Schedule::new(ids.iter(), start);
+        let due = |schedule: &mut Schedule, secs, factor| {
+            schedule.due(
+                start + Duration::from_secs(secs),
+                &settings(),
+                |_| factor,
+                |_| false,
+            )
+        };
+
+        assert_eq!(due(&mut schedule, 0, 1), ["em1", "th1"]);
+        assert!(due(&mut schedule, 1, 1).is_empty());
+        assert_eq!(due(&mut schedule, 2, 1), ["em1"]);
+        // Degraded: twice the interval.
+        assert_eq!(due(&mut schedule, 4, 2), ["em1"]);
+        assert!(due(&mut schedule, 6, 1).is_empty());
+        assert_eq!(due(&mut schedule, 60, 1), ["em1", "th1"]);
+
+        // A busy device stays due until it is free.
+        let busy = schedule.due(
+            start + Duration::from_secs(62),
+            &settings(),
+            |_| 1,
+            |id| id == "em1",
+        );
+        assert!(busy.is_empty());
+        assert_eq!(due(&mut schedule, 63, 1), ["em1"]);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn hung_device_does_not_stall_the_others(pool: PgPool) {
+        time::pause();
+        let backend = FakeBackend::new();
+        backend.hang("ws1");
+        let service = SensorService::new(
+            pool,
+            backend.clone(),
+            ReadingCache::new(),
+            HashMap::from([
+                ("em1".to_owned(), DeviceType::EnergyMeter),
+                ("ws1".to_owned(), DeviceType::WeatherStation),
+            ]),
+        );
+        let poller = tokio::spawn(service.run(settings()));
+
+        time::sleep(Duration::from_millis(10_500)).await;
+        poller.abort();
+
+        // Polled at 0, 2, 4, 6, 8 and 10 s while ws1 hangs.
+        assert_eq!(backend.calls("em1"), 6);
+        // Abandoned after 5 s and not due again before 60 s.
+        assert_eq!(backend.calls("ws1"), 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn timed_out_device_goes_offline(pool: PgPool) {
+        time::pause();
+        let backend = FakeBackend::new();
+        backend.hang("ws1");
+        let availability = DeviceAvailability::new(pool.clone());
+        let service = SensorService::new(
+            pool,
+            backend,
+            ReadingCache::new(),
+            HashMap::from([("ws1".to_owned(), DeviceType::WeatherStation)]),
+        )
+        .with_availability(availability.clone());
+        let settings = PollSettings {
+            intervals: HashMap::from([("ws1".to_owned(), Duration::from_secs(2))]),
+            timeout: Duration::from_secs(1),
+            ..settings()
+        };
+        let poller = tokio::spawn(service.run(settings));
+
+        // Polls at 0, 2 and 4 s, each abandoned a second later.
+        time::sleep(Duration::from_millis(5_500)).await;
+        poller.abort();
+
+        let status = availability.get("ws1").await.unwrap();
+        assert_eq!(status.consecutive_failures, OFFLINE_AFTER_FAILURES);
+        assert_eq!(status.online, Some(false));
+    }
+}
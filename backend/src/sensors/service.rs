@@ -1,34 +1,52 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use sqlx::PgPool;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
 use crate::{
     config::DeviceType,
     db::models::{SensorReading, SensorType},
     reading_cache::ReadingCache,
+    sensors::DeviceAvailability,
     tuya::{
-        models::{EnergyMeterStatus, ThermostatStatus, WeatherStationStatus},
-        TuyaClient,
+        models::{
+            ContactSensorStatus, DeviceProperty, DeviceSpecs, EnergyMeterStatus, PhaseReading,
+            ThermostatStatus, WeatherStationStatus,
+        },
+        DeviceBackend, TuyaClient, TuyaError,
     },
 };
 
-pub struct SensorService {
+pub struct SensorService<B = TuyaClient> {
     pool: PgPool,
-    tuya: TuyaClient,
+    backend: B,
     cache: ReadingCache,
     device_ids: HashMap<String, DeviceType>,
+    /// Fed with the outcome of every poll, when set.
+    availability: Option<DeviceAvailability>,
 }
 
-impl SensorService {
+impl<B: DeviceBackend> SensorService<B> {
     pub fn new(
         pool: PgPool,
-        tuya: TuyaClient,
+        backend: B,
         cache: ReadingCache,
         device_ids: HashMap<String, DeviceType>,
     ) -> Self {
-        Self { pool, tuya, cache, device_ids }
+        Self {
+            pool,
+            backend,
+            cache,
+            device_ids,
+            availability: None,
+        }
+    }
+
+    /// Track whether devices are reachable from the outcome of each poll.
+    pub fn with_availability(mut self, availability: DeviceAvailability) -> Self {
+        self.availability = Some(availability);
+        self
     }
 
     /// Returns the set of device IDs this service is configured to poll.
@@ -36,75 +54,150 @@ impl SensorService {
         self.device_ids.keys()
     }
 
+    /// Configured type of `device_id`.
+    pub fn device_type(&self, device_id: &str) -> Option<DeviceType> {
+        self.device_ids.get(device_id).cloned()
+    }
+
+    /// Multiplier for the polling interval of `device_id`; see
+    /// [`DeviceBackend::poll_factor`].
+    pub fn poll_factor(&self, device_id: &str) -> u32 {
+        self.backend.poll_factor(device_id)
+    }
+
     /// Fetches the current status of `device_id` from Tuya using the endpoint
     /// appropriate for its device type, maps each DP to a `(SensorType, i64)`
-    /// pair, inserts one row per DP, and updates the shared in-memory cache.
-    ///
-    /// # Value encoding
-    ///
-    /// All values are stored as `round(real_value * 100)` per DB convention:
-    ///
-    /// | Device         | DP            | Raw | Real     | Stored |
-    /// |----------------|---------------|-----|----------|--------|
-    /// | Thermostat     | temp_current  | 189 | 18.9 °C  | 1890   |
-    /// | Thermostat     | temp_set      | 220 | 22.0 °C  | 2200   |
-    /// | EnergyMeter    | temp_current  |  16 | 16.0 °C  | 1600   |
-    /// | WeatherStation | local_temp    | 208 | 20.8 °C  | 2080   |
-    /// | WeatherStation | local_hum     |  51 | 51 %     | 5100   |
+    /// pair with [`encode_dps`], inserts one row per DP, and updates the
+    /// shared in-memory cache.
     pub async fn fetch_and_persist(&self, device_id: &str) -> Result<()> {
         info!(device_id = %device_id, "Fetching sensor readings");
 
-        let readings = match self.device_ids.get(device_id) {
-            Some(DeviceType::Thermostat) => {
-                let dps = self.tuya.get_device_status(device_id).await?;
-                let s = ThermostatStatus::try_from(dps.as_slice())?;
-                // Raw ÷ 10 = °C → stored as °C × 100 = raw × 10
-                vec![
-                    (SensorType::Temperature, s.temp_current * 10),
-                    (SensorType::TemperatureSetpoint, s.temp_set * 10),
-                    (SensorType::RelayState, encode_bool(s.switch)),
-                ]
-            }
+        let Some(kind) = self.device_ids.get(device_id) else {
+            warn!(
+                device_id = %device_id,
+                "No device type configured for this device — skipping DP mapping. \
+                 Add it to TUYA_DEVICE_IDS."
+            );
+            return Ok(());
+        };
 
-            Some(DeviceType::EnergyMeter) => {
-                let dps = self.tuya.get_device_status(device_id).await?;
-                let s = EnergyMeterStatus::try_from(dps.as_slice())?;
-                let mut r = vec![(SensorType::RelayState, encode_bool(s.switch))];
-                // Raw is already in °C (×1) → stored as °C × 100
-                if let Some(t) = s.temp_current {
-                    r.push((SensorType::Temperature, t * 100));
-                }
-                r
-            }
+        let specs = self.specs(device_id).await;
+        let dps = self.fetch(device_id, kind).await?;
+        self.persist(device_id, encode_dps(kind, &dps, &specs))
+            .await?;
 
-            Some(DeviceType::WeatherStation) => {
-                let props = self.tuya.get_weather_station_status(device_id).await?;
-                let s = WeatherStationStatus::try_from(props.as_slice())?;
-                // Raw ÷ 10 = °C → stored as °C × 100 = raw × 10
-                // Raw humidity is already % → stored as % × 100
-                let mut r = vec![
-                    (SensorType::Temperature, s.local_temp * 10),
-                    (SensorType::Humidity, s.local_hum * 100),
-                ];
-                if let Some(v) = s.sub1_temp { r.push((SensorType::Sub1Temperature, v * 10)); }
-                if let Some(v) = s.sub1_hum  { r.push((SensorType::Sub1Humidity,    v * 100)); }
-                if let Some(v) = s.sub2_temp { r.push((SensorType::Sub2Temperature, v * 10)); }
-                if let Some(v) = s.sub2_hum  { r.push((SensorType::Sub2Humidity,    v * 100)); }
-                if let Some(v) = s.sub3_temp { r.push((SensorType::Sub3Temperature, v * 10)); }
-                if let Some(v) = s.sub3_hum  { r.push((SensorType::Sub3Humidity,    v * 100)); }
-                r
-            }
+        info!(device_id = %device_id, "Sensor readings persisted and cache updated");
+        Ok(())
+    }
 
-            None => {
-                warn!(
-                    device_id = %device_id,
-                    "No device type configured for this device — skipping DP mapping. \
-                     Add it to TUYA_DEVICE_IDS."
-                );
-                vec![]
+    /// Poll `device_ids` once. Devices with a v1 status endpoint are fetched
+    /// together, one round trip per batch; weather stations are fetched one
+    /// by one. Returns the devices that failed, with the error.
+    pub async fn poll(&self, device_ids: &[String]) -> Vec<(String, anyhow::Error)> {
+        let (stations, devices): (Vec<_>, Vec<_>) = device_ids
+            .iter()
+            .filter(|id| self.device_ids.contains_key(*id))
+            .cloned()
+            .partition(|id| self.device_ids[id] == DeviceType::WeatherStation);
+
+        let mut failures = Vec::new();
+        let mut statuses = self.backend.get_devices_status(&devices).await;
+        for device_id in devices {
+            let status = statuses
+                .remove(&device_id)
+                .unwrap_or_else(|| Err(TuyaError::Decode("no status returned".into())));
+            if let Err(e) = self.persist_status(&device_id, status).await {
+                failures.push((device_id, e));
+            }
+        }
+        for device_id in stations {
+            if let Err(e) = self.fetch_and_persist(&device_id).await {
+                failures.push((device_id, e));
             }
+        }
+        failures
+    }
+
+    /// Check and store a polled status.
+    async fn persist_status(
+        &self,
+        device_id: &str,
+        status: Result<Vec<DeviceProperty>, TuyaError>,
+    ) -> Result<()> {
+        let kind = &self.device_ids[device_id];
+        self.record_poll(device_id, &status).await;
+        let dps = status?;
+        check(kind, &dps)?;
+        let specs = self.specs(device_id).await;
+        self.persist(device_id, encode_dps(kind, &dps, &specs))
+            .await?;
+
+        debug!(device_id = %device_id, "Sensor readings persisted and cache updated");
+        Ok(())
+    }
+
+    /// Persist DPs reported by `device_id` outside of polling, e.g. by a
+    /// status-change event. Only the DPs present are stored; DPs of devices
+    /// that are not configured are ignored.
+    pub async fn persist_dps(&self, device_id: &str, dps: &[DeviceProperty]) -> Result<()> {
+        let Some(kind) = self.device_ids.get(device_id) else {
+            debug!(device_id = %device_id, "Ignoring DPs of unconfigured device");
+            return Ok(());
         };
 
+        let specs = self.specs(device_id).await;
+        self.persist(device_id, encode_dps(kind, dps, &specs)).await
+    }
+
+    /// Fetch the full status of a device, checking that the DPs its type
+    /// requires are present.
+    async fn fetch(&self, device_id: &str, kind: &DeviceType) -> Result<Vec<DeviceProperty>> {
+        if *kind == DeviceType::WeatherStation {
+            let props = self.backend.get_weather_station_status(device_id).await;
+            self.record_poll(device_id, &props).await;
+            let props = props?;
+            WeatherStationStatus::try_from(props.as_slice())?;
+            return Ok(props
+                .into_iter()
+                .map(|p| DeviceProperty {
+                    code: p.code,
+                    value: p.value,
+                })
+                .collect());
+        }
+
+        let dps = self.backend.get_device_status(device_id).await;
+        self.record_poll(device_id, &dps).await;
+        let dps = dps?;
+        check(kind, &dps)?;
+        Ok(dps)
+    }
+
+    async fn record_poll<T>(&self, device_id: &str, result: &Result<T, TuyaError>) {
+        if let Some(availability) = &self.availability {
+            availability.record_poll(device_id, result).await;
+        }
+    }
+
+    /// Count a poll of `device_id` abandoned after `timeout` as failed.
+    pub async fn record_timeout(&self, device_id: &str, timeout: Duration) {
+        self.record_poll::<()>(device_id, &Err(TuyaError::Timeout(timeout)))
+            .await;
+    }
+
+    /// Record Tuya's `online` flag for `device_id`, e.g. from an
+    /// online/offline event. Devices that are not configured are ignored.
+    pub async fn record_online_flag(&self, device_id: &str, online: bool) {
+        if !self.device_ids.contains_key(device_id) {
+            return;
+        }
+        if let Some(availability) = &self.availability {
+            availability.record_online_flag(device_id, online).await;
+        }
+    }
+
+    /// Insert one row per reading and update the cache.
+    async fn persist(&self, device_id: &str, readings: Vec<(SensorType, i64)>) -> Result<()> {
         for (sensor_type, value) in readings {
             let reading = sqlx::query_as!(
                 SensorReading,
@@ -126,10 +219,211 @@ impl SensorService {
                 self.cache.update(reading).await;
             }
         }
-
-        info!(device_id = %device_id, "Sensor readings persisted and cache updated");
         Ok(())
     }
+
+    /// DP specifications of `device_id`, or empty ones (so default scales
+    /// apply) if they can not be fetched.
+    async fn specs(&self, device_id: &str) -> Arc<DeviceSpecs> {
+        match self.backend.device_specs(device_id).await {
+            Ok(specs) => specs,
+            Err(e) => {
+                warn!(device_id = %device_id, error = %e, "No DP specifications; default scales");
+                Arc::default()
+            }
+        }
+    }
+}
+
+/// Check that `dps` has the DPs devices of type `kind` require.
+fn check(kind: &DeviceType, dps: &[DeviceProperty]) -> Result<()> {
+    match kind {
+        DeviceType::Thermostat => {
+            ThermostatStatus::try_from(dps)?;
+        }
+        DeviceType::EnergyMeter => {
+            // A malformed phase blob fails the poll instead of silently
+            // dropping the phase's readings.
+            EnergyMeterStatus::try_from(dps)?.phases()?;
+        }
+        DeviceType::ContactSensor => {
+            ContactSensorStatus::try_from(dps)?;
+        }
+        DeviceType::WeatherStation => {}
+    }
+    Ok(())
+}
+
+/// How a reported DP is stored.
+#[derive(Debug, Clone, Copy)]
+enum Encoding {
+    /// `false` → 0, `true` → 1.
+    Bool,
+    /// Integer stored as reported, e.g. a fault bitmask.
+    Raw,
+    /// Integer scaled by the DP specification; the default scale applies if
+    /// the device has no specification for it.
+    Scaled(u32),
+    /// One quantity of an energy meter phase blob.
+    Phase(PhaseQuantity),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PhaseQuantity {
+    Voltage,
+    Current,
+    Power,
+}
+
+/// Scale of a thermostat's `temp_set` when its specification gives none.
+const SETPOINT_DEFAULT_SCALE: u32 = 1;
+
+/// Scale a thermostat's `temp_set` is reported and commanded in.
+pub(crate) fn setpoint_scale(specs: &DeviceSpecs) -> u32 {
+    specs.scale("temp_set").unwrap_or(SETPOINT_DEFAULT_SCALE)
+}
+
+/// Reported DPs stored as readings, per device type.
+///
+/// All values are stored as `round(real_value * 100)` per DB convention,
+/// where `real_value = raw ÷ 10^scale`:
+///
+/// | Device         | DP            | Scale | Raw | Real     | Stored |
+/// |----------------|---------------|-------|-----|----------|--------|
+/// | Thermostat     | temp_current  | 1     | 189 | 18.9 °C  | 1890   |
+/// | Thermostat     | temp_set      | 1     | 220 | 22.0 °C  | 2200   |
+/// | Thermostat     | upper_temp    | 0     |  60 | 60 °C    | 6000   |
+/// | Thermostat     | fault         | –     |   2 | bitmask  | 2      |
+/// | EnergyMeter    | temp_current  | 0     |  16 | 16.0 °C  | 1600   |
+/// | EnergyMeter    | phase_b V     | 1     |2281 | 228.1 V  | 22810  |
+/// | EnergyMeter    | phase_b mA    | 0     |  21 | 21 mA    | 2100   |
+/// | EnergyMeter    | phase_b W     | 0     |   1 | 1 W      | 100    |
+/// | WeatherStation | local_temp    | 1     | 208 | 20.8 °C  | 2080   |
+/// | WeatherStation | local_hum     | 0     |  51 | 51 %     | 5100   |
+///
+/// The meter's energy counters (`total_forward_energy`,
+/// `reverse_energy_total`, scale 2) are stored as kWh × 100, e.g. 531309 →
+/// 5313.09 kWh.
+///
+/// Boolean DPs (`switch`, `doorcontact_state`) are stored as 0/1. Phase
+/// quantities are decoded from the `phase_a/b/c` blobs (see [`PhaseReading`]);
+/// the meter's total active power is stored as `PowerConsumption` by
+/// [`encode_dps`].
+fn dp_readings(kind: &DeviceType) -> &'static [(&'static str, SensorType, Encoding)] {
+    use Encoding::*;
+    use SensorType::*;
+    match kind {
+        DeviceType::Thermostat => &[
+            ("temp_current", Temperature, Scaled(1)),
+            (
+                "temp_set",
+                TemperatureSetpoint,
+                Scaled(SETPOINT_DEFAULT_SCALE),
+            ),
+            ("switch", RelayState, Bool),
+            ("fault", Fault, Raw),
+            ("upper_temp", MaxSetpoint, Scaled(0)),
+        ],
+        DeviceType::EnergyMeter => &[
+            ("switch", RelayState, Bool),
+            ("temp_current", Temperature, Scaled(0)),
+            ("total_forward_energy", ForwardEnergy, Scaled(2)),
+            ("reverse_energy_total", ReverseEnergy, Scaled(2)),
+            ("phase_a", PhaseAVoltage, Phase(PhaseQuantity::Voltage)),
+            ("phase_a", PhaseACurrent, Phase(PhaseQuantity::Current)),
+            ("phase_a", PhaseAPower, Phase(PhaseQuantity::Power)),
+            ("phase_b", PhaseBVoltage, Phase(PhaseQuantity::Voltage)),
+            ("phase_b", PhaseBCurrent, Phase(PhaseQuantity::Current)),
+            ("phase_b", PhaseBPower, Phase(PhaseQuantity::Power)),
+            ("phase_c", PhaseCVoltage, Phase(PhaseQuantity::Voltage)),
+            ("phase_c", PhaseCCurrent, Phase(PhaseQuantity::Current)),
+            ("phase_c", PhaseCPower, Phase(PhaseQuantity::Power)),
+        ],
+        DeviceType::WeatherStation => &[
+            ("local_temp", Temperature, Scaled(1)),
+            ("local_hum", Humidity, Scaled(0)),
+            ("sub1_temp", Sub1Temperature, Scaled(1)),
+            ("sub1_hum", Sub1Humidity, Scaled(0)),
+            ("sub2_temp", Sub2Temperature, Scaled(1)),
+            ("sub2_hum", Sub2Humidity, Scaled(0)),
+            ("sub3_temp", Sub3Temperature, Scaled(1)),
+            ("sub3_hum", Sub3Humidity, Scaled(0)),
+        ],
+        DeviceType::ContactSensor => &[("doorcontact_state", DoorOpen, Bool)],
+    }
+}
+
+/// Map the DPs of a device of type `kind` to readings. DPs that are not
+/// stored, or whose value has an unexpected type, are skipped. An energy
+/// meter reporting all three phases also gets its total active power.
+pub(crate) fn encode_dps(
+    kind: &DeviceType,
+    dps: &[DeviceProperty],
+    specs: &DeviceSpecs,
+) -> Vec<(SensorType, i64)> {
+    let mut readings: Vec<_> = dp_readings(kind)
+        .iter()
+        .filter_map(|&(code, sensor_type, encoding)| {
+            let value = &dps.iter().find(|dp| dp.code == code)?.value;
+            let encoded = match encoding {
+                Encoding::Bool => encode_bool(value.as_bool()?),
+                Encoding::Raw => value.as_i64()?,
+                Encoding::Scaled(default_scale) => {
+                    encode_scaled(value.as_i64()?, specs.scale(code).unwrap_or(default_scale))
+                }
+                Encoding::Phase(quantity) => {
+                    encode_phase(&PhaseReading::decode(value.as_str()?).ok()?, quantity)
+                }
+            };
+            Some((sensor_type, encoded))
+        })
+        .collect();
+
+    if *kind == DeviceType::EnergyMeter {
+        let power: Option<i64> = ["phase_a", "phase_b", "phase_c"]
+            .into_iter()
+            .map(|code| {
+                let value = dps.iter().find(|dp| dp.code == code)?.value.as_str()?;
+                let phase = PhaseReading::decode(value).ok()?;
+                Some(encode_phase(&phase, PhaseQuantity::Power))
+            })
+            .sum();
+        if let Some(power) = power {
+            readings.push((SensorType::PowerConsumption, power));
+        }
+    }
+    readings
+}
+
+/// Encode one quantity of a phase as `round(real_value * 100)`, with the
+/// voltage in V, the current in mA and the power in W. The current is kept
+/// in mA so that small loads are not rounded away.
+fn encode_phase(phase: &PhaseReading, quantity: PhaseQuantity) -> i64 {
+    match quantity {
+        PhaseQuantity::Voltage => encode_scaled(phase.voltage.into(), 1),
+        PhaseQuantity::Current => encode_scaled(phase.current_ma.into(), 0),
+        PhaseQuantity::Power => encode_scaled(phase.power_w.into(), 0),
+    }
+}
+
+/// Encode a raw integer DP as `round(real_value * 100)`, where
+/// `real_value = raw ÷ 10^scale`.
+pub(crate) fn encode_scaled(raw: i64, scale: u32) -> i64 {
+    if scale <= 2 {
+        raw * 10_i64.pow(2 - scale)
+    } else {
+        (raw as f64 / 10_f64.powi(scale as i32 - 2)).round() as i64
+    }
+}
+
+/// Raw integer DP for a value stored as `round(real_value * 100)`; the
+/// inverse of [`encode_scaled`], rounded to the DP's resolution.
+pub(crate) fn decode_scaled(stored: i64, scale: u32) -> i64 {
+    if scale >= 2 {
+        stored * 10_i64.pow(scale - 2)
+    } else {
+        (stored as f64 / 10_f64.powi(2 - scale as i32)).round() as i64
+    }
 }
 
 /// Encode a boolean reading as an integer (`false` → 0, `true` → 1).
@@ -151,4 +445,249 @@ mod tests {
     fn encode_bool_false_is_zero() {
         assert_eq!(encode_bool(false), 0);
     }
+
+    #[test]
+    fn encode_scaled_stores_hundredths() {
+        assert_eq!(encode_scaled(16, 0), 1600);
+        assert_eq!(encode_scaled(189, 1), 1890);
+        assert_eq!(encode_scaled(1895, 2), 1895);
+        assert_eq!(encode_scaled(18956, 3), 1896);
+        assert_eq!(encode_scaled(-55, 1), -550);
+    }
+
+    #[test]
+    fn decode_scaled_rounds_to_the_dp_resolution() {
+        assert_eq!(decode_scaled(2146, 1), 215);
+        assert_eq!(decode_scaled(2146, 2), 2146);
+        assert_eq!(decode_scaled(2146, 3), 21460);
+        assert_eq!(decode_scaled(2150, 0), 22);
+    }
+
+    #[test]
+    fn energy_meter_counters_and_phases_are_stored() {
+        let dps: Vec<DeviceProperty> = serde_json::from_value(serde_json::json!([
+            {"code": "switch", "value": true},
+            {"code": "total_forward_energy", "value": 531309},
+            {"code": "phase_a", "value": "CPAAAAAAAAA="},
+            {"code": "phase_b", "value": "COkAABUAAAE="},
+            {"code": "phase_c", "value": "COMAACEAAAY="}
+        ]))
+        .unwrap();
+        let specs = DeviceSpecs::default();
+        let readings: HashMap<_, _> = encode_dps(&DeviceType::EnergyMeter, &dps, &specs)
+            .into_iter()
+            .collect();
+
+        assert_eq!(readings[&SensorType::PhaseAVoltage], 22880);
+        assert_eq!(readings[&SensorType::PhaseAPower], 0);
+        assert_eq!(readings[&SensorType::PhaseBVoltage], 22810);
+        assert_eq!(readings[&SensorType::PhaseBCurrent], 2100);
+        assert_eq!(readings[&SensorType::PhaseCCurrent], 3300);
+        assert_eq!(readings[&SensorType::PhaseCPower], 600);
+        assert_eq!(readings[&SensorType::PowerConsumption], 700);
+        assert_eq!(readings[&SensorType::ForwardEnergy], 531309);
+        assert_eq!(readings.len(), 12);
+
+        // A phase reported on its own, e.g. by an event, has no total.
+        let readings = encode_dps(&DeviceType::EnergyMeter, &dps[3..4], &specs);
+        assert_eq!(readings.len(), 3);
+    }
+
+    // -----------------------------------------------------------------------
+    // fetch_and_persist
+    // -----------------------------------------------------------------------
+
+    use sqlx::PgPool;
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock, MockServer,
+    };
+
+    use crate::tuya::{
+        fake::FakeBackend,
+        mock::{self, failed, ok},
+    };
+
+    /// Fake Tuya Cloud with one thermostat reporting `temp_current` 1895.
+    async fn fake_tuya() -> MockServer {
+        let server = mock::cloud().await;
+        mock::mount_status(
+            &server,
+            "th1",
+            serde_json::json!([
+                {"code": "switch", "value": true},
+                {"code": "temp_set", "value": 210},
+                {"code": "temp_current", "value": 1895},
+                {"code": "mode", "value": "manual"}
+            ]),
+        )
+        .await;
+        server
+    }
+
+    fn service(pool: PgPool, tuya: &MockServer) -> SensorService {
+        SensorService::new(
+            pool,
+            TuyaClient::with_credentials(&tuya.uri(), "client", "secret"),
+            ReadingCache::new(),
+            HashMap::from([("th1".to_owned(), DeviceType::Thermostat)]),
+        )
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn spec_scale_is_used_for_encoding(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/th1/specifications"))
+            .respond_with(ok(serde_json::json!({
+                "category": "wk",
+                "functions": [],
+                "status": [
+                    {"code": "temp_current", "type": "Integer",
+                     "values": "{\"unit\":\"℃\",\"scale\":2}"},
+                    {"code": "temp_set", "type": "Integer",
+                     "values": "{\"unit\":\"℃\",\"scale\":1}"}
+                ]
+            })))
+            .expect(1)
+            .mount(&tuya)
+            .await;
+        let service = service(pool, &tuya);
+
+        service.fetch_and_persist("th1").await.unwrap();
+        // Specifications are cached.
+        service.fetch_and_persist("th1").await.unwrap();
+
+        let temperature = service
+            .cache
+            .get("th1", SensorType::Temperature)
+            .await
+            .unwrap();
+        assert_eq!(temperature.value, 1895);
+        let setpoint = service
+            .cache
+            .get("th1", SensorType::TemperatureSetpoint)
+            .await
+            .unwrap();
+        assert_eq!(setpoint.value, 2100);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn missing_specs_fall_back_to_default_scale(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/th1/specifications"))
+            .respond_with(failed(2003))
+            .expect(1)
+            .mount(&tuya)
+            .await;
+        let service = service(pool, &tuya);
+
+        service.fetch_and_persist("th1").await.unwrap();
+        // Refusal is remembered, not asked for again.
+        service.fetch_and_persist("th1").await.unwrap();
+
+        let temperature = service
+            .cache
+            .get("th1", SensorType::Temperature)
+            .await
+            .unwrap();
+        assert_eq!(temperature.value, 18950);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn poll_fetches_devices_in_one_batch(pool: PgPool) {
+        let tuya = fake_tuya().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/th1/specifications"))
+            .respond_with(failed(2003))
+            .mount(&tuya)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/iot-03/devices/status"))
+            .and(query_param("device_ids", "th1,th2"))
+            .respond_with(ok(serde_json::json!([
+                {"id": "th1", "status": [
+                    {"code": "switch", "value": true},
+                    {"code": "temp_set", "value": 210},
+                    {"code": "temp_current", "value": 190},
+                    {"code": "mode", "value": "manual"}
+                ]},
+                {"id": "th2", "status": [{"code": "switch", "value": true}]}
+            ])))
+            .expect(1)
+            .mount(&tuya)
+            .await;
+        let service = SensorService::new(
+            pool,
+            TuyaClient::with_credentials(&tuya.uri(), "client", "secret"),
+            ReadingCache::new(),
+            HashMap::from([
+                ("th1".to_owned(), DeviceType::Thermostat),
+                ("th2".to_owned(), DeviceType::Thermostat),
+            ]),
+        );
+
+        let failures = service.poll(&["th1".to_owned(), "th2".to_owned()]).await;
+
+        // th2 lacks the thermostat DPs.
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "th2");
+        let temperature = service
+            .cache
+            .get("th1", SensorType::Temperature)
+            .await
+            .unwrap();
+        assert_eq!(temperature.value, 1900);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn snapshots_are_persisted_as_readings(pool: PgPool) {
+        let snapshots = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tuya");
+        let backend = FakeBackend::new();
+        for (device_id, snapshot) in [
+            ("th1", "device_status/th1.json"),
+            ("th1", "device_status/offline.json"),
+            ("ws1", "weather_station/ws1.json"),
+        ] {
+            backend
+                .push_snapshot(device_id, format!("{snapshots}/{snapshot}"))
+                .unwrap();
+        }
+        let service = SensorService::new(
+            pool,
+            backend,
+            ReadingCache::new(),
+            HashMap::from([
+                ("th1".to_owned(), DeviceType::Thermostat),
+                ("ws1".to_owned(), DeviceType::WeatherStation),
+            ]),
+        );
+
+        service.fetch_and_persist("th1").await.unwrap();
+        service.fetch_and_persist("ws1").await.unwrap();
+        // The device has gone offline; its last readings stay.
+        assert!(service.fetch_and_persist("th1").await.is_err());
+
+        let reading = |device_id, sensor_type| service.cache.get(device_id, sensor_type);
+        assert_eq!(
+            reading("th1", SensorType::Temperature).await.unwrap().value,
+            1890
+        );
+        assert_eq!(
+            reading("th1", SensorType::RelayState).await.unwrap().value,
+            1
+        );
+        assert_eq!(
+            reading("ws1", SensorType::Sub1Temperature)
+                .await
+                .unwrap()
+                .value,
+            1960
+        );
+        assert_eq!(
+            reading("ws1", SensorType::Humidity).await.unwrap().value,
+            5100
+        );
+    }
 }
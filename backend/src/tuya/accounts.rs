@@ -0,0 +1,267 @@
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use sqlx::PgPool;
+use tracing::warn;
+
+use crate::config::{Config, DEFAULT_ACCOUNT};
+
+use super::{
+    models::{Command, CommandAck, DeviceProperty, DeviceSpecs, ShadowProperty, TuyaDevice},
+    DeviceBackend, QuotaSettings, TuyaClient, TuyaError, Usage,
+};
+
+/// Tuya clients of all configured accounts, each with its own token cache,
+/// rate limit, call quota and local devices. Device calls go to the client
+/// of the account the device is bound to; unbound devices use the default
+/// account.
+#[derive(Debug, Clone)]
+pub struct TuyaAccounts {
+    /// Client per account name, the default account included.
+    clients: Arc<HashMap<String, TuyaClient>>,
+    /// device_id → account name.
+    devices: Arc<HashMap<String, String>>,
+}
+
+impl TuyaAccounts {
+    /// Build a client per configured account, each continuing to count its
+    /// calls from this month's `tuya_usage`.
+    pub async fn load(config: &Config, pool: PgPool) -> Result<Self, sqlx::Error> {
+        let quota = QuotaSettings::from_config(config);
+        let names =
+            std::iter::once(DEFAULT_ACCOUNT).chain(config.tuya_accounts.keys().map(String::as_str));
+        let mut clients = HashMap::new();
+        for name in names {
+            let usage = Usage::load(pool.clone(), name, quota).await?;
+            let client = TuyaClient::for_account(config, name)
+                .expect("configured accounts have credentials")
+                .with_usage(usage);
+            clients.insert(name.to_owned(), client);
+        }
+        Ok(Self {
+            clients: Arc::new(clients),
+            devices: Arc::new(config.device_accounts.clone()),
+        })
+    }
+
+    /// Add the client of another account.
+    pub fn with_account(mut self, name: &str, client: TuyaClient) -> Self {
+        Arc::make_mut(&mut self.clients).insert(name.to_owned(), client);
+        self
+    }
+
+    /// Bind `device_id` to `account`, e.g. after discovering it there.
+    pub fn bind(&mut self, device_id: &str, account: &str) {
+        Arc::make_mut(&mut self.devices).insert(device_id.to_owned(), account.to_owned());
+    }
+
+    /// Client of the account `device_id` belongs to.
+    pub fn client(&self, device_id: &str) -> &TuyaClient {
+        // Bindings are checked against the accounts when the config is read.
+        self.clients
+            .get(self.account(device_id))
+            .unwrap_or(&self.clients[DEFAULT_ACCOUNT])
+    }
+
+    /// Name of the account `device_id` belongs to.
+    fn account(&self, device_id: &str) -> &str {
+        self.devices
+            .get(device_id)
+            .map_or(DEFAULT_ACCOUNT, String::as_str)
+    }
+
+    /// All clients, by account name in name order.
+    pub fn clients(&self) -> Vec<(&str, &TuyaClient)> {
+        let mut clients: Vec<_> = self
+            .clients
+            .iter()
+            .map(|(name, client)| (name.as_str(), client))
+            .collect();
+        clients.sort_by_key(|(name, _)| *name);
+        clients
+    }
+
+    /// List the devices linked to every account, with the account each one
+    /// was found in. An account whose list fails is logged and left out;
+    /// this fails only if every account does.
+    pub async fn list_devices(&self) -> Result<Vec<(String, TuyaDevice)>, TuyaError> {
+        let mut found = Vec::new();
+        let mut listed = false;
+        let mut failure = None;
+        for (name, client) in self.clients() {
+            match client.list_devices().await {
+                Ok(devices) => {
+                    listed = true;
+                    found.extend(devices.into_iter().map(|d| (name.to_owned(), d)));
+                }
+                Err(e) => {
+                    warn!(account = name, error = %e, "Failed to list Tuya devices");
+                    failure = Some(e);
+                }
+            }
+        }
+        match failure {
+            Some(e) if !listed => Err(e),
+            _ => Ok(found),
+        }
+    }
+}
+
+/// A single default account.
+impl From<TuyaClient> for TuyaAccounts {
+    fn from(client: TuyaClient) -> Self {
+        Self {
+            clients: Arc::new(HashMap::from([(DEFAULT_ACCOUNT.to_owned(), client)])),
+            devices: Arc::default(),
+        }
+    }
+}
+
+impl DeviceBackend for TuyaAccounts {
+    fn get_device_status(
+        &self,
+        device_id: &str,
+    ) -> impl Future<Output = Result<Vec<DeviceProperty>, TuyaError>> + Send {
+        self.client(device_id).get_device_status(device_id)
+    }
+
+    /// One batch per account.
+    async fn get_devices_status(
+        &self,
+        device_ids: &[String],
+    ) -> HashMap<String, Result<Vec<DeviceProperty>, TuyaError>> {
+        let mut by_account: HashMap<&str, Vec<String>> = HashMap::new();
+        for device_id in device_ids {
+            let account = self.account(device_id);
+            by_account
+                .entry(account)
+                .or_default()
+                .push(device_id.clone());
+        }
+        let mut statuses = HashMap::new();
+        for device_ids in by_account.into_values() {
+            let client = self.client(&device_ids[0]);
+            statuses.extend(client.get_devices_status(&device_ids).await);
+        }
+        statuses
+    }
+
+    fn get_weather_station_status(
+        &self,
+        device_id: &str,
+    ) -> impl Future<Output = Result<Vec<ShadowProperty>, TuyaError>> + Send {
+        self.client(device_id).get_weather_station_status(device_id)
+    }
+
+    fn send_commands(
+        &self,
+        device_id: &str,
+        commands: Vec<Command>,
+    ) -> impl Future<Output = Result<CommandAck, TuyaError>> + Send {
+        self.client(device_id).send_commands(device_id, commands)
+    }
+
+    fn device_specs(
+        &self,
+        device_id: &str,
+    ) -> impl Future<Output = Result<Arc<DeviceSpecs>, TuyaError>> + Send {
+        self.client(device_id).device_specs(device_id)
+    }
+
+    fn poll_factor(&self, device_id: &str) -> u32 {
+        self.client(device_id).usage().poll_factor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer,
+    };
+
+    use super::*;
+    use crate::tuya::mock::{self, failed, ok, token};
+
+    /// Fake Tuya data centre whose token and status calls only accept
+    /// `client_id`.
+    async fn data_centre(client_id: &str, device_id: &str) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/token"))
+            .and(header("client_id", client_id))
+            .respond_with(token(&format!("{client_id}-token"), 7200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/v1.0/devices/{device_id}/status")))
+            .and(header(
+                "access_token",
+                format!("{client_id}-token").as_str(),
+            ))
+            .respond_with(ok(serde_json::json!([{"code": "switch", "value": true}])))
+            .expect(2)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn calls_go_to_the_account_of_the_device() {
+        let eu = data_centre("eu-id", "th1").await;
+        let us = data_centre("us-id", "th2").await;
+        let mut accounts = TuyaAccounts::from(TuyaClient::with_credentials(
+            &eu.uri(),
+            "eu-id",
+            "eu-secret",
+        ))
+        .with_account(
+            "us",
+            TuyaClient::with_credentials(&us.uri(), "us-id", "us-secret"),
+        );
+        accounts.bind("th2", "us");
+
+        // Each account fetches its own token once and reuses it.
+        for _ in 0..2 {
+            accounts.get_device_status("th1").await.unwrap();
+            accounts.get_device_status("th2").await.unwrap();
+        }
+        assert_eq!(
+            accounts
+                .clients()
+                .iter()
+                .map(|(n, _)| *n)
+                .collect::<Vec<_>>(),
+            vec!["default", "us"]
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_account_is_left_out_of_the_device_list() {
+        let eu = mock::cloud().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/users/u/devices"))
+            .respond_with(ok(serde_json::json!([
+                {"id": "th1", "category": "wk", "product_id": "p1", "online": true}
+            ])))
+            .mount(&eu)
+            .await;
+        let us = mock::cloud().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/users/u/devices"))
+            .respond_with(failed(1106))
+            .mount(&us)
+            .await;
+        let accounts = TuyaAccounts::from(TuyaClient::with_credentials(&eu.uri(), "eu", "s"))
+            .with_account("us", TuyaClient::with_credentials(&us.uri(), "us", "s"));
+
+        let found = accounts.list_devices().await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, "default");
+        assert_eq!(found[0].1.id, "th1");
+
+        // With no account left, the failure is reported.
+        let only_us = TuyaAccounts::from(TuyaClient::with_credentials(&us.uri(), "us", "s"));
+        assert!(only_us.list_devices().await.is_err());
+    }
+}
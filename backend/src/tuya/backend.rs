@@ -0,0 +1,106 @@
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use super::{
+    models::{Command, CommandAck, DeviceProperty, DeviceSpecs, ShadowProperty},
+    TuyaClient, TuyaError,
+};
+
+/// Device access the sensor and control services need, so they can run
+/// against something other than Tuya Cloud, such as the in-memory
+/// `FakeBackend` in tests.
+pub trait DeviceBackend: Send + Sync {
+    /// All DP properties of a device.
+    fn get_device_status(
+        &self,
+        device_id: &str,
+    ) -> impl Future<Output = Result<Vec<DeviceProperty>, TuyaError>> + Send;
+
+    /// DP properties of several devices, by device ID. Backends without a
+    /// batch call ask for each device in turn.
+    fn get_devices_status(
+        &self,
+        device_ids: &[String],
+    ) -> impl Future<Output = HashMap<String, Result<Vec<DeviceProperty>, TuyaError>>> + Send {
+        async move {
+            let mut statuses = HashMap::new();
+            for device_id in device_ids {
+                statuses.insert(device_id.clone(), self.get_device_status(device_id).await);
+            }
+            statuses
+        }
+    }
+
+    /// Shadow properties of a device without a v1 status endpoint, such as a
+    /// weather station.
+    fn get_weather_station_status(
+        &self,
+        device_id: &str,
+    ) -> impl Future<Output = Result<Vec<ShadowProperty>, TuyaError>> + Send;
+
+    /// Send one or more commands to a device.
+    fn send_commands(
+        &self,
+        device_id: &str,
+        commands: Vec<Command>,
+    ) -> impl Future<Output = Result<CommandAck, TuyaError>> + Send;
+
+    /// DP specifications of a device. Backends without any report none, so
+    /// default scales apply.
+    fn device_specs(
+        &self,
+        device_id: &str,
+    ) -> impl Future<Output = Result<Arc<DeviceSpecs>, TuyaError>> + Send {
+        let _ = device_id;
+        async { Ok(Arc::default()) }
+    }
+
+    /// Multiplier for the polling interval of `device_id`, above `1` once
+    /// the call quota of its account runs low. Backends without a quota
+    /// always poll at the normal interval.
+    fn poll_factor(&self, device_id: &str) -> u32 {
+        let _ = device_id;
+        1
+    }
+}
+
+impl DeviceBackend for TuyaClient {
+    fn get_device_status(
+        &self,
+        device_id: &str,
+    ) -> impl Future<Output = Result<Vec<DeviceProperty>, TuyaError>> + Send {
+        TuyaClient::get_device_status(self, device_id)
+    }
+
+    fn get_devices_status(
+        &self,
+        device_ids: &[String],
+    ) -> impl Future<Output = HashMap<String, Result<Vec<DeviceProperty>, TuyaError>>> + Send {
+        TuyaClient::get_devices_status(self, device_ids)
+    }
+
+    fn get_weather_station_status(
+        &self,
+        device_id: &str,
+    ) -> impl Future<Output = Result<Vec<ShadowProperty>, TuyaError>> + Send {
+        TuyaClient::get_weather_station_status(self, device_id)
+    }
+
+    fn send_commands(
+        &self,
+        device_id: &str,
+        commands: Vec<Command>,
+    ) -> impl Future<Output = Result<CommandAck, TuyaError>> + Send {
+        TuyaClient::send_commands(self, device_id, commands)
+    }
+
+    fn device_specs(
+        &self,
+        device_id: &str,
+    ) -> impl Future<Output = Result<Arc<DeviceSpecs>, TuyaError>> + Send {
+        TuyaClient::device_specs(self, device_id)
+    }
+
+    fn poll_factor(&self, _device_id: &str) -> u32 {
+        self.usage().poll_factor()
+    }
+}
@@ -0,0 +1,159 @@
+use std::{sync::Arc, time::Duration};
+
+use reqwest::StatusCode;
+
+use super::models::TuyaApiError;
+
+// Codes from Tuya Cloud's global error-code list.
+const TOKEN_INVALID: &[i32] = &[1010, 1011, 1012];
+const PERMISSION_DENIED: &[i32] = &[1106, 2003];
+const DEVICE_OFFLINE: &[i32] = &[2001];
+const RATE_LIMITED: &[i32] = &[40000309];
+
+/// Failure of a Tuya Cloud call.
+#[derive(Debug, thiserror::Error)]
+pub enum TuyaError {
+    /// The access token is invalid or expired; a fresh one may succeed.
+    #[error("token invalid: {0}")]
+    TokenInvalid(TuyaApiError),
+    /// Too many requests; retry after a delay.
+    #[error("rate limited: {0}")]
+    RateLimited(TuyaApiError),
+    #[error("device offline: {0}")]
+    DeviceOffline(TuyaApiError),
+    /// The project may not use this API or device (e.g. 2003 on the v1
+    /// status endpoint for weather stations).
+    #[error("permission denied: {0}")]
+    PermissionDenied(TuyaApiError),
+    /// Any other API-level failure.
+    #[error(transparent)]
+    Api(TuyaApiError),
+    /// Non-2xx HTTP status.
+    #[error("Tuya returned HTTP {0}")]
+    Status(StatusCode),
+    /// Connection, timeout or body read failure.
+    #[error("Tuya request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+    /// The caller gave up waiting, e.g. the polling loop after its timeout.
+    #[error("no answer within {}s", .0.as_secs())]
+    Timeout(Duration),
+    #[error("invalid Tuya response: {0}")]
+    Decode(String),
+    /// Failure talking to a device over the local protocol.
+    #[error("local device call failed: {0:#}")]
+    Local(anyhow::Error),
+    /// Failure while building the request.
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+    /// Failure of a batch call, reported for every device in the batch.
+    #[error(transparent)]
+    Batch(Arc<TuyaError>),
+}
+
+impl TuyaError {
+    /// Map an API-level failure onto its kind by error code.
+    pub fn classify(api: TuyaApiError) -> Self {
+        match api.code {
+            c if TOKEN_INVALID.contains(&c) => Self::TokenInvalid(api),
+            c if PERMISSION_DENIED.contains(&c) => Self::PermissionDenied(api),
+            c if DEVICE_OFFLINE.contains(&c) => Self::DeviceOffline(api),
+            c if RATE_LIMITED.contains(&c) => Self::RateLimited(api),
+            _ => Self::Api(api),
+        }
+    }
+
+    /// Whether the same call may succeed after backing off: rate limiting,
+    /// transport failures and 5xx/429 responses.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::RateLimited(_) | Self::Transport(_) => true,
+            Self::Status(s) => s.is_server_error() || *s == StatusCode::TOO_MANY_REQUESTS,
+            Self::Batch(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// Whether Tuya reported the device itself as offline.
+    pub fn is_device_offline(&self) -> bool {
+        match self {
+            Self::DeviceOffline(_) => true,
+            Self::Batch(e) => e.is_device_offline(),
+            _ => false,
+        }
+    }
+
+    /// The API-level error, for failures Tuya reported with `success: false`.
+    pub fn api(&self) -> Option<&TuyaApiError> {
+        match self {
+            Self::TokenInvalid(api)
+            | Self::RateLimited(api)
+            | Self::DeviceOffline(api)
+            | Self::PermissionDenied(api)
+            | Self::Api(api) => Some(api),
+            Self::Batch(e) => e.api(),
+            _ => None,
+        }
+    }
+
+    /// Server-side request trace ID, if Tuya returned one.
+    pub fn tid(&self) -> Option<&str> {
+        self.api().and_then(|api| api.tid.as_deref())
+    }
+}
+
+impl From<serde_json::Error> for TuyaError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Decode(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api(code: i32) -> TuyaApiError {
+        TuyaApiError {
+            code,
+            msg: "msg".into(),
+            tid: Some("tid".into()),
+        }
+    }
+
+    #[test]
+    fn codes_are_classified() {
+        assert!(matches!(
+            TuyaError::classify(api(1010)),
+            TuyaError::TokenInvalid(_)
+        ));
+        assert!(matches!(
+            TuyaError::classify(api(2003)),
+            TuyaError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            TuyaError::classify(api(2001)),
+            TuyaError::DeviceOffline(_)
+        ));
+        assert!(matches!(
+            TuyaError::classify(api(40000309)),
+            TuyaError::RateLimited(_)
+        ));
+        assert!(matches!(TuyaError::classify(api(2009)), TuyaError::Api(_)));
+    }
+
+    #[test]
+    fn only_transient_failures_are_retryable() {
+        assert!(TuyaError::classify(api(40000309)).is_retryable());
+        assert!(TuyaError::Status(StatusCode::BAD_GATEWAY).is_retryable());
+        assert!(TuyaError::Status(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!TuyaError::Status(StatusCode::NOT_FOUND).is_retryable());
+        assert!(!TuyaError::classify(api(1106)).is_retryable());
+        assert!(!TuyaError::classify(api(2001)).is_retryable());
+        assert!(!TuyaError::classify(api(1010)).is_retryable());
+    }
+
+    #[test]
+    fn api_failures_keep_tid() {
+        assert_eq!(TuyaError::classify(api(2009)).tid(), Some("tid"));
+        assert_eq!(TuyaError::Status(StatusCode::BAD_GATEWAY).tid(), None);
+    }
+}
@@ -0,0 +1,249 @@
+//! In-memory [`DeviceBackend`] for tests, scripted with DP snapshots such as
+//! the responses `response_store` saves under `RESPONSE_DIR`.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use serde_json::Value;
+
+use super::{
+    models::{
+        Command, CommandAck, DeviceProperty, DeviceSpecs, ShadowPropertiesResult, ShadowProperty,
+        TuyaApiError, TuyaResponse,
+    },
+    DeviceBackend, TuyaError,
+};
+
+/// Replies to status calls are scripted per device and played in order; the
+/// last one keeps being returned. Commands are recorded and applied to the
+/// last scripted status, so later polls see their effect. Status calls of
+/// hung devices never return.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FakeBackend {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    replies: HashMap<String, VecDeque<Reply>>,
+    commands: Vec<(String, Vec<Command>)>,
+    hung: HashSet<String>,
+    /// Status calls per device.
+    calls: HashMap<String, usize>,
+    specs: HashMap<String, Arc<DeviceSpecs>>,
+}
+
+#[derive(Debug, Clone)]
+enum Reply {
+    Status(Vec<DeviceProperty>),
+    Shadow(Vec<ShadowProperty>),
+    Failure(TuyaApiError),
+}
+
+impl FakeBackend {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report `dps` on the next status call for `device_id`.
+    pub(crate) fn push_status(&self, device_id: &str, dps: Vec<DeviceProperty>) {
+        self.push(device_id, Reply::Status(dps));
+    }
+
+    /// Script the next reply for `device_id` from a saved `device_status` or
+    /// `weather_station` response, successful or not.
+    pub(crate) fn push_snapshot(
+        &self,
+        device_id: &str,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let response: TuyaResponse<Value> = serde_json::from_slice(&bytes)?;
+        let reply = match response.into_result() {
+            Ok(dps @ Value::Array(_)) => Reply::Status(serde_json::from_value(dps)?),
+            Ok(shadow) => {
+                Reply::Shadow(serde_json::from_value::<ShadowPropertiesResult>(shadow)?.properties)
+            }
+            Err(e) => Reply::Failure(e.api().cloned().context("snapshot is not a Tuya reply")?),
+        };
+        self.push(device_id, reply);
+        Ok(())
+    }
+
+    /// Report `specs` as the DP specifications of `device_id`; devices
+    /// without any get empty ones.
+    pub(crate) fn set_specs(&self, device_id: &str, specs: DeviceSpecs) {
+        self.state
+            .lock()
+            .unwrap()
+            .specs
+            .insert(device_id.to_owned(), Arc::new(specs));
+    }
+
+    /// Never answer status calls for `device_id`.
+    pub(crate) fn hang(&self, device_id: &str) {
+        self.state.lock().unwrap().hung.insert(device_id.to_owned());
+    }
+
+    /// Status calls made for `device_id` so far, answered or not.
+    pub(crate) fn calls(&self, device_id: &str) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .get(device_id)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Commands received so far, in order.
+    pub(crate) fn commands(&self) -> Vec<(String, Vec<Command>)> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    fn push(&self, device_id: &str, reply: Reply) {
+        self.state
+            .lock()
+            .unwrap()
+            .replies
+            .entry(device_id.to_owned())
+            .or_default()
+            .push_back(reply);
+    }
+
+    async fn next(&self, device_id: &str) -> Result<Reply, TuyaError> {
+        let hung = {
+            let mut state = self.state.lock().unwrap();
+            *state.calls.entry(device_id.to_owned()).or_default() += 1;
+            state.hung.contains(device_id)
+        };
+        if hung {
+            std::future::pending::<()>().await;
+        }
+        let mut state = self.state.lock().unwrap();
+        let queue = state
+            .replies
+            .get_mut(device_id)
+            .filter(|q| !q.is_empty())
+            .ok_or_else(|| TuyaError::Decode(format!("nothing scripted for {device_id}")))?;
+        let reply = match queue.len() {
+            1 => queue[0].clone(),
+            _ => queue.pop_front().expect("queue is not empty"),
+        };
+        match reply {
+            Reply::Failure(api) => Err(TuyaError::classify(api)),
+            reply => Ok(reply),
+        }
+    }
+}
+
+impl DeviceBackend for FakeBackend {
+    async fn get_device_status(&self, device_id: &str) -> Result<Vec<DeviceProperty>, TuyaError> {
+        match self.next(device_id).await? {
+            Reply::Status(dps) => Ok(dps),
+            _ => Err(TuyaError::Decode(format!(
+                "no status scripted for {device_id}"
+            ))),
+        }
+    }
+
+    async fn get_weather_station_status(
+        &self,
+        device_id: &str,
+    ) -> Result<Vec<ShadowProperty>, TuyaError> {
+        match self.next(device_id).await? {
+            Reply::Shadow(props) => Ok(props),
+            _ => Err(TuyaError::Decode(format!(
+                "no shadow scripted for {device_id}"
+            ))),
+        }
+    }
+
+    async fn send_commands(
+        &self,
+        device_id: &str,
+        commands: Vec<Command>,
+    ) -> Result<CommandAck, TuyaError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(Reply::Status(dps)) =
+            state.replies.get_mut(device_id).and_then(|q| q.back_mut())
+        {
+            for command in &commands {
+                match dps.iter_mut().find(|dp| dp.code == command.code) {
+                    Some(dp) => dp.value = command.value.clone(),
+                    None => dps.push(DeviceProperty {
+                        code: command.code.clone(),
+                        value: command.value.clone(),
+                    }),
+                }
+            }
+        }
+        state.commands.push((device_id.to_owned(), commands));
+        Ok(CommandAck {
+            result: true,
+            tid: Some("fake".into()),
+        })
+    }
+
+    async fn device_specs(&self, device_id: &str) -> Result<Arc<DeviceSpecs>, TuyaError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.specs.get(device_id).cloned().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tuya::models::DpValue;
+
+    const SNAPSHOTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tuya");
+
+    #[tokio::test]
+    async fn snapshots_are_replayed_in_order() {
+        let fake = FakeBackend::new();
+        fake.push_snapshot("th1", format!("{SNAPSHOTS}/device_status/th1.json"))
+            .unwrap();
+        fake.push_snapshot("th1", format!("{SNAPSHOTS}/device_status/offline.json"))
+            .unwrap();
+        fake.push_snapshot("ws1", format!("{SNAPSHOTS}/weather_station/ws1.json"))
+            .unwrap();
+
+        let dps = fake.get_device_status("th1").await.unwrap();
+        assert!(dps.iter().any(|dp| dp.code == "temp_current"));
+        // The last reply repeats.
+        for _ in 0..2 {
+            let err = fake.get_device_status("th1").await.unwrap_err();
+            assert!(matches!(err, TuyaError::DeviceOffline(_)));
+        }
+
+        let props = fake.get_weather_station_status("ws1").await.unwrap();
+        assert!(props.iter().any(|p| p.code == "local_temp"));
+        assert!(fake.get_device_status("other").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn commands_are_recorded_and_applied() {
+        let fake = FakeBackend::new();
+        fake.push_status(
+            "th1",
+            vec![DeviceProperty {
+                code: "switch".into(),
+                value: DpValue::Bool(true),
+            }],
+        );
+        let command = Command {
+            code: "switch".into(),
+            value: DpValue::Bool(false),
+        };
+        fake.send_commands("th1", vec![command]).await.unwrap();
+
+        assert_eq!(fake.commands().len(), 1);
+        let dps = fake.get_device_status("th1").await.unwrap();
+        assert_eq!(dps[0].value.as_bool(), Some(false));
+    }
+}
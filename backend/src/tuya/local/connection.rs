@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+use tracing::debug;
+
+use super::protocol::{self, cmd, Frame, Version};
+
+/// Limit for connecting and for each read or write.
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An open TCP connection to one device.
+#[derive(Debug)]
+pub(super) struct Connection {
+    stream: TcpStream,
+    version: Version,
+    local_key: [u8; 16],
+    /// Key frames are encrypted with: the local key, or the session key once
+    /// it has been negotiated.
+    key: [u8; 16],
+    seq: u32,
+    /// Received bytes not yet parsed into a frame.
+    buf: Vec<u8>,
+}
+
+impl Connection {
+    /// Connect to `addr`, negotiating a session key where the version needs
+    /// one.
+    pub async fn open(addr: &str, version: Version, local_key: [u8; 16]) -> Result<Self> {
+        let stream = timeout(IO_TIMEOUT, TcpStream::connect(addr))
+            .await
+            .context("timed out connecting")?
+            .context("failed to connect")?;
+        let mut conn = Self {
+            stream,
+            version,
+            local_key,
+            key: local_key,
+            seq: 0,
+            buf: Vec::new(),
+        };
+        if version.negotiates_session_key() {
+            conn.negotiate()
+                .await
+                .context("session key negotiation failed")?;
+        }
+        Ok(conn)
+    }
+
+    async fn negotiate(&mut self) -> Result<()> {
+        let local_nonce: [u8; 16] = rand::random();
+        let reply = self
+            .request(
+                cmd::SESS_KEY_NEG_START,
+                &local_nonce,
+                &[cmd::SESS_KEY_NEG_RESP],
+            )
+            .await?;
+        if reply.len() < 48 {
+            bail!("negotiation reply of {} bytes is too short", reply.len());
+        }
+        let (remote_nonce, mac) = reply.split_at(16);
+        if mac[..32] != protocol::hmac(&self.local_key, &local_nonce) {
+            bail!("device did not prove it has the local key");
+        }
+        let remote_nonce: [u8; 16] = remote_nonce.try_into()?;
+
+        self.send(
+            cmd::SESS_KEY_NEG_FINISH,
+            &protocol::hmac(&self.local_key, &remote_nonce),
+        )
+        .await?;
+        self.key =
+            protocol::session_key(self.version, &self.local_key, &local_nonce, &remote_nonce);
+        Ok(())
+    }
+
+    /// Send `body` as `cmd` and return the body of the first reply whose
+    /// command is one of `replies`. Other frames, such as status pushes,
+    /// are skipped.
+    pub async fn request(&mut self, cmd: u32, body: &[u8], replies: &[u32]) -> Result<Vec<u8>> {
+        self.send(cmd, body).await?;
+        loop {
+            let frame = self.recv().await?;
+            if !replies.contains(&frame.cmd) {
+                debug!(cmd = frame.cmd, "Skipping unrelated frame from device");
+                continue;
+            }
+            if let Some(code) = frame.retcode.filter(|&code| code != 0) {
+                bail!("device answered command {cmd} with return code {code}");
+            }
+            return Ok(frame.body);
+        }
+    }
+
+    async fn send(&mut self, cmd: u32, body: &[u8]) -> Result<()> {
+        self.seq = self.seq.wrapping_add(1);
+        let frame = Frame {
+            seq: self.seq,
+            cmd,
+            retcode: None,
+            body: body.to_vec(),
+        };
+        let bytes = protocol::encode(&frame, self.version, &self.key);
+        timeout(IO_TIMEOUT, self.stream.write_all(&bytes))
+            .await
+            .context("timed out sending to device")?
+            .context("failed to send to device")
+    }
+
+    async fn recv(&mut self) -> Result<Frame> {
+        loop {
+            if let Some((frame, used)) = protocol::decode(&self.buf, self.version, &self.key, true)?
+            {
+                self.buf.drain(..used);
+                return Ok(frame);
+            }
+            let mut chunk = [0; 1024];
+            let n = timeout(IO_TIMEOUT, self.stream.read(&mut chunk))
+                .await
+                .context("timed out waiting for device")?
+                .context("failed to read from device")?;
+            if n == 0 {
+                bail!("device closed the connection");
+            }
+            self.buf.extend(&chunk[..n]);
+        }
+    }
+}
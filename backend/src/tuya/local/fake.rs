@@ -0,0 +1,163 @@
+//! A fake device speaking the local protocol, for tests.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use serde_json::{json, Map, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use super::protocol::{self, cmd, Frame, Version};
+
+pub(crate) struct FakeDevice {
+    port: u16,
+    state: Arc<State>,
+}
+
+struct State {
+    version: Version,
+    local_key: [u8; 16],
+    dps: Mutex<Map<String, Value>>,
+    connections: AtomicUsize,
+    heartbeats: AtomicUsize,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl FakeDevice {
+    /// Listen on a free port with the given DP values.
+    pub(crate) async fn start(version: Version, local_key: [u8; 16], dps: Value) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let Value::Object(dps) = dps else {
+            panic!("fake DPs must be an object");
+        };
+        let state = Arc::new(State {
+            version,
+            local_key,
+            dps: Mutex::new(dps),
+            connections: AtomicUsize::new(0),
+            heartbeats: AtomicUsize::new(0),
+            tasks: Mutex::new(Vec::new()),
+        });
+
+        let accepting = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepting.connections.fetch_add(1, Ordering::SeqCst);
+                let task = tokio::spawn(serve(stream, accepting.clone()));
+                accepting.tasks.lock().unwrap().push(task);
+            }
+        });
+        Self { port, state }
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub(crate) fn dps(&self) -> Map<String, Value> {
+        self.state.dps.lock().unwrap().clone()
+    }
+
+    pub(crate) fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn heartbeats(&self) -> usize {
+        self.state.heartbeats.load(Ordering::SeqCst)
+    }
+
+    /// Close all open connections, as a device does after a restart.
+    pub(crate) fn drop_connections(&self) {
+        for task in self.state.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+/// Answer requests on one connection until the client goes away or sends
+/// a frame that does not check out.
+async fn serve(mut stream: TcpStream, state: Arc<State>) {
+    let version = state.version;
+    let mut key = state.local_key;
+    let remote_nonce: [u8; 16] = rand::random();
+    let mut local_nonce = [0; 16];
+    let mut buf = Vec::new();
+
+    loop {
+        let frame = match protocol::decode(&buf, version, &key, false) {
+            Ok(Some((frame, used))) => {
+                buf.drain(..used);
+                frame
+            }
+            Ok(None) => {
+                let mut chunk = [0; 1024];
+                match stream.read(&mut chunk).await {
+                    Ok(n) if n > 0 => buf.extend(&chunk[..n]),
+                    _ => return,
+                }
+                continue;
+            }
+            Err(_) => return,
+        };
+
+        let mut replies = Vec::new();
+        match frame.cmd {
+            cmd::SESS_KEY_NEG_START => {
+                local_nonce.copy_from_slice(&frame.body[..16]);
+                let body = [&remote_nonce[..], &protocol::hmac(&key, &local_nonce)].concat();
+                replies.push((cmd::SESS_KEY_NEG_RESP, body));
+            }
+            cmd::SESS_KEY_NEG_FINISH => {
+                key = protocol::session_key(version, &state.local_key, &local_nonce, &remote_nonce);
+            }
+            cmd::DP_QUERY | cmd::DP_QUERY_NEW => {
+                let dps = state.dps.lock().unwrap().clone();
+                replies.push((frame.cmd, status(version, dps)));
+            }
+            cmd::CONTROL | cmd::CONTROL_NEW => {
+                let request: Value = serde_json::from_slice(&frame.body).unwrap();
+                let changed = request
+                    .get("dps")
+                    .or_else(|| request.pointer("/data/dps"))
+                    .and_then(Value::as_object)
+                    .cloned()
+                    .unwrap_or_default();
+                state.dps.lock().unwrap().extend(changed.clone());
+                replies.push((frame.cmd, Vec::new()));
+                replies.push((cmd::STATUS, status(version, changed)));
+            }
+            cmd::HEART_BEAT => {
+                state.heartbeats.fetch_add(1, Ordering::SeqCst);
+                replies.push((cmd::HEART_BEAT, Vec::new()));
+            }
+            _ => {}
+        }
+
+        for (cmd, body) in replies {
+            let reply = Frame {
+                seq: frame.seq,
+                cmd,
+                retcode: Some(0),
+                body,
+            };
+            let bytes = protocol::encode(&reply, version, &key);
+            if stream.write_all(&bytes).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn status(version: Version, dps: Map<String, Value>) -> Vec<u8> {
+    let body = match version {
+        Version::V33 => json!({ "devId": "th1", "dps": dps }),
+        _ => json!({ "protocol": 4, "t": 1, "data": { "dps": dps } }),
+    };
+    body.to_string().into_bytes()
+}
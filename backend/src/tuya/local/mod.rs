@@ -0,0 +1,355 @@
+//! Direct LAN access to Tuya devices on TCP port 6668, so readings and
+//! control keep working while the internet connection is down.
+//!
+//! The local protocol addresses DPs by numeric ID rather than by code; the
+//! mapping is configured per device or learned from the cloud while it is
+//! reachable (see `TuyaClient`).
+
+mod connection;
+pub mod protocol;
+
+#[cfg(test)]
+pub(crate) mod fake;
+
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use self::{
+    connection::Connection,
+    protocol::{cmd, Version},
+};
+
+/// Port devices listen on.
+pub const PORT: u16 = 6668;
+
+/// Idle connections are kept open with a heartbeat this often; devices drop
+/// connections that stay silent for about 30 seconds.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How a device is reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// Tuya Cloud only.
+    #[default]
+    Cloud,
+    /// The local protocol only.
+    Local,
+    /// The local protocol, falling back to the cloud when it fails.
+    LocalFallback,
+}
+
+impl FromStr for Transport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cloud" => Ok(Self::Cloud),
+            "local" => Ok(Self::Local),
+            "local_fallback" => Ok(Self::LocalFallback),
+            other => Err(anyhow::anyhow!("unknown transport: {other:?}")),
+        }
+    }
+}
+
+/// Connection details of a device reachable on the LAN.
+#[derive(Debug, Clone)]
+pub struct LocalDevice {
+    pub device_id: String,
+    pub host: String,
+    pub local_key: [u8; 16],
+    pub version: Version,
+    pub transport: Transport,
+    /// DP ID → DP code. Learned from the cloud when empty.
+    pub dp_codes: HashMap<String, String>,
+}
+
+/// Client for the local protocol. Keeps one connection per device, opened
+/// on first use and re-opened after a failure.
+#[derive(Debug)]
+pub struct LocalClient {
+    devices: HashMap<String, LocalDevice>,
+    port: u16,
+    connections: HashMap<String, Mutex<Option<Connection>>>,
+    /// DP ID → DP code per device, configured or learned.
+    dp_codes: std::sync::Mutex<HashMap<String, Arc<HashMap<String, String>>>>,
+}
+
+impl LocalClient {
+    pub fn new(devices: Vec<LocalDevice>) -> Self {
+        let connections = devices
+            .iter()
+            .map(|d| (d.device_id.clone(), Mutex::new(None)))
+            .collect();
+        let dp_codes = devices
+            .iter()
+            .filter(|d| !d.dp_codes.is_empty())
+            .map(|d| (d.device_id.clone(), Arc::new(d.dp_codes.clone())))
+            .collect();
+        Self {
+            devices: devices
+                .into_iter()
+                .map(|d| (d.device_id.clone(), d))
+                .collect(),
+            port: PORT,
+            connections,
+            dp_codes: std::sync::Mutex::new(dp_codes),
+        }
+    }
+
+    /// Connect on `port` instead of the standard one.
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Transport configured for a device; `Cloud` for devices not listed.
+    pub fn transport(&self, device_id: &str) -> Transport {
+        self.devices
+            .get(device_id)
+            .map_or(Transport::Cloud, |d| d.transport)
+    }
+
+    /// DP ID → DP code mapping of a device, if known.
+    pub fn dp_codes(&self, device_id: &str) -> Option<Arc<HashMap<String, String>>> {
+        self.dp_codes.lock().unwrap().get(device_id).cloned()
+    }
+
+    /// Remember the DP ID → DP code mapping of a device.
+    pub fn learn_dp_codes(
+        &self,
+        device_id: &str,
+        codes: HashMap<String, String>,
+    ) -> Arc<HashMap<String, String>> {
+        let codes = Arc::new(codes);
+        self.dp_codes
+            .lock()
+            .unwrap()
+            .insert(device_id.to_owned(), codes.clone());
+        codes
+    }
+
+    /// Current DP values of a device, by DP ID.
+    pub async fn query(&self, device_id: &str) -> Result<Map<String, Value>> {
+        let device = self.device(device_id)?;
+        let (command, body) = match device.version {
+            Version::V33 => (
+                cmd::DP_QUERY,
+                json!({
+                    "gwId": device_id,
+                    "devId": device_id,
+                    "uid": device_id,
+                    "t": timestamp(),
+                }),
+            ),
+            _ => (cmd::DP_QUERY_NEW, json!({})),
+        };
+        let reply = self.exchange(device, command, body, &[command]).await?;
+        dps(&reply).with_context(|| format!("invalid DP query reply from {device_id}"))
+    }
+
+    /// Set DPs of a device, by DP ID.
+    pub async fn set(&self, device_id: &str, dps: Map<String, Value>) -> Result<()> {
+        let device = self.device(device_id)?;
+        let (command, body) = match device.version {
+            Version::V33 => (
+                cmd::CONTROL,
+                json!({
+                    "devId": device_id,
+                    "uid": device_id,
+                    "t": timestamp(),
+                    "dps": dps,
+                }),
+            ),
+            _ => (
+                cmd::CONTROL_NEW,
+                json!({
+                    "protocol": 5,
+                    "t": timestamp(),
+                    "data": { "dps": dps },
+                }),
+            ),
+        };
+        // Some devices only answer with the status push that follows.
+        self.exchange(device, command, body, &[command, cmd::STATUS])
+            .await
+            .map(drop)
+    }
+
+    /// Send a heartbeat on every open connection, dropping those that do
+    /// not answer.
+    pub async fn heartbeat(&self) {
+        for (device_id, slot) in &self.connections {
+            let mut slot = slot.lock().await;
+            let Some(conn) = slot.as_mut() else {
+                continue;
+            };
+            let body = json!({ "gwId": device_id, "devId": device_id }).to_string();
+            if let Err(e) = conn
+                .request(cmd::HEART_BEAT, body.as_bytes(), &[cmd::HEART_BEAT])
+                .await
+            {
+                debug!(device_id = %device_id, error = %e, "Local heartbeat failed; closing");
+                *slot = None;
+            }
+        }
+    }
+
+    /// Keep connections alive. Runs forever; spawn it next to the polling
+    /// and control loops.
+    pub async fn run_heartbeats(self: Arc<Self>) {
+        info!(devices = self.devices.len(), "Local heartbeat task started");
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.heartbeat().await;
+        }
+    }
+
+    fn device(&self, device_id: &str) -> Result<&LocalDevice> {
+        self.devices
+            .get(device_id)
+            .with_context(|| format!("device {device_id} is not configured for local access"))
+    }
+
+    /// Send one request to a device. A failure on a reused connection is
+    /// retried once on a fresh one, since the device may have dropped it.
+    async fn exchange(
+        &self,
+        device: &LocalDevice,
+        command: u32,
+        body: Value,
+        replies: &[u32],
+    ) -> Result<Vec<u8>> {
+        let body = body.to_string();
+        let mut slot = self.connections[&device.device_id].lock().await;
+        loop {
+            let reused = slot.is_some();
+            let conn = match slot.as_mut() {
+                Some(conn) => conn,
+                None => {
+                    let addr = format!("{}:{}", device.host, self.port);
+                    let conn = Connection::open(&addr, device.version, device.local_key)
+                        .await
+                        .with_context(|| {
+                            format!("failed to reach {} at {addr}", device.device_id)
+                        })?;
+                    slot.insert(conn)
+                }
+            };
+            match conn.request(command, body.as_bytes(), replies).await {
+                Ok(reply) => return Ok(reply),
+                Err(e) => {
+                    *slot = None;
+                    if !reused {
+                        return Err(e.context(format!("request to {} failed", device.device_id)));
+                    }
+                    warn!(
+                        device_id = %device.device_id,
+                        error = %e,
+                        "Local connection lost; reconnecting"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// DPs of a query reply or status push: `{"dps": ...}` for 3.3 and
+/// `{"data": {"dps": ...}}` for later versions.
+fn dps(body: &[u8]) -> Result<Map<String, Value>> {
+    let mut reply: Value = serde_json::from_slice(body).context("reply is not JSON")?;
+    let dps = match reply.get_mut("dps") {
+        Some(dps) => dps.take(),
+        None => reply
+            .pointer_mut("/data/dps")
+            .context("reply has no dps")?
+            .take(),
+    };
+    match dps {
+        Value::Object(dps) => Ok(dps),
+        _ => anyhow::bail!("dps is not an object"),
+    }
+}
+
+fn timestamp() -> String {
+    chrono::Utc::now().timestamp().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fake::FakeDevice, *};
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+
+    fn client(fake: &FakeDevice, version: Version) -> LocalClient {
+        LocalClient::new(vec![LocalDevice {
+            device_id: "th1".into(),
+            host: "127.0.0.1".into(),
+            local_key: KEY,
+            version,
+            transport: Transport::Local,
+            dp_codes: HashMap::new(),
+        }])
+        .with_port(fake.port())
+    }
+
+    #[tokio::test]
+    async fn queries_and_sets_dps_in_every_version() {
+        for version in [Version::V33, Version::V34, Version::V35] {
+            let fake = FakeDevice::start(version, KEY, json!({"1": true, "2": 215})).await;
+            let client = client(&fake, version);
+
+            let dps = client.query("th1").await.unwrap();
+            assert_eq!(dps["2"], 215, "{version:?}");
+
+            let mut update = Map::new();
+            update.insert("1".into(), json!(false));
+            client.set("th1", update).await.unwrap();
+            assert_eq!(fake.dps()["1"], false, "{version:?}");
+            assert_eq!(client.query("th1").await.unwrap()["1"], false);
+
+            // One connection served all calls.
+            assert_eq!(fake.connections(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn dropped_connection_is_reopened() {
+        let fake = FakeDevice::start(Version::V33, KEY, json!({"1": true})).await;
+        let client = client(&fake, Version::V33);
+        client.query("th1").await.unwrap();
+
+        fake.drop_connections();
+        assert_eq!(client.query("th1").await.unwrap()["1"], true);
+        assert_eq!(fake.connections(), 2);
+    }
+
+    #[tokio::test]
+    async fn heartbeats_are_answered() {
+        let fake = FakeDevice::start(Version::V34, KEY, json!({})).await;
+        let client = client(&fake, Version::V34);
+        client.query("th1").await.unwrap();
+
+        client.heartbeat().await;
+        assert_eq!(fake.heartbeats(), 1);
+        assert!(client.connections["th1"].lock().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn wrong_local_key_fails() {
+        let fake = FakeDevice::start(Version::V34, *b"ffffffffffffffff", json!({})).await;
+        assert!(client(&fake, Version::V34).query("th1").await.is_err());
+    }
+
+    #[test]
+    fn dps_are_read_from_either_layout() {
+        let flat = dps(br#"{"devId":"th1","dps":{"1":true}}"#).unwrap();
+        let nested = dps(br#"{"protocol":4,"t":1,"data":{"dps":{"1":true}}}"#).unwrap();
+        assert_eq!(flat, nested);
+        assert!(dps(br#"{"devId":"th1"}"#).is_err());
+    }
+}
@@ -0,0 +1,466 @@
+//! Framing of the Tuya local protocol, versions 3.3 to 3.5.
+//!
+//! 3.3 and 3.4 frames are `0x55AA | seq | cmd | len | payload | check |
+//! 0xAA55`, all integers big-endian `u32`, where `len` counts everything
+//! after itself. 3.3 checks frames with CRC32 and encrypts payloads with
+//! AES-ECB under the device's local key; 3.4 checks them with HMAC-SHA256
+//! and uses a session key negotiated per connection.
+//!
+//! 3.5 frames are `0x6699 | 0u16 | seq | cmd | len | iv | ciphertext | tag |
+//! 0x9966`: the payload is sealed with AES-GCM, authenticating the header,
+//! and `len` counts IV, ciphertext and tag. Session keys work as in 3.4.
+//!
+//! Frames sent by a device start their payload with a `u32` return code.
+
+use std::str::FromStr;
+
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes128Gcm, Nonce,
+};
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const PREFIX_55AA: u32 = 0x0000_55AA;
+const SUFFIX_55AA: u32 = 0x0000_AA55;
+const PREFIX_6699: u32 = 0x0000_6699;
+const SUFFIX_6699: u32 = 0x0000_9966;
+/// Prefix, sequence number, command and length.
+const HEADER_LEN_55AA: usize = 16;
+/// Prefix, two reserved bytes, sequence number, command and length.
+const HEADER_LEN_6699: usize = 18;
+const GCM_IV_LEN: usize = 12;
+const GCM_TAG_LEN: usize = 16;
+/// Version tag and the zero bytes after it that precede some payloads.
+const VERSION_HEADER_LEN: usize = 15;
+/// Frames longer than this are treated as garbage rather than buffered.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Command codes.
+pub mod cmd {
+    pub const SESS_KEY_NEG_START: u32 = 3;
+    pub const SESS_KEY_NEG_RESP: u32 = 4;
+    pub const SESS_KEY_NEG_FINISH: u32 = 5;
+    pub const CONTROL: u32 = 7;
+    /// Status pushed by the device after a change.
+    pub const STATUS: u32 = 8;
+    pub const HEART_BEAT: u32 = 9;
+    pub const DP_QUERY: u32 = 10;
+    /// 3.4+ replacement for `CONTROL`.
+    pub const CONTROL_NEW: u32 = 13;
+    /// 3.4+ replacement for `DP_QUERY`.
+    pub const DP_QUERY_NEW: u32 = 16;
+}
+
+/// Local protocol version of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V33,
+    V34,
+    V35,
+}
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "3.3" => Ok(Self::V33),
+            "3.4" => Ok(Self::V34),
+            "3.5" => Ok(Self::V35),
+            other => Err(anyhow::anyhow!(
+                "unsupported local protocol version: {other:?}"
+            )),
+        }
+    }
+}
+
+impl Version {
+    fn tag(self) -> &'static [u8] {
+        match self {
+            Self::V33 => b"3.3",
+            Self::V34 => b"3.4",
+            Self::V35 => b"3.5",
+        }
+    }
+
+    /// Whether connections start with a session key negotiation.
+    pub fn negotiates_session_key(self) -> bool {
+        self != Self::V33
+    }
+}
+
+/// One protocol frame, with its body decrypted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub seq: u32,
+    pub cmd: u32,
+    /// Return code of a frame sent by the device.
+    pub retcode: Option<u32>,
+    pub body: Vec<u8>,
+}
+
+/// Encrypt and serialise `frame` with `key`: the local key for 3.3 and
+/// during negotiation, the session key afterwards.
+pub fn encode(frame: &Frame, version: Version, key: &[u8; 16]) -> Vec<u8> {
+    if version == Version::V35 {
+        return encode_6699(frame, key);
+    }
+
+    let payload = match (version, has_version_header(frame.cmd)) {
+        _ if frame.body.is_empty() => Vec::new(),
+        (_, false) => ecb_encrypt(key, &frame.body),
+        // 3.3 puts the version header in front of the ciphertext, 3.4
+        // encrypts it with the body.
+        (Version::V33, true) => [version_header(version), ecb_encrypt(key, &frame.body)].concat(),
+        (_, true) => ecb_encrypt(key, &[version_header(version), frame.body.clone()].concat()),
+    };
+    let check_len = if version == Version::V33 { 4 } else { 32 };
+    let retcode_len = if frame.retcode.is_some() { 4 } else { 0 };
+    let len = retcode_len + payload.len() + check_len + 4;
+
+    let mut out = Vec::with_capacity(HEADER_LEN_55AA + len);
+    out.extend(PREFIX_55AA.to_be_bytes());
+    out.extend(frame.seq.to_be_bytes());
+    out.extend(frame.cmd.to_be_bytes());
+    out.extend((len as u32).to_be_bytes());
+    if let Some(retcode) = frame.retcode {
+        out.extend(retcode.to_be_bytes());
+    }
+    out.extend(payload);
+    match version {
+        Version::V33 => out.extend(crc32fast::hash(&out).to_be_bytes()),
+        _ => out.extend(hmac(key, &out)),
+    }
+    out.extend(SUFFIX_55AA.to_be_bytes());
+    out
+}
+
+fn encode_6699(frame: &Frame, key: &[u8; 16]) -> Vec<u8> {
+    let mut plain = Vec::new();
+    if let Some(retcode) = frame.retcode {
+        plain.extend(retcode.to_be_bytes());
+    }
+    if has_version_header(frame.cmd) {
+        plain.extend(version_header(Version::V35));
+    }
+    plain.extend(&frame.body);
+    let len = GCM_IV_LEN + plain.len() + GCM_TAG_LEN;
+
+    let mut out = Vec::with_capacity(HEADER_LEN_6699 + len + 4);
+    out.extend(PREFIX_6699.to_be_bytes());
+    out.extend(0u16.to_be_bytes());
+    out.extend(frame.seq.to_be_bytes());
+    out.extend(frame.cmd.to_be_bytes());
+    out.extend((len as u32).to_be_bytes());
+
+    let iv: [u8; GCM_IV_LEN] = rand::random();
+    let sealed = Aes128Gcm::new(GenericArray::from_slice(key))
+        .encrypt(
+            Nonce::from_slice(&iv),
+            Payload {
+                msg: &plain,
+                aad: &out[4..],
+            },
+        )
+        .expect("AES-GCM encryption does not fail for frame-sized input");
+    out.extend(iv);
+    out.extend(sealed);
+    out.extend(SUFFIX_6699.to_be_bytes());
+    out
+}
+
+/// Parse and decrypt the first frame in `buf`, returning it and the number
+/// of bytes it took, or `None` if `buf` does not hold a whole frame yet.
+///
+/// `from_device` selects whether the payload starts with a return code.
+pub fn decode(
+    buf: &[u8],
+    version: Version,
+    key: &[u8; 16],
+    from_device: bool,
+) -> Result<Option<(Frame, usize)>> {
+    let (header_len, prefix, suffix, len_at) = match version {
+        Version::V35 => (HEADER_LEN_6699, PREFIX_6699, SUFFIX_6699, 14),
+        _ => (HEADER_LEN_55AA, PREFIX_55AA, SUFFIX_55AA, 12),
+    };
+    if buf.len() < header_len {
+        return Ok(None);
+    }
+    let word = |at: usize| u32::from_be_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+    if word(0) != prefix {
+        bail!("frame does not start with the protocol prefix");
+    }
+    let len = word(len_at) as usize;
+    let total = match version {
+        Version::V35 if len >= GCM_IV_LEN + GCM_TAG_LEN => header_len + len + 4,
+        Version::V33 if len >= 8 => header_len + len,
+        Version::V34 if len >= 36 => header_len + len,
+        _ => bail!("invalid frame length {len}"),
+    };
+    if len > MAX_FRAME_LEN {
+        bail!("invalid frame length {len}");
+    }
+    if buf.len() < total {
+        return Ok(None);
+    }
+    if word(total - 4) != suffix {
+        bail!("frame does not end with the protocol suffix");
+    }
+
+    let (seq, cmd) = match version {
+        Version::V35 => (word(6), word(10)),
+        _ => (word(4), word(8)),
+    };
+    let mut payload = match version {
+        Version::V35 => {
+            let (iv, sealed) = buf[header_len..total - 4].split_at(GCM_IV_LEN);
+            Aes128Gcm::new(GenericArray::from_slice(key))
+                .decrypt(
+                    Nonce::from_slice(iv),
+                    Payload {
+                        msg: sealed,
+                        aad: &buf[4..header_len],
+                    },
+                )
+                .map_err(|_| anyhow::anyhow!("frame failed authentication; wrong local key?"))?
+        }
+        _ => {
+            let body_end = total - 4 - if version == Version::V33 { 4 } else { 32 };
+            let check = &buf[body_end..total - 4];
+            let valid = match version {
+                Version::V33 => check == crc32fast::hash(&buf[..body_end]).to_be_bytes(),
+                _ => check == hmac(key, &buf[..body_end]),
+            };
+            if !valid {
+                bail!("frame failed its integrity check; wrong local key?");
+            }
+            buf[header_len..body_end].to_vec()
+        }
+    };
+
+    let mut retcode = None;
+    if from_device && payload.len() >= 4 {
+        let code = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        // Some frames carry no return code; their payload never starts with
+        // three zero bytes.
+        if code & 0xFFFF_FF00 == 0 {
+            retcode = Some(code);
+            payload.drain(..4);
+        }
+    }
+
+    let body = match version {
+        Version::V35 => strip_version_header(version, payload),
+        _ if payload.is_empty() => payload,
+        Version::V33 => ecb_decrypt(key, &strip_version_header(version, payload))?,
+        Version::V34 => strip_version_header(version, ecb_decrypt(key, &payload)?),
+    };
+    Ok(Some((
+        Frame {
+            seq,
+            cmd,
+            retcode,
+            body,
+        },
+        total,
+    )))
+}
+
+/// Whether payloads of `cmd` carry the version header.
+fn has_version_header(cmd: u32) -> bool {
+    !matches!(
+        cmd,
+        cmd::DP_QUERY
+            | cmd::DP_QUERY_NEW
+            | cmd::HEART_BEAT
+            | cmd::SESS_KEY_NEG_START
+            | cmd::SESS_KEY_NEG_RESP
+            | cmd::SESS_KEY_NEG_FINISH
+    )
+}
+
+fn version_header(version: Version) -> Vec<u8> {
+    let mut header = version.tag().to_vec();
+    header.resize(VERSION_HEADER_LEN, 0);
+    header
+}
+
+fn strip_version_header(version: Version, mut data: Vec<u8>) -> Vec<u8> {
+    if data.starts_with(version.tag()) && data.len() >= VERSION_HEADER_LEN {
+        data.drain(..VERSION_HEADER_LEN);
+    }
+    data
+}
+
+/// AES-128-ECB with PKCS#7 padding.
+pub fn ecb_encrypt(key: &[u8; 16], plain: &[u8]) -> Vec<u8> {
+    let pad = 16 - plain.len() % 16;
+    let mut buf = plain.to_vec();
+    buf.extend(std::iter::repeat_n(pad as u8, pad));
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    for block in buf.chunks_exact_mut(16) {
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+    }
+    buf
+}
+
+/// Decrypt AES-128-ECB with PKCS#7 padding.
+pub fn ecb_decrypt(key: &[u8; 16], data: &[u8]) -> Result<Vec<u8>> {
+    if data.is_empty() || !data.len().is_multiple_of(16) {
+        bail!("ECB payload length {} is not a multiple of 16", data.len());
+    }
+
+    let mut buf = data.to_vec();
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    for block in buf.chunks_exact_mut(16) {
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+
+    let pad = usize::from(*buf.last().unwrap_or(&0));
+    if pad == 0
+        || pad > 16
+        || buf[buf.len() - pad..]
+            .iter()
+            .any(|&b| usize::from(b) != pad)
+    {
+        bail!("ECB payload has invalid padding; wrong key?");
+    }
+    buf.truncate(buf.len() - pad);
+    Ok(buf)
+}
+
+pub fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Session key of a 3.4/3.5 connection: both negotiation nonces XOR-ed
+/// together, encrypted with the local key.
+pub fn session_key(
+    version: Version,
+    local_key: &[u8; 16],
+    local_nonce: &[u8; 16],
+    remote_nonce: &[u8; 16],
+) -> [u8; 16] {
+    let mut mixed = [0; 16];
+    for (i, byte) in mixed.iter_mut().enumerate() {
+        *byte = local_nonce[i] ^ remote_nonce[i];
+    }
+    let encrypted = match version {
+        Version::V35 => Aes128Gcm::new(GenericArray::from_slice(local_key))
+            .encrypt(Nonce::from_slice(&local_nonce[..GCM_IV_LEN]), &mixed[..])
+            .expect("AES-GCM encryption does not fail for 16 bytes"),
+        _ => ecb_encrypt(local_key, &mixed),
+    };
+    let mut key = [0; 16];
+    key.copy_from_slice(&encrypted[..16]);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8; 16] = b"0123456789abcdef";
+    const VERSIONS: [Version; 3] = [Version::V33, Version::V34, Version::V35];
+
+    fn frame(cmd: u32, retcode: Option<u32>, body: &[u8]) -> Frame {
+        Frame {
+            seq: 7,
+            cmd,
+            retcode,
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        for version in VERSIONS {
+            for cmd in [cmd::CONTROL, cmd::DP_QUERY] {
+                let sent = frame(cmd, None, b"{\"dps\":{\"1\":true}}");
+                let bytes = encode(&sent, version, KEY);
+                let (decoded, used) = decode(&bytes, version, KEY, false).unwrap().unwrap();
+                assert_eq!(decoded, sent, "{version:?}");
+                assert_eq!(used, bytes.len());
+            }
+
+            let reply = frame(cmd::STATUS, Some(0), b"{\"dps\":{}}");
+            let bytes = encode(&reply, version, KEY);
+            let (decoded, _) = decode(&bytes, version, KEY, true).unwrap().unwrap();
+            assert_eq!(decoded, reply, "{version:?}");
+        }
+    }
+
+    #[test]
+    fn version_header_is_added_only_where_expected() {
+        let bytes = encode(&frame(cmd::CONTROL, None, b"{}"), Version::V33, KEY);
+        assert!(bytes[HEADER_LEN_55AA..].starts_with(b"3.3\0"));
+        let bytes = encode(&frame(cmd::DP_QUERY, None, b"{}"), Version::V33, KEY);
+        assert!(!bytes[HEADER_LEN_55AA..].starts_with(b"3.3"));
+    }
+
+    #[test]
+    fn partial_frames_wait_for_more_data() {
+        for version in VERSIONS {
+            let bytes = encode(&frame(cmd::HEART_BEAT, None, b"{}"), version, KEY);
+            for cut in [0, 10, bytes.len() - 1] {
+                assert!(decode(&bytes[..cut], version, KEY, false)
+                    .unwrap()
+                    .is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_frames_are_rejected() {
+        for version in VERSIONS {
+            let mut bytes = encode(&frame(cmd::CONTROL, None, b"payload"), version, KEY);
+            bytes[HEADER_LEN_6699 + 2] ^= 1;
+            assert!(decode(&bytes, version, KEY, false).is_err(), "{version:?}");
+        }
+        for version in [Version::V34, Version::V35] {
+            let bytes = encode(&frame(cmd::CONTROL, None, b"x"), version, KEY);
+            assert!(decode(&bytes, version, b"ffffffffffffffff", false).is_err());
+        }
+    }
+
+    #[test]
+    fn both_sides_derive_the_same_session_key() {
+        let (client_nonce, device_nonce) = ([1u8; 16], [2u8; 16]);
+        let mut keys = Vec::new();
+        for version in [Version::V34, Version::V35] {
+            // The negotiation as `Connection` and `local/fake.rs` run it.
+            let start = encode(
+                &frame(cmd::SESS_KEY_NEG_START, None, &client_nonce),
+                version,
+                KEY,
+            );
+            let (start, _) = decode(&start, version, KEY, false).unwrap().unwrap();
+            let received: [u8; 16] = start.body[..16].try_into().unwrap();
+            let device = session_key(version, KEY, &received, &device_nonce);
+
+            let body = [&device_nonce[..], &hmac(KEY, &received)].concat();
+            let resp = encode(&frame(cmd::SESS_KEY_NEG_RESP, Some(0), &body), version, KEY);
+            let (resp, _) = decode(&resp, version, KEY, true).unwrap().unwrap();
+            assert_eq!(resp.body[16..48], hmac(KEY, &client_nonce));
+            let received: [u8; 16] = resp.body[..16].try_into().unwrap();
+            let client = session_key(version, KEY, &client_nonce, &received);
+
+            assert_eq!(client, device, "{version:?}");
+            assert_ne!(&client, KEY);
+            // Frames sealed by one side open with the other side's key.
+            let bytes = encode(&frame(cmd::CONTROL, None, b"{}"), version, &client);
+            let (opened, _) = decode(&bytes, version, &device, false).unwrap().unwrap();
+            assert_eq!(opened.body, b"{}");
+            keys.push(client);
+        }
+        assert_ne!(keys[0], keys[1]);
+    }
+}
@@ -0,0 +1,50 @@
+//! Wiremock stand-in for Tuya Cloud, shared by tests that go through a real
+//! [`TuyaClient`](super::TuyaClient) rather than [`FakeBackend`](super::fake::FakeBackend).
+
+use serde_json::Value;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+/// Successful envelope around `result`.
+pub(crate) fn ok(result: Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "success": true, "t": 0, "tid": "ok", "result": result
+    }))
+}
+
+/// Failed envelope with Tuya error `code`.
+pub(crate) fn failed(code: i32) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "success": false, "t": 0, "tid": "err", "code": code, "msg": "failed"
+    }))
+}
+
+/// Token grant whose refresh token is `rt-<access_token>`.
+pub(crate) fn token(access_token: &str, expire_time: i64) -> ResponseTemplate {
+    ok(serde_json::json!({
+        "access_token": access_token, "expire_time": expire_time,
+        "refresh_token": format!("rt-{access_token}"), "uid": "u"
+    }))
+}
+
+/// Server that grants a long-lived token to any client.
+pub(crate) async fn cloud() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/v1.0/token"))
+        .respond_with(token("at", 7200))
+        .mount(&server)
+        .await;
+    server
+}
+
+/// Answer every status call for `device_id` with `dps`.
+pub(crate) async fn mount_status(server: &MockServer, device_id: &str, dps: Value) {
+    Mock::given(method("GET"))
+        .and(path(format!("/v1.0/devices/{device_id}/status")))
+        .respond_with(ok(dps))
+        .mount(server)
+        .await;
+}
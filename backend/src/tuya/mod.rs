@@ -1,19 +1,45 @@
+pub mod accounts;
+pub mod backend;
+pub mod error;
+#[cfg(test)]
+pub(crate) mod fake;
+pub mod limiter;
+pub mod local;
+#[cfg(test)]
+pub(crate) mod mock;
 pub mod models;
+pub mod retry;
+pub mod usage;
 
-use anyhow::{Context, Result};
+use anyhow::Context;
 use hmac::{Hmac, Mac};
-use reqwest::Client;
+use reqwest::{Client, Method};
+use serde::de::DeserializeOwned;
 use sha2::{Digest, Sha256};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::Mutex;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
 use crate::{config::Config, response_store};
 
 use self::models::{
-    Command, DeviceProperty, DeviceStatusResponse, SendCommandRequest, SendCommandResponse,
-    ShadowPropertiesResponse, ShadowProperty, TokenResponse, TokenResult,
+    Command, CommandAck, DeviceProperty, DeviceSpecs, DeviceStatus, SendCommandRequest,
+    ShadowPropertiesResult, ShadowProperty, Specifications, TokenResult, TuyaDevice, TuyaResponse,
+};
+pub use self::{
+    accounts::TuyaAccounts,
+    backend::DeviceBackend,
+    error::TuyaError,
+    limiter::RateLimiter,
+    local::{LocalClient, Transport},
+    retry::RetryPolicy,
+    usage::{QuotaSettings, Usage},
 };
 
 type HmacSha256 = Hmac<Sha256>;
@@ -21,6 +47,14 @@ type HmacSha256 = Hmac<Sha256>;
 #[derive(Debug, Clone)]
 pub struct TuyaClient {
     inner: Arc<Inner>,
+    retry: RetryPolicy,
+    /// Shared by all clones, like `inner`.
+    limiter: Arc<RateLimiter>,
+    usage: Usage,
+    /// LAN access for devices configured with a local transport.
+    local: Option<Arc<LocalClient>>,
+    /// Where raw responses are saved; `None` to not save them.
+    response_dir: Option<Arc<Path>>,
 }
 
 #[derive(Debug)]
@@ -30,177 +64,659 @@ struct Inner {
     client_id: String,
     client_secret: String,
     token: Mutex<Option<CachedToken>>,
+    /// DP specifications per device ID.
+    specs: Mutex<HashMap<String, CachedSpecs>>,
 }
 
 #[derive(Debug)]
 struct CachedToken {
     access_token: String,
+    refresh_token: String,
+    /// Tuya user the token was issued for.
+    uid: String,
     /// Unix timestamp (seconds) when this token expires
     expires_at: i64,
 }
 
+#[derive(Debug)]
+struct CachedSpecs {
+    specs: Arc<DeviceSpecs>,
+    fetched_at: Instant,
+}
+
+/// Specifications are fetched again after this long, so a firmware update
+/// that changes a DP's scale is picked up.
+const SPECS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Calls renew the token this many seconds before it expires.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// The background task renews the token this many seconds before it expires,
+/// well ahead of `EXPIRY_MARGIN_SECS`, so calls never wait on it.
+const REFRESH_AHEAD_SECS: i64 = 300;
+
+/// Delay before the background task tries again after a failed renewal.
+const REFRESH_RETRY: Duration = Duration::from_secs(30);
+
+/// Most device IDs the batch status endpoint accepts per call.
+pub const STATUS_BATCH_SIZE: usize = 20;
+
+/// One Tuya Cloud call, kept so it can be re-sent on retry.
+struct Request<'a> {
+    method: Method,
+    path: &'a str,
+    body: Option<Vec<u8>>,
+    /// `response_store` endpoint name and suffix. The name also labels the
+    /// call in `tuya_usage`.
+    store: (&'a str, &'a str),
+}
+
 impl TuyaClient {
-    pub fn new(config: &Config) -> Self {
+    /// Build the client of a named account, with LAN access to the local
+    /// devices bound to it. `None` if the account is not configured.
+    pub fn for_account(config: &Config, name: &str) -> Option<Self> {
+        let account = config.tuya_account(name)?;
+        let client = Self::with_credentials(
+            &account.base_url,
+            &account.client_id,
+            &account.client_secret,
+        )
+        .with_retry(RetryPolicy {
+            max_attempts: config.tuya_retry_attempts,
+            base_delay: Duration::from_millis(config.tuya_retry_base_ms),
+            max_delay: Duration::from_millis(config.tuya_retry_max_ms),
+        })
+        .with_rate_limit(RateLimiter::new(
+            config.tuya_rate_per_sec,
+            config.tuya_rate_burst,
+        ))
+        .with_response_dir(config.response_dir.clone());
+        let local_devices: Vec<_> = config
+            .local_devices
+            .iter()
+            .filter(|d| config.account_of(&d.device_id) == name)
+            .cloned()
+            .collect();
+        if local_devices.is_empty() {
+            Some(client)
+        } else {
+            Some(client.with_local(LocalClient::new(local_devices)))
+        }
+    }
+
+    /// Build a client for an explicit endpoint and credential pair.
+    pub fn with_credentials(base_url: &str, client_id: &str, client_secret: &str) -> Self {
         Self {
             inner: Arc::new(Inner {
                 http: Client::new(),
-                base_url: config.tuya_base_url.clone(),
-                client_id: config.tuya_client_id.clone(),
-                client_secret: config.tuya_client_secret.clone(),
+                base_url: base_url.to_owned(),
+                client_id: client_id.to_owned(),
+                client_secret: client_secret.to_owned(),
                 token: Mutex::new(None),
+                specs: Mutex::new(HashMap::new()),
             }),
+            retry: RetryPolicy::default(),
+            limiter: Arc::new(RateLimiter::default()),
+            usage: Usage::default(),
+            local: None,
+            response_dir: None,
         }
     }
 
+    /// Replace the retry policy.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Save raw responses under `dir` (see `response_store`); off by default.
+    pub fn with_response_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.response_dir = dir.map(Arc::from);
+        self
+    }
+
+    /// Replace the request rate limiter.
+    pub fn with_rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Arc::new(limiter);
+        self
+    }
+
+    /// Count calls in `usage`; by default they are counted in memory only.
+    pub fn with_usage(mut self, usage: Usage) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// Route status and command calls of the devices `local` knows over the
+    /// LAN, as their transport says.
+    pub fn with_local(mut self, local: LocalClient) -> Self {
+        self.local = Some(Arc::new(local));
+        self
+    }
+
+    /// The LAN client, if any device uses a local transport.
+    pub fn local(&self) -> Option<&Arc<LocalClient>> {
+        self.local.as_ref()
+    }
+
+    /// Calls made this month against the quota.
+    pub fn usage(&self) -> &Usage {
+        &self.usage
+    }
+
     /// Returns a valid access token, refreshing it if necessary.
-    async fn access_token(&self) -> Result<String> {
+    async fn access_token(&self) -> Result<String, TuyaError> {
         let mut guard = self.inner.token.lock().await;
         let now = chrono::Utc::now().timestamp();
 
         if let Some(ref cached) = *guard {
-            // Refresh 60 s before expiry
-            if cached.expires_at > now + 60 {
+            if cached.expires_at > now + EXPIRY_MARGIN_SECS {
                 return Ok(cached.access_token.clone());
             }
         }
 
-        info!("Fetching new Tuya access token");
-        let token = self.fetch_token().await?;
-        let expires_at = now + token.expire_time;
-        let access_token = token.access_token.clone();
+        self.renew(&mut guard).await
+    }
+
+    /// Keep the access token fresh so calls never wait on token acquisition.
+    /// Runs forever; spawn it next to the polling and control loops.
+    pub async fn run_token_refresh(self) {
+        info!("Tuya token refresh task started");
+        loop {
+            let expires_at = self.inner.token.lock().await.as_ref().map(|t| t.expires_at);
+            let delay = refresh_delay(expires_at, chrono::Utc::now().timestamp());
+            tokio::time::sleep(delay).await;
+
+            let mut guard = self.inner.token.lock().await;
+            let now = chrono::Utc::now().timestamp();
+            // A call may have renewed it in the meantime.
+            if guard
+                .as_ref()
+                .is_some_and(|t| t.expires_at > now + REFRESH_AHEAD_SECS)
+            {
+                continue;
+            }
+            if let Err(e) = self.renew(&mut guard).await {
+                warn!(error = %e, "Background Tuya token refresh failed");
+                drop(guard);
+                tokio::time::sleep(REFRESH_RETRY).await;
+            }
+        }
+    }
+
+    /// Replace the cached token, using its refresh token when there is one
+    /// and falling back to a full grant if the refresh fails.
+    async fn renew(&self, cached: &mut Option<CachedToken>) -> Result<String, TuyaError> {
+        let now = chrono::Utc::now().timestamp();
+        let refreshed = match cached.as_ref() {
+            Some(old) => {
+                info!("Refreshing Tuya access token");
+                self.refresh_token(&old.refresh_token)
+                    .await
+                    .inspect_err(
+                        |e| warn!(error = %e, "Tuya token refresh failed; requesting a new grant"),
+                    )
+                    .ok()
+            }
+            None => None,
+        };
+        let token = match refreshed {
+            Some(token) => token,
+            None => {
+                info!("Fetching new Tuya access token");
+                self.fetch_token().await?
+            }
+        };
 
-        *guard = Some(CachedToken {
+        let access_token = token.access_token.clone();
+        *cached = Some(CachedToken {
             access_token: token.access_token,
-            expires_at,
+            refresh_token: token.refresh_token,
+            uid: token.uid,
+            expires_at: now + token.expire_time,
         });
 
         Ok(access_token)
     }
 
-    async fn fetch_token(&self) -> Result<TokenResult> {
-        let path = "/v1.0/token?grant_type=1";
-        let url = format!("{}{}", self.inner.base_url, path);
-        debug!(url = %url, "Requesting Tuya token");
+    async fn fetch_token(&self) -> Result<TokenResult, TuyaError> {
+        let req = Request {
+            method: Method::GET,
+            path: "/v1.0/token?grant_type=1",
+            body: None,
+            store: ("token", ""),
+        };
+        debug!(path = req.path, "Requesting Tuya token");
+        self.with_backoff(|| self.request(&req, None))
+            .await
+            .map(|(token, _)| token)
+    }
+
+    async fn refresh_token(&self, refresh_token: &str) -> Result<TokenResult, TuyaError> {
+        let path = format!("/v1.0/token/{refresh_token}");
+        let req = Request {
+            method: Method::GET,
+            path: &path,
+            body: None,
+            store: ("token", "refresh"),
+        };
+        debug!("Refreshing Tuya token");
+        self.with_backoff(|| self.request(&req, None))
+            .await
+            .map(|(token, _)| token)
+    }
 
+    /// Send an authenticated request under the retry policy.
+    ///
+    /// A token-invalid response drops the cached token and retries once with
+    /// a fresh one; retryable failures are retried with backoff.
+    async fn call<T: DeserializeOwned>(
+        &self,
+        req: &Request<'_>,
+    ) -> Result<(T, Option<String>), TuyaError> {
+        let attempt = || async {
+            let token = self.access_token().await?;
+            self.request(req, Some(&token)).await
+        };
+        match self.with_backoff(attempt).await {
+            Err(TuyaError::TokenInvalid(api)) => {
+                warn!(
+                    path = req.path,
+                    code = api.code,
+                    "Tuya token rejected; fetching a new one"
+                );
+                *self.inner.token.lock().await = None;
+                self.with_backoff(attempt).await
+            }
+            result => result,
+        }
+    }
+
+    /// Run `attempt` until it succeeds, fails with a non-retryable error or
+    /// the policy's attempts are used up.
+    async fn with_backoff<T, F, Fut>(&self, attempt: F) -> Result<T, TuyaError>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<T, TuyaError>>,
+    {
+        let mut retry = 0;
+        loop {
+            match attempt().await {
+                Err(e) if e.is_retryable() && retry + 1 < self.retry.max_attempts => {
+                    let delay = self.retry.backoff(retry);
+                    warn!(
+                        error = %e,
+                        retry = retry + 1,
+                        delay_ms = delay.as_millis() as u64,
+                        "Tuya call failed; retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Send one signed request and decode the response envelope. Returns the
+    /// result and the Tuya request trace ID.
+    async fn request<T: DeserializeOwned>(
+        &self,
+        req: &Request<'_>,
+        access_token: Option<&str>,
+    ) -> Result<(T, Option<String>), TuyaError> {
+        // Sign only once the limiter lets the call through, so the signed
+        // timestamp is not stale after waiting in its queue.
+        let (endpoint, suffix) = req.store;
+        self.limiter.acquire().await;
+        self.usage.record(endpoint);
+
+        let url = format!("{}{}", self.inner.base_url, req.path);
+        let body = req.body.as_deref().unwrap_or_default();
         let headers = build_signed_headers(
-            "GET",
-            path,
-            &[],
+            req.method.as_str(),
+            req.path,
+            body,
             &self.inner.client_id,
             &self.inner.client_secret,
-            None,
+            access_token,
         );
 
-        let bytes = self
+        let mut builder = self
             .inner
             .http
-            .get(&url)
-            .headers(to_header_map(headers)?)
-            .send()
-            .await
-            .context("Tuya token request failed")?
-            .error_for_status()
-            .context("Tuya token endpoint returned error status")?
-            .bytes()
-            .await
-            .context("Failed to read Tuya token response body")?;
+            .request(req.method.clone(), &url)
+            .headers(to_header_map(headers)?);
+        if let Some(body) = &req.body {
+            builder = builder
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+        }
 
-        response_store::save("token", "", &bytes).await;
+        let resp = builder.send().await?;
+        if !resp.status().is_success() {
+            return Err(TuyaError::Status(resp.status()));
+        }
+        let bytes = resp.bytes().await?;
 
-        let resp = serde_json::from_slice::<TokenResponse>(&bytes)
-            .context("Failed to deserialize Tuya token response")?
-            .into_result()
-            .context("Tuya token API call failed")?;
+        if let Some(dir) = &self.response_dir {
+            response_store::save(dir, endpoint, suffix, &bytes).await;
+        }
 
-        Ok(resp)
+        let resp = serde_json::from_slice::<TuyaResponse<T>>(&bytes)?;
+        let tid = resp.tid.clone();
+        Ok((resp.into_result()?, tid))
     }
 
-    /// Fetch all data-point (DP) properties for a device.
-    pub async fn get_device_status(&self, device_id: &str) -> Result<Vec<DeviceProperty>> {
-        let token = self.access_token().await?;
-        let path = format!("/v1.0/devices/{}/status", device_id);
-        let url = format!("{}{}", self.inner.base_url, path);
-        debug!(device_id = %device_id, url = %url, "Fetching device status");
+    /// Fetch all data-point (DP) properties for a device, over the transport
+    /// configured for it.
+    pub async fn get_device_status(
+        &self,
+        device_id: &str,
+    ) -> Result<Vec<DeviceProperty>, TuyaError> {
+        match self.transport(device_id) {
+            Transport::Cloud => self.cloud_device_status(device_id).await,
+            Transport::Local => self.local_device_status(device_id).await,
+            Transport::LocalFallback => match self.local_device_status(device_id).await {
+                Ok(dps) => Ok(dps),
+                Err(e) => {
+                    warn!(device_id = %device_id, error = %e, "Local status failed; using cloud");
+                    self.cloud_device_status(device_id).await
+                }
+            },
+        }
+    }
 
-        let headers = build_signed_headers(
-            "GET",
-            &path,
-            &[],
-            &self.inner.client_id,
-            &self.inner.client_secret,
-            Some(&token),
-        );
+    async fn cloud_device_status(&self, device_id: &str) -> Result<Vec<DeviceProperty>, TuyaError> {
+        let path = format!("/v1.0/devices/{}/status", device_id);
+        debug!(device_id = %device_id, path = %path, "Fetching device status");
 
-        let bytes = self
-            .inner
-            .http
-            .get(&url)
-            .headers(to_header_map(headers)?)
-            .send()
-            .await
-            .context("Tuya get_device_status request failed")?
-            .error_for_status()
-            .context("Tuya device status endpoint returned error status")?
-            .bytes()
-            .await
-            .context("Failed to read Tuya device status response body")?;
+        let req = Request {
+            method: Method::GET,
+            path: &path,
+            body: None,
+            store: ("device_status", device_id),
+        };
+        self.call(&req).await.map(|(dps, _)| dps)
+    }
 
-        response_store::save("device_status", device_id, &bytes).await;
+    /// Fetch the DP properties of several devices: those on the cloud
+    /// transport in one call per `STATUS_BATCH_SIZE` devices, the others one
+    /// by one. Every device gets an entry; a failed batch call fails each of
+    /// its devices.
+    pub async fn get_devices_status(
+        &self,
+        device_ids: &[String],
+    ) -> HashMap<String, Result<Vec<DeviceProperty>, TuyaError>> {
+        let (cloud, other): (Vec<_>, Vec<_>) = device_ids
+            .iter()
+            .partition(|id| self.transport(id) == Transport::Cloud);
+
+        let mut statuses = HashMap::new();
+        for chunk in cloud.chunks(STATUS_BATCH_SIZE) {
+            match self.cloud_devices_status(chunk).await {
+                Ok(mut found) => {
+                    for id in chunk {
+                        let status = found.remove(id.as_str()).ok_or_else(|| {
+                            TuyaError::Decode(format!("no status for {id} in batch response"))
+                        });
+                        statuses.insert(id.to_string(), status);
+                    }
+                }
+                Err(e) => {
+                    let e = Arc::new(e);
+                    for id in chunk {
+                        statuses.insert(id.to_string(), Err(TuyaError::Batch(e.clone())));
+                    }
+                }
+            }
+        }
+        for id in other {
+            statuses.insert(id.clone(), self.get_device_status(id).await);
+        }
+        statuses
+    }
 
-        let resp = serde_json::from_slice::<DeviceStatusResponse>(&bytes)
-            .context("Failed to deserialize Tuya device status response")?
-            .into_result()
-            .context("Tuya device status API call failed")?;
+    async fn cloud_devices_status(
+        &self,
+        device_ids: &[&String],
+    ) -> Result<HashMap<String, Vec<DeviceProperty>>, TuyaError> {
+        let ids: Vec<_> = device_ids.iter().map(|id| id.as_str()).collect();
+        let path = format!("/v1.0/iot-03/devices/status?device_ids={}", ids.join(","));
+        debug!(devices = ids.len(), "Fetching batch device status");
+
+        let req = Request {
+            method: Method::GET,
+            path: &path,
+            body: None,
+            store: ("device_status_batch", ""),
+        };
+        let (devices, _): (Vec<DeviceStatus>, _) = self.call(&req).await?;
+        Ok(devices.into_iter().map(|d| (d.id, d.status)).collect())
+    }
 
-        Ok(resp)
+    async fn local_device_status(&self, device_id: &str) -> Result<Vec<DeviceProperty>, TuyaError> {
+        let local = self.local_client()?;
+        let codes = self.dp_codes(local, device_id).await?;
+        debug!(device_id = %device_id, "Querying device status over the LAN");
+        let dps = local.query(device_id).await.map_err(TuyaError::Local)?;
+
+        Ok(dps
+            .into_iter()
+            .filter_map(|(id, value)| {
+                let code = codes.get(&id)?;
+                match serde_json::from_value(value) {
+                    Ok(value) => Some(DeviceProperty {
+                        code: code.clone(),
+                        value,
+                    }),
+                    Err(e) => {
+                        debug!(device_id = %device_id, code = %code, error = %e, "Skipping DP");
+                        None
+                    }
+                }
+            })
+            .collect())
     }
 
-    /// Send one or more commands to a device.
+    /// Send one or more commands to a device, over the transport configured
+    /// for it.
     pub async fn send_commands(
         &self,
         device_id: &str,
         commands: Vec<Command>,
-    ) -> Result<bool> {
-        let token = self.access_token().await?;
+    ) -> Result<CommandAck, TuyaError> {
+        match self.transport(device_id) {
+            Transport::Cloud => self.cloud_send_commands(device_id, commands).await,
+            Transport::Local => self.local_send_commands(device_id, &commands).await,
+            Transport::LocalFallback => {
+                match self.local_send_commands(device_id, &commands).await {
+                    Ok(ack) => Ok(ack),
+                    Err(e) => {
+                        warn!(device_id = %device_id, error = %e, "Local command failed; using cloud");
+                        self.cloud_send_commands(device_id, commands).await
+                    }
+                }
+            }
+        }
+    }
+
+    async fn cloud_send_commands(
+        &self,
+        device_id: &str,
+        commands: Vec<Command>,
+    ) -> Result<CommandAck, TuyaError> {
         let path = format!("/v1.0/devices/{}/commands", device_id);
-        let url = format!("{}{}", self.inner.base_url, path);
         debug!(device_id = %device_id, "Sending commands to device");
 
-        let body = SendCommandRequest { commands };
-        let body_bytes = serde_json::to_vec(&body).context("Failed to serialize command body")?;
+        let body = serde_json::to_vec(&SendCommandRequest { commands })
+            .context("Failed to serialize command body")?;
+        let req = Request {
+            method: Method::POST,
+            path: &path,
+            body: Some(body),
+            store: ("send_commands", device_id),
+        };
+        let (result, tid) = self.call(&req).await?;
 
-        let headers = build_signed_headers(
-            "POST",
-            &path,
-            &body_bytes,
-            &self.inner.client_id,
-            &self.inner.client_secret,
-            Some(&token),
-        );
+        Ok(CommandAck { result, tid })
+    }
 
-        let bytes = self
-            .inner
-            .http
-            .post(&url)
-            .headers(to_header_map(headers)?)
-            .json(&body)
-            .send()
-            .await
-            .context("Tuya send_commands request failed")?
-            .error_for_status()
-            .context("Tuya commands endpoint returned error status")?
-            .bytes()
-            .await
-            .context("Failed to read Tuya send_commands response body")?;
+    async fn local_send_commands(
+        &self,
+        device_id: &str,
+        commands: &[Command],
+    ) -> Result<CommandAck, TuyaError> {
+        let local = self.local_client()?;
+        let codes = self.dp_codes(local, device_id).await?;
+        let mut dps = serde_json::Map::new();
+        for command in commands {
+            let id = codes
+                .iter()
+                .find_map(|(id, code)| (*code == command.code).then_some(id))
+                .ok_or_else(|| {
+                    TuyaError::Local(anyhow::anyhow!("no DP ID known for {}", command.code))
+                })?;
+            let value = serde_json::to_value(&command.value).context("Failed to serialize DP")?;
+            dps.insert(id.clone(), value);
+        }
+        debug!(device_id = %device_id, "Sending commands over the LAN");
+        local.set(device_id, dps).await.map_err(TuyaError::Local)?;
+
+        Ok(CommandAck {
+            result: true,
+            tid: None,
+        })
+    }
+
+    fn transport(&self, device_id: &str) -> Transport {
+        self.local
+            .as_ref()
+            .map_or(Transport::Cloud, |local| local.transport(device_id))
+    }
+
+    fn local_client(&self) -> Result<&LocalClient, TuyaError> {
+        self.local
+            .as_deref()
+            .ok_or_else(|| TuyaError::Local(anyhow::anyhow!("no devices are reachable locally")))
+    }
+
+    /// DP ID → DP code mapping for the local protocol: configured, or
+    /// learned once from the device's shadow properties while the cloud is
+    /// reachable.
+    async fn dp_codes(
+        &self,
+        local: &LocalClient,
+        device_id: &str,
+    ) -> Result<Arc<HashMap<String, String>>, TuyaError> {
+        if let Some(codes) = local.dp_codes(device_id) {
+            return Ok(codes);
+        }
+        let props = self.get_shadow_properties(device_id, "dp_codes").await?;
+        info!(device_id = %device_id, dps = props.len(), "Learned DP IDs from the cloud");
+        Ok(local.learn_dp_codes(
+            device_id,
+            props
+                .into_iter()
+                .map(|p| (p.dp_id.to_string(), p.code))
+                .collect(),
+        ))
+    }
+
+    /// DP specifications of a device, cached for `SPECS_TTL`.
+    ///
+    /// If the specifications can not be refreshed, the cached ones are used
+    /// until they can. A changed scale is logged, since it changes how the
+    /// device's readings are stored. Devices Tuya refuses specifications for
+    /// get empty ones.
+    pub async fn device_specs(&self, device_id: &str) -> Result<Arc<DeviceSpecs>, TuyaError> {
+        let cached = self.inner.specs.lock().await.get(device_id).map(|c| {
+            let fresh = c.fetched_at.elapsed() < SPECS_TTL;
+            (c.specs.clone(), fresh)
+        });
+        let stale = match cached {
+            Some((specs, true)) => return Ok(specs),
+            Some((specs, false)) => Some(specs),
+            None => None,
+        };
+
+        let specs = match self.get_specifications(device_id).await {
+            Ok(specs) => Arc::new(DeviceSpecs::from(specs)),
+            Err(e) => match stale {
+                Some(specs) => {
+                    warn!(device_id = %device_id, error = %e, "Keeping stale DP specifications");
+                    return Ok(specs);
+                }
+                // Some devices (e.g. weather stations) have no v1 endpoints;
+                // remember that instead of asking on every poll.
+                None if matches!(e, TuyaError::PermissionDenied(_)) => {
+                    debug!(device_id = %device_id, "Device has no DP specifications");
+                    Arc::new(DeviceSpecs::default())
+                }
+                None => return Err(e),
+            },
+        };
+        if let Some(old) = &stale {
+            for (code, spec) in &specs.status {
+                let previous = old.scale(code);
+                if previous.is_some() && previous != spec.values.scale {
+                    warn!(
+                        device_id = %device_id,
+                        code = %code,
+                        from = ?previous,
+                        to = ?spec.values.scale,
+                        "DP scale changed"
+                    );
+                }
+            }
+        }
 
-        response_store::save("send_commands", device_id, &bytes).await;
+        self.inner.specs.lock().await.insert(
+            device_id.to_owned(),
+            CachedSpecs {
+                specs: specs.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+        Ok(specs)
+    }
 
-        let resp = serde_json::from_slice::<SendCommandResponse>(&bytes)
-            .context("Failed to deserialize Tuya send_commands response")?
-            .into_result()
-            .context("Tuya send_commands API call failed")?;
+    /// Fetch the DP specifications of a device.
+    pub async fn get_specifications(&self, device_id: &str) -> Result<Specifications, TuyaError> {
+        let path = format!("/v1.0/devices/{}/specifications", device_id);
+        debug!(device_id = %device_id, path = %path, "Fetching device specifications");
 
-        Ok(resp)
+        let req = Request {
+            method: Method::GET,
+            path: &path,
+            body: None,
+            store: ("specifications", device_id),
+        };
+        self.call(&req).await.map(|(specs, _)| specs)
+    }
+
+    /// List all devices linked to the Tuya user the access token was issued
+    /// for.
+    pub async fn list_devices(&self) -> Result<Vec<TuyaDevice>, TuyaError> {
+        self.access_token().await?;
+        let uid = match self.inner.token.lock().await.as_ref() {
+            Some(token) => token.uid.clone(),
+            None => {
+                return Err(TuyaError::Decode(
+                    "no access token after fetching one".into(),
+                ))
+            }
+        };
+        let path = format!("/v1.0/users/{uid}/devices");
+        debug!(path = %path, "Listing devices");
+
+        let req = Request {
+            method: Method::GET,
+            path: &path,
+            body: None,
+            store: ("device_list", ""),
+        };
+        self.call(&req).await.map(|(devices, _)| devices)
     }
 
     /// Fetch shadow properties for a device using the v2 IoT Core endpoint.
@@ -210,43 +726,42 @@ impl TuyaClient {
     pub async fn get_weather_station_status(
         &self,
         device_id: &str,
-    ) -> Result<Vec<ShadowProperty>> {
-        let token = self.access_token().await?;
-        let path = format!("/v2.0/cloud/thing/{}/shadow/properties", device_id);
-        let url = format!("{}{}", self.inner.base_url, path);
-        debug!(device_id = %device_id, url = %url, "Fetching weather station shadow properties");
-
-        let headers = build_signed_headers(
-            "GET",
-            &path,
-            &[],
-            &self.inner.client_id,
-            &self.inner.client_secret,
-            Some(&token),
-        );
-
-        let bytes = self
-            .inner
-            .http
-            .get(&url)
-            .headers(to_header_map(headers)?)
-            .send()
+    ) -> Result<Vec<ShadowProperty>, TuyaError> {
+        debug!(device_id = %device_id, "Fetching weather station shadow properties");
+        self.get_shadow_properties(device_id, "weather_station")
             .await
-            .context("Tuya get_weather_station_status request failed")?
-            .error_for_status()
-            .context("Tuya shadow properties endpoint returned error status")?
-            .bytes()
-            .await
-            .context("Failed to read Tuya shadow properties response body")?;
+    }
 
-        response_store::save("weather_station", device_id, &bytes).await;
+    /// `store` names the call in `response_store` and `tuya_usage`.
+    async fn get_shadow_properties(
+        &self,
+        device_id: &str,
+        store: &str,
+    ) -> Result<Vec<ShadowProperty>, TuyaError> {
+        let path = format!("/v2.0/cloud/thing/{}/shadow/properties", device_id);
+        debug!(device_id = %device_id, path = %path, "Fetching shadow properties");
 
-        let resp = serde_json::from_slice::<ShadowPropertiesResponse>(&bytes)
-            .context("Failed to deserialize Tuya shadow properties response")?
-            .into_result()
-            .context("Tuya shadow properties API call failed")?;
+        let req = Request {
+            method: Method::GET,
+            path: &path,
+            body: None,
+            store: (store, device_id),
+        };
+        self.call::<ShadowPropertiesResult>(&req)
+            .await
+            .map(|(shadow, _)| shadow.properties)
+    }
+}
 
-        Ok(resp.properties)
+/// How long the background task waits before renewing a token that expires
+/// at `expires_at`: immediately when there is none yet.
+fn refresh_delay(expires_at: Option<i64>, now: i64) -> Duration {
+    match expires_at {
+        Some(expires_at) => {
+            let secs = (expires_at - REFRESH_AHEAD_SECS - now).max(0) as u64;
+            Duration::from_secs(secs).max(REFRESH_RETRY)
+        }
+        None => Duration::ZERO,
     }
 }
 
@@ -301,7 +816,14 @@ pub(crate) fn build_signed_headers_inner(
     secret: &str,
     ctx: &SigningContext<'_>,
 ) -> HashMap<String, String> {
-    let SigningContext { method, path_and_query, body_bytes, access_token, t, nonce } = ctx;
+    let SigningContext {
+        method,
+        path_and_query,
+        body_bytes,
+        access_token,
+        t,
+        nonce,
+    } = ctx;
     // 1. SHA-256 of the request body (empty body → well-known hash).
     let content_sha256 = {
         let mut hasher = Sha256::new();
@@ -345,7 +867,7 @@ pub(crate) fn build_signed_headers_inner(
 }
 
 /// Convert our string `HashMap` into a `reqwest::header::HeaderMap`.
-fn to_header_map(map: HashMap<String, String>) -> Result<reqwest::header::HeaderMap> {
+fn to_header_map(map: HashMap<String, String>) -> anyhow::Result<reqwest::header::HeaderMap> {
     let mut header_map = reqwest::header::HeaderMap::new();
     for (k, v) in map {
         let name = reqwest::header::HeaderName::from_bytes(k.as_bytes())
@@ -394,8 +916,8 @@ mod tests {
         "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
 
     fn hmac_sign(s: &str) -> String {
-        let mut mac = HmacSha256::new_from_slice(SECRET.as_bytes())
-            .expect("HMAC accepts any key length");
+        let mut mac =
+            HmacSha256::new_from_slice(SECRET.as_bytes()).expect("HMAC accepts any key length");
         mac.update(s.as_bytes());
         hex::encode(mac.finalize().into_bytes()).to_uppercase()
     }
@@ -484,8 +1006,7 @@ mod tests {
 
         // Different body → different sign
         assert_ne!(
-            headers_empty["sign"],
-            headers_with_body["sign"],
+            headers_empty["sign"], headers_with_body["sign"],
             "body content must affect the signature"
         );
     }
@@ -516,4 +1037,322 @@ mod tests {
         assert_eq!(hm["client_id"], "abc");
         assert_eq!(hm["sign"], "DEF123");
     }
+
+    // -----------------------------------------------------------------------
+    // Retry and token refresh
+    // -----------------------------------------------------------------------
+
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::mock::{cloud, failed, ok, token};
+
+    fn fast_retry() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        }
+    }
+
+    fn client(server: &MockServer) -> TuyaClient {
+        TuyaClient::with_credentials(&server.uri(), "client", "secret").with_retry(fast_retry())
+    }
+
+    fn status() -> serde_json::Value {
+        serde_json::json!([{"code": "switch", "value": true}])
+    }
+
+    #[tokio::test]
+    async fn invalid_token_is_refreshed_once() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/token"))
+            .respond_with(token("at", 7200))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/d1/status"))
+            .respond_with(failed(1010))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/d1/status"))
+            .respond_with(ok(status()))
+            .mount(&server)
+            .await;
+
+        let dps = client(&server).get_device_status("d1").await.unwrap();
+        assert_eq!(dps.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let server = cloud().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/d1/status"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/d1/status"))
+            .respond_with(failed(40000309))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/d1/status"))
+            .respond_with(ok(status()))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dps = client(&server).get_device_status("d1").await.unwrap();
+        assert_eq!(dps.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_give_up_after_max_attempts() {
+        let server = cloud().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/d1/status"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let err = client(&server).get_device_status("d1").await.unwrap_err();
+        assert!(matches!(err, TuyaError::Status(s) if s.as_u16() == 500));
+    }
+
+    #[tokio::test]
+    async fn permanent_failures_are_not_retried() {
+        let server = cloud().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/d1/status"))
+            .respond_with(failed(1106))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = client(&server).get_device_status("d1").await.unwrap_err();
+        assert!(matches!(err, TuyaError::PermissionDenied(_)));
+        assert_eq!(err.tid(), Some("err"));
+    }
+
+    #[tokio::test]
+    async fn batch_status_is_fetched_in_chunks() {
+        let server = cloud().await;
+        let ids: Vec<String> = (0..STATUS_BATCH_SIZE + 5)
+            .map(|i| format!("d{i:02}"))
+            .collect();
+        // The first chunk answers for all but its last device.
+        let first: Vec<_> = ids[..STATUS_BATCH_SIZE - 1]
+            .iter()
+            .map(|id| serde_json::json!({"id": id, "status": status()}))
+            .collect();
+        Mock::given(method("GET"))
+            .and(path("/v1.0/iot-03/devices/status"))
+            .and(query_param(
+                "device_ids",
+                ids[..STATUS_BATCH_SIZE].join(","),
+            ))
+            .respond_with(ok(serde_json::Value::Array(first)))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/iot-03/devices/status"))
+            .and(query_param(
+                "device_ids",
+                ids[STATUS_BATCH_SIZE..].join(","),
+            ))
+            .respond_with(failed(1106))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let statuses = client(&server).get_devices_status(&ids).await;
+        assert_eq!(statuses.len(), ids.len());
+        assert_eq!(statuses["d00"].as_ref().unwrap()[0].code, "switch");
+        assert!(matches!(
+            statuses[&ids[STATUS_BATCH_SIZE - 1]],
+            Err(TuyaError::Decode(_))
+        ));
+        for id in &ids[STATUS_BATCH_SIZE..] {
+            let err = statuses[id].as_ref().unwrap_err();
+            let TuyaError::Batch(e) = err else {
+                panic!("expected a batch failure, got {err:?}");
+            };
+            assert!(matches!(**e, TuyaError::PermissionDenied(_)));
+            assert_eq!(err.tid(), Some("err"));
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // Token refresh
+    // -----------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn expired_token_is_renewed_with_refresh_token() {
+        let server = MockServer::start().await;
+        // Expires immediately, so the second call has to renew it.
+        Mock::given(method("GET"))
+            .and(path("/v1.0/token"))
+            .respond_with(token("at1", 0))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/token/rt-at1"))
+            .respond_with(token("at2", 7200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/d1/status"))
+            .respond_with(ok(status()))
+            .mount(&server)
+            .await;
+
+        let client = client(&server);
+        client.get_device_status("d1").await.unwrap();
+        client.get_device_status("d1").await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let last = requests.last().unwrap();
+        assert_eq!(last.headers["access_token"], "at2");
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_falls_back_to_a_new_grant() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/token"))
+            .respond_with(token("at1", 0))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/token/rt-at1"))
+            .respond_with(failed(1012))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/d1/status"))
+            .respond_with(ok(status()))
+            .mount(&server)
+            .await;
+
+        let client = client(&server);
+        client.get_device_status("d1").await.unwrap();
+        client.get_device_status("d1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn background_refresh_acquires_token_ahead_of_calls() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/token"))
+            .respond_with(token("at1", 7200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = client(&server);
+        let task = tokio::spawn(client.clone().run_token_refresh());
+        for _ in 0..100 {
+            if client.inner.token.lock().await.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        task.abort();
+
+        assert_eq!(client.access_token().await.unwrap(), "at1");
+    }
+
+    #[test]
+    fn refresh_is_scheduled_ahead_of_expiry() {
+        assert_eq!(refresh_delay(None, 1_000), Duration::ZERO);
+        assert_eq!(
+            refresh_delay(Some(1_000 + 7200), 1_000),
+            Duration::from_secs(7200 - REFRESH_AHEAD_SECS as u64)
+        );
+        // Nearly expired: wait a little rather than spinning.
+        assert_eq!(refresh_delay(Some(1_000), 1_000), REFRESH_RETRY);
+    }
+
+    fn local_client(port: u16, dp_codes: HashMap<String, String>) -> LocalClient {
+        LocalClient::new(vec![local::LocalDevice {
+            device_id: "th1".into(),
+            host: "127.0.0.1".into(),
+            local_key: *b"0123456789abcdef",
+            version: local::protocol::Version::V33,
+            transport: Transport::LocalFallback,
+            dp_codes,
+        }])
+        .with_port(port)
+    }
+
+    #[tokio::test]
+    async fn local_transport_learns_dp_ids_from_the_cloud() {
+        let fake = local::fake::FakeDevice::start(
+            local::protocol::Version::V33,
+            *b"0123456789abcdef",
+            serde_json::json!({"1": true, "3": 215}),
+        )
+        .await;
+        let server = cloud().await;
+        Mock::given(method("GET"))
+            .and(path("/v2.0/cloud/thing/th1/shadow/properties"))
+            .respond_with(ok(serde_json::json!({"properties": [
+                {"code": "switch", "dp_id": 1, "time": 0, "type": "bool", "value": true},
+                {"code": "temp_current", "dp_id": 3, "time": 0, "type": "value", "value": 215}
+            ]})))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = client(&server).with_local(local_client(fake.port(), HashMap::new()));
+
+        let mut dps = client.get_device_status("th1").await.unwrap();
+        dps.sort_by(|a, b| a.code.cmp(&b.code));
+        assert_eq!(dps[0].code, "switch");
+        assert_eq!(dps[1].code, "temp_current");
+        assert_eq!(dps[1].value.as_i64(), Some(215));
+
+        let command = Command {
+            code: "switch".into(),
+            value: models::DpValue::Bool(false),
+        };
+        let ack = client.send_commands("th1", vec![command]).await.unwrap();
+        assert!(ack.result);
+        assert_eq!(fake.dps()["1"], false);
+    }
+
+    #[tokio::test]
+    async fn local_fallback_uses_the_cloud_when_the_device_is_unreachable() {
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let server = cloud().await;
+        Mock::given(method("GET"))
+            .and(path("/v1.0/devices/th1/status"))
+            .respond_with(ok(status()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let codes = HashMap::from([("1".to_owned(), "switch".to_owned())]);
+        let client = client(&server).with_local(local_client(port, codes));
+
+        let dps = client.get_device_status("th1").await.unwrap();
+        assert_eq!(dps[0].code, "switch");
+    }
 }
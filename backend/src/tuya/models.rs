@@ -1,5 +1,9 @@
-use anyhow::{anyhow, Context};
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use anyhow::Context;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::error::TuyaError;
 
 // ---------------------------------------------------------------------------
 // Generic response envelope
@@ -38,21 +42,33 @@ pub struct TuyaResponse<T> {
 }
 
 impl<T> TuyaResponse<T> {
-    /// Convert into `anyhow::Result<T>`, mapping API-level failures to errors.
-    pub fn into_result(self) -> anyhow::Result<T> {
+    /// Convert into a `Result`, mapping API-level failures to a [`TuyaError`]
+    /// classified by error code.
+    pub fn into_result(self) -> Result<T, TuyaError> {
         if self.success {
-            self.result
-                .ok_or_else(|| anyhow!("Tuya response: success=true but result field is missing"))
+            self.result.ok_or_else(|| {
+                TuyaError::Decode("success=true but result field is missing".to_owned())
+            })
         } else {
-            Err(anyhow!(
-                "Tuya API error: code={}, msg={}",
-                self.code.unwrap_or(-1),
-                self.msg.as_deref().unwrap_or("(no message)")
-            ))
+            Err(TuyaError::classify(TuyaApiError {
+                code: self.code.unwrap_or(-1),
+                msg: self.msg.unwrap_or_else(|| "(no message)".to_owned()),
+                tid: self.tid,
+            }))
         }
     }
 }
 
+/// An API-level failure reported by Tuya (`success: false`).
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("Tuya API error: code={code}, msg={msg}")]
+pub struct TuyaApiError {
+    pub code: i32,
+    pub msg: String,
+    /// Server-side request trace ID, if Tuya returned one.
+    pub tid: Option<String>,
+}
+
 // ---------------------------------------------------------------------------
 // DpValue — typed replacement for serde_json::Value in device properties
 //
@@ -126,7 +142,7 @@ pub struct TokenResult {
 pub type DeviceStatusResponse = TuyaResponse<Vec<DeviceProperty>>;
 
 /// A single data-point (DP) from the v1 device status endpoint.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct DeviceProperty {
     /// DP code, e.g. `"temp_current"`, `"switch_1"`, `"cur_power"`.
     pub code: String,
@@ -135,6 +151,19 @@ pub struct DeviceProperty {
     pub value: DpValue,
 }
 
+// ---------------------------------------------------------------------------
+// Batch device status  —  GET /v1.0/iot-03/devices/status?device_ids=...
+// ---------------------------------------------------------------------------
+
+/// Status of one device in a batch status response.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceStatus {
+    pub id: String,
+
+    #[serde(default)]
+    pub status: Vec<DeviceProperty>,
+}
+
 // ---------------------------------------------------------------------------
 // Shadow properties  —  GET /v2.0/cloud/thing/{device_id}/shadow/properties
 //
@@ -154,7 +183,7 @@ pub struct ShadowPropertiesResult {
 ///
 /// Compared to `DeviceProperty`, shadow properties carry a per-property
 /// last-updated timestamp and an explicit type tag.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ShadowProperty {
     /// DP code, e.g. `"local_temp"`, `"sub1_hum"`.
     pub code: String,
@@ -176,6 +205,120 @@ pub struct ShadowProperty {
     pub custom_name: Option<String>,
 }
 
+// ---------------------------------------------------------------------------
+// Specifications  —  GET /v1.0/devices/{device_id}/specifications
+//
+// Describes each DP of a device: `functions` are the commandable DPs,
+// `status` the reported ones. `values` is itself a JSON document, sent as a
+// string, e.g. "{\"unit\":\"℃\",\"min\":0,\"max\":500,\"scale\":1,\"step\":5}".
+// ---------------------------------------------------------------------------
+
+/// Full response type: `TuyaResponse<Specifications>`.
+pub type SpecificationsResponse = TuyaResponse<Specifications>;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Specifications {
+    /// Tuya product category code, e.g. `"wk"`.
+    pub category: String,
+    #[serde(default)]
+    pub functions: Vec<DpSpec>,
+    #[serde(default)]
+    pub status: Vec<DpSpec>,
+}
+
+/// Type and value range of one DP.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DpSpec {
+    pub code: String,
+
+    /// `"Integer"` | `"Boolean"` | `"Enum"` | `"Bitmap"` | `"Json"` | ...
+    #[serde(rename = "type")]
+    pub dp_type: String,
+
+    #[serde(deserialize_with = "json_string", default)]
+    pub values: DpValues,
+}
+
+/// Range of an `Integer` DP; other types leave most fields empty.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct DpValues {
+    pub unit: Option<String>,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    /// Real value = raw ÷ 10^scale.
+    pub scale: Option<u32>,
+    pub step: Option<i64>,
+}
+
+/// Deserialize a JSON document embedded in a string. Unparseable documents
+/// yield the default, so an odd DP never fails the whole specification.
+fn json_string<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: serde::de::DeserializeOwned + Default,
+{
+    let raw = String::deserialize(deserializer)?;
+    Ok(serde_json::from_str(&raw).unwrap_or_default())
+}
+
+/// A device's DP specifications, indexed by code.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceSpecs {
+    pub category: String,
+    /// Reported DPs.
+    pub status: HashMap<String, DpSpec>,
+}
+
+impl DeviceSpecs {
+    /// Scale of a reported integer DP, if the specification gives one.
+    pub fn scale(&self, code: &str) -> Option<u32> {
+        self.status.get(code).and_then(|spec| spec.values.scale)
+    }
+}
+
+impl From<Specifications> for DeviceSpecs {
+    fn from(s: Specifications) -> Self {
+        Self {
+            category: s.category,
+            status: s
+                .status
+                .into_iter()
+                .map(|spec| (spec.code.clone(), spec))
+                .collect(),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Device list  —  GET /v1.0/users/{uid}/devices
+// ---------------------------------------------------------------------------
+
+/// Full response type: `TuyaResponse<Vec<TuyaDevice>>`.
+pub type DeviceListResponse = TuyaResponse<Vec<TuyaDevice>>;
+
+/// A device linked to the Tuya account. Only the fields needed for
+/// discovery are parsed; the response carries many more.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TuyaDevice {
+    pub id: String,
+
+    /// User-defined device name.
+    #[serde(default)]
+    pub name: String,
+
+    /// Tuya product category code, e.g. `"wk"` (thermostat) or `"mcs"`
+    /// (contact sensor).
+    pub category: String,
+
+    pub product_id: String,
+
+    #[serde(default)]
+    pub product_name: Option<String>,
+
+    /// Whether the device is currently connected to Tuya Cloud.
+    pub online: bool,
+}
+
 // ---------------------------------------------------------------------------
 // Send commands  —  POST /v1.0/devices/{device_id}/commands
 // ---------------------------------------------------------------------------
@@ -183,6 +326,15 @@ pub struct ShadowProperty {
 /// Full response type: `TuyaResponse<bool>`.
 pub type SendCommandResponse = TuyaResponse<bool>;
 
+/// Outcome of an accepted send-commands call.
+#[derive(Debug, Clone)]
+pub struct CommandAck {
+    /// The `result` flag returned by Tuya.
+    pub result: bool,
+    /// Server-side request trace ID.
+    pub tid: Option<String>,
+}
+
 /// Request body sent to the commands endpoint.
 #[derive(Debug, Serialize)]
 pub struct SendCommandRequest {
@@ -190,7 +342,7 @@ pub struct SendCommandRequest {
 }
 
 /// A single command to send to a device DP.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Command {
     /// DP code to target, e.g. `"switch_1"`.
     pub code: String,
@@ -240,7 +392,36 @@ pub struct ThermostatStatus {
     pub sound: Option<bool>,
 }
 
+/// Lowest setpoint accepted from clients, in °C.
+pub const THERMOSTAT_MIN_SETPOINT_CELSIUS: f64 = 5.0;
+
+/// Upper setpoint limit used when the device does not report `upper_temp`, in °C.
+pub const THERMOSTAT_DEFAULT_MAX_SETPOINT_CELSIUS: f64 = 35.0;
+
 impl ThermostatStatus {
+    /// Highest setpoint the device accepts in °C, from `upper_temp`
+    /// (whole °C on this family — observed value 60).
+    pub fn max_setpoint_celsius(&self) -> f64 {
+        self.upper_temp
+            .map(|v| v as f64)
+            .unwrap_or(THERMOSTAT_DEFAULT_MAX_SETPOINT_CELSIUS)
+    }
+
+    /// Clamp `celsius` to the device's setpoint range and encode it as a raw
+    /// `temp_set` value of the given `scale` (raw ÷ 10^scale = °C).
+    ///
+    /// Returns `(raw, clamped)` where `clamped` is `true` if the requested
+    /// value was outside the range.
+    pub fn clamp_setpoint(&self, celsius: f64, scale: u32) -> (i64, bool) {
+        let max = self.max_setpoint_celsius();
+        let min = THERMOSTAT_MIN_SETPOINT_CELSIUS.min(max);
+        let bounded = celsius.clamp(min, max);
+        (
+            (bounded * 10_f64.powi(scale as i32)).round() as i64,
+            bounded != celsius,
+        )
+    }
+
     /// Current temperature in °C.
     pub fn temp_current_celsius(&self) -> f64 {
         self.temp_current as f64 / 10.0
@@ -294,14 +475,14 @@ impl TryFrom<&[DeviceProperty]> for ThermostatStatus {
 //
 // Observed DPs (device_status, v1 endpoint):
 //   switch                bool
-//   total_forward_energy  i64     Wh accumulated
-//   phase_a/b/c           String  Base64 binary blobs (3-phase data)
+//   total_forward_energy  i64     kWh accumulated, scale 2
+//   phase_a/b/c           String  Base64 binary blobs (3-phase data, see PhaseReading)
 //   fault                 i64     bitmask
 //   switch_prepayment     bool
 //   balance_energy        i64     Wh remaining (prepayment)
 //   charge_energy         i64     Wh charged
 //   leakage_current       i64     mA
-//   reverse_energy_total  i64     Wh
+//   reverse_energy_total  i64     kWh accumulated, scale 2
 //   temp_current          i64     °C  (scale ×1 on this device)
 //   countdown_1           i64     seconds
 //   alarm_set_1/2         String  Base64
@@ -313,7 +494,7 @@ impl TryFrom<&[DeviceProperty]> for ThermostatStatus {
 #[derive(Debug, Clone)]
 pub struct EnergyMeterStatus {
     pub switch: bool,
-    /// Accumulated forward energy in Wh.
+    /// Raw value: 531309 → 5313.09 kWh  (divide by 100 to get kWh).
     pub total_forward_energy: i64,
     /// Base64-encoded 3-phase A data blob.
     pub phase_a: String,
@@ -401,6 +582,56 @@ impl TryFrom<&[DeviceProperty]> for EnergyMeterStatus {
     }
 }
 
+impl EnergyMeterStatus {
+    /// Decoded phases A, B and C.
+    pub fn phases(&self) -> anyhow::Result<[PhaseReading; 3]> {
+        Ok([
+            PhaseReading::decode(&self.phase_a).context("energy_meter: bad DP 'phase_a'")?,
+            PhaseReading::decode(&self.phase_b).context("energy_meter: bad DP 'phase_b'")?,
+            PhaseReading::decode(&self.phase_c).context("energy_meter: bad DP 'phase_c'")?,
+        ])
+    }
+}
+
+/// One phase of an energy meter, decoded from a `phase_a/b/c` DP.
+///
+/// The DP is 8 bytes, Base64-encoded, big-endian:
+///
+/// | Bytes | Field        | Unit  |
+/// |-------|--------------|-------|
+/// | 0–1   | voltage      | 0.1 V |
+/// | 2–4   | current      | mA    |
+/// | 5–7   | active power | W     |
+///
+/// e.g. `COkAABUAAAE=` → `08 E9 00 00 15 00 00 01` → 228.1 V, 21 mA, 1 W.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseReading {
+    /// Raw value: 2281 → 228.1 V  (divide by 10 to get V).
+    pub voltage: u16,
+    /// Current in mA.
+    pub current_ma: u32,
+    /// Active power in W.
+    pub power_w: u32,
+}
+
+impl PhaseReading {
+    /// Decode a Base64 `phase_a/b/c` value.
+    pub fn decode(value: &str) -> anyhow::Result<Self> {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let bytes = STANDARD.decode(value).context("phase data is not base64")?;
+        let Ok(bytes) = <[u8; 8]>::try_from(bytes.as_slice()) else {
+            anyhow::bail!("phase data is {} bytes, expected 8", bytes.len());
+        };
+        let u24 = |b: &[u8]| u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        Ok(Self {
+            voltage: u16::from_be_bytes([bytes[0], bytes[1]]),
+            current_ma: u24(&bytes[2..5]),
+            power_w: u24(&bytes[5..8]),
+        })
+    }
+}
+
 // --- Weather station (bf13e057 family) -------------------------------------
 //
 // Observed properties (shadow/properties, v2 endpoint):
@@ -477,6 +708,45 @@ impl TryFrom<&[ShadowProperty]> for WeatherStationStatus {
     }
 }
 
+// --- Door/window contact sensor (category mcs) ------------------------------
+//
+// Standard DPs (device_status, v1 endpoint):
+//   doorcontact_state   bool    true = open
+//   battery_percentage  i64     %
+//   battery_state       String  "low" | "middle" | "high" (battery-type variants)
+//   temper_alarm        bool    tamper switch triggered
+
+/// Typed view of a door/window contact sensor status.
+#[derive(Debug, Clone)]
+pub struct ContactSensorStatus {
+    /// `true` when the door/window is open.
+    pub doorcontact_state: bool,
+    pub battery_percentage: Option<i64>,
+    pub battery_state: Option<String>,
+    pub temper_alarm: Option<bool>,
+}
+
+impl TryFrom<&[DeviceProperty]> for ContactSensorStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(dps: &[DeviceProperty]) -> anyhow::Result<Self> {
+        let get = |code: &str| dps.iter().find(|dp| dp.code == code);
+
+        let doorcontact_state = get("doorcontact_state")
+            .and_then(|dp| dp.value.as_bool())
+            .with_context(|| "contact_sensor: missing required DP 'doorcontact_state'")?;
+
+        Ok(Self {
+            doorcontact_state,
+            battery_percentage: get("battery_percentage").and_then(|dp| dp.value.as_i64()),
+            battery_state: get("battery_state")
+                .and_then(|dp| dp.value.as_str())
+                .map(str::to_owned),
+            temper_alarm: get("temper_alarm").and_then(|dp| dp.value.as_bool()),
+        })
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
@@ -527,6 +797,36 @@ mod tests {
         assert_eq!(v.as_str(), Some(""));
     }
 
+    // --- TuyaResponse -------------------------------------------------------
+
+    #[test]
+    fn failed_response_becomes_tuya_api_error() {
+        let resp: TuyaResponse<bool> = serde_json::from_str(
+            r#"{"success":false,"t":1561348644346,"code":2009,"msg":"not support","tid":"abc"}"#,
+        )
+        .unwrap();
+        let err = resp.into_result().unwrap_err();
+        let api = err.api().unwrap();
+        assert_eq!(api.code, 2009);
+        assert_eq!(err.tid(), Some("abc"));
+        assert_eq!(
+            err.to_string(),
+            "Tuya API error: code=2009, msg=not support"
+        );
+    }
+
+    #[test]
+    fn failed_response_is_classified_by_code() {
+        let resp: TuyaResponse<bool> = serde_json::from_str(
+            r#"{"success":false,"t":1561348644346,"code":1010,"msg":"token invalid","tid":"abc"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            resp.into_result().unwrap_err(),
+            TuyaError::TokenInvalid(_)
+        ));
+    }
+
     // --- ThermostatStatus ---------------------------------------------------
 
     fn thermostat_dps() -> Vec<DeviceProperty> {
@@ -571,6 +871,23 @@ mod tests {
         assert!((s.temp_set_celsius() - 22.0).abs() < f64::EPSILON);
     }
 
+    #[test]
+    fn thermostat_clamp_setpoint_to_upper_temp() {
+        let dps = thermostat_dps();
+        let s = ThermostatStatus::try_from(dps.as_slice()).unwrap();
+        assert_eq!(s.clamp_setpoint(22.5, 1), (225, false));
+        assert_eq!(s.clamp_setpoint(75.0, 1), (600, true));
+        assert_eq!(s.clamp_setpoint(1.0, 1), (50, true));
+        assert_eq!(s.clamp_setpoint(22.46, 2), (2246, false));
+    }
+
+    #[test]
+    fn thermostat_clamp_setpoint_without_upper_temp_uses_default() {
+        let mut s = ThermostatStatus::try_from(thermostat_dps().as_slice()).unwrap();
+        s.upper_temp = None;
+        assert_eq!(s.clamp_setpoint(40.0, 1), (350, true));
+    }
+
     #[test]
     fn thermostat_missing_required_dp_errors() {
         // Missing 'switch'
@@ -639,6 +956,36 @@ mod tests {
         assert!(err.to_string().contains("phase_b"));
     }
 
+    #[test]
+    fn energy_meter_phases_decode_captured_payloads() {
+        let dps = energy_meter_dps();
+        let s = EnergyMeterStatus::try_from(dps.as_slice()).unwrap();
+        let [a, b, c] = s.phases().unwrap();
+        let phase = |voltage, current_ma, power_w| PhaseReading {
+            voltage,
+            current_ma,
+            power_w,
+        };
+        // No load on phase A.
+        assert_eq!(a, phase(2288, 0, 0));
+        assert_eq!(b, phase(2281, 21, 1));
+        assert_eq!(c, phase(2275, 33, 6));
+    }
+
+    #[test]
+    fn phase_reading_uses_all_three_bytes() {
+        // 230.0 V, 65.536 A, 15 kW
+        let p = PhaseReading::decode("CPwBAAAAOpg=").unwrap();
+        assert_eq!((p.voltage, p.current_ma, p.power_w), (2300, 65_536, 15_000));
+    }
+
+    #[test]
+    fn phase_reading_rejects_bad_payloads() {
+        let err = PhaseReading::decode("abc=").unwrap_err();
+        assert!(err.to_string().contains("expected 8"), "{err}");
+        assert!(PhaseReading::decode("not base64!").is_err());
+    }
+
     // --- WeatherStationStatus -----------------------------------------------
 
     fn weather_props() -> Vec<ShadowProperty> {
@@ -703,4 +1050,63 @@ mod tests {
         assert_eq!(s.sub1_temp, None);
         assert_eq!(s.sub3_hum, None);
     }
+
+    // --- ContactSensorStatus ------------------------------------------------
+
+    #[test]
+    fn contact_sensor_try_from_dps() {
+        let dps: Vec<DeviceProperty> = serde_json::from_str(
+            r#"[
+            {"code":"doorcontact_state","value":true},
+            {"code":"battery_percentage","value":90},
+            {"code":"temper_alarm","value":false}
+        ]"#,
+        )
+        .unwrap();
+        let s = ContactSensorStatus::try_from(dps.as_slice()).unwrap();
+        assert!(s.doorcontact_state);
+        assert_eq!(s.battery_percentage, Some(90));
+        assert_eq!(s.battery_state, None);
+        assert_eq!(s.temper_alarm, Some(false));
+    }
+
+    #[test]
+    fn contact_sensor_missing_state_errors() {
+        let dps: Vec<DeviceProperty> =
+            serde_json::from_str(r#"[{"code":"battery_percentage","value":90}]"#).unwrap();
+        let err = ContactSensorStatus::try_from(dps.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("doorcontact_state"));
+    }
+
+    // --- Specifications -----------------------------------------------------
+
+    #[test]
+    fn specifications_parse_embedded_values() {
+        let json = r#"{
+            "category": "wk",
+            "functions": [
+                {"code": "temp_set", "type": "Integer",
+                 "values": "{\"unit\":\"℃\",\"min\":50,\"max\":350,\"scale\":1,\"step\":5}"}
+            ],
+            "status": [
+                {"code": "temp_current", "type": "Integer",
+                 "values": "{\"unit\":\"℃\",\"min\":0,\"max\":500,\"scale\":1,\"step\":1}"},
+                {"code": "switch", "type": "Boolean", "values": "{}"},
+                {"code": "mode", "type": "Enum", "values": "{\"range\":[\"auto\",\"manual\"]}"},
+                {"code": "odd", "type": "Json", "values": "not json"}
+            ]
+        }"#;
+        let specs: DeviceSpecs = serde_json::from_str::<Specifications>(json).unwrap().into();
+        assert_eq!(specs.category, "wk");
+        assert_eq!(specs.scale("temp_current"), Some(1));
+        assert_eq!(specs.status["temp_current"].values.max, Some(500));
+        assert_eq!(
+            specs.status["temp_current"].values.unit.as_deref(),
+            Some("℃")
+        );
+        assert_eq!(specs.scale("switch"), None);
+        assert_eq!(specs.status["odd"].values, DpValues::default());
+        // Functions are not reported DPs.
+        assert_eq!(specs.scale("temp_set"), None);
+    }
 }
@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{Datelike, NaiveDate, Utc};
+use sqlx::PgPool;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{error, warn};
+
+use crate::config::Config;
+
+/// Monthly Tuya Cloud call quota and how polling reacts as it runs out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuotaSettings {
+    /// Calls allowed per calendar month; `0` means no quota.
+    pub monthly_quota: u64,
+    /// Fraction of the quota after which polling slows down.
+    pub degrade_at: f64,
+    /// Polling interval multiplier once degraded.
+    pub degraded_poll_factor: u32,
+}
+
+impl QuotaSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            monthly_quota: config.tuya_monthly_quota,
+            degrade_at: config.tuya_quota_degrade_at,
+            degraded_poll_factor: config.tuya_degraded_poll_factor,
+        }
+    }
+}
+
+impl Default for QuotaSettings {
+    fn default() -> Self {
+        Self {
+            monthly_quota: 0,
+            degrade_at: 0.8,
+            degraded_poll_factor: 4,
+        }
+    }
+}
+
+/// How often counted calls are written to `tuya_usage`.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Counts one account's Tuya Cloud calls per month and endpoint in the
+/// `tuya_usage` table and keeps this month's total in memory for the quota
+/// check. Calls are counted in memory and written in batches by
+/// [`Usage::flush`], so a call costs no database round trip. Clones share
+/// the counter, so the flush task sees the calls of the account's client.
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// `None` counts in memory only.
+    pool: Option<PgPool>,
+    account: String,
+    quota: QuotaSettings,
+    /// First day of the month being counted and its total.
+    month: Mutex<(NaiveDate, u64)>,
+    /// Calls per month and endpoint not yet written to `tuya_usage`.
+    pending: Mutex<HashMap<(NaiveDate, String), i64>>,
+}
+
+impl Usage {
+    /// Start counting `account`'s calls from zero.
+    pub fn new(pool: PgPool, account: &str, quota: QuotaSettings) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                pool: Some(pool),
+                account: account.to_owned(),
+                quota,
+                month: Mutex::new((month_of(Utc::now().date_naive()), 0)),
+                pending: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Continue counting from `account`'s calls already recorded this month.
+    pub async fn load(
+        pool: PgPool,
+        account: &str,
+        quota: QuotaSettings,
+    ) -> Result<Self, sqlx::Error> {
+        let month = month_of(Utc::now().date_naive());
+        let calls = sqlx::query_scalar!(
+            r#"
+            SELECT COALESCE(SUM(calls), 0)::bigint AS "calls!"
+            FROM tuya_usage
+            WHERE account = $1 AND month = $2
+            "#,
+            account,
+            month,
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        let usage = Self::new(pool, account, quota);
+        *usage.inner.month.lock().expect("usage lock poisoned") = (month, calls as u64);
+        Ok(usage)
+    }
+
+    /// Name of the account whose calls are counted.
+    pub fn account(&self) -> &str {
+        &self.inner.account
+    }
+
+    pub fn quota(&self) -> QuotaSettings {
+        self.inner.quota
+    }
+
+    /// Count one call to `endpoint`; it reaches `tuya_usage` on the next
+    /// [`Usage::flush`].
+    pub fn record(&self, endpoint: &str) {
+        let month = month_of(Utc::now().date_naive());
+        let was_degraded = self.degraded();
+        {
+            let mut current = self.inner.month.lock().expect("usage lock poisoned");
+            if current.0 != month {
+                *current = (month, 0);
+            }
+            current.1 += 1;
+        }
+        if !was_degraded && self.degraded() {
+            warn!(
+                account = %self.inner.account,
+                calls = self.calls_this_month(),
+                quota = self.inner.quota.monthly_quota,
+                factor = self.inner.quota.degraded_poll_factor,
+                "Tuya call quota running out; slowing down polling"
+            );
+        }
+
+        if self.inner.pool.is_some() {
+            let mut pending = self.inner.pending.lock().expect("usage lock poisoned");
+            *pending.entry((month, endpoint.to_owned())).or_default() += 1;
+        }
+    }
+
+    /// Add the calls counted since the last flush to `tuya_usage`.
+    ///
+    /// Calls whose insert fails are logged and kept for the next flush, so a
+    /// database outage only delays them.
+    pub async fn flush(&self) {
+        let Some(pool) = &self.inner.pool else {
+            return;
+        };
+        let pending = std::mem::take(&mut *self.inner.pending.lock().expect("usage lock poisoned"));
+        for ((month, endpoint), calls) in pending {
+            let upserted = sqlx::query!(
+                r#"
+                INSERT INTO tuya_usage (account, month, endpoint, calls)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (account, month, endpoint) DO UPDATE SET calls = tuya_usage.calls + $4
+                "#,
+                self.inner.account,
+                month,
+                endpoint,
+                calls,
+            )
+            .execute(pool)
+            .await;
+
+            if let Err(e) = upserted {
+                error!(
+                    account = %self.inner.account,
+                    endpoint,
+                    calls,
+                    error = %e,
+                    "Failed to record Tuya calls"
+                );
+                let mut pending = self.inner.pending.lock().expect("usage lock poisoned");
+                *pending.entry((month, endpoint)).or_default() += calls;
+            }
+        }
+    }
+
+    /// Flush counted calls every `every`, until the task is dropped.
+    pub async fn run_flush(self, every: Duration) {
+        let mut ticker = time::interval(every);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            self.flush().await;
+        }
+    }
+
+    /// Calls counted in the current month.
+    pub fn calls_this_month(&self) -> u64 {
+        let current = self.inner.month.lock().expect("usage lock poisoned");
+        if current.0 == month_of(Utc::now().date_naive()) {
+            current.1
+        } else {
+            0
+        }
+    }
+
+    /// Whether `degrade_at` of the monthly quota is used up.
+    pub fn degraded(&self) -> bool {
+        let quota = &self.inner.quota;
+        quota.monthly_quota > 0
+            && self.calls_this_month() as f64 >= quota.monthly_quota as f64 * quota.degrade_at
+    }
+
+    /// Multiplier for the sensor polling interval: `1` until degraded.
+    pub fn poll_factor(&self) -> u32 {
+        if self.degraded() {
+            self.inner.quota.degraded_poll_factor.max(1)
+        } else {
+            1
+        }
+    }
+}
+
+/// First day of the month containing `date`.
+pub fn month_of(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).expect("every month has a first day")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(monthly_quota: u64) -> QuotaSettings {
+        QuotaSettings {
+            monthly_quota,
+            degrade_at: 0.5,
+            degraded_poll_factor: 3,
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn calls_are_persisted_per_endpoint(pool: PgPool) {
+        let usage = Usage::new(pool.clone(), "default", quota(0));
+        usage.record("device_status");
+        usage.record("device_status");
+        usage.record("token");
+        assert_eq!(usage.calls_this_month(), 3);
+
+        // Nothing is written until the next flush.
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tuya_usage")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, 0);
+
+        usage.flush().await;
+        usage.record("token");
+        usage.flush().await;
+        let rows: Vec<(String, i64)> =
+            sqlx::query_as("SELECT endpoint, calls FROM tuya_usage ORDER BY endpoint")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            rows,
+            vec![("device_status".to_owned(), 2), ("token".to_owned(), 2)]
+        );
+
+        // A restart continues from the persisted count.
+        let reloaded = Usage::load(pool.clone(), "default", quota(0))
+            .await
+            .unwrap();
+        assert_eq!(reloaded.calls_this_month(), 4);
+        // Other accounts have their own count.
+        let other = Usage::load(pool, "us", quota(0)).await.unwrap();
+        assert_eq!(other.calls_this_month(), 0);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn polling_degrades_once_quota_fraction_is_used(pool: PgPool) {
+        let usage = Usage::new(pool, "default", quota(4));
+        usage.record("device_status");
+        assert!(!usage.degraded());
+        assert_eq!(usage.poll_factor(), 1);
+
+        usage.record("device_status");
+        assert!(usage.degraded());
+        assert_eq!(usage.poll_factor(), 3);
+    }
+
+    #[test]
+    fn no_quota_never_degrades() {
+        let usage = Usage::default();
+        assert!(!usage.degraded());
+        assert_eq!(usage.poll_factor(), 1);
+    }
+
+    #[test]
+    fn month_is_its_first_day() {
+        let date = NaiveDate::from_ymd_opt(2026, 2, 17).unwrap();
+        assert_eq!(month_of(date), NaiveDate::from_ymd_opt(2026, 2, 1).unwrap());
+    }
+}